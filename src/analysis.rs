@@ -0,0 +1,237 @@
+//! Watching audio as it plays, for meters and visualizers.
+//!
+//! A [`Tap`] on the audio thread publishes the latest samples to any number of [`Scope`]s
+//! without locking, so a user interface can look at them at its own frame rate without ever
+//! holding up the audio. [`Spectrum`] turns a snapshot into levels per frequency.
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Level reported for silence, in dBFS.
+pub const FLOOR_DB: f32 = -120.0;
+
+struct Shared {
+    /// Ring of mono samples stored as their bit patterns.
+    samples: Box<[AtomicU32]>,
+    /// Samples written since the start; the next one goes at this index modulo the length.
+    written: AtomicUsize,
+    sample_rate: u32,
+}
+
+/// The writing end of a scope, owned by the audio thread. Never blocks or allocates.
+pub struct Tap {
+    shared: Arc<Shared>,
+}
+
+/// The reading end of a scope. Cloning gives another reader of the same tap.
+#[derive(Clone)]
+pub struct Scope {
+    shared: Arc<Shared>,
+}
+
+/// Creates a tap keeping the last `capacity` mono samples at `sample_rate`, and a scope
+/// reading them.
+pub fn scope(capacity: usize, sample_rate: u32) -> (Tap, Scope) {
+    let shared = Arc::new(Shared {
+        samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+        written: AtomicUsize::new(0),
+        sample_rate,
+    });
+    (
+        Tap {
+            shared: Arc::clone(&shared),
+        },
+        Scope { shared },
+    )
+}
+
+impl Tap {
+    /// Publishes one mono sample.
+    pub fn write(&self, sample: f32) {
+        let shared = &*self.shared;
+        let written = shared.written.load(Ordering::Relaxed);
+        shared.samples[written % shared.samples.len()].store(sample.to_bits(), Ordering::Relaxed);
+        shared
+            .written
+            .store(written.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Publishes interleaved samples, downmixed to mono.
+    pub fn push(&self, samples: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        for frame in samples.chunks_exact(channels) {
+            self.write(frame.iter().sum::<f32>() / channels as f32);
+        }
+    }
+}
+
+impl Scope {
+    pub fn sample_rate(&self) -> u32 {
+        self.shared.sample_rate
+    }
+
+    /// Samples published so far, to tell whether anything new has arrived.
+    pub fn written(&self) -> usize {
+        self.shared.written.load(Ordering::Acquire)
+    }
+
+    /// Copies the most recent samples into `out`, oldest first, with silence in front of
+    /// anything not written yet. Returns [`Scope::written`] as of the copy.
+    ///
+    /// If the tap writes a whole capacity's worth while this runs, the oldest samples copied
+    /// may be newer than the rest. Harmless for display; keep the capacity comfortably larger
+    /// than what is read to make it rare.
+    pub fn latest(&self, out: &mut [f32]) -> usize {
+        let shared = &*self.shared;
+        let written = shared.written.load(Ordering::Acquire);
+        let available = written.min(shared.samples.len()).min(out.len());
+        let (silence, recent) = out.split_at_mut(out.len() - available);
+        silence.fill(0.0);
+        let start = written.wrapping_sub(available);
+        for (offset, sample) in recent.iter_mut().enumerate() {
+            let index = start.wrapping_add(offset) % shared.samples.len();
+            *sample = f32::from_bits(shared.samples[index].load(Ordering::Relaxed));
+        }
+        written
+    }
+}
+
+/// Smallest and largest sample in each of `out.len()` equal slices of `samples`, for drawing
+/// a waveform one column per slice.
+pub fn peaks(samples: &[f32], out: &mut [(f32, f32)]) {
+    let columns = out.len();
+    for (column, peak) in out.iter_mut().enumerate() {
+        let slice =
+            &samples[column * samples.len() / columns..(column + 1) * samples.len() / columns];
+        *peak = slice
+            .iter()
+            .fold(None, |range: Option<(f32, f32)>, &sample| match range {
+                Some((low, high)) => Some((low.min(sample), high.max(sample))),
+                None => Some((sample, sample)),
+            })
+            .unwrap_or_default();
+    }
+}
+
+/// Measures how loud each frequency is in a block of audio.
+///
+/// Uses a Hann-windowed radix-2 FFT. Allocates only when created, so it can be reused every
+/// frame.
+pub struct Spectrum {
+    window: Vec<f32>,
+    /// Twiddle factors for the largest butterfly stage.
+    cos: Vec<f32>,
+    sin: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    levels: Vec<f32>,
+}
+
+impl Spectrum {
+    /// Analyzes blocks of `size` samples, which must be a power of two.
+    pub fn new(size: usize) -> Self {
+        assert!(
+            size.is_power_of_two() && size >= 2,
+            "FFT size must be a power of two"
+        );
+        let window = (0..size)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / size as f32).cos())
+            .collect();
+        let angles = (0..size / 2).map(|k| -2.0 * PI * k as f32 / size as f32);
+        Self {
+            window,
+            cos: angles.clone().map(f32::cos).collect(),
+            sin: angles.map(f32::sin).collect(),
+            re: vec![0.0; size],
+            im: vec![0.0; size],
+            levels: vec![FLOOR_DB; size / 2 + 1],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.window.len()
+    }
+
+    /// The levels of the last analysis, in dBFS, one per bin from 0 Hz up to half the sample
+    /// rate.
+    pub fn levels(&self) -> &[f32] {
+        &self.levels
+    }
+
+    /// Analyzes the last [`Spectrum::size`] samples of `samples`, padding with silence in
+    /// front if there are fewer, and returns [`Spectrum::levels`]. A full-scale sine wave
+    /// reads 0 dBFS at its frequency.
+    pub fn analyze(&mut self, samples: &[f32]) -> &[f32] {
+        let size = self.size();
+        let samples = &samples[samples.len().saturating_sub(size)..];
+        let padding = size - samples.len();
+        self.re[..padding].fill(0.0);
+        for ((re, sample), window) in self.re[padding..]
+            .iter_mut()
+            .zip(samples)
+            .zip(&self.window[padding..])
+        {
+            *re = sample * window;
+        }
+        self.im.fill(0.0);
+        self.transform();
+
+        // The window halves a sine's amplitude and a real signal splits between two bins.
+        let full_scale = size as f32 / 4.0;
+        for (bin, level) in self.levels.iter_mut().enumerate() {
+            let magnitude = self.re[bin].hypot(self.im[bin]) / full_scale;
+            *level = (20.0 * magnitude.log10()).max(FLOOR_DB);
+        }
+        &self.levels
+    }
+
+    /// Averages the last analysis into `out.len()` bands spaced evenly on a logarithmic scale
+    /// from `low` to `high` Hz, the way frequencies are heard.
+    pub fn bands(&self, sample_rate: u32, low: f32, high: f32, out: &mut [f32]) {
+        let bin_width = sample_rate as f32 / self.size() as f32;
+        let ratio = (high / low).powf(1.0 / out.len() as f32);
+        let last = self.levels.len() - 1;
+        for (band, level) in out.iter_mut().enumerate() {
+            let from = low * ratio.powi(band as i32);
+            let first = ((from / bin_width).round() as usize).min(last);
+            let end = (((from * ratio) / bin_width).round() as usize).clamp(first + 1, last + 1);
+            let power = self.levels[first..end]
+                .iter()
+                .map(|level| 10f32.powf(level / 10.0))
+                .sum::<f32>()
+                / (end - first) as f32;
+            *level = (10.0 * power.log10()).max(FLOOR_DB);
+        }
+    }
+
+    /// In-place iterative Cooley-Tukey FFT of `re` and `im`.
+    fn transform(&mut self) {
+        let size = self.size();
+        let bits = size.trailing_zeros();
+        for index in 0..size {
+            let reversed = index.reverse_bits() >> (usize::BITS - bits);
+            if reversed > index {
+                self.re.swap(index, reversed);
+                self.im.swap(index, reversed);
+            }
+        }
+        let mut half = 1;
+        while half < size {
+            let stride = size / (half * 2);
+            for start in (0..size).step_by(half * 2) {
+                for k in 0..half {
+                    let (cos, sin) = (self.cos[k * stride], self.sin[k * stride]);
+                    let (a, b) = (start + k, start + k + half);
+                    let re = self.re[b] * cos - self.im[b] * sin;
+                    let im = self.re[b] * sin + self.im[b] * cos;
+                    self.re[b] = self.re[a] - re;
+                    self.im[b] = self.im[a] - im;
+                    self.re[a] += re;
+                    self.im[a] += im;
+                }
+            }
+            half *= 2;
+        }
+    }
+}
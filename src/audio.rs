@@ -0,0 +1,345 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use tracing::warn;
+
+use crate::drift::Resampler;
+use crate::util::extend_channels;
+use crate::wav::{self, WavSpec, WavWriter};
+
+/// Something that produces interleaved f32 samples, such as a microphone or a file.
+pub trait AudioSource {
+    /// Fills `buf` with as many samples as are available and returns how many were written.
+    fn read(&mut self, buf: &mut [f32]) -> usize;
+}
+
+/// Something that consumes interleaved f32 samples, such as a speaker or a file.
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32]);
+}
+
+/// Reads a source with `from` channels as if it had `to`, with [`extend_channels`].
+pub struct RemixSource {
+    source: Box<dyn AudioSource + Send>,
+    from: usize,
+    to: usize,
+    scratch: Vec<f32>,
+    converted: Vec<f32>,
+}
+
+impl RemixSource {
+    pub fn new(source: Box<dyn AudioSource + Send>, from: usize, to: usize) -> Self {
+        Self {
+            source,
+            from,
+            to,
+            scratch: Vec::new(),
+            converted: Vec::new(),
+        }
+    }
+}
+
+impl AudioSource for RemixSource {
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        self.scratch.resize(buf.len() / self.to * self.from, 0.0);
+        let count = self.source.read(&mut self.scratch);
+        self.converted.clear();
+        extend_channels(
+            &mut self.converted,
+            &self.scratch[..count],
+            self.from,
+            self.to,
+        );
+        buf[..self.converted.len()].copy_from_slice(&self.converted);
+        self.converted.len()
+    }
+}
+
+/// Writes audio with `from` channels to a sink with `to`, with [`extend_channels`].
+pub struct RemixSink {
+    sink: Box<dyn AudioSink + Send>,
+    from: usize,
+    to: usize,
+    scratch: Vec<f32>,
+}
+
+impl RemixSink {
+    pub fn new(sink: Box<dyn AudioSink + Send>, from: usize, to: usize) -> Self {
+        Self {
+            sink,
+            from,
+            to,
+            scratch: Vec::new(),
+        }
+    }
+}
+
+impl AudioSink for RemixSink {
+    fn write(&mut self, samples: &[f32]) {
+        self.scratch.clear();
+        extend_channels(&mut self.scratch, samples, self.from, self.to);
+        self.sink.write(&self.scratch);
+    }
+}
+
+/// Reads a source running at `from` Hz as if it ran at `to`.
+pub struct ResampleSource {
+    source: Box<dyn AudioSource + Send>,
+    resampler: Resampler,
+}
+
+impl ResampleSource {
+    pub fn new(source: Box<dyn AudioSource + Send>, channels: usize, from: u32, to: u32) -> Self {
+        let mut resampler = Resampler::new(channels);
+        resampler.set_ratio(from as f64 / to as f64);
+        Self { source, resampler }
+    }
+}
+
+impl AudioSource for ResampleSource {
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        self.resampler.read(&mut self.source, buf)
+    }
+}
+
+/// Writes audio at `from` Hz to a sink running at `to`.
+pub struct ResampleSink {
+    sink: Box<dyn AudioSink + Send>,
+    channels: usize,
+    ratio: f64,
+    resampler: Resampler,
+    /// Written but not resampled yet.
+    pending: Pending,
+    scratch: Vec<f32>,
+}
+
+impl ResampleSink {
+    pub fn new(sink: Box<dyn AudioSink + Send>, channels: usize, from: u32, to: u32) -> Self {
+        let ratio = from as f64 / to as f64;
+        let mut resampler = Resampler::new(channels);
+        resampler.set_ratio(ratio);
+        Self {
+            sink,
+            channels,
+            ratio,
+            resampler,
+            pending: Pending(Vec::new()),
+            scratch: Vec::new(),
+        }
+    }
+}
+
+impl AudioSink for ResampleSink {
+    fn write(&mut self, samples: &[f32]) {
+        self.pending.0.extend_from_slice(samples);
+        // Room for everything the input held so far can make; the resampler stops short.
+        let input = self.pending.0.len() / self.channels + self.resampler.buffered();
+        let frames = (input as f64 / self.ratio).ceil() as usize + 1;
+        self.scratch.resize(frames * self.channels, 0.0);
+        let count = self.resampler.read(&mut self.pending, &mut self.scratch);
+        self.sink.write(&self.scratch[..count]);
+    }
+}
+
+/// Samples handed out in the order they were added.
+struct Pending(Vec<f32>);
+
+impl AudioSource for Pending {
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        let count = self.0.len().min(buf.len());
+        buf[..count].copy_from_slice(&self.0[..count]);
+        self.0.drain(..count);
+        count
+    }
+}
+
+impl<S: AudioSource + ?Sized> AudioSource for Box<S> {
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        (**self).read(buf)
+    }
+}
+
+/// Plays back the contents of a WAV file, then silence.
+pub struct FileSource {
+    spec: WavSpec,
+    samples: Vec<f32>,
+    position: usize,
+}
+
+impl FileSource {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let (spec, samples) = wav::read(path)?;
+        Ok(Self::from_samples(spec, samples))
+    }
+
+    pub fn from_samples(spec: WavSpec, samples: Vec<f32>) -> Self {
+        Self {
+            spec,
+            samples,
+            position: 0,
+        }
+    }
+
+    pub fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    /// Returns true once every sample has been read.
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.samples.len()
+    }
+}
+
+impl AudioSource for FileSource {
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        let remaining = &self.samples[self.position..];
+        let count = remaining.len().min(buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        self.position += count;
+        count
+    }
+}
+
+/// Records everything written to it into a WAV file.
+pub struct FileSink {
+    writer: WavWriter,
+}
+
+impl FileSink {
+    pub fn create(path: impl AsRef<Path>, spec: WavSpec) -> io::Result<Self> {
+        Ok(Self {
+            writer: WavWriter::create(path, spec)?,
+        })
+    }
+}
+
+impl AudioSink for FileSink {
+    fn write(&mut self, samples: &[f32]) {
+        if let Err(err) = self.writer.write_samples(samples) {
+            warn!(error = %err, "failed to write samples");
+        }
+    }
+}
+
+/// A bounded FIFO shared between an audio device callback and a worker thread.
+///
+/// When full, the oldest samples are discarded so latency stays bounded.
+#[derive(Clone)]
+pub struct SharedBuffer {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+    capacity: usize,
+}
+
+impl SharedBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&self, data: &[f32]) {
+        let mut samples = self.samples.lock().unwrap();
+        samples.extend(data);
+        let overflow = samples.len().saturating_sub(self.capacity);
+        samples.drain(..overflow);
+    }
+
+    pub fn pop_into(&self, out: &mut [f32]) -> usize {
+        let mut samples = self.samples.lock().unwrap();
+        let count = samples.len().min(out.len());
+        for (out, sample) in out.iter_mut().zip(samples.drain(..count)) {
+            *out = sample;
+        }
+        count
+    }
+}
+
+impl AudioSource for SharedBuffer {
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        self.pop_into(buf)
+    }
+}
+
+impl AudioSink for SharedBuffer {
+    fn write(&mut self, samples: &[f32]) {
+        self.push(samples);
+    }
+}
+
+/// Generates an endless sine tone, the same on every channel.
+pub struct ToneSource {
+    frequency: f32,
+    amplitude: f32,
+    sample_rate: u32,
+    channels: usize,
+    position: u64,
+}
+
+impl ToneSource {
+    pub fn new(frequency: f32, amplitude: f32, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            frequency,
+            amplitude,
+            sample_rate,
+            channels,
+            position: 0,
+        }
+    }
+}
+
+impl AudioSource for ToneSource {
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        for frame in buf.chunks_mut(self.channels) {
+            // Wrap once per second so the phase stays precise however long it runs.
+            let n = self.position % self.sample_rate as u64;
+            let phase = 2.0 * PI * self.frequency * n as f32 / self.sample_rate as f32;
+            frame.fill(self.amplitude * phase.sin());
+            self.position += 1;
+        }
+        buf.len()
+    }
+}
+
+/// Keeps everything written to it in memory. Clones share the same samples, so one can be
+/// handed to a pipeline and the other inspected afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    samples: Arc<Mutex<Vec<f32>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far.
+    pub fn samples(&self) -> Vec<f32> {
+        self.samples.lock().unwrap().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl AudioSink for MemorySink {
+    fn write(&mut self, samples: &[f32]) {
+        self.samples.lock().unwrap().extend_from_slice(samples);
+    }
+}
@@ -1,125 +1,211 @@
-use cpal::{
-    BufferSize, SampleFormat, StreamConfig,
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-};
-use opus::{Channels, Decoder, Encoder};
-use std::sync::{Arc, Mutex};
+use clap::Parser;
+use cpal::{BufferSize, StreamConfig, traits::DeviceTrait, traits::StreamTrait};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use test_gpui::audio::{AudioSource, SharedBuffer};
+use test_gpui::clock::SystemClock;
+use test_gpui::codec::Codec;
+use test_gpui::device;
+use test_gpui::drift::DriftCompensator;
+use test_gpui::error::{Context, Result};
+use test_gpui::latency::LatencyProbe;
+use test_gpui::logging::{self, Event, LogArgs, REALTIME_CAPACITY};
+use test_gpui::pipeline::{ConvertChannels, Decode, Encode, PacedSource, Pipeline, SinkWriter};
+use tracing::{error, info};
 
 const ENCODING_SAMPLE_RATE: u32 = 48_000;
-const ENCODING_CHANNELS: Channels = Channels::Stereo;
-const ENCODING_MS: usize = (ENCODING_SAMPLE_RATE as usize) * 2 * 20 / 1000;
+const ENCODING_CHANNELS: usize = 2;
+const FRAME_MS: u32 = 20;
+/// Audio kept on either side of the pipeline, where it meets a device's clock.
+const LATENCY: Duration = Duration::from_millis(40);
+
+/// Captures the default input device, encodes it with Opus and plays it back decoded on the
+/// default output device.
+#[derive(Parser)]
+struct Args {
+    /// Send a marker through every second, and report how long it takes to get through
+    /// each stage and to come back in, through the air or a loopback cable.
+    #[arg(long)]
+    probe: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
 
 fn main() {
+    let args = Args::parse();
+    args.log.init();
+    if let Err(err) = run(args.probe) {
+        error!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(probe: bool) -> Result<()> {
+    // Audio callbacks log through here rather than printing, which could glitch the audio.
+    let (log, drain) = logging::realtime_log(REALTIME_CAPACITY);
+    drain.spawn();
+
     let host = cpal::default_host();
 
-    // Select input and output devices
-    let input_device = host
-        .default_input_device()
-        .expect("No default input device.");
-    let output_device = host
-        .default_output_device()
-        .expect("No default output device.");
-
-    println!("Input device: {}", input_device.name().unwrap());
-    println!("Output device: {}", output_device.name().unwrap());
-
-    // Get input and output configurations
-    let input_config = input_device
-        .default_input_config()
-        .expect("Failed to get input config.");
-    let output_config = output_device
-        .default_output_config()
-        .expect("Failed to get output config.");
-
-    println!("Input config: {:?}", input_config);
-    println!("Output config: {:?}", output_config);
-
-    // Ensure both input and output use the same sample rate
-    let sample_rate = input_config.sample_rate().0;
-    let channels = input_config.channels();
-
-    let stream_config = StreamConfig {
-        channels,
-        sample_rate: cpal::SampleRate(sample_rate),
-        buffer_size: BufferSize::Fixed(960),
-    };
+    // Select input and output devices with their configurations
+    let (input_device, input_config) = device::default_input(&host)?;
+    let (output_device, output_config) = device::default_output(&host)?;
 
-    // Shared buffer between input and output
-    let audio_buffer = Arc::new(Mutex::new(Vec::<f32>::new()));
+    info!(device = %device_name(&input_device), config = ?input_config, "input");
+    info!(device = %device_name(&output_device), config = ?output_config, "output");
 
-    // Clone the buffer for input handling
-    let input_buffer = Arc::clone(&audio_buffer);
+    let input_channels = input_config.channels() as usize;
+    let output_channels = output_config.channels() as usize;
+    let input_stream_config = StreamConfig {
+        channels: input_channels as u16,
+        sample_rate: cpal::SampleRate(ENCODING_SAMPLE_RATE),
+        buffer_size: BufferSize::Default,
+    };
+    let output_stream_config = StreamConfig {
+        channels: output_channels as u16,
+        ..input_stream_config.clone()
+    };
+
+    // Keep at most half a second of audio queued on either side of the pipeline
+    let capture = SharedBuffer::new(ENCODING_SAMPLE_RATE as usize * input_channels / 2);
+    let playback = SharedBuffer::new(ENCODING_SAMPLE_RATE as usize * output_channels / 2);
 
-    let mut encoder = Encoder::new(
+    // Each side is read resampled to the clock of whatever fills it, so neither buffer
+    // slowly fills up or runs dry
+    let capture_reader = DriftCompensator::new(
+        capture.clone(),
         ENCODING_SAMPLE_RATE,
-        ENCODING_CHANNELS,
-        opus::Application::Voip,
+        input_channels,
+        LATENCY,
+    );
+    let mut playback_reader = DriftCompensator::new(
+        playback.clone(),
+        ENCODING_SAMPLE_RATE,
+        output_channels,
+        LATENCY,
+    );
+
+    // Markers go in with the captured audio, and are looked for after each stage that
+    // holds on to it and when the microphone hears them played.
+    let probe = probe.then(LatencyProbe::default);
+    let stage = |name: &str| {
+        probe
+            .as_ref()
+            .map(|probe| probe.detector(name, ENCODING_SAMPLE_RATE, SystemClock))
+    };
+    let capture_probe = stage("capture");
+    let codec_probe = stage("codec");
+    let mut output_probe = stage("playback");
+    let mut input_probe = stage("loopback").zip(
+        probe
+            .as_ref()
+            .map(|probe| probe.injector(ENCODING_SAMPLE_RATE, SystemClock)),
+    );
+
+    let mut pipeline = Pipeline::source(
+        "capture",
+        PacedSource::new(
+            capture_reader,
+            ENCODING_SAMPLE_RATE,
+            input_channels,
+            FRAME_MS,
+        ),
     )
-    .unwrap();
-    let mut decoder = Decoder::new(ENCODING_SAMPLE_RATE, ENCODING_CHANNELS).unwrap();
-    let encoded_buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
-    let input_encoded_buffer = Arc::clone(&encoded_buffer);
-    let output_encoded_buffer = Arc::clone(&encoded_buffer);
+    .then("upmix", ConvertChannels::new(ENCODING_CHANNELS));
+    if let Some(detector) = capture_probe {
+        pipeline = pipeline.then("probe capture", detector);
+    }
+    let mut pipeline = pipeline
+        .then(
+            "encode",
+            Codec::Opus
+                .encoder(ENCODING_SAMPLE_RATE, ENCODING_CHANNELS)
+                .and_then(|encoder| Encode::new(encoder, FRAME_MS))
+                .context("creating the encoder")?,
+        )
+        .then(
+            "decode",
+            Decode::new(
+                Codec::Opus
+                    .decoder(ENCODING_SAMPLE_RATE, ENCODING_CHANNELS)
+                    .context("creating the decoder")?,
+            ),
+        );
+    if let Some(detector) = codec_probe {
+        pipeline = pipeline.then("probe codec", detector);
+    }
+    let pipeline = pipeline
+        .then("remix", ConvertChannels::new(output_channels))
+        .sink("playback", SinkWriter::new(playback.clone()));
 
     // Start input stream (recording)
-    let input_stream = input_device
-        .build_input_stream(
-            &stream_config,
-            move |data: &[f32], _| {
-                let mut buffer = input_buffer.lock().unwrap();
-                buffer.extend_from_slice(data);
-                println!("Captured {} samples", data.len());
-                if buffer.len() >= ENCODING_MS {
-                    let mut abuffer = input_encoded_buffer.lock().unwrap();
-                    let mut tbuffer = [0u8; ENCODING_MS];
-                    let size = encoder
-                        .encode_float(&buffer[..ENCODING_MS], &mut tbuffer)
-                        .expect("Failed to encode buffer");
-                    abuffer.extend_from_slice(&tbuffer[..size]);
+    let (input_log, input_errors) = (log.clone(), log.clone());
+    let output_log = log.clone();
+    let input_stream = device::open_input(
+        &input_device,
+        &input_stream_config,
+        input_config.sample_format(),
+        move |data| {
+            match &mut input_probe {
+                Some((heard, injector)) => {
+                    heard.process(data, input_channels);
+                    let mut data = data.to_vec();
+                    injector.process(&mut data, input_channels);
+                    capture.push(&data);
                 }
-            },
-            |err| eprintln!("Input stream error: {}", err),
-            None,
-        )
-        .expect("Failed to create input stream");
-
-    // Clone buffer for output handling
-    let output_buffer = Arc::clone(&audio_buffer);
+                None => capture.push(data),
+            }
+            input_log.record(Event::Captured {
+                samples: data.len(),
+            });
+        },
+        move |err| input_errors.record(Event::InputFailed(err)),
+    )
+    .context("opening the input stream")?;
 
     // Start output stream (playback)
-    let output_stream = output_device
-        .build_output_stream(
-            &stream_config,
-            move |output: &mut [f32], _| {
-                let mut buffer = output_encoded_buffer.lock().unwrap();
-                let mut decoded_buffer = [0f32; ENCODING_MS];
-                if buffer.len() >= 1 {
-                    let size = decoder
-                        .decode_float(&buffer, &mut decoded_buffer, true)
-                        .expect("Failed to decode buffer");
-                    let needed_samples = output.len();
-                    // Fill output with available samples
-                    for (out_sample, in_sample) in output.iter_mut().zip(decoded_buffer.iter()) {
-                        *out_sample = *in_sample;
-                    }
-
-                    // Drain the processed samples
-                    buffer.drain(..needed_samples.min(size));
-                }
-            },
-            |err| eprintln!("Output stream error: {}", err),
-            None,
-        )
-        .expect("Failed to create output stream");
+    let output_stream = device::open_output(
+        &output_device,
+        &output_stream_config,
+        output_config.sample_format(),
+        move |output| {
+            let available = playback_reader.read(output);
+            output[available..].fill(0.0);
+            if let Some(played) = &mut output_probe {
+                played.process(output, output_channels);
+            }
+            if available < output.len() {
+                output_log.record(Event::Underrun {
+                    wanted: output.len(),
+                    available,
+                });
+            }
+        },
+        move |err| log.record(Event::OutputFailed(err)),
+    )
+    .context("opening the output stream")?;
+
+    let running = pipeline.start().context("starting the pipeline")?;
 
     // Start both streams
-    input_stream.play().expect("Failed to start input stream");
-    output_stream.play().expect("Failed to start output stream");
+    input_stream.play().context("starting the input stream")?;
+    output_stream.play().context("starting the output stream")?;
 
     println!("Listening... Press Ctrl+C to stop.");
-
-    // Keep running
-    loop {
-        std::thread::sleep(std::time::Duration::from_secs(1));
+    if let Some(probe) = probe {
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                info!("latency: {}", probe.report());
+            }
+        });
     }
+    running.wait();
+    Ok(())
+}
+
+fn device_name(device: &cpal::Device) -> String {
+    device.name().unwrap_or_else(|_| String::from("unknown"))
 }
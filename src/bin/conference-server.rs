@@ -0,0 +1,88 @@
+use std::io;
+use std::process;
+use std::sync::mpsc::{self, TryRecvError};
+use std::time::Duration;
+
+use clap::Parser;
+use test_gpui::conference::{ConferenceConfig, ConferenceServer, Event, Mode};
+use test_gpui::config::{NetArgs, NetConfig, NetDefaults};
+use test_gpui::error::{Context, Result};
+use test_gpui::logging::LogArgs;
+use test_gpui::net::bind_socket;
+use tracing::{error, info};
+
+const DEFAULTS: NetDefaults = NetDefaults {
+    bind_port: 6000,
+    peer_port: None,
+};
+
+/// Hosts multi-party calls. Participants join a room by calling the server with the room
+/// number as call ID.
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    net: NetArgs,
+
+    /// Forward every stream to the rest of the room, or send each listener its own mix.
+    #[arg(long, value_enum, default_value_t = Mode::Forward)]
+    mode: Mode,
+
+    /// Most participants allowed in one room.
+    #[arg(long, default_value_t = ConferenceConfig::default().max_participants)]
+    max_participants: usize,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+fn main() {
+    let args = Args::parse();
+    args.log.init();
+    let net = match args.net.resolve("conference-server", DEFAULTS) {
+        Ok(net) => net,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(&args, net) {
+        error!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args, net: NetConfig) -> Result<()> {
+    let socket = bind_socket(&net).context(format!("binding {}", net.bind))?;
+    let config = ConferenceConfig {
+        mode: args.mode,
+        max_participants: args.max_participants.max(1),
+        ..ConferenceConfig::default()
+    };
+    let server = ConferenceServer::start(socket, config).context("starting the server")?;
+
+    let (stop_tx, stop_rx) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = stop_tx.send(());
+    })
+    .map_err(io::Error::other)
+    .context("installing the Ctrl+C handler")?;
+
+    info!(bind = %server.local_addr(), mode = ?config.mode, "serving conferences");
+    println!("Press Ctrl+C to stop.");
+    while let Err(TryRecvError::Empty) = stop_rx.try_recv() {
+        match server.next_event(Duration::from_millis(200)) {
+            Some(Event::Joined { room, peer, ssrc }) => {
+                info!(%peer, ssrc = format_args!("{:08x}", ssrc), room, "joined");
+            }
+            Some(Event::Left { room, peer, reason }) => {
+                info!(%peer, room, ?reason, "left");
+            }
+            None => {}
+        }
+    }
+
+    info!("shutting down");
+    server.stop();
+    Ok(())
+}
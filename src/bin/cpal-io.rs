@@ -1,33 +1,54 @@
-use cpal::{
-    BufferSize, SampleFormat, StreamConfig,
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-};
-use std::sync::{Arc, Mutex};
+use clap::Parser;
+use cpal::{BufferSize, StreamConfig, traits::DeviceTrait, traits::StreamTrait};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use test_gpui::audio::{AudioSource, SharedBuffer};
+use test_gpui::clock::SystemClock;
+use test_gpui::device;
+use test_gpui::drift::DriftCompensator;
+use test_gpui::error::{Context, Result};
+use test_gpui::latency::LatencyProbe;
+use test_gpui::logging::{self, Event, LogArgs, REALTIME_CAPACITY};
+use tracing::{error, info};
+
+/// Audio kept between the two devices, whose clocks are compensated for drifting apart.
+const LATENCY: Duration = Duration::from_millis(50);
+
+/// Plays the default input device straight back on the default output device.
+#[derive(Parser)]
+struct Args {
+    /// Send a marker through every second, and report how long it takes to reach the
+    /// output and to come back in, through the air or a loopback cable.
+    #[arg(long)]
+    probe: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
 
 fn main() {
-    let host = cpal::default_host();
+    let args = Args::parse();
+    args.log.init();
+    if let Err(err) = run(args.probe) {
+        error!("{}", err);
+        process::exit(1);
+    }
+}
 
-    // Select input and output devices
-    let input_device = host
-        .default_input_device()
-        .expect("No default input device.");
-    let output_device = host
-        .default_output_device()
-        .expect("No default output device.");
+fn run(probe: bool) -> Result<()> {
+    // Audio callbacks log through here rather than printing, which could glitch the audio.
+    let (log, drain) = logging::realtime_log(REALTIME_CAPACITY);
+    drain.spawn();
 
-    println!("Input device: {}", input_device.name().unwrap());
-    println!("Output device: {}", output_device.name().unwrap());
+    let host = cpal::default_host();
 
-    // Get input and output configurations
-    let input_config = input_device
-        .default_input_config()
-        .expect("Failed to get input config.");
-    let output_config = output_device
-        .default_output_config()
-        .expect("Failed to get output config.");
+    // Select input and output devices with their configurations
+    let (input_device, input_config) = device::default_input(&host)?;
+    let (output_device, output_config) = device::default_output(&host)?;
 
-    println!("Input config: {:?}", input_config);
-    println!("Output config: {:?}", output_config);
+    info!(device = %device_name(&input_device), config = ?input_config, "input");
+    info!(device = %device_name(&output_device), config = ?output_config, "output");
 
     // Ensure both input and output use the same sample rate
     let sample_rate = input_config.sample_rate().0;
@@ -39,67 +60,93 @@ fn main() {
         buffer_size: BufferSize::Fixed(1024),
     };
 
-    // Shared buffer between input and output
-    let audio_buffer = Arc::new(Mutex::new(Vec::<f32>::new()));
+    // Shared buffer between input and output, holding up to a second
+    let audio_buffer = SharedBuffer::new(sample_rate as usize * channels as usize);
 
     // Clone the buffer for input handling
-    let input_buffer = Arc::clone(&audio_buffer);
-
-    // Start input stream (recording)
-    let input_stream = input_device
-        .build_input_stream(
-            &stream_config,
-            move |data: &[f32], _| {
-                let mut buffer = input_buffer.lock().unwrap();
-                buffer.extend_from_slice(data);
-                println!("Captured {} samples", data.len());
-            },
-            |err| eprintln!("Input stream error: {}", err),
-            None,
+    let input_buffer = audio_buffer.clone();
+
+    // Markers go in with the captured audio, and are looked for on their way out to the
+    // speaker and when the microphone hears them played
+    let probe = probe.then(LatencyProbe::default);
+    let mut output_probe = probe
+        .as_ref()
+        .map(|probe| probe.detector("playback", sample_rate, SystemClock));
+    let mut input_probe = probe.as_ref().map(|probe| {
+        (
+            probe.detector("loopback", sample_rate, SystemClock),
+            probe.injector(sample_rate, SystemClock),
         )
-        .expect("Failed to create input stream");
-
-    // Clone buffer for output handling
-    let output_buffer = Arc::clone(&audio_buffer);
+    });
 
-    // Start output stream (playback)
-    let output_stream = output_device
-        .build_output_stream(
-            &stream_config,
-            move |output: &mut [f32], _| {
-                let mut buffer = output_buffer.lock().unwrap();
-
-                let available_samples = buffer.len();
-                let needed_samples = output.len();
-
-                if available_samples < needed_samples {
-                    println!(
-                        "Buffer underflow! Available: {}, Needed: {}",
-                        available_samples, needed_samples
-                    );
-                }
-
-                // Fill output with available samples
-                for (out_sample, in_sample) in output.iter_mut().zip(buffer.iter()) {
-                    *out_sample = *in_sample;
+    // Start input stream (recording)
+    let (input_log, input_errors) = (log.clone(), log.clone());
+    let input_stream = device::open_input(
+        &input_device,
+        &stream_config,
+        input_config.sample_format(),
+        move |data| {
+            match &mut input_probe {
+                Some((heard, injector)) => {
+                    heard.process(data, channels as usize);
+                    let mut data = data.to_vec();
+                    injector.process(&mut data, channels as usize);
+                    input_buffer.push(&data);
                 }
+                None => input_buffer.push(data),
+            }
+            input_log.record(Event::Captured {
+                samples: data.len(),
+            });
+        },
+        move |err| input_errors.record(Event::InputFailed(err)),
+    )
+    .context("opening the input stream")?;
+
+    // The output reads the buffer resampled to the input's clock
+    let mut output_buffer =
+        DriftCompensator::new(audio_buffer, sample_rate, channels as usize, LATENCY);
 
-                // Drain the processed samples
-                buffer.drain(..needed_samples.min(available_samples));
-            },
-            |err| eprintln!("Output stream error: {}", err),
-            None,
-        )
-        .expect("Failed to create output stream");
+    // Start output stream (playback)
+    let output_log = log.clone();
+    let output_stream = device::open_output(
+        &output_device,
+        &stream_config,
+        output_config.sample_format(),
+        move |output| {
+            // Fill output with available samples, and silence past them
+            let available = output_buffer.read(output);
+            output[available..].fill(0.0);
+            if let Some(played) = &mut output_probe {
+                played.process(output, channels as usize);
+            }
+
+            if available < output.len() {
+                output_log.record(Event::Underrun {
+                    wanted: output.len(),
+                    available,
+                });
+            }
+        },
+        move |err| log.record(Event::OutputFailed(err)),
+    )
+    .context("opening the output stream")?;
 
     // Start both streams
-    input_stream.play().expect("Failed to start input stream");
-    output_stream.play().expect("Failed to start output stream");
+    input_stream.play().context("starting the input stream")?;
+    output_stream.play().context("starting the output stream")?;
 
     println!("Listening... Press Ctrl+C to stop.");
 
     // Keep running
     loop {
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        thread::sleep(Duration::from_secs(1));
+        if let Some(probe) = &probe {
+            info!("latency: {}", probe.report());
+        }
     }
 }
+
+fn device_name(device: &cpal::Device) -> String {
+    device.name().unwrap_or_else(|_| String::from("unknown"))
+}
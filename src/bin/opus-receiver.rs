@@ -1,92 +1,196 @@
-use cpal::{
-    BufferSize, default_host,
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-};
-use opus::{Channels, Decoder};
-use std::net::UdpSocket;
+use clap::Parser;
+use cpal::{BufferSize, default_host, traits::StreamTrait};
+use std::path::PathBuf;
+use std::process;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
+use test_gpui::capture::Capture;
+use test_gpui::codec::Codec;
+use test_gpui::config::{NetArgs, NetConfig, NetDefaults};
+use test_gpui::device;
+use test_gpui::dtx::{ComfortNoise, NoiseDescription};
+use test_gpui::error::{Context, Result};
+use test_gpui::logging::{self, Event, LogArgs, REALTIME_CAPACITY, Stage};
+use test_gpui::net::bind_socket;
+use test_gpui::rtcp::{REPORT_INTERVAL, RtcpPacket, RtcpSession};
+use test_gpui::rtp::{self, CN_PAYLOAD_TYPE, OPUS_CLOCK_RATE, RtpHeader};
+use test_gpui::util::extend_channels;
+use tracing::{error, info, warn};
 
-fn convert_mono_to_stereo(pcm_data: &[f32]) -> Vec<f32> {
-    let mut stereo_data = Vec::with_capacity(pcm_data.len() * 2);
-    for &sample in pcm_data.iter() {
-        stereo_data.push(sample); // Left channel
-        stereo_data.push(sample); // Right channel
-    }
-    stereo_data
+/// Samples per channel in 120 ms at 48 kHz.
+const MAX_FRAME: usize = 48_000 * 120 / 1000;
+const DEFAULTS: NetDefaults = NetDefaults {
+    bind_port: 5001,
+    peer_port: None,
+};
+
+/// Receives an Opus stream over UDP and plays it on the default output device.
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    net: NetArgs,
+
+    /// Log every packet received and sent to this pcap file, for `replay` or Wireshark.
+    #[arg(long, value_name = "FILE")]
+    capture: Option<PathBuf>,
+
+    /// Channels the sender sends, as given to its `--channels`. They are remixed to the
+    /// output device's.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=8))]
+    channels: u8,
+
+    #[command(flatten)]
+    log: LogArgs,
 }
 
 fn main() {
-    let host = default_host();
-    let device = host
-        .default_output_device()
-        .expect("No output device available");
+    let args = Args::parse();
+    args.log.init();
+    let net = match args.net.resolve("opus-receiver", DEFAULTS) {
+        Ok(net) => net,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(2);
+        }
+    };
 
-    let supported_config = device
-        .default_output_config()
-        .expect("Failed to get supported config");
+    if let Err(err) = run(net, args.capture, args.channels as usize) {
+        error!("{}", err);
+        process::exit(1);
+    }
+}
 
-    let mut config: cpal::StreamConfig = supported_config.clone().into();
+fn run(net: NetConfig, capture: Option<PathBuf>, stream_channels: usize) -> Result<()> {
+    let host = default_host();
+    let (device, supported_config) = device::default_output(&host)?;
+
+    let mut config = supported_config.config();
     config.buffer_size = BufferSize::Fixed(4096);
 
     let sample_rate = config.sample_rate.0;
     let channels = config.channels;
 
-    println!(
-        "Using sample rate: {} Hz, channels: {}",
-        sample_rate, channels
-    );
+    info!(sample_rate, channels, "output");
 
-    let mut decoder =
-        Decoder::new(sample_rate, Channels::Stereo).expect("Failed to create Opus decoder");
+    let mut decoder = Codec::Opus
+        .decoder(sample_rate, stream_channels)
+        .context("creating the decoder")?;
 
-    let socket = UdpSocket::bind("0.0.0.0:5001").expect("Failed to bind UDP socket");
+    let socket = bind_socket(&net).context(format!("binding {}", net.bind))?;
+    info!(bind = %net.bind, "listening");
+    let local = socket.local_addr()?;
+    let capture = match capture {
+        Some(path) => Some(Capture::create(&path).context(format!("creating {}", path.display()))?),
+        None => None,
+    };
     let audio_buffer = Arc::new(Mutex::new(Vec::new()));
+    // Fills whatever the sender doesn't send, as while it is in DTX, with noise like its
+    // background. Learnt from what is decoded, remixed to the device like the buffer.
+    let comfort = Arc::new(Mutex::new(ComfortNoise::new(
+        sample_rate,
+        channels as usize,
+    )));
 
+    // The output callback logs through here rather than printing, which could glitch the
+    // audio.
+    let (log, drain) = logging::realtime_log(REALTIME_CAPACITY);
+    drain.spawn();
     let buffer_clone = Arc::clone(&audio_buffer);
-    let stream = device
-        .build_output_stream(
-            &config,
-            move |output: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let mut buffer = buffer_clone.lock().unwrap();
-                println!("Playing {} samples", buffer.len());
-                for (sample, value) in output.iter_mut().zip(buffer.drain(..)) {
-                    *sample = value; // Play data or silence if buffer is empty
-                }
-            },
-            |err| eprintln!("Stream error: {}", err),
-            None,
-        )
-        .expect("Failed to create output stream");
+    let callback_comfort = Arc::clone(&comfort);
+    let stream = device::open_output(
+        &device,
+        &config,
+        supported_config.sample_format(),
+        move |output| {
+            let mut buffer = buffer_clone.lock().unwrap();
+            let played = output.len().min(buffer.len());
+            for (sample, value) in output.iter_mut().zip(buffer.drain(..)) {
+                *sample = value;
+            }
+            drop(buffer);
+            callback_comfort
+                .lock()
+                .unwrap()
+                .generate(&mut output[played..]);
+        },
+        move |err| log.record(Event::OutputFailed(err)),
+    )
+    .context("opening the output stream")?;
 
-    stream.play().expect("Failed to start output stream");
+    stream.play().context("starting the output stream")?;
+
+    let rtcp = Arc::new(Mutex::new(RtcpSession::new(
+        rtp::random_ssrc(),
+        OPUS_CLOCK_RATE,
+        Instant::now(),
+    )));
 
     // UDP receiving and decoding thread
     let buffer_clone = Arc::clone(&audio_buffer);
+    let thread_rtcp = Arc::clone(&rtcp);
     thread::spawn(move || {
-        let mut packet = [0; 960];
+        let _network = Stage::Network.span().entered();
+        let mut packet = [0; 1500];
+        // Room for the longest Opus frame, since the sender may adapt its frame size
+        let mut pcm = vec![0.0; MAX_FRAME * stream_channels];
+        let mut remixed = Vec::new();
         loop {
             if let Ok((size, src)) = socket.recv_from(&mut packet) {
-                println!(
-                    "Received {} bytes from {} with data {:?}",
-                    size,
-                    src,
-                    packet.len()
-                );
-                let mut pcm_data = [0_i16; 960]; // Buffer for decoded PCM
-                match decoder.decode(&packet[..size], &mut pcm_data, true) {
+                let now = Instant::now();
+                if let Some(capture) = &capture {
+                    capture.record(src, local, &packet[..size]);
+                }
+                let mut rtcp = thread_rtcp.lock().unwrap();
+                if RtcpPacket::is_rtcp(&packet[..size]) {
+                    rtcp.receive(&packet[..size], now);
+                    continue;
+                }
+                let Some((header, payload)) = RtpHeader::parse(&packet[..size]) else {
+                    continue;
+                };
+                rtcp.received(&header, now);
+                // Reports go back to whoever is sending to us.
+                if let Some(report) = rtcp.poll(now) {
+                    match socket.send_to(&report, src) {
+                        Ok(_) => {
+                            if let Some(capture) = &capture {
+                                capture.record(local, src, &report);
+                            }
+                        }
+                        Err(err) => warn!(error = %err, "failed to send RTCP report"),
+                    }
+                }
+                drop(rtcp);
+                if header.payload_type == CN_PAYLOAD_TYPE {
+                    if let Some(description) = NoiseDescription::parse(payload) {
+                        comfort.lock().unwrap().update(description);
+                    }
+                    continue;
+                }
+
+                match decoder.decode_fec(payload, &mut pcm) {
                     Ok(len) => {
-                        println!("Decoded {} samples", len);
-                        let mut buffer = buffer_clone.lock().unwrap();
-                        buffer.extend(pcm_data[..len].iter().map(|&x| x as f32 / i16::MAX as f32)); // Convert i16 -> f32
+                        remixed.clear();
+                        extend_channels(
+                            &mut remixed,
+                            &pcm[..len * stream_channels],
+                            stream_channels,
+                            channels as usize,
+                        );
+                        comfort.lock().unwrap().learn(&remixed);
+                        buffer_clone.lock().unwrap().extend_from_slice(&remixed);
                     }
-                    Err(e) => eprintln!("Error decoding: {}", e),
+                    Err(err) => Stage::Decode
+                        .span()
+                        .in_scope(|| warn!(error = %err, "failed to decode")),
                 }
             }
         }
     });
 
     loop {
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        std::thread::sleep(REPORT_INTERVAL);
+        info!("{}", rtcp.lock().unwrap().stats());
     }
 }
@@ -1,74 +1,358 @@
-use std::net::UdpSocket;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use cpal::{
-    InputCallbackInfo, SampleRate, default_host,
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-};
-use opus::{Application, Channels, Encoder};
-use test_gpui::util::{convert_to_stereo, float_into_i16};
+use clap::Parser;
+use cpal::{default_host, traits::StreamTrait};
+use test_gpui::audio::SharedBuffer;
+use test_gpui::bitrate::{BitrateController, EncoderSettings, Feedback, RateLimits};
+use test_gpui::capture::Capture;
+use test_gpui::codec::{Codec, MAX_PAYLOAD};
+use test_gpui::config::{NetArgs, NetConfig, NetDefaults};
+use test_gpui::device;
+use test_gpui::dtx::OPUS_DTX_PACKET_LEN;
+use test_gpui::error::{Context, Result};
+use test_gpui::logging::{self, Event, LogArgs, REALTIME_CAPACITY, Stage};
+use test_gpui::net::{advertised_ip, bind_socket};
+use test_gpui::rtcp::{REPORT_INTERVAL, RtcpPacket, RtcpSession};
+use test_gpui::rtp::{self, HEADER_LEN, OPUS_CLOCK_RATE, RtpHeader};
+use test_gpui::sdp::{OpusConfig, SessionDescription};
+use test_gpui::talk::{Gate, TalkControl, TalkState};
+use test_gpui::util::extend_channels;
+use tracing::{error, info};
 
 const SAMPLE_RATE: u32 = 48_000;
-const CHANNELS: Channels = Channels::Mono;
-const STERIO20MS: usize = SAMPLE_RATE as usize * 20 / 1000;
+/// The longest Opus frame, 120 ms, in samples per channel.
+const MAX_FRAME: usize = SAMPLE_RATE as usize * 120 / 1000;
+/// How long the encode thread waits when no audio is queued.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+const DEFAULTS: NetDefaults = NetDefaults {
+    bind_port: 5000,
+    peer_port: Some(5001),
+};
+
+/// Captures the default input device and streams it to a peer as Opus over UDP.
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    net: NetArgs,
+
+    /// Print an SDP offer describing the stream and exit.
+    #[arg(long)]
+    print_sdp: bool,
+
+    /// Address to put in the SDP offer. Defaults to the bound address, or when bound to all
+    /// interfaces, to that of the interface facing the peer.
+    #[arg(long, value_name = "IP")]
+    advertise: Option<IpAddr>,
+
+    /// Lowest bitrate in bits per second the sender backs off to under congestion.
+    #[arg(long, default_value_t = RateLimits::default().min_bitrate)]
+    min_bitrate: u32,
+
+    /// Highest bitrate in bits per second the sender grows to on a clean network.
+    #[arg(long, default_value_t = RateLimits::default().max_bitrate)]
+    max_bitrate: u32,
+
+    /// Stop sending while nobody talks; the receiver plays comfort noise instead.
+    #[arg(long)]
+    dtx: bool,
+
+    /// Channels to send, up to 7.1 surround; the microphone is remixed to match. SDP only
+    /// describes mono and stereo, so `--print-sdp` refuses more.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=8))]
+    channels: u8,
+
+    /// Start with the microphone muted.
+    #[arg(long)]
+    muted: bool,
+
+    /// Log every packet sent and received to this pcap file, for `replay` or Wireshark.
+    #[arg(long, value_name = "FILE")]
+    capture: Option<PathBuf>,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
 
 fn main() {
+    let args = Args::parse();
+    args.log.init();
+    let net = args
+        .net
+        .resolve("opus-sender", DEFAULTS)
+        .and_then(|net| Ok((net.require_peer()?, net)));
+    let (peer, net) = match net {
+        Ok(net) => net,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    let opus = OpusConfig {
+        sample_rate: SAMPLE_RATE,
+        stereo: args.channels == 2,
+        ptime: 20,
+        dtx: args.dtx,
+        ..OpusConfig::default()
+    };
+    if args.print_sdp {
+        if args.channels > 2 {
+            error!("SDP cannot describe {} channels", args.channels);
+            process::exit(2);
+        }
+        let ip = match args.advertise {
+            Some(ip) => ip,
+            None => match advertised_ip(net.bind, peer) {
+                Ok(ip) => ip,
+                Err(err) => {
+                    error!("finding an address to advertise: {}; pass --advertise", err);
+                    process::exit(2);
+                }
+            },
+        };
+        let session_id = rtp::random_ssrc() as u64;
+        let sdp = SessionDescription::offer(ip, net.bind.port(), session_id, &opus);
+        print!("{}", sdp);
+        return;
+    }
+
+    if let Err(err) = run(&args, net, peer, opus) {
+        error!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args, net: NetConfig, peer: SocketAddr, opus: OpusConfig) -> Result<()> {
     let host = default_host();
-    let device = host
-        .default_input_device()
-        .expect("Failed to get default input device.");
-    let config = device
-        .default_input_config()
-        .expect("Failed to get default input config.");
-
-    let mut encoder = Encoder::new(SAMPLE_RATE, CHANNELS, Application::Voip)
-        .expect("Failed to initialize encoder.");
+    let channels = args.channels as usize;
+    let (device, config) = device::default_input(&host)?;
+
+    let mut encoder = Codec::Opus
+        .encoder(opus.sample_rate, channels)
+        .context("creating the encoder")?;
+    opus.apply(encoder.as_mut())
+        .context("configuring the encoder")?;
+    let mut controller = BitrateController::new(RateLimits {
+        min_bitrate: args.min_bitrate,
+        max_bitrate: args.max_bitrate.max(args.min_bitrate),
+        ..RateLimits::default()
+    });
+    controller
+        .settings()
+        .apply(encoder.as_mut())
+        .context("configuring the encoder")?;
+    // Samples per channel in a frame, which is also the RTP timestamp step.
+    let mut samples = SAMPLE_RATE as usize * controller.settings().frame_ms as usize / 1000;
     let mut buffer = Vec::new();
     let input_channels = config.channels();
 
-    let socket = UdpSocket::bind("0.0.0.0:5000").expect("Failed to bind UDP socket");
+    let socket = bind_socket(&net).context(format!("binding {}", net.bind))?;
+    info!(bind = %net.bind, %peer, "sending");
+    let local = socket.local_addr()?;
+    let capture = match &args.capture {
+        Some(path) => Some(Capture::create(path).context(format!("creating {}", path.display()))?),
+        None => None,
+    };
+    let encode_capture = capture.clone();
 
-    let stream = device
-        .build_input_stream(
-            &config.into(),
-            move |pcm_data: &[f32], _: &InputCallbackInfo| {
-                buffer.extend_from_slice(pcm_data);
+    let ssrc = rtp::random_ssrc();
+    let rtcp = Arc::new(Mutex::new(RtcpSession::new(
+        ssrc,
+        OPUS_CLOCK_RATE,
+        Instant::now(),
+    )));
+    let mut header = RtpHeader {
+        marker: true,
+        payload_type: opus.payload_type,
+        sequence: 0,
+        timestamp: 0,
+        ssrc,
+    };
+    let report_socket = socket.try_clone()?;
+    let encode_rtcp = Arc::clone(&rtcp);
+    // Settings chosen by the controller, picked up by the encode thread.
+    let (adapt, adapted) = mpsc::channel::<EncoderSettings>();
+    // Muting keeps sending, but silence, so the receiver's clock and reports carry on.
+    let talk = TalkControl::new(TalkState {
+        muted: args.muted,
+        ..TalkState::default()
+    });
+    let encode_talk = talk.clone();
+    let mut gate = Gate::new(SAMPLE_RATE);
+    let (log, drain) = logging::realtime_log(REALTIME_CAPACITY);
+    drain.spawn();
+    let errors = log.clone();
 
-                if buffer.len() >= STERIO20MS {
-                    println!("Size of buffer before encoding: {}", buffer.len());
+    // The capture callback only queues audio, and a thread of its own encodes and sends it,
+    // so neither the network nor the RTCP lock can hold up the device. At most half a second
+    // is queued; past that the oldest audio goes.
+    let captured = SharedBuffer::new(SAMPLE_RATE as usize / 2 * channels);
+    let callback_captured = captured.clone();
+    let widest = channels.max(input_channels as usize);
+    let mut remixed = Vec::with_capacity(SAMPLE_RATE as usize / 10 * widest);
+    let stream = device::open_input(
+        &device,
+        &config.config(),
+        config.sample_format(),
+        move |pcm_data| {
+            remixed.clear();
+            extend_channels(&mut remixed, pcm_data, input_channels as usize, channels);
+            callback_captured.push(&remixed);
+        },
+        move |err| errors.record(Event::InputFailed(err)),
+    )
+    .context("opening the input stream")?;
 
-                    // Convert mono to stereo
-                    let stereo_buffer = convert_to_stereo(&buffer);
+    thread::Builder::new()
+        .name("encode".into())
+        .spawn(move || {
+            let _encode = Stage::Encode.span().entered();
+            let mut queued = vec![0f32; MAX_FRAME * channels];
+            loop {
+                let count = captured.pop_into(&mut queued);
+                if count == 0 {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                let start = buffer.len();
+                buffer.extend_from_slice(&queued[..count]);
+                gate.process(
+                    &mut buffer[start..],
+                    channels,
+                    encode_talk.state().transmitting(),
+                );
 
-                    // Convert stereo float data to i16
-                    let buffer_i16: Vec<i16> = stereo_buffer.iter().map(float_into_i16).collect();
+                while let Ok(settings) = adapted.try_recv() {
+                    if let Err(err) = settings.apply(encoder.as_mut()) {
+                        log.record(Event::EncodeFailed(err));
+                    }
+                    samples = SAMPLE_RATE as usize * settings.frame_ms as usize / 1000;
+                }
 
-                    // Prepare buffer for encoding
-                    let mut encoded_audio = [0u8; STERIO20MS];
-                    let size = encoder
-                        .encode(&buffer_i16[..STERIO20MS], &mut encoded_audio)
-                        .expect("Failed to encode audio.");
-                    println!("Size of encoded data: {}", size);
+                // The queue can hold several frames, so encode all that are complete.
+                let frame = samples * channels;
+                while buffer.len() >= frame {
+                    // Encode behind room for the RTP header
+                    let mut packet = [0u8; HEADER_LEN + MAX_PAYLOAD];
+                    let size = match encoder.encode(&buffer[..frame], &mut packet[HEADER_LEN..]) {
+                        Ok(size) => size,
+                        Err(err) => {
+                            log.record(Event::EncodeFailed(err));
+                            buffer.drain(..frame);
+                            continue;
+                        }
+                    };
+                    log.record(Event::Encoded { bytes: size });
+                    if opus.dtx && size <= OPUS_DTX_PACKET_LEN {
+                        // Nothing worth sending, and the next packet sent starts a talkspurt.
+                        header.marker = true;
+                        header.timestamp = header.timestamp.wrapping_add(samples as u32);
+                        buffer.drain(..frame);
+                        continue;
+                    }
+                    header.write(&mut packet);
 
                     // Send packet over UDP
-                    let packet = socket
-                        .send_to(&encoded_audio[..size], "0.0.0.0:5001")
-                        .expect("Failed to send packet!.");
-                    println!("Sent a packet of size: {}", packet);
+                    let packet = &packet[..HEADER_LEN + size];
+                    match socket.send_to(packet, peer) {
+                        Ok(_) => {
+                            if let Some(capture) = &encode_capture {
+                                capture.record(local, peer, packet);
+                            }
+                        }
+                        Err(err) => log.record(Event::SendFailed(err)),
+                    }
+
+                    let now = Instant::now();
+                    let report = {
+                        let mut rtcp = encode_rtcp.lock().unwrap();
+                        rtcp.sent(header.timestamp, size, now);
+                        rtcp.poll(now)
+                    };
+                    if let Some(report) = report {
+                        match socket.send_to(&report, peer) {
+                            Ok(_) => {
+                                if let Some(capture) = &encode_capture {
+                                    capture.record(local, peer, &report);
+                                }
+                            }
+                            Err(err) => log.record(Event::SendFailed(err)),
+                        }
+                    }
+                    header.marker = false;
+                    header.sequence = header.sequence.wrapping_add(1);
+                    header.timestamp = header.timestamp.wrapping_add(samples as u32);
 
                     // Remove processed data from buffer
-                    buffer.drain(..STERIO20MS);
-                    println!("Size of buffer after draining: {}", buffer.len());
+                    buffer.drain(..frame);
                 }
-            },
-            move |err| eprintln!("error: {}", err),
-            None,
-        )
-        .expect("Failed to create stream.");
+            }
+        })
+        .context("starting the encode thread")?;
+
+    stream.play().context("starting the input stream")?;
 
-    stream.play().expect("Failed to play stream");
+    println!("Type m and press Enter to mute or unmute.");
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else { break };
+            match line.trim() {
+                "m" => {
+                    talk.toggle_mute();
+                    println!(
+                        "{}",
+                        if talk.state().muted {
+                            "Muted."
+                        } else {
+                            "Live."
+                        }
+                    );
+                }
+                "" => {}
+                other => eprintln!("Unknown command '{}'; type m to toggle mute.", other),
+            }
+        }
+    });
 
-    // Keep running
+    // Collect the receiver's reports and print call quality while running
+    report_socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut packet = [0u8; 1500];
+    let mut next_stats = Instant::now() + REPORT_INTERVAL;
     loop {
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        let received = report_socket.recv_from(&mut packet);
+        if let (Ok((size, from)), Some(capture)) = (&received, &capture) {
+            capture.record(*from, local, &packet[..*size]);
+        }
+        if let Ok((size, from)) = received
+            && from == peer
+            && RtcpPacket::is_rtcp(&packet[..size])
+        {
+            let now = Instant::now();
+            let stats = {
+                let mut rtcp = rtcp.lock().unwrap();
+                rtcp.receive(&packet[..size], now);
+                rtcp.stats()
+            };
+            if let Some(settings) = controller.update(&Feedback::from_stats(&stats), now) {
+                info!(
+                    bitrate = settings.bitrate,
+                    frame_ms = settings.frame_ms,
+                    fec = settings.fec,
+                    expected_loss = settings.expected_loss,
+                    "adapting encoder"
+                );
+                let _ = adapt.send(settings);
+            }
+        }
+        if Instant::now() >= next_stats {
+            info!("{}", rtcp.lock().unwrap().stats());
+            next_stats += REPORT_INTERVAL;
+        }
     }
 }
@@ -0,0 +1,189 @@
+use clap::Parser;
+use cpal::{BufferSize, traits::StreamTrait};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Instant, SystemTime};
+use test_gpui::audio::{FileSink, SharedBuffer};
+use test_gpui::capture::{Datagram, PcapReader, Replay};
+use test_gpui::clock::VirtualClock;
+use test_gpui::codec::Codec;
+use test_gpui::device;
+use test_gpui::error::{Context, Result};
+use test_gpui::logging::{self, Event, LogArgs, REALTIME_CAPACITY};
+use test_gpui::pipeline::{
+    Builder, ConvertChannels, Decode, Frame, POLL_INTERVAL, Packet, Pipeline, SinkWriter, Transform,
+};
+use test_gpui::rtcp::{RtcpPacket, RtcpSession};
+use test_gpui::rtp::{self, OPUS_CLOCK_RATE, RtpHeader};
+use test_gpui::sdp::OpusConfig;
+use test_gpui::wav::WavSpec;
+use tracing::{error, info};
+
+/// What `opus-receiver` decodes: stereo, at the output's rate or this one.
+const CHANNELS: usize = 2;
+const FILE_SAMPLE_RATE: u32 = 48_000;
+
+/// Feeds a capture taken with `--capture` through the receive path, to reproduce what a
+/// receiver heard.
+#[derive(Parser)]
+struct Args {
+    /// Capture file to replay.
+    capture: PathBuf,
+
+    /// Only replay packets sent to this port, such as the receiver's. By default every RTP
+    /// packet of the payload type is.
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// RTP payload type of the Opus stream.
+    #[arg(long, default_value_t = OpusConfig::default().payload_type)]
+    payload_type: u8,
+
+    /// Replay as fast as possible instead of with the original timing.
+    #[arg(long, requires = "output")]
+    fast: bool,
+
+    /// Write the decoded audio to this WAV file instead of playing it.
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+fn main() {
+    let args = Args::parse();
+    args.log.init();
+    if let Err(err) = run(args) {
+        error!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    let reader =
+        PcapReader::open(&args.capture).context(format!("opening {}", args.capture.display()))?;
+    let rtcp = Arc::new(Mutex::new(RtcpSession::new(
+        rtp::random_ssrc(),
+        OPUS_CLOCK_RATE,
+        Instant::now(),
+    )));
+    let depacketize = Depacketize {
+        port: args.port,
+        payload_type: args.payload_type,
+        rtcp: Arc::clone(&rtcp),
+        start: None,
+    };
+    let replay = match args.fast {
+        true => Pipeline::source("replay", Replay::with_clock(reader, VirtualClock::new())),
+        false => Pipeline::source("replay", Replay::new(reader)),
+    }
+    .then("depacketize", depacketize);
+
+    match &args.output {
+        Some(path) => {
+            let spec = WavSpec {
+                sample_rate: FILE_SAMPLE_RATE,
+                channels: 2,
+            };
+            let file =
+                FileSink::create(path, spec).context(format!("creating {}", path.display()))?;
+            info!(capture = %args.capture.display(), output = %path.display(), "replaying");
+            decode(replay, FILE_SAMPLE_RATE)?
+                .sink("output", SinkWriter::new(file))
+                .start()
+                .context("starting the pipeline")?
+                .wait();
+        }
+        None => play(replay)?,
+    }
+    info!("{}", rtcp.lock().unwrap().stats());
+    Ok(())
+}
+
+fn decode(packets: Builder<Packet>, sample_rate: u32) -> Result<Builder<Frame>> {
+    let decoder = Codec::Opus
+        .decoder(sample_rate, CHANNELS)
+        .context("creating the decoder")?;
+    Ok(packets.then("decode", Decode::new(decoder)))
+}
+
+/// Plays the replay on the default output device, and returns once all of it has played.
+fn play(packets: Builder<Packet>) -> Result<()> {
+    let host = cpal::default_host();
+    let (device, supported_config) = device::default_output(&host)?;
+    let mut config = supported_config.config();
+    config.buffer_size = BufferSize::Fixed(4096);
+    let channels = config.channels as usize;
+
+    // Half a second, like the receiver's buffer in practice.
+    let playback = SharedBuffer::new(config.sample_rate.0 as usize * channels / 2);
+    let running = decode(packets, config.sample_rate.0)?
+        .then("remix", ConvertChannels::new(channels))
+        .sink("playback", SinkWriter::new(playback.clone()))
+        .start()
+        .context("starting the pipeline")?;
+
+    let (log, drain) = logging::realtime_log(REALTIME_CAPACITY);
+    drain.spawn();
+    let output = playback.clone();
+    let stream = device::open_output(
+        &device,
+        &config,
+        supported_config.sample_format(),
+        move |data| {
+            let available = output.pop_into(data);
+            data[available..].fill(0.0);
+        },
+        move |err| log.record(Event::OutputFailed(err)),
+    )
+    .context("opening the output stream")?;
+    stream.play().context("starting the output stream")?;
+
+    running.wait();
+    while !playback.is_empty() {
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// Picks the RTP packets of the stream out of the captured datagrams, and keeps reception
+/// statistics as the receiver would have, at the times the capture says.
+struct Depacketize {
+    port: Option<u16>,
+    payload_type: u8,
+    rtcp: Arc<Mutex<RtcpSession>>,
+    /// When the first datagram was captured, and the instant standing for it.
+    start: Option<(SystemTime, Instant)>,
+}
+
+impl Transform for Depacketize {
+    type Input = Datagram;
+    type Output = Packet;
+
+    fn process(&mut self, datagram: Datagram, out: &mut Vec<Packet>) {
+        if self.port.is_some_and(|port| datagram.to.port() != port)
+            || RtcpPacket::is_rtcp(&datagram.payload)
+        {
+            return;
+        }
+        let Some((header, payload)) = RtpHeader::parse(&datagram.payload) else {
+            return;
+        };
+        if header.payload_type != self.payload_type {
+            return;
+        }
+        let (first, start) = *self
+            .start
+            .get_or_insert_with(|| (datagram.time, Instant::now()));
+        let now = start + datagram.time.duration_since(first).unwrap_or_default();
+        self.rtcp.lock().unwrap().received(&header, now);
+        out.push(Packet {
+            sequence: header.sequence,
+            timestamp: header.timestamp,
+            payload: payload.to_vec(),
+        });
+    }
+}
@@ -1,40 +1,63 @@
+use std::process;
 use std::sync::{Arc, Mutex};
 
-use cpal::{
-    InputCallbackInfo, OutputCallbackInfo, default_host,
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-};
-use opus::{Channels, Decoder, Encoder, packet::get_nb_samples};
-use test_gpui::util::{convert_to_mono, convert_to_stereo};
+use clap::Parser;
+use cpal::{default_host, traits::StreamTrait};
+use test_gpui::codec::Codec;
+use test_gpui::device;
+use test_gpui::error::{Context, Result};
+use test_gpui::logging::{self, Event, LogArgs, REALTIME_CAPACITY};
+use test_gpui::util::convert_to_mono;
+use tracing::{error, info};
 
 const ENCODING_SAMPLE_RATE: u32 = 48_000;
 // 48_000 * channels * 20ms / 1000;
 const FRAME_SIZE: usize = 960;
 
+/// Encodes the default input device with Opus and plays the decoded audio on the default
+/// output device.
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    log: LogArgs,
+}
+
 fn main() {
+    let args = Args::parse();
+    args.log.init();
+    if let Err(err) = run() {
+        error!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    // Audio callbacks log through here rather than printing, which could glitch the audio.
+    let (log, drain) = logging::realtime_log(REALTIME_CAPACITY);
+    drain.spawn();
+
     // Prepping audio input
     let host = default_host();
-    let device = host.default_input_device().unwrap();
-    let config = device.default_input_config().unwrap();
-    println!("Input Sample Rate: {:?}", &config.sample_rate());
-    println!("Input Channel Count: {:?}", &config.channels());
+    let (device, config) = device::default_input(&host)?;
+    info!(
+        sample_rate = config.sample_rate().0,
+        channels = config.channels(),
+        "input"
+    );
 
     let encoded_bytes: Arc<Mutex<Vec<u8>>> = Arc::new(Mutex::new(Vec::new()));
     let encoded_input_clone = encoded_bytes.clone();
     let encoded_output_clone = encoded_bytes.clone();
-    let encoded_decode_clone = encoded_bytes.clone();
 
     // Initialize the encoder
-    let mut encoder = Encoder::new(
-        ENCODING_SAMPLE_RATE,
-        Channels::Stereo,
-        opus::Application::Voip,
-    )
-    .unwrap();
+    let mut encoder = Codec::Opus
+        .encoder(ENCODING_SAMPLE_RATE, 2)
+        .context("creating the encoder")?;
 
     let mut buffer = Vec::with_capacity(FRAME_SIZE);
+    let input_log = log.clone();
 
-    let data_callback = move |data: &[f32], _: &InputCallbackInfo| {
+    let data_callback = move |data: &[f32]| {
         // Convert and accumulate stereo data
         let stereo_data: Vec<f32> = data.iter().flat_map(|&s| vec![s, s]).collect();
         buffer.extend_from_slice(&stereo_data);
@@ -45,46 +68,64 @@ fn main() {
 
             // Encode the complete frame
             let mut encoded_buffer = [0u8; FRAME_SIZE * 2];
-            let size = encoder
-                .encode_float(&drain[..], &mut encoded_buffer)
-                .unwrap();
-            println!("Encoded packet size: {}", size); // Monitor the encoded packet size
+            let size = match encoder.encode(&drain[..], &mut encoded_buffer) {
+                Ok(size) => size,
+                Err(err) => {
+                    input_log.record(Event::EncodeFailed(err));
+                    return;
+                }
+            };
+            input_log.record(Event::Encoded { bytes: size });
 
             let mut encoded = encoded_input_clone.lock().unwrap();
             encoded.extend_from_slice(&encoded_buffer[..size]); // Store the encoded packet
         }
     };
 
-    let error_callback = move |error| eprintln!("Error: {}", error);
-
-    let stream = device
-        .build_input_stream(&config.into(), data_callback, error_callback, None)
-        .unwrap();
+    let input_errors = log.clone();
+    let stream = device::open_input(
+        &device,
+        &config.config(),
+        config.sample_format(),
+        data_callback,
+        move |err| input_errors.record(Event::InputFailed(err)),
+    )
+    .context("opening the input stream")?;
 
-    stream.play().unwrap();
+    stream.play().context("starting the input stream")?;
 
-    let output_device = host.default_output_device().unwrap();
-    let mut output_config = output_device.default_output_config().unwrap();
+    let (output_device, output_config) = device::default_output(&host)?;
     let output_channels = output_config.channels();
-    println!("Output sample rate: {:?}", &output_config.sample_rate());
-    println!("Output Channel Count: {:?}", &output_config.channels());
+    info!(
+        sample_rate = output_config.sample_rate().0,
+        channels = output_config.channels(),
+        "output"
+    );
 
-    let mut decoder = Decoder::new(ENCODING_SAMPLE_RATE, Channels::Stereo).unwrap();
+    let mut decoder = Codec::Opus
+        .decoder(ENCODING_SAMPLE_RATE, 2)
+        .context("creating the decoder")?;
 
     let mut output_buffer_mono = [0f32; FRAME_SIZE * 2];
     let mut overflow_buffer = Vec::<f32>::new();
+    let output_log = log.clone();
 
-    let output_data_callback = move |data: &mut [f32], _: &OutputCallbackInfo| {
+    let output_data_callback = move |data: &mut [f32]| {
         let mut encoded = encoded_output_clone.lock().unwrap();
         if encoded.is_empty() {
             return;
         }
 
         // Decode the packet
-        let size = decoder
-            .decode_float(&encoded, &mut output_buffer_mono, false)
-            .unwrap();
-        println!("Decoded output size: {}", size); // Monitor decoded output size
+        let size = match decoder.decode(&encoded, &mut output_buffer_mono) {
+            Ok(size) => size,
+            Err(err) => {
+                output_log.record(Event::DecodeFailed(err));
+                encoded.clear();
+                return;
+            }
+        };
+        output_log.record(Event::Decoded { samples: size });
 
         // Handle output for stereo or mono
         if output_channels >= 2 {
@@ -104,16 +145,16 @@ fn main() {
         encoded.drain(..);
     };
 
-    let output_stream = output_device
-        .build_output_stream(
-            &output_config.into(),
-            output_data_callback,
-            error_callback,
-            None,
-        )
-        .unwrap();
+    let output_stream = device::open_output(
+        &output_device,
+        &output_config.config(),
+        output_config.sample_format(),
+        output_data_callback,
+        move |err| log.record(Event::OutputFailed(err)),
+    )
+    .context("opening the output stream")?;
 
-    output_stream.play().unwrap();
+    output_stream.play().context("starting the output stream")?;
 
     loop {
         std::thread::sleep(std::time::Duration::from_secs(1));
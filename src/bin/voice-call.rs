@@ -0,0 +1,269 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use cpal::{SampleRate, StreamConfig, default_host, traits::StreamTrait};
+use test_gpui::audio::SharedBuffer;
+use test_gpui::call::{Call, CallConfig};
+use test_gpui::codec::Codec;
+use test_gpui::config::{NetArgs, NetConfig, NetDefaults};
+use test_gpui::device;
+use test_gpui::error::{Context, Result};
+use test_gpui::logging::{self, Event, LogArgs, REALTIME_CAPACITY};
+use test_gpui::net::bind_socket;
+use test_gpui::recorder::{RecordFormat, Recorder};
+use test_gpui::talk::{GatedSink, GatedSource, TalkControl};
+use test_gpui::util::extend_channels;
+use tracing::{error, info};
+
+/// How often call quality is printed.
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+const DEFAULTS: NetDefaults = NetDefaults {
+    bind_port: 5000,
+    peer_port: None,
+};
+
+/// Two-way voice call: captures the default input device and plays the peer's audio on the
+/// default output device over one UDP socket.
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    net: NetArgs,
+
+    /// Wait for an incoming call instead of calling --peer.
+    #[arg(long)]
+    answer: bool,
+
+    /// Stop sending while nobody talks; the peer plays comfort noise instead.
+    #[arg(long)]
+    dtx: bool,
+
+    /// Channels to send, up to 7.1 surround. More than two go out as Opus multistream; the
+    /// devices are remixed to match.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=8))]
+    channels: u8,
+
+    /// Codecs to offer or accept, comma separated. The call uses the first of them both sides
+    /// know, in the order Opus, PCMU, PCMA, L16.
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = Codec::ALL)]
+    codecs: Vec<Codec>,
+
+    /// Join this room on the conference server at --peer rather than call it directly. The
+    /// server mixes or forwards the media, so it is sent unencrypted.
+    #[arg(long, value_name = "ROOM", conflicts_with = "answer")]
+    room: Option<u32>,
+
+    /// Record the call into this directory, one file for each side.
+    #[arg(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+
+    /// Write recordings as WAV, or as Ogg Opus holding the packets exactly as sent and
+    /// received.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = RecordFormat::Wav)]
+    record_format: RecordFormat,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+fn main() {
+    let args = Args::parse();
+    args.log.init();
+    let net = args.net.resolve("voice-call", DEFAULTS).and_then(|net| {
+        let peer = if args.answer {
+            None
+        } else {
+            Some(net.require_peer()?)
+        };
+        Ok((peer, net))
+    });
+    let (peer, net) = match net {
+        Ok(net) => net,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(&args, net, peer) {
+        error!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args, net: NetConfig, peer: Option<SocketAddr>) -> Result<()> {
+    let config = CallConfig {
+        dtx: args.dtx,
+        channels: args.channels as usize,
+        codecs: args.codecs.iter().copied().collect(),
+        encrypt: args.room.is_none(),
+        call_id: args.room,
+        ..CallConfig::default()
+    };
+    let call_channels = config.channels;
+    // Keep at most half a second of audio queued in either direction.
+    let capacity = config.sample_rate as usize * call_channels / 2;
+    let capture = SharedBuffer::new(capacity);
+    let playback = SharedBuffer::new(capacity);
+
+    let host = default_host();
+    let (input_device, input_default) = device::default_input(&host)?;
+    let (output_device, output_default) = device::default_output(&host)?;
+    let input_channels = input_default.channels();
+    let output_channels = output_default.channels();
+
+    let input_config = StreamConfig {
+        channels: input_channels,
+        sample_rate: SampleRate(config.sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let output_config = StreamConfig {
+        channels: output_channels,
+        ..input_config.clone()
+    };
+
+    // Device callbacks log through here rather than printing, which could glitch the audio.
+    let (log, drain) = logging::realtime_log(REALTIME_CAPACITY);
+    drain.spawn();
+    let input_log = log.clone();
+    let input_buffer = capture.clone();
+    // Scratch space for remixing, sized for 100 ms so the callbacks rarely need to grow it.
+    let widest = call_channels.max(input_channels.max(output_channels) as usize);
+    let scratch = config.sample_rate as usize / 10 * widest;
+    let mut remixed = Vec::with_capacity(scratch);
+    let input_stream = device::open_input(
+        &input_device,
+        &input_config,
+        input_default.sample_format(),
+        move |data| {
+            remixed.clear();
+            extend_channels(&mut remixed, data, input_channels as usize, call_channels);
+            input_buffer.push(&remixed);
+        },
+        move |err| input_log.record(Event::InputFailed(err)),
+    )
+    .context("opening the input stream")?;
+
+    let output_buffer = playback.clone();
+    let (mut pcm, mut remixed) = (Vec::with_capacity(scratch), Vec::with_capacity(scratch));
+    let output_stream = device::open_output(
+        &output_device,
+        &output_config,
+        output_default.sample_format(),
+        move |data| {
+            let frames = data.len() / output_channels as usize;
+            pcm.resize(frames * call_channels, 0.0);
+            let count = output_buffer.pop_into(&mut pcm);
+            pcm[count..].fill(0.0);
+            remixed.clear();
+            extend_channels(&mut remixed, &pcm, call_channels, output_channels as usize);
+            data.copy_from_slice(&remixed);
+        },
+        move |err| log.record(Event::OutputFailed(err)),
+    )
+    .context("opening the output stream")?;
+
+    let socket = bind_socket(&net).context(format!("binding {}", net.bind))?;
+
+    let talk = TalkControl::default();
+    let capture_source = Box::new(GatedSource::new(
+        capture,
+        talk.clone(),
+        config.sample_rate,
+        call_channels,
+    ));
+    let playback_sink = Box::new(GatedSink::new(
+        playback,
+        talk.clone(),
+        config.sample_rate,
+        call_channels,
+    ));
+    let call = match peer {
+        Some(peer) => {
+            info!(%peer, "calling");
+            Call::dial(socket, peer, config, capture_source, playback_sink)
+        }
+        None => {
+            info!(bind = %net.bind, "waiting for a call");
+            Call::answer(socket, config, capture_source, playback_sink)
+        }
+    };
+    let call = call.context("starting the call")?;
+
+    let recorder = Recorder::new(args.record_format);
+    if let Some(directory) = &args.record {
+        recorder
+            .start(directory, Instant::now())
+            .context(format!("recording to {}", directory.display()))?;
+        call.record(Some(recorder.clone()));
+        info!(directory = %directory.display(), "recording");
+    }
+
+    input_stream.play().context("starting the input stream")?;
+    output_stream.play().context("starting the output stream")?;
+
+    let (stop_tx, stop_rx) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = stop_tx.send(());
+    })
+    .map_err(io::Error::other)
+    .context("installing the Ctrl+C handler")?;
+
+    info!(local = %call.local_addr(), peer = %call.peer(), "in call");
+    println!("Press Ctrl+C to hang up.");
+    println!("Type m to mute or d to deafen, then press Enter.");
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else { break };
+            match line.trim() {
+                "m" => talk.toggle_mute(),
+                "d" => talk.toggle_deafen(),
+                "" => continue,
+                other => {
+                    eprintln!("Unknown command '{}'; type m or d.", other);
+                    continue;
+                }
+            }
+            let state = talk.state();
+            println!(
+                "Microphone {}, speaker {}.",
+                if state.transmitting() {
+                    "live"
+                } else {
+                    "muted"
+                },
+                if state.hearing() { "on" } else { "off" }
+            );
+        }
+    });
+    let mut next_stats = Instant::now() + STATS_INTERVAL;
+    while call.is_active() {
+        match stop_rx.recv_timeout(Duration::from_millis(200)) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if Instant::now() >= next_stats {
+            info!("{}", call.stats());
+            next_stats += STATS_INTERVAL;
+        }
+    }
+
+    if call.is_active() {
+        info!("hanging up");
+    } else {
+        info!("the call has ended");
+    }
+    info!("{}", call.stats());
+    call.hangup();
+    recorder.stop().context("finishing the recording")?;
+    if let Some(directory) = &args.record {
+        info!(directory = %directory.display(), "saved the recording");
+    }
+    Ok(())
+}
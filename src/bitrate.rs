@@ -0,0 +1,226 @@
+//! Adapts the Opus encoder to network conditions reported by the receiver.
+//!
+//! The controller runs once per receiver report and follows a loss- and delay-based law
+//! similar to the loss controller of Google Congestion Control:
+//!
+//! * If the peer lost more than 10% of our packets, the bitrate is cut to
+//!   `bitrate * (1 - loss / 2)`.
+//! * If the round-trip time exceeds the lowest one seen by more than 100 ms, or jitter is
+//!   above 30 ms, queues are building up and the bitrate is cut by 15%. When both signals
+//!   fire, the larger cut wins.
+//! * If loss is below 2% and delay looks normal, the bitrate grows by 8% per second since
+//!   the previous report. Between 2% and 10% the bitrate is held.
+//! * The result is clamped to the configured bounds.
+//!
+//! The other settings follow from a smoothed loss estimate and the bitrate:
+//!
+//! * In-band FEC is turned on once smoothed loss reaches 1% and off again below 0.5%.
+//! * The expected packet loss given to the encoder is the smoothed loss in whole percent,
+//!   which decides how many bits it spends on FEC.
+//! * Below 16 kb/s frames are made longer to cut RTP/UDP/IP overhead, which is 16 kb/s on its
+//!   own with 20 ms frames. Above 24 kb/s they are made shorter again, down to 20 ms, for
+//!   lower latency.
+
+use std::time::{Duration, Instant};
+
+use crate::codec::{AudioEncoder, CodecError};
+use crate::signaling::FRAME_SIZES_MS;
+use crate::stats::CallStats;
+
+const HIGH_LOSS: f32 = 0.10;
+const LOW_LOSS: f32 = 0.02;
+const RTT_SLACK: Duration = Duration::from_millis(100);
+const JITTER_LIMIT: Duration = Duration::from_millis(30);
+const DELAY_BACKOFF: f64 = 0.85;
+const GROWTH_PER_SECOND: f64 = 1.08;
+/// Longest gap between reports credited towards growth, so a late report cannot cause a jump.
+const MAX_GROWTH_INTERVAL: Duration = Duration::from_secs(5);
+const FEC_ON_LOSS: f32 = 0.01;
+const FEC_OFF_LOSS: f32 = 0.005;
+/// Weight of the newest report in the smoothed loss.
+const LOSS_SMOOTHING: f32 = 0.3;
+const DEFAULT_FRAME_MS: u32 = 20;
+const LONG_FRAMES_BELOW: u32 = 16_000;
+const SHORT_FRAMES_ABOVE: u32 = 24_000;
+
+/// Bounds the controller stays within.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimits {
+    pub min_bitrate: u32,
+    pub max_bitrate: u32,
+    /// Bitrate used until the first report arrives.
+    pub start_bitrate: u32,
+    pub min_frame_ms: u32,
+    pub max_frame_ms: u32,
+    /// Whether the controller may turn on in-band FEC.
+    pub fec: bool,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            min_bitrate: 6_000,
+            max_bitrate: 64_000,
+            start_bitrate: 32_000,
+            min_frame_ms: 20,
+            max_frame_ms: 60,
+            fec: true,
+        }
+    }
+}
+
+/// What the receiver told us about our stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Feedback {
+    pub fraction_lost: f32,
+    pub jitter: Duration,
+    pub round_trip_time: Option<Duration>,
+}
+
+impl Feedback {
+    /// The peer's view of our stream from the latest statistics.
+    pub fn from_stats(stats: &CallStats) -> Self {
+        Self {
+            fraction_lost: stats.remote_fraction_lost,
+            jitter: stats.remote_jitter,
+            round_trip_time: stats.round_trip_time,
+        }
+    }
+}
+
+/// Encoder settings chosen by the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderSettings {
+    pub bitrate: u32,
+    pub frame_ms: u32,
+    pub fec: bool,
+    /// Expected packet loss in percent.
+    pub expected_loss: u8,
+}
+
+impl EncoderSettings {
+    /// Applies everything but the frame size, which is up to the caller feeding the encoder.
+    pub fn apply(&self, encoder: &mut dyn AudioEncoder) -> Result<(), CodecError> {
+        encoder.set_bitrate(self.bitrate)?;
+        encoder.set_fec(self.fec, self.expected_loss)
+    }
+}
+
+pub struct BitrateController {
+    limits: RateLimits,
+    settings: EncoderSettings,
+    smoothed_loss: f32,
+    min_rtt: Option<Duration>,
+    last_update: Option<Instant>,
+}
+
+impl BitrateController {
+    pub fn new(limits: RateLimits) -> Self {
+        let bitrate = limits
+            .start_bitrate
+            .clamp(limits.min_bitrate, limits.max_bitrate);
+        Self {
+            limits,
+            settings: EncoderSettings {
+                bitrate,
+                frame_ms: frame_size(DEFAULT_FRAME_MS, &limits),
+                // Protect the first packets until we know whether that is needed.
+                fec: limits.fec,
+                expected_loss: 0,
+            },
+            smoothed_loss: 0.0,
+            min_rtt: None,
+            last_update: None,
+        }
+    }
+
+    pub fn settings(&self) -> EncoderSettings {
+        self.settings
+    }
+
+    /// Runs the control law on a new report. Returns the new settings if anything changed.
+    pub fn update(&mut self, feedback: &Feedback, now: Instant) -> Option<EncoderSettings> {
+        let elapsed = self
+            .last_update
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last))
+            .min(MAX_GROWTH_INTERVAL);
+        self.last_update = Some(now);
+
+        let loss = feedback.fraction_lost.clamp(0.0, 1.0);
+        self.smoothed_loss += LOSS_SMOOTHING * (loss - self.smoothed_loss);
+
+        let delayed = feedback.jitter > JITTER_LIMIT
+            || feedback.round_trip_time.is_some_and(|rtt| {
+                let min_rtt = *self.min_rtt.get_or_insert(rtt);
+                rtt > min_rtt + RTT_SLACK
+            });
+        if let Some(rtt) = feedback.round_trip_time {
+            self.min_rtt = Some(self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt)));
+        }
+
+        let bitrate = self.settings.bitrate as f64;
+        let mut factor: f64 = 1.0;
+        if loss > HIGH_LOSS {
+            factor = factor.min(1.0 - loss as f64 / 2.0);
+        }
+        if delayed {
+            factor = factor.min(DELAY_BACKOFF);
+        }
+        if loss < LOW_LOSS && !delayed {
+            factor = GROWTH_PER_SECOND.powf(elapsed.as_secs_f64());
+        }
+        let bitrate =
+            ((bitrate * factor) as u32).clamp(self.limits.min_bitrate, self.limits.max_bitrate);
+
+        let fec = self.limits.fec
+            && if self.settings.fec {
+                self.smoothed_loss >= FEC_OFF_LOSS
+            } else {
+                self.smoothed_loss >= FEC_ON_LOSS
+            };
+        let expected_loss = (self.smoothed_loss * 100.0).round().min(100.0) as u8;
+
+        let mut frame_ms = self.settings.frame_ms;
+        if bitrate < LONG_FRAMES_BELOW {
+            frame_ms = next_frame_size(frame_ms, &self.limits, true);
+        } else if bitrate > SHORT_FRAMES_ABOVE && frame_ms > DEFAULT_FRAME_MS {
+            frame_ms = next_frame_size(frame_ms, &self.limits, false);
+        }
+
+        let settings = EncoderSettings {
+            bitrate,
+            frame_ms,
+            fec,
+            expected_loss,
+        };
+        if settings == self.settings {
+            return None;
+        }
+        self.settings = settings;
+        Some(settings)
+    }
+}
+
+/// The closest allowed frame size to `frame_ms` within the limits.
+fn frame_size(frame_ms: u32, limits: &RateLimits) -> u32 {
+    FRAME_SIZES_MS
+        .iter()
+        .map(|&size| size as u32)
+        .filter(|size| (limits.min_frame_ms..=limits.max_frame_ms).contains(size))
+        .min_by_key(|size| size.abs_diff(frame_ms))
+        .unwrap_or(frame_ms)
+}
+
+/// The next longer or shorter allowed frame size, or `frame_ms` if there is none.
+fn next_frame_size(frame_ms: u32, limits: &RateLimits, longer: bool) -> u32 {
+    let allowed = FRAME_SIZES_MS
+        .iter()
+        .map(|&size| size as u32)
+        .filter(|size| (limits.min_frame_ms..=limits.max_frame_ms).contains(size));
+    let next = if longer {
+        allowed.filter(|&size| size > frame_ms).min()
+    } else {
+        allowed.filter(|&size| size < frame_ms).max()
+    };
+    next.unwrap_or(frame_ms)
+}
@@ -0,0 +1,986 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::audio::{AudioSink, AudioSource, RemixSink, RemixSource, ResampleSink, ResampleSource};
+use crate::bitrate::{BitrateController, Feedback, RateLimits};
+use crate::codec::{AudioDecoder, AudioEncoder, Codec, CodecError, CodecSet};
+use crate::dtx::{ComfortNoise, Dtx, NoiseDescription, OPUS_DTX_PACKET_LEN, Transmit};
+use crate::jitter::{JitterBuffer, Playout};
+use crate::logging::Stage;
+use crate::recorder::{Recorder, Track};
+use crate::rtcp::{RtcpPacket, RtcpSession};
+use crate::rtp::{self, CN_PAYLOAD_TYPE, HEADER_LEN, RtpHeader};
+use crate::signaling::{Action, CodecParams, EndReason, Media, Message, RejectReason, Session};
+use crate::srtp::{
+    self, CallKeys, CryptoError, KeyExchange, Role, SignalingReceiver, SignalingSender,
+    SrtcpReceiver, SrtcpSender, SrtpReceiver, SrtpSender,
+};
+use crate::stats::CallStats;
+use crate::wav::WavSpec;
+
+/// Largest packet we will produce or accept.
+const MAX_PACKET: usize = 1500;
+
+/// How long the receive thread blocks on the socket before checking the playout clock.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Codec and buffering parameters for a call.
+#[derive(Debug, Clone, Copy)]
+pub struct CallConfig {
+    pub sample_rate: u32,
+    /// Opus carries more than two channels as a multistream.
+    pub channels: usize,
+    pub frame_ms: u32,
+    pub bitrate: u32,
+    pub fec: bool,
+    /// Stop sending while nobody talks. The peer fills the gaps with comfort noise.
+    pub dtx: bool,
+    /// Codecs to offer, or the one to use without call setup. Narrowed to the agreed one
+    /// once set up, which may change the sample rate and channels too.
+    pub codecs: CodecSet,
+    /// Number of packets buffered before playout starts.
+    pub jitter_depth: usize,
+    /// Agree on keys during call setup and encrypt media with them. Both sides have to
+    /// agree: a conference server reads the media, so it only takes plaintext calls.
+    pub encrypt: bool,
+    /// Call ID to invite with, which a conference server takes as the room to join. A
+    /// random one if unset.
+    pub call_id: Option<u32>,
+}
+
+impl Default for CallConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48_000,
+            channels: 1,
+            frame_ms: 20,
+            bitrate: 32_000,
+            fec: true,
+            dtx: false,
+            codecs: CodecSet::all(),
+            jitter_depth: 3,
+            encrypt: true,
+            call_id: None,
+        }
+    }
+}
+
+impl CallConfig {
+    /// Samples per channel in one frame.
+    pub fn frame_samples(&self) -> usize {
+        self.sample_rate as usize * self.frame_ms as usize / 1000
+    }
+
+    /// Interleaved samples in one frame.
+    pub fn frame_len(&self) -> usize {
+        self.frame_samples() * self.channels
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_millis(self.frame_ms as u64)
+    }
+
+    /// The layout of the call's audio, as files store it.
+    pub fn wav_spec(&self) -> WavSpec {
+        WavSpec {
+            sample_rate: self.sample_rate,
+            channels: self.channels as u16,
+        }
+    }
+
+    /// The parameters we offer or accept during call setup.
+    pub fn codec_params(&self) -> CodecParams {
+        CodecParams {
+            sample_rate: self.sample_rate,
+            channels: self.channels as u8,
+            frame_ms: self.frame_ms as u8,
+            bitrate: self.bitrate,
+            fec: self.fec,
+            codecs: self.codecs,
+        }
+    }
+
+    /// The codec the call uses: the agreed one once set up.
+    pub fn codec(&self) -> Codec {
+        self.codecs.preferred().unwrap_or(Codec::Opus)
+    }
+
+    /// Applies the parameters agreed on during call setup.
+    pub fn with_params(self, params: CodecParams) -> Self {
+        Self {
+            sample_rate: params.sample_rate,
+            channels: params.channels as usize,
+            frame_ms: params.frame_ms as u32,
+            bitrate: params.bitrate,
+            fec: params.fec,
+            codecs: params.codecs,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CallError {
+    Io(io::Error),
+    Codec(CodecError),
+    /// Media keys could not be agreed on.
+    Crypto(CryptoError),
+    /// Call setup did not complete.
+    Ended(EndReason),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Io(err) => write!(f, "socket error: {}", err),
+            CallError::Codec(err) => write!(f, "codec error: {}", err),
+            CallError::Crypto(err) => write!(f, "key exchange failed: {}", err),
+            CallError::Ended(reason) => write!(f, "call setup failed: {:?}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+impl From<io::Error> for CallError {
+    fn from(err: io::Error) -> Self {
+        CallError::Io(err)
+    }
+}
+
+impl From<CodecError> for CallError {
+    fn from(err: CodecError) -> Self {
+        CallError::Codec(err)
+    }
+}
+
+impl From<opus::Error> for CallError {
+    fn from(err: opus::Error) -> Self {
+        CallError::Codec(CodecError::Opus(err))
+    }
+}
+
+impl From<CryptoError> for CallError {
+    fn from(err: CryptoError) -> Self {
+        CallError::Crypto(err)
+    }
+}
+
+/// What a call plays from and to, and the rate and channels they run at.
+struct Audio {
+    source: Box<dyn AudioSource + Send>,
+    sink: Box<dyn AudioSink + Send>,
+    sample_rate: u32,
+    channels: usize,
+}
+
+impl Audio {
+    /// Remixes and resamples the source and sink to the format the call agreed on.
+    fn adapt(
+        self,
+        sample_rate: u32,
+        channels: usize,
+    ) -> (Box<dyn AudioSource + Send>, Box<dyn AudioSink + Send>) {
+        let (mut source, mut sink) = (self.source, self.sink);
+        if self.channels != channels {
+            source = Box::new(RemixSource::new(source, self.channels, channels));
+            sink = Box::new(RemixSink::new(sink, channels, self.channels));
+        }
+        if self.sample_rate != sample_rate {
+            source = Box::new(ResampleSource::new(
+                source,
+                channels,
+                self.sample_rate,
+                sample_rate,
+            ));
+            sink = Box::new(ResampleSink::new(
+                sink,
+                channels,
+                sample_rate,
+                self.sample_rate,
+            ));
+        }
+        (source, sink)
+    }
+}
+
+/// Where both directions of a call are recorded, if anywhere.
+type SharedRecorder = Arc<Mutex<Option<Recorder>>>;
+
+/// A bidirectional voice call with one peer over a single UDP socket.
+///
+/// One thread captures, encodes and sends; another receives, jitter-buffers, decodes and
+/// plays. Both stop when the call is hung up, dropped, or ended by the peer.
+pub struct Call {
+    running: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    local_addr: SocketAddr,
+    peer: SocketAddr,
+    ssrc: u32,
+    config: CallConfig,
+    rtcp: Arc<Mutex<RtcpSession>>,
+    recorder: SharedRecorder,
+}
+
+impl Call {
+    /// Starts sending and receiving media right away, without call setup or encryption.
+    ///
+    /// The call uses the most preferred of `config.codecs`, at the format it runs at. Here
+    /// and in [`dial`](Self::dial) and [`answer`](Self::answer), `source` and `sink` run at
+    /// `config.sample_rate` with `config.channels`, whatever format the call ends up with,
+    /// and are resampled and remixed to match.
+    pub fn start(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        config: CallConfig,
+        source: Box<dyn AudioSource + Send>,
+        sink: Box<dyn AudioSink + Send>,
+    ) -> Result<Call, CallError> {
+        let ssrc = rtp::random_ssrc();
+        let audio = Audio {
+            source,
+            sink,
+            sample_rate: config.sample_rate,
+            channels: config.channels,
+        };
+        Self::launch(socket, peer, config, ssrc, None, audio)
+    }
+
+    /// Invites `peer` and starts the call once it accepts.
+    ///
+    /// Media is encrypted with keys agreed on during setup; peers that do not offer a key are
+    /// hung up on. With `config.encrypt` off, no key is offered and media goes in the clear.
+    pub fn dial(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        config: CallConfig,
+        source: Box<dyn AudioSource + Send>,
+        sink: Box<dyn AudioSink + Send>,
+    ) -> Result<Call, CallError> {
+        let ssrc = rtp::random_ssrc();
+        let now = Instant::now();
+        let exchange = config.encrypt.then(KeyExchange::new);
+        let mut session = offer_key(Session::new(config.codec_params(), ssrc, now), &exchange);
+        let call_id = config.call_id.unwrap_or_else(rtp::random_ssrc);
+        let actions = session.invite(call_id, now);
+        let (peer, media) = handshake(&socket, Some(peer), &mut session, actions)?;
+        let keys = exchange
+            .map(|exchange| media_keys(exchange, &media, Role::Caller))
+            .transpose()?;
+        let audio = Audio {
+            source,
+            sink,
+            sample_rate: config.sample_rate,
+            channels: config.channels,
+        };
+        let config = config.with_params(media.params);
+        let setup = Some((session, keys));
+        Self::launch(socket, peer, config, ssrc, setup, audio)
+    }
+
+    /// Waits for an invite from anyone, accepts it and starts the call.
+    ///
+    /// Invites without a key are rejected, so media is always encrypted unless
+    /// `config.encrypt` is off.
+    pub fn answer(
+        socket: UdpSocket,
+        config: CallConfig,
+        source: Box<dyn AudioSource + Send>,
+        sink: Box<dyn AudioSink + Send>,
+    ) -> Result<Call, CallError> {
+        let ssrc = rtp::random_ssrc();
+        let exchange = config.encrypt.then(KeyExchange::new);
+        let session = Session::new(config.codec_params(), ssrc, Instant::now());
+        let mut session = offer_key(session, &exchange);
+        let (peer, media) = handshake(&socket, None, &mut session, Vec::new())?;
+        let keys = exchange
+            .map(|exchange| media_keys(exchange, &media, Role::Callee))
+            .transpose()?;
+        let audio = Audio {
+            source,
+            sink,
+            sample_rate: config.sample_rate,
+            channels: config.channels,
+        };
+        let config = config.with_params(media.params);
+        let setup = Some((session, keys));
+        Self::launch(socket, peer, config, ssrc, setup, audio)
+    }
+
+    fn launch(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        config: CallConfig,
+        ssrc: u32,
+        setup: Option<(Session, Option<CallKeys>)>,
+        audio: Audio,
+    ) -> Result<Call, CallError> {
+        let codec = config.codec();
+        let (sample_rate, channels) = codec.format(config.sample_rate, config.channels);
+        if !codec.fits(sample_rate, channels, config.frame_ms) {
+            return Err(CodecError::FrameSize(config.frame_ms).into());
+        }
+        let config = CallConfig {
+            sample_rate,
+            channels,
+            codecs: CodecSet::only(codec),
+            ..config
+        };
+        let mut encoder = codec.encoder(config.sample_rate, channels)?;
+        encoder.set_bitrate(config.bitrate)?;
+        encoder.set_fec(config.fec, 0)?;
+        encoder.set_dtx(config.dtx)?;
+        let decoder = codec.decoder(config.sample_rate, channels)?;
+        let (source, sink) = audio.adapt(config.sample_rate, channels);
+        // Codecs without DTX of their own leave it to us, sending comfort noise descriptions
+        // while silent.
+        let dtx =
+            (config.dtx && !encoder.supports_dtx()).then(|| Dtx::new(config.sample_rate, channels));
+        let local_addr = socket.local_addr()?;
+        let receive_socket = socket.try_clone()?;
+        receive_socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let rtcp = Arc::new(Mutex::new(RtcpSession::new(
+            ssrc,
+            encoder.clock_rate(),
+            Instant::now(),
+        )));
+        let (session, keys) = setup.unzip();
+        let (srtp, srtcp, signaling) = match keys.flatten() {
+            Some(keys) => (
+                Some((keys.send, keys.receive)),
+                Some((keys.send_rtcp, keys.receive_rtcp)),
+                Some((keys.send_signaling, keys.receive_signaling)),
+            ),
+            None => (None, None, None),
+        };
+        let (protect, unprotect) = srtp.unzip();
+        let recorder = Arc::new(Mutex::new(None));
+
+        let sender = Sender {
+            socket,
+            peer,
+            config,
+            encoder,
+            dtx,
+            source,
+            ssrc,
+            srtp: protect,
+            rtcp: Arc::clone(&rtcp),
+            recorder: Arc::clone(&recorder),
+            // The frame size is fixed by call setup, so only bitrate and FEC adapt.
+            controller: BitrateController::new(RateLimits {
+                max_bitrate: config.bitrate,
+                start_bitrate: config.bitrate,
+                min_frame_ms: config.frame_ms,
+                max_frame_ms: config.frame_ms,
+                fec: config.fec,
+                ..RateLimits::default()
+            }),
+        };
+        let receiver = Receiver {
+            socket: receive_socket,
+            peer,
+            config,
+            decoder,
+            sink,
+            jitter: JitterBuffer::new(config.jitter_depth),
+            comfort: ComfortNoise::new(config.sample_rate, config.channels),
+            session,
+            srtp: unprotect,
+            srtcp,
+            signaling,
+            rtcp: Arc::clone(&rtcp),
+            recorder: Arc::clone(&recorder),
+            remote_ssrc: None,
+        };
+
+        let send_running = Arc::clone(&running);
+        let receive_running = Arc::clone(&running);
+        let threads = vec![
+            thread::Builder::new()
+                .name("call-send".into())
+                .spawn(move || Stage::Encode.span().in_scope(|| sender.run(&send_running)))?,
+            thread::Builder::new()
+                .name("call-receive".into())
+                .spawn(move || {
+                    Stage::Network
+                        .span()
+                        .in_scope(|| receiver.run(&receive_running))
+                })?,
+        ];
+
+        Ok(Call {
+            running,
+            threads,
+            local_addr,
+            peer,
+            ssrc,
+            config,
+            rtcp,
+            recorder,
+        })
+    }
+
+    /// What the call runs with once set up. Its source and sink carry audio in this format.
+    pub fn config(&self) -> CallConfig {
+        self.config
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// SSRC identifying our outgoing stream.
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    /// Loss, jitter and round-trip time in both directions so far.
+    pub fn stats(&self) -> CallStats {
+        self.rtcp.lock().unwrap().stats()
+    }
+
+    /// Records both directions into `recorder` whenever it is recording: what we send as
+    /// [`Track::Microphone`] and what the peer sends as a [`Track::Participant`] named by
+    /// its SSRC. `None` stops feeding it.
+    pub fn record(&self, recorder: Option<Recorder>) {
+        *self.recorder.lock().unwrap() = recorder;
+    }
+
+    /// Returns false once the call was hung up on either side.
+    pub fn is_active(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Stops both directions and waits for the worker threads to finish.
+    pub fn hangup(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Drives `session` over `socket` until the call is established or fails.
+///
+/// With no `peer`, the first invite received decides who we are talking to and is accepted.
+fn handshake(
+    socket: &UdpSocket,
+    mut peer: Option<SocketAddr>,
+    session: &mut Session,
+    actions: Vec<Action>,
+) -> Result<(SocketAddr, Media), CallError> {
+    socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+    let mut pending = VecDeque::from(actions);
+    let mut packet = [0u8; MAX_PACKET];
+    loop {
+        while let Some(action) = pending.pop_front() {
+            match action {
+                Action::Send(message) => {
+                    if let Some(peer) = peer {
+                        send_signal(socket, &message.encode(), peer);
+                    }
+                }
+                Action::Incoming(_) => pending.extend(session.accept(Instant::now())),
+                Action::Connected(media) => {
+                    if let Some(peer) = peer {
+                        return Ok((peer, media));
+                    }
+                }
+                Action::Ended(reason) => return Err(CallError::Ended(reason)),
+            }
+        }
+
+        match socket.recv_from(&mut packet) {
+            Ok((size, from)) => {
+                if let Some(message) = Message::decode(&packet[..size]) {
+                    if peer.is_none() && matches!(message, Message::Invite { .. }) {
+                        peer = Some(from);
+                    }
+                    if peer == Some(from) {
+                        pending.extend(session.receive(message, Instant::now()));
+                    }
+                }
+            }
+            Err(err) if is_transient(&err) => {}
+            Err(err) => return Err(err.into()),
+        }
+        pending.extend(session.tick(Instant::now()));
+    }
+}
+
+/// Offers the key of `exchange`, if the call is to be encrypted.
+fn offer_key(session: Session, exchange: &Option<KeyExchange>) -> Session {
+    match exchange {
+        Some(exchange) => session.with_public_key(exchange.public_key()),
+        None => session,
+    }
+}
+
+/// Finishes the key exchange for an established call.
+fn media_keys(exchange: KeyExchange, media: &Media, role: Role) -> Result<CallKeys, CallError> {
+    // The session refuses peers without a key once we offered one.
+    let remote = media
+        .remote_key
+        .ok_or(CallError::Ended(EndReason::Rejected(
+            RejectReason::Incompatible,
+        )))?;
+    Ok(exchange.derive(remote, role, media.call_id)?)
+}
+
+fn send_signal(socket: &UdpSocket, packet: &[u8], peer: SocketAddr) {
+    if let Err(err) = socket.send_to(packet, peer)
+        && !is_transient(&err)
+    {
+        warn!(error = %err, "failed to send signaling message");
+    }
+}
+
+/// What a sender made of one frame.
+#[derive(Debug, Clone, Copy)]
+enum Outgoing {
+    /// A packet of this payload type and size.
+    Packet(u8, usize),
+    /// Nothing worth sending while nobody talks.
+    Skipped,
+    Failed,
+}
+
+struct Sender {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    config: CallConfig,
+    encoder: Box<dyn AudioEncoder>,
+    /// Decides when to send, for codecs without DTX of their own.
+    dtx: Option<Dtx>,
+    source: Box<dyn AudioSource + Send>,
+    ssrc: u32,
+    srtp: Option<SrtpSender>,
+    rtcp: Arc<Mutex<RtcpSession>>,
+    recorder: SharedRecorder,
+    controller: BitrateController,
+}
+
+impl Sender {
+    fn run(mut self, running: &AtomicBool) {
+        let mut pcm = vec![0f32; self.config.frame_len()];
+        let mut packet = [0u8; MAX_PACKET];
+        let mut header = RtpHeader {
+            marker: true,
+            payload_type: self.config.codec().payload_type(),
+            sequence: 0,
+            timestamp: 0,
+            ssrc: self.ssrc,
+        };
+        // Opus timestamps count at 48 kHz whatever the sample rate.
+        let step = self.encoder.clock_rate() * self.config.frame_ms / 1000;
+        let mut next_frame = Instant::now();
+        let mut reports_seen = 0;
+
+        while running.load(Ordering::Relaxed) {
+            let stats = self.rtcp.lock().unwrap().stats();
+            if stats.reports_received != reports_seen {
+                reports_seen = stats.reports_received;
+                let feedback = Feedback::from_stats(&stats);
+                if let Some(settings) = self.controller.update(&feedback, Instant::now())
+                    && let Err(err) = settings.apply(self.encoder.as_mut())
+                {
+                    warn!(error = %err, "failed to adapt encoder");
+                }
+            }
+
+            let count = self.source.read(&mut pcm);
+            pcm[count..].fill(0.0);
+
+            // Leave room for the authentication tag.
+            let outgoing = self.encode(&pcm, &mut packet[HEADER_LEN..MAX_PACKET - srtp::TAG_LEN]);
+            let mut encoded = None;
+            if let Outgoing::Packet(payload_type, size) = outgoing {
+                let speech = payload_type == header.payload_type;
+                RtpHeader {
+                    payload_type,
+                    marker: header.marker && speech,
+                    ..header
+                }
+                .write(&mut packet);
+                self.send(&packet[..HEADER_LEN + size]);
+                self.rtcp
+                    .lock()
+                    .unwrap()
+                    .sent(header.timestamp, size, Instant::now());
+                if speech {
+                    encoded = Some(&packet[HEADER_LEN..HEADER_LEN + size]);
+                }
+            }
+            self.record(&pcm, encoded, header.sequence);
+            // The first packet after a silence starts a talkspurt (RFC 3551 section 4.1).
+            match outgoing {
+                Outgoing::Skipped => header.marker = true,
+                Outgoing::Packet(payload_type, _) => {
+                    header.marker = payload_type == CN_PAYLOAD_TYPE;
+                    header.sequence = header.sequence.wrapping_add(1);
+                }
+                Outgoing::Failed => {
+                    header.marker = false;
+                    header.sequence = header.sequence.wrapping_add(1);
+                }
+            }
+            header.timestamp = header.timestamp.wrapping_add(step);
+
+            next_frame += self.config.frame_duration();
+            if let Some(wait) = next_frame.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+        }
+    }
+
+    /// Encodes one frame into `payload`, or a comfort noise description in its place.
+    fn encode(&mut self, pcm: &[f32], payload: &mut [u8]) -> Outgoing {
+        let transmit = match &mut self.dtx {
+            Some(dtx) => dtx.process(pcm),
+            None => Transmit::Speech,
+        };
+        match transmit {
+            Transmit::Speech => {}
+            Transmit::Noise(description) => {
+                let encoded = description.encode();
+                payload[..encoded.len()].copy_from_slice(&encoded);
+                return Outgoing::Packet(CN_PAYLOAD_TYPE, encoded.len());
+            }
+            Transmit::Nothing => return Outgoing::Skipped,
+        }
+
+        let codec = self.encoder.codec();
+        match self.encoder.encode(pcm, payload) {
+            // With DTX of its own, the encoder shrinks frames not worth sending to a byte or two.
+            Ok(size) if self.config.dtx && self.dtx.is_none() && size <= OPUS_DTX_PACKET_LEN => {
+                Outgoing::Skipped
+            }
+            Ok(size) => Outgoing::Packet(codec.payload_type(), size),
+            Err(err) => {
+                warn!(error = %err, "failed to encode frame");
+                Outgoing::Failed
+            }
+        }
+    }
+
+    /// Records the frame just sent, as encoded if the recorder keeps packets and they are
+    /// Opus.
+    fn record(&self, pcm: &[f32], encoded: Option<&[u8]>, sequence: u16) {
+        let Some(recorder) = &*self.recorder.lock().unwrap() else {
+            return;
+        };
+        let spec = self.config.wav_spec();
+        let now = Instant::now();
+        match encoded {
+            _ if !recorder.wants_packets() || self.encoder.codec() != Codec::Opus => {
+                recorder.write(Track::Microphone, pcm, spec, now)
+            }
+            Some(payload) => recorder.write_packet(Track::Microphone, sequence, payload, spec, now),
+            None => {}
+        }
+    }
+
+    fn send(&mut self, packet: &[u8]) {
+        let protected;
+        let packet = match &mut self.srtp {
+            Some(srtp) => match srtp.protect(packet) {
+                Ok(packet) => {
+                    protected = packet;
+                    &protected[..]
+                }
+                Err(err) => {
+                    warn!(error = %err, "failed to encrypt packet");
+                    return;
+                }
+            },
+            None => packet,
+        };
+        if let Err(err) = self.socket.send_to(packet, self.peer)
+            && !is_transient(&err)
+        {
+            Stage::Network
+                .span()
+                .in_scope(|| warn!(error = %err, "failed to send packet"));
+        }
+    }
+}
+
+struct Receiver {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    config: CallConfig,
+    decoder: Box<dyn AudioDecoder>,
+    sink: Box<dyn AudioSink + Send>,
+    jitter: JitterBuffer,
+    /// Played whenever the peer sends nothing, as while it is in DTX.
+    comfort: ComfortNoise,
+    session: Option<Session>,
+    srtp: Option<SrtpReceiver>,
+    /// Keys for the RTCP we send and receive, which come and go on this thread.
+    srtcp: Option<(SrtcpSender, SrtcpReceiver)>,
+    /// Keys signing byes and keepalives once the call is set up.
+    signaling: Option<(SignalingSender, SignalingReceiver)>,
+    rtcp: Arc<Mutex<RtcpSession>>,
+    recorder: SharedRecorder,
+    /// SSRC of the peer's stream, once media has arrived.
+    remote_ssrc: Option<u32>,
+}
+
+impl Receiver {
+    fn run(mut self, running: &AtomicBool) {
+        let mut packet = [0u8; MAX_PACKET];
+        let mut pcm = vec![0f32; self.config.frame_len()];
+        let mut next_frame = Instant::now() + self.config.frame_duration();
+
+        while running.load(Ordering::Relaxed) {
+            match self.socket.recv_from(&mut packet) {
+                Ok((size, from)) if from == self.peer => {
+                    self.handle_packet(&packet[..size], running)
+                }
+                Ok(_) => {}
+                Err(err) if is_transient(&err) => {}
+                Err(err) => warn!(error = %err, "failed to receive packet"),
+            }
+
+            if let Some(session) = &mut self.session {
+                let actions = session.tick(Instant::now());
+                self.perform(actions, running);
+            }
+            let report = self.rtcp.lock().unwrap().poll(Instant::now());
+            if let Some(report) = report {
+                self.send_rtcp(&report);
+            }
+
+            while Instant::now() >= next_frame {
+                self.play_frame(&mut pcm);
+                next_frame += self.config.frame_duration();
+            }
+        }
+
+        let bye = self.rtcp.lock().unwrap().bye(Instant::now());
+        self.send_rtcp(&bye);
+        if let Some(session) = &mut self.session {
+            let actions = session.hangup(Instant::now());
+            self.perform(actions, running);
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &[u8], running: &AtomicBool) {
+        if Message::is_signaling(packet) {
+            // Anyone who sees the call id could otherwise hang up or keep alive the call.
+            let (packet, authentic) = match &mut self.signaling {
+                Some((_, verifier)) => match verifier.verify(packet) {
+                    Ok(message) => (message, true),
+                    Err(_) => (packet, false),
+                },
+                None => (packet, true),
+            };
+            if let Some(message) = Message::decode(packet)
+                && (authentic || message.is_setup())
+                && let Some(session) = &mut self.session
+            {
+                let actions = session.receive(message, Instant::now());
+                self.perform(actions, running);
+            }
+            return;
+        }
+        // Reports steer our bitrate and a BYE ends the call, so RTCP is authenticated too.
+        if RtcpPacket::is_rtcp(packet) {
+            let decrypted;
+            let packet = match &mut self.srtcp {
+                Some((_, srtcp)) => match srtcp.unprotect(packet) {
+                    Ok(packet) => {
+                        decrypted = packet;
+                        &decrypted[..]
+                    }
+                    Err(_) => return,
+                },
+                None => packet,
+            };
+            let mut rtcp = self.rtcp.lock().unwrap();
+            rtcp.receive(packet, Instant::now());
+            // Without call setup, an RTCP BYE is the only way the peer can hang up.
+            if self.session.is_none() && rtcp.remote_left() {
+                running.store(false, Ordering::Relaxed);
+            }
+            return;
+        }
+
+        // Forged, tampered and replayed packets are dropped silently, like any other noise.
+        let decrypted;
+        let packet = match &mut self.srtp {
+            Some(srtp) => match srtp.unprotect(packet) {
+                Ok(packet) => {
+                    decrypted = packet;
+                    &decrypted[..]
+                }
+                Err(_) => return,
+            },
+            None => packet,
+        };
+        let Some((header, payload)) = RtpHeader::parse(packet) else {
+            return;
+        };
+        if header.payload_type != self.config.codec().payload_type()
+            && header.payload_type != CN_PAYLOAD_TYPE
+        {
+            return;
+        }
+        if let Some(session) = &mut self.session {
+            match session.media() {
+                Some(media) if media.remote_ssrc == header.ssrc => {
+                    session.media_received(Instant::now())
+                }
+                _ => return,
+            }
+        }
+        self.rtcp.lock().unwrap().received(&header, Instant::now());
+        self.remote_ssrc = Some(header.ssrc);
+        if header.payload_type == CN_PAYLOAD_TYPE {
+            if let Some(description) = NoiseDescription::parse(payload) {
+                self.comfort.update(description);
+            }
+            // Holds its place in the sequence, so it isn't concealed as lost.
+            self.jitter.push(header.sequence, Vec::new());
+            return;
+        }
+        if let Some(recorder) = &*self.recorder.lock().unwrap()
+            && recorder.wants_packets()
+            && self.decoder.codec() == Codec::Opus
+        {
+            recorder.write_packet(
+                Track::Participant(header.ssrc),
+                header.sequence,
+                payload,
+                self.config.wav_spec(),
+                Instant::now(),
+            );
+        }
+        self.jitter.push(header.sequence, payload.to_vec());
+    }
+
+    fn send_rtcp(&mut self, report: &[u8]) {
+        let protected;
+        let report = match &mut self.srtcp {
+            Some((srtcp, _)) => match srtcp.protect(report) {
+                Ok(report) => {
+                    protected = report;
+                    &protected[..]
+                }
+                Err(err) => {
+                    warn!(error = %err, "failed to protect RTCP report");
+                    return;
+                }
+            },
+            None => report,
+        };
+        if let Err(err) = self.socket.send_to(report, self.peer)
+            && !is_transient(&err)
+        {
+            warn!(error = %err, "failed to send RTCP report");
+        }
+    }
+
+    /// Sends `message`, signed unless the peer may not have the keys yet.
+    fn send_signal(&mut self, message: &Message) {
+        let packet = match &mut self.signaling {
+            Some((signer, _)) if !message.is_setup() => match signer.sign(&message.encode()) {
+                Ok(packet) => packet,
+                Err(err) => {
+                    warn!(error = %err, "failed to sign signaling message");
+                    return;
+                }
+            },
+            _ => message.encode(),
+        };
+        send_signal(&self.socket, &packet, self.peer);
+    }
+
+    /// Carries out signaling actions produced while the call is running.
+    fn perform(&mut self, actions: Vec<Action>, running: &AtomicBool) {
+        for action in actions {
+            match action {
+                Action::Send(message) => self.send_signal(&message),
+                Action::Ended(_) => running.store(false, Ordering::Relaxed),
+                Action::Incoming(_) | Action::Connected(_) => {}
+            }
+        }
+    }
+
+    fn play_frame(&mut self, pcm: &mut [f32]) {
+        let channels = self.config.channels;
+        let decoded = match self.jitter.pop() {
+            // Comfort noise packets, and gaps while the peer sends nothing.
+            Playout::Packet(packet) if packet.is_empty() => None,
+            Playout::Empty => None,
+            Playout::Packet(packet) => {
+                let decoded = self.decoder.decode(&packet, pcm);
+                if let Ok(samples) = decoded {
+                    self.comfort.learn(&pcm[..samples * channels]);
+                }
+                Some(decoded)
+            }
+            // An empty packet asks the decoder for loss concealment.
+            Playout::Lost => Some(self.decoder.decode(&[], pcm)),
+        };
+        let samples = match decoded {
+            Some(Ok(samples)) => samples * channels,
+            Some(Err(err)) => {
+                Stage::Decode
+                    .span()
+                    .in_scope(|| warn!(error = %err, "failed to decode packet"));
+                0
+            }
+            None => {
+                self.comfort.generate(pcm);
+                pcm.len()
+            }
+        };
+        pcm[samples..].fill(0.0);
+        if let Some(recorder) = &*self.recorder.lock().unwrap()
+            && let Some(ssrc) = self.remote_ssrc
+            && (!recorder.wants_packets() || self.decoder.codec() != Codec::Opus)
+        {
+            let spec = self.config.wav_spec();
+            recorder.write(Track::Participant(ssrc), pcm, spec, Instant::now());
+        }
+        self.sink.write(pcm);
+    }
+}
+
+/// Errors that are expected on an unconnected UDP socket and not worth reporting.
+pub(crate) fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+    )
+}
@@ -0,0 +1,475 @@
+//! Capturing the packets of a call, and replaying them.
+//!
+//! A [`Capture`] logs every datagram a socket sends or receives, with the time it did, to a
+//! pcap file. Each is written as the IPv4 or IPv6 UDP packet it travelled in, so the file
+//! opens in Wireshark or tcpdump as well as in [`PcapReader`]. A [`Replay`] feeds a capture
+//! back into a [`Pipeline`](crate::pipeline::Pipeline) with its original timing, or as fast
+//! as its clock allows, to reproduce offline what a receiver got.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::warn;
+
+use crate::clock::{Clock, SystemClock};
+use crate::pipeline::{POLL_INTERVAL, Source};
+
+/// Datagrams queued before more are dropped: several seconds of a call.
+pub const QUEUE_CAPACITY: usize = 1024;
+
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_MINOR: u16 = 4;
+const SNAPLEN: u32 = 65_535;
+const FILE_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+
+/// Link types read; captures are written as raw IP, which holds both versions.
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const ETHERNET_HEADER_LEN: usize = 14;
+
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const PROTOCOL_UDP: u8 = 17;
+const TTL: u8 = 64;
+
+/// A UDP datagram, and when it was sent or received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub time: SystemTime,
+    pub from: SocketAddr,
+    pub to: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+/// Writes datagrams to a pcap file with nanosecond timestamps.
+pub struct PcapWriter<W: Write = BufWriter<File>> {
+    output: W,
+}
+
+impl PcapWriter {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Starts a capture on `output` by writing the file header.
+    pub fn new(mut output: W) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(FILE_HEADER_LEN);
+        header.extend_from_slice(&MAGIC_NANOS.to_le_bytes());
+        header.extend_from_slice(&VERSION_MAJOR.to_le_bytes());
+        header.extend_from_slice(&VERSION_MINOR.to_le_bytes());
+        // Timestamps are UTC, with no stated accuracy.
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        output.write_all(&header)?;
+        Ok(Self { output })
+    }
+
+    pub fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        let packet = ip_packet(datagram.from, datagram.to, &datagram.payload);
+        let time = datagram.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut header = Vec::with_capacity(RECORD_HEADER_LEN);
+        header.extend_from_slice(&(time.as_secs() as u32).to_le_bytes());
+        header.extend_from_slice(&time.subsec_nanos().to_le_bytes());
+        header.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        self.output.write_all(&header)?;
+        self.output.write_all(&packet)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+/// Wraps `payload` in the UDP and IP headers it travelled with. A capture mixing the two
+/// versions writes IPv4 addresses as IPv4-mapped IPv6 ones.
+fn ip_packet(from: SocketAddr, to: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = UDP_HEADER_LEN + payload.len();
+    let mut udp = Vec::with_capacity(udp_len);
+    udp.extend_from_slice(&from.port().to_be_bytes());
+    udp.extend_from_slice(&to.port().to_be_bytes());
+    udp.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+
+    let mut packet;
+    let mut pseudo = Vec::new();
+    match (from.ip(), to.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            packet = Vec::with_capacity(IPV4_HEADER_LEN + udp_len);
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((IPV4_HEADER_LEN + udp_len) as u16).to_be_bytes());
+            // No identification, and don't fragment.
+            packet.extend_from_slice(&[0, 0, 0x40, 0, TTL, PROTOCOL_UDP, 0, 0]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+            let checksum = internet_checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+            pseudo.extend_from_slice(&source.octets());
+            pseudo.extend_from_slice(&destination.octets());
+            pseudo.extend_from_slice(&[0, PROTOCOL_UDP]);
+            pseudo.extend_from_slice(&(udp_len as u16).to_be_bytes());
+        }
+        (source, destination) => {
+            let (source, destination) = (to_ipv6(source), to_ipv6(destination));
+            packet = Vec::with_capacity(IPV6_HEADER_LEN + udp_len);
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+            packet.extend_from_slice(&[PROTOCOL_UDP, TTL]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+
+            pseudo.extend_from_slice(&source.octets());
+            pseudo.extend_from_slice(&destination.octets());
+            pseudo.extend_from_slice(&(udp_len as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, PROTOCOL_UDP]);
+        }
+    }
+    // A sum of zero is sent as all ones, zero meaning no checksum.
+    let checksum = match internet_checksum(&[&pseudo, &udp]) {
+        0 => 0xffff,
+        checksum => checksum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&udp);
+    packet
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// The ones' complement sum of IP and UDP headers (RFC 1071), over `parts` in a row.
+pub fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    let mut odd = None;
+    for &byte in parts.iter().flat_map(|part| part.iter()) {
+        match odd.take() {
+            Some(high) => sum += u16::from_be_bytes([high, byte]) as u32,
+            None => odd = Some(byte),
+        }
+    }
+    if let Some(high) = odd {
+        sum += u16::from_be_bytes([high, 0]) as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Reads the UDP datagrams of a pcap file, whether written by a [`PcapWriter`] or by
+/// tcpdump on an Ethernet or raw IP interface. Other packets are skipped.
+pub struct PcapReader<R: Read = BufReader<File>> {
+    input: R,
+    big_endian: bool,
+    nanos: bool,
+    link_type: u32,
+    /// The longest record the file header allows.
+    snaplen: u32,
+    record: Vec<u8>,
+}
+
+impl PcapReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads the file header from `input`.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0; FILE_HEADER_LEN];
+        input.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let (big_endian, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            _ if magic.swap_bytes() == MAGIC_MICROS => (true, false),
+            _ if magic.swap_bytes() == MAGIC_NANOS => (true, true),
+            _ => return Err(invalid("not a pcap file")),
+        };
+        let mut reader = Self {
+            input,
+            big_endian,
+            nanos,
+            link_type: 0,
+            snaplen: 0,
+            record: Vec::new(),
+        };
+        reader.snaplen = reader.u32_at(&header, 16);
+        reader.link_type = reader.u32_at(&header, 20);
+        if ![
+            LINKTYPE_ETHERNET,
+            LINKTYPE_RAW,
+            LINKTYPE_IPV4,
+            LINKTYPE_IPV6,
+        ]
+        .contains(&reader.link_type)
+        {
+            return Err(invalid("unsupported link type"));
+        }
+        Ok(reader)
+    }
+
+    fn u32_at(&self, bytes: &[u8], at: usize) -> u32 {
+        let bytes = bytes[at..at + 4].try_into().unwrap();
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+
+    /// Reads the next record, or `None` at the end of the file.
+    fn read_record(&mut self) -> io::Result<Option<SystemTime>> {
+        let mut header = [0; RECORD_HEADER_LEN];
+        match self.input.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.input.read_exact(&mut header[1..])?,
+        }
+        let seconds = Duration::from_secs(self.u32_at(&header, 0) as u64);
+        // A corrupt fraction can exceed a second, so it is widened rather than multiplied.
+        let fraction = self.u32_at(&header, 4) as u64;
+        let fraction = match self.nanos {
+            true => Duration::from_nanos(fraction),
+            false => Duration::from_micros(fraction),
+        };
+        let len = self.u32_at(&header, 8);
+        if len > self.snaplen {
+            return Err(invalid("record longer than the snapshot length"));
+        }
+        self.record.resize(len as usize, 0);
+        self.input.read_exact(&mut self.record)?;
+        Ok(Some(UNIX_EPOCH + seconds + fraction))
+    }
+
+    /// The UDP datagram in the current record, if that is what it holds.
+    fn datagram(&self) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+        let packet = match self.link_type {
+            LINKTYPE_ETHERNET => self.record.get(ETHERNET_HEADER_LEN..)?,
+            _ => &self.record[..],
+        };
+        let (source, destination, udp) = match packet.first()? >> 4 {
+            4 => {
+                let header_len = (packet[0] & 0x0f) as usize * 4;
+                let total_len = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as usize;
+                // Only whole, unfragmented datagrams can be read.
+                let fragment = u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff;
+                if packet.get(9)? != &PROTOCOL_UDP || fragment != 0 {
+                    return None;
+                }
+                let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+                let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+                let udp = packet.get(header_len..total_len.min(packet.len()))?;
+                (IpAddr::from(source), IpAddr::from(destination), udp)
+            }
+            6 => {
+                if packet.get(6)? != &PROTOCOL_UDP {
+                    return None;
+                }
+                let source: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+                let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+                (
+                    IpAddr::from(source),
+                    IpAddr::from(destination),
+                    packet.get(IPV6_HEADER_LEN..)?,
+                )
+            }
+            _ => return None,
+        };
+        let source_port = u16::from_be_bytes([*udp.first()?, *udp.get(1)?]);
+        let destination_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+        let len = u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]) as usize;
+        let payload = udp.get(UDP_HEADER_LEN..len.min(udp.len()))?;
+        Some((
+            SocketAddr::new(source, source_port),
+            SocketAddr::new(destination, destination_port),
+            payload,
+        ))
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<Datagram>;
+
+    fn next(&mut self) -> Option<io::Result<Datagram>> {
+        loop {
+            let time = match self.read_record() {
+                Ok(Some(time)) => time,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            };
+            if let Some((from, to, payload)) = self.datagram() {
+                return Some(Ok(Datagram {
+                    time,
+                    from,
+                    to,
+                    payload: payload.to_vec(),
+                }));
+            }
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Logs datagrams to a capture file. Clones log to the same file, so the threads sending
+/// and receiving can each hold one; the file is complete once all of them are dropped.
+///
+/// Datagrams are written on a thread of their own, so audio callbacks never wait for the
+/// disk. The file is flushed whenever that thread catches up, so a capture cut short by
+/// Ctrl+C still holds everything up to shortly before.
+#[derive(Clone)]
+pub struct Capture {
+    writes: SyncSender<Datagram>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Capture {
+    /// Creates the capture file at `path` and starts writing to it.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut writer = PcapWriter::create(path)?;
+        writer.flush()?;
+        let (writes, queue) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let thread_dropped = Arc::clone(&dropped);
+        thread::Builder::new()
+            .name("capture".into())
+            .spawn(move || write_capture(writer, queue, thread_dropped))?;
+        Ok(Self { writes, dropped })
+    }
+
+    /// Logs `payload`, sent or received just now.
+    pub fn record(&self, from: SocketAddr, to: SocketAddr, payload: &[u8]) {
+        let datagram = Datagram {
+            time: SystemTime::now(),
+            from,
+            to,
+            payload: payload.to_vec(),
+        };
+        if let Err(TrySendError::Full(_)) = self.writes.try_send(datagram) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn write_capture(mut writer: PcapWriter, queue: Receiver<Datagram>, dropped: Arc<AtomicUsize>) {
+    let mut result = Ok(());
+    while let Ok(datagram) = queue.recv() {
+        result = writer.write(&datagram);
+        while result.is_ok()
+            && let Ok(datagram) = queue.try_recv()
+        {
+            result = writer.write(&datagram);
+        }
+        result = result.and_then(|()| writer.flush());
+        if result.is_err() {
+            break;
+        }
+    }
+    if let Err(err) = result {
+        warn!(error = %err, "failed to write capture; stopped capturing");
+    }
+    let dropped = dropped.load(Ordering::Relaxed);
+    if dropped > 0 {
+        warn!(
+            dropped,
+            "capture fell behind; some packets are missing from it"
+        );
+    }
+}
+
+/// Plays a capture back as a pipeline source, each datagram when it was captured relative
+/// to the first. With a [`VirtualClock`](crate::clock::VirtualClock) nothing waits, and the
+/// capture goes through as fast as the pipeline takes it.
+pub struct Replay<I, C = SystemClock> {
+    datagrams: I,
+    clock: C,
+    /// When the first datagram was captured, and when it was replayed.
+    start: Option<(SystemTime, Instant)>,
+    /// The next datagram, waiting for its time.
+    pending: Option<Datagram>,
+}
+
+impl<I: Iterator<Item = io::Result<Datagram>>> Replay<I> {
+    pub fn new(datagrams: I) -> Self {
+        Self::with_clock(datagrams, SystemClock)
+    }
+}
+
+impl<I: Iterator<Item = io::Result<Datagram>>, C: Clock> Replay<I, C> {
+    pub fn with_clock(datagrams: I, clock: C) -> Self {
+        Self {
+            datagrams,
+            clock,
+            start: None,
+            pending: None,
+        }
+    }
+}
+
+impl<I, C> Source for Replay<I, C>
+where
+    I: Iterator<Item = io::Result<Datagram>> + Send + 'static,
+    C: Clock,
+{
+    type Output = Datagram;
+
+    fn pull(&mut self, out: &mut Vec<Datagram>) -> bool {
+        let datagram = match self
+            .pending
+            .take()
+            .map(Ok)
+            .or_else(|| self.datagrams.next())
+        {
+            Some(Ok(datagram)) => datagram,
+            Some(Err(err)) => {
+                warn!(error = %err, "failed to read capture; replay ends here");
+                return false;
+            }
+            None => return false,
+        };
+        let (first, started) = *self
+            .start
+            .get_or_insert_with(|| (datagram.time, self.clock.now()));
+        let offset = datagram.time.duration_since(first).unwrap_or_default();
+        let due = started + offset;
+        // Long silences are waited out a poll interval at a time, so stopping stays prompt.
+        let now = self.clock.now();
+        if due > now + POLL_INTERVAL {
+            self.clock.sleep_until(now + POLL_INTERVAL);
+            self.pending = Some(datagram);
+            return true;
+        }
+        self.clock.sleep_until(due);
+        out.push(datagram);
+        true
+    }
+}
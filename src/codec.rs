@@ -0,0 +1,537 @@
+//! Codecs a call can use, behind one encoder and one decoder trait.
+//!
+//! Opus is what we want to speak. G.711 μ-law and A-law are what legacy SIP gear speaks,
+//! and L16 sends samples as they are, which makes it easy to see what went over the wire.
+//! Which one a call uses is agreed during setup, from the [`CodecSet`] each side offers.
+
+use std::fmt;
+
+use clap::ValueEnum;
+use opus::{Application, Bitrate, Channels};
+
+use crate::g711::{self, G711Decoder, G711Encoder, Law};
+use crate::multistream::{ChannelMapping, MultistreamDecoder, MultistreamEncoder};
+use crate::rtp::{self, OPUS_CLOCK_RATE, OPUS_PAYLOAD_TYPE};
+use crate::srtp;
+use crate::util::{FromChannels, IntoChannels, float_into_i16};
+
+/// Dynamic payload type we use for L16. The static types 10 and 11 only cover 44.1 kHz.
+pub const L16_PAYLOAD_TYPE: u8 = 96;
+
+/// Largest payload we put in a packet: what fits in a 1500-byte MTU next to the RTP header
+/// and an SRTP tag.
+pub const MAX_PAYLOAD: usize = 1500 - rtp::HEADER_LEN - srtp::TAG_LEN;
+
+/// Frame durations Opus can put in one packet, in milliseconds.
+pub const OPUS_FRAME_MS: [u32; 4] = [10, 20, 40, 60];
+
+#[derive(Debug)]
+pub enum CodecError {
+    Opus(opus::Error),
+    /// The output buffer cannot hold the frame.
+    BufferTooSmall,
+    /// The packet is not a whole number of samples.
+    InvalidPacket,
+    /// The codec cannot put a frame this many milliseconds long in one packet.
+    FrameSize(u32),
+    /// The codec has no way to carry this many channels.
+    Channels(usize),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Opus(err) => write!(f, "{}", err),
+            CodecError::BufferTooSmall => write!(f, "buffer too small for the frame"),
+            CodecError::InvalidPacket => write!(f, "packet is not a whole number of samples"),
+            CodecError::FrameSize(frame_ms) => write!(f, "unsupported frame size {} ms", frame_ms),
+            CodecError::Channels(channels) => write!(f, "unsupported channel count {}", channels),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<opus::Error> for CodecError {
+    fn from(err: opus::Error) -> Self {
+        CodecError::Opus(err)
+    }
+}
+
+/// A codec we can encode and decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Codec {
+    Opus,
+    /// G.711 μ-law.
+    Pcmu,
+    /// G.711 A-law.
+    Pcma,
+    /// Uncompressed 16-bit big-endian samples (RFC 3551 section 4.5.11).
+    L16,
+}
+
+impl Codec {
+    /// Every codec, most preferred first: L16 is only worth its bandwidth when asked for.
+    pub const ALL: [Codec; 4] = [Codec::Opus, Codec::Pcmu, Codec::Pcma, Codec::L16];
+
+    /// Encoding name, as in an `a=rtpmap` line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Opus => "opus",
+            Codec::Pcmu => "PCMU",
+            Codec::Pcma => "PCMA",
+            Codec::L16 => "L16",
+        }
+    }
+
+    pub fn payload_type(self) -> u8 {
+        match self {
+            Codec::Opus => OPUS_PAYLOAD_TYPE,
+            Codec::Pcmu => 0,
+            Codec::Pcma => 8,
+            Codec::L16 => L16_PAYLOAD_TYPE,
+        }
+    }
+
+    pub fn from_payload_type(payload_type: u8) -> Option<Codec> {
+        Codec::ALL
+            .into_iter()
+            .find(|codec| codec.payload_type() == payload_type)
+    }
+
+    /// Sample rate and channel count the codec runs at, given the ones asked for: G.711 is
+    /// always 8 kHz mono, and only Opus carries more than two channels.
+    pub fn format(self, sample_rate: u32, channels: usize) -> (u32, usize) {
+        match self {
+            Codec::Pcmu | Codec::Pcma => (g711::SAMPLE_RATE, 1),
+            Codec::Opus => (sample_rate, channels),
+            Codec::L16 => (sample_rate, channels.min(2)),
+        }
+    }
+
+    /// Whether a frame of `frame_ms` at this format fits in [`MAX_PAYLOAD`]. L16 frames grow
+    /// with the rate and channels and soon outgrow it, the others stay well below.
+    pub fn fits(self, sample_rate: u32, channels: usize, frame_ms: u32) -> bool {
+        match self {
+            Codec::L16 => {
+                sample_rate as usize * frame_ms as usize / 1000 * channels * 2 <= MAX_PAYLOAD
+            }
+            _ => true,
+        }
+    }
+
+    /// Creates an encoder for audio at `sample_rate` with `channels`, which must be a
+    /// [`format`](Self::format) the codec runs at. Opus codes more than two channels as a
+    /// multistream, in the layout [`ChannelMapping::for_channels`] picks.
+    pub fn encoder(
+        self,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<Box<dyn AudioEncoder>, CodecError> {
+        Ok(match self {
+            Codec::Opus if channels <= 2 => Box::new(OpusEncoder::new(
+                sample_rate,
+                (channels as u16).into_channels(),
+                Application::Voip,
+            )?),
+            Codec::Opus => Box::new(MultistreamEncoder::new(
+                sample_rate,
+                ChannelMapping::for_channels(channels).ok_or(CodecError::Channels(channels))?,
+                Application::Voip,
+            )?),
+            Codec::Pcmu => Box::new(G711Encoder::new(Law::Mu)),
+            Codec::Pcma => Box::new(G711Encoder::new(Law::A)),
+            Codec::L16 => Box::new(L16Encoder::new(sample_rate, channels)),
+        })
+    }
+
+    /// Creates a decoder for audio at `sample_rate` with `channels`.
+    pub fn decoder(
+        self,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<Box<dyn AudioDecoder>, CodecError> {
+        Ok(match self {
+            Codec::Opus if channels <= 2 => Box::new(OpusDecoder::new(
+                sample_rate,
+                (channels as u16).into_channels(),
+            )?),
+            Codec::Opus => Box::new(MultistreamDecoder::new(
+                sample_rate,
+                ChannelMapping::for_channels(channels).ok_or(CodecError::Channels(channels))?,
+            )?),
+            Codec::Pcmu => Box::new(G711Decoder::new(Law::Mu)),
+            Codec::Pcma => Box::new(G711Decoder::new(Law::A)),
+            Codec::L16 => Box::new(L16Decoder::new(sample_rate, channels)),
+        })
+    }
+
+    fn bit(self) -> u8 {
+        match self {
+            Codec::Opus => 1,
+            Codec::Pcmu => 2,
+            Codec::Pcma => 4,
+            Codec::L16 => 8,
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The codecs one side supports: offered in an invite, or the one agreed on in an accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodecSet(u8);
+
+impl CodecSet {
+    pub const EMPTY: CodecSet = CodecSet(0);
+
+    pub fn all() -> Self {
+        Codec::ALL.into_iter().collect()
+    }
+
+    pub fn only(codec: Codec) -> Self {
+        CodecSet(codec.bit())
+    }
+
+    pub fn contains(self, codec: Codec) -> bool {
+        self.0 & codec.bit() != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn intersection(self, other: CodecSet) -> CodecSet {
+        CodecSet(self.0 & other.0)
+    }
+
+    /// The most preferred codec in the set.
+    pub fn preferred(self) -> Option<Codec> {
+        self.iter().next()
+    }
+
+    /// The codecs in the set, most preferred first.
+    pub fn iter(self) -> impl Iterator<Item = Codec> {
+        Codec::ALL
+            .into_iter()
+            .filter(move |codec| self.contains(*codec))
+    }
+
+    /// The set as one byte on the wire.
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Reads a set from the wire, ignoring codecs we don't know.
+    pub fn from_bits(bits: u8) -> Self {
+        CodecSet(bits & CodecSet::all().0)
+    }
+}
+
+impl Default for CodecSet {
+    fn default() -> Self {
+        CodecSet::all()
+    }
+}
+
+impl FromIterator<Codec> for CodecSet {
+    fn from_iter<I: IntoIterator<Item = Codec>>(codecs: I) -> Self {
+        CodecSet(codecs.into_iter().fold(0, |bits, codec| bits | codec.bit()))
+    }
+}
+
+/// Compresses interleaved audio one frame at a time.
+///
+/// Only [`encode`](Self::encode) does any work. The rest describe the codec, or control
+/// what it can adjust and do nothing for codecs that cannot.
+pub trait AudioEncoder: Send {
+    fn codec(&self) -> Codec;
+
+    fn sample_rate(&self) -> u32;
+
+    fn channels(&self) -> usize;
+
+    /// Rate of the RTP timestamps of the packets, which need not be the sample rate.
+    fn clock_rate(&self) -> u32 {
+        self.sample_rate()
+    }
+
+    /// Interleaved samples in a frame of `frame_ms`, or `None` if the codec cannot put
+    /// that much in one packet.
+    fn frame_len(&self, frame_ms: u32) -> Option<usize> {
+        let len = self.sample_rate() as usize * frame_ms as usize / 1000 * self.channels();
+        (len > 0).then_some(len)
+    }
+
+    /// Whether packets can carry a copy of the previous frame to recover it when lost.
+    fn supports_fec(&self) -> bool {
+        false
+    }
+
+    /// Whether the encoder shrinks frames nobody talks in to almost nothing by itself.
+    fn supports_dtx(&self) -> bool {
+        false
+    }
+
+    /// Encodes one frame of `pcm` into `out` and returns the size of the packet.
+    fn encode(&mut self, pcm: &[f32], out: &mut [u8]) -> Result<usize, CodecError>;
+
+    /// Aims for `bitrate` bits per second.
+    fn set_bitrate(&mut self, _bitrate: u32) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    /// Turns forward error correction on or off, tuned for `expected_loss` percent of
+    /// packets going missing.
+    fn set_fec(&mut self, _fec: bool, _expected_loss: u8) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    fn set_dtx(&mut self, _dtx: bool) -> Result<(), CodecError> {
+        Ok(())
+    }
+}
+
+/// Expands packets back into interleaved audio.
+pub trait AudioDecoder: Send {
+    fn codec(&self) -> Codec;
+
+    fn sample_rate(&self) -> u32;
+
+    fn channels(&self) -> usize;
+
+    /// Whether a lost packet is concealed with something better than silence.
+    fn supports_plc(&self) -> bool {
+        false
+    }
+
+    /// Whether [`decode_fec`](Self::decode_fec) can recover a lost packet.
+    fn supports_fec(&self) -> bool {
+        false
+    }
+
+    /// Decodes `packet` into `out` and returns the samples decoded per channel. An empty
+    /// packet stands for a lost one, and fills `out` with whatever the codec can conceal it
+    /// with.
+    fn decode(&mut self, packet: &[u8], out: &mut [f32]) -> Result<usize, CodecError>;
+
+    /// Recovers the frame lost just before `packet` from the copy it carries, filling all of
+    /// `out`. Codecs without FEC conceal the loss instead.
+    fn decode_fec(&mut self, _packet: &[u8], out: &mut [f32]) -> Result<usize, CodecError> {
+        self.decode(&[], out)
+    }
+}
+
+pub struct OpusEncoder {
+    encoder: opus::Encoder,
+    sample_rate: u32,
+    channels: Channels,
+}
+
+impl OpusEncoder {
+    pub fn new(
+        sample_rate: u32,
+        channels: Channels,
+        application: Application,
+    ) -> Result<Self, opus::Error> {
+        Ok(Self {
+            encoder: opus::Encoder::new(sample_rate, channels, application)?,
+            sample_rate,
+            channels,
+        })
+    }
+
+    /// The encoder itself, for settings only Opus has.
+    pub fn inner(&mut self) -> &mut opus::Encoder {
+        &mut self.encoder
+    }
+}
+
+impl AudioEncoder for OpusEncoder {
+    fn codec(&self) -> Codec {
+        Codec::Opus
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels.from_channels()
+    }
+
+    fn clock_rate(&self) -> u32 {
+        OPUS_CLOCK_RATE
+    }
+
+    fn frame_len(&self, frame_ms: u32) -> Option<usize> {
+        let len = self.sample_rate as usize * frame_ms as usize / 1000 * self.channels();
+        OPUS_FRAME_MS.contains(&frame_ms).then_some(len)
+    }
+
+    fn supports_fec(&self) -> bool {
+        true
+    }
+
+    fn supports_dtx(&self) -> bool {
+        true
+    }
+
+    fn encode(&mut self, pcm: &[f32], out: &mut [u8]) -> Result<usize, CodecError> {
+        Ok(self.encoder.encode_float(pcm, out)?)
+    }
+
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<(), CodecError> {
+        Ok(self.encoder.set_bitrate(Bitrate::Bits(bitrate as i32))?)
+    }
+
+    fn set_fec(&mut self, fec: bool, expected_loss: u8) -> Result<(), CodecError> {
+        self.encoder.set_inband_fec(fec)?;
+        Ok(self.encoder.set_packet_loss_perc(expected_loss as i32)?)
+    }
+
+    fn set_dtx(&mut self, dtx: bool) -> Result<(), CodecError> {
+        Ok(self.encoder.set_dtx(dtx)?)
+    }
+}
+
+pub struct OpusDecoder {
+    decoder: opus::Decoder,
+    sample_rate: u32,
+    channels: Channels,
+}
+
+impl OpusDecoder {
+    pub fn new(sample_rate: u32, channels: Channels) -> Result<Self, opus::Error> {
+        Ok(Self {
+            decoder: opus::Decoder::new(sample_rate, channels)?,
+            sample_rate,
+            channels,
+        })
+    }
+}
+
+impl AudioDecoder for OpusDecoder {
+    fn codec(&self) -> Codec {
+        Codec::Opus
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels.from_channels()
+    }
+
+    fn supports_plc(&self) -> bool {
+        true
+    }
+
+    fn supports_fec(&self) -> bool {
+        true
+    }
+
+    fn decode(&mut self, packet: &[u8], out: &mut [f32]) -> Result<usize, CodecError> {
+        Ok(self.decoder.decode_float(packet, out, false)?)
+    }
+
+    fn decode_fec(&mut self, packet: &[u8], out: &mut [f32]) -> Result<usize, CodecError> {
+        Ok(self.decoder.decode_float(packet, out, true)?)
+    }
+}
+
+/// Sends samples as 16-bit big-endian integers.
+#[derive(Debug, Clone)]
+pub struct L16Encoder {
+    sample_rate: u32,
+    channels: usize,
+}
+
+impl L16Encoder {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            sample_rate,
+            channels,
+        }
+    }
+}
+
+impl AudioEncoder for L16Encoder {
+    fn codec(&self) -> Codec {
+        Codec::L16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn encode(&mut self, pcm: &[f32], out: &mut [u8]) -> Result<usize, CodecError> {
+        let out = out
+            .get_mut(..pcm.len() * 2)
+            .ok_or(CodecError::BufferTooSmall)?;
+        for (bytes, sample) in out.chunks_exact_mut(2).zip(pcm) {
+            bytes.copy_from_slice(&float_into_i16(sample).to_be_bytes());
+        }
+        Ok(out.len())
+    }
+}
+
+/// Reads 16-bit big-endian samples. Lost packets are played as silence.
+#[derive(Debug, Clone)]
+pub struct L16Decoder {
+    sample_rate: u32,
+    channels: usize,
+}
+
+impl L16Decoder {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            sample_rate,
+            channels,
+        }
+    }
+}
+
+impl AudioDecoder for L16Decoder {
+    fn codec(&self) -> Codec {
+        Codec::L16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn decode(&mut self, packet: &[u8], out: &mut [f32]) -> Result<usize, CodecError> {
+        if packet.is_empty() {
+            out.fill(0.0);
+            return Ok(out.len() / self.channels);
+        }
+        if !packet.len().is_multiple_of(2 * self.channels) {
+            return Err(CodecError::InvalidPacket);
+        }
+        let out = out
+            .get_mut(..packet.len() / 2)
+            .ok_or(CodecError::BufferTooSmall)?;
+        for (sample, bytes) in out.iter_mut().zip(packet.chunks_exact(2)) {
+            *sample = i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / i16::MAX as f32;
+        }
+        Ok(out.len() / self.channels)
+    }
+}
@@ -0,0 +1,633 @@
+//! Multi-party calls through a central server.
+//!
+//! Participants join a room by inviting the server with the room number as call ID and
+//! leave with a bye, exactly as if they were calling a peer. The server then either
+//! forwards every participant's packets to the rest of the room ([`Mode::Forward`], a
+//! selective forwarding unit), or decodes all streams and sends every listener a mix of
+//! everybody but themselves ([`Mode::Mix`], a multipoint control unit).
+//!
+//! The server has to read the media in mix mode and sits between every pair of participants
+//! in forward mode, so media through it is not encrypted and invites carrying a key are
+//! rejected: a [`Call`](crate::call::Call) joins with
+//! [`CallConfig::encrypt`](crate::call::CallConfig::encrypt) off.
+//!
+//! [`Conference`] is a pure state machine like [`Session`](crate::signaling::Session);
+//! [`ConferenceServer`] runs one on a socket.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use tracing::warn;
+
+use crate::call::is_transient;
+use crate::codec::{AudioDecoder, AudioEncoder, Codec, CodecError};
+use crate::jitter::{JitterBuffer, Playout};
+use crate::logging::Stage;
+use crate::mixer::Limiter;
+use crate::rtcp::RtcpPacket;
+use crate::rtp::{self, HEADER_LEN, RtpHeader};
+use crate::signaling::{CodecParams, EndReason, Message, RejectReason, Timers};
+
+/// Largest packet we will produce or accept.
+const MAX_PACKET: usize = 1500;
+
+/// How long the server thread blocks on the socket before checking its timers.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    /// Forward each participant's packets unchanged to the rest of the room.
+    Forward,
+    /// Decode every stream and send each listener its own mix.
+    Mix,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConferenceConfig {
+    pub mode: Mode,
+    /// Limits offers are negotiated against. In mix mode, mixes are made at this sample rate
+    /// and frame size, and participants have to use the same frame size. Mixes are mono,
+    /// whatever the channels here.
+    pub params: CodecParams,
+    pub max_participants: usize,
+    /// Packets buffered per participant before they are mixed in.
+    pub jitter_depth: usize,
+    /// Participants not heard from for this long are dropped.
+    pub idle_timeout: Duration,
+}
+
+impl Default for ConferenceConfig {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Forward,
+            params: CodecParams::default(),
+            max_participants: 16,
+            jitter_depth: 3,
+            idle_timeout: Timers::default().idle_timeout,
+        }
+    }
+}
+
+/// A change in room membership.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Joined {
+        room: u32,
+        peer: SocketAddr,
+        ssrc: u32,
+    },
+    Left {
+        room: u32,
+        peer: SocketAddr,
+        reason: EndReason,
+    },
+}
+
+/// Something the owner of a [`Conference`] has to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Send { to: SocketAddr, packet: Vec<u8> },
+    Event(Event),
+}
+
+/// Decoding and encoding state for one participant in mix mode.
+struct MixState {
+    decoder: Box<dyn AudioDecoder>,
+    jitter: JitterBuffer,
+    encoder: Box<dyn AudioEncoder>,
+    /// Header of the mix sent to this participant.
+    header: RtpHeader,
+    /// The participant's latest decoded frame.
+    frame: Vec<f32>,
+    /// Keeps the mix sent to this participant from clipping.
+    limiter: Limiter,
+}
+
+impl MixState {
+    fn new(config: &ConferenceConfig, params: &CodecParams, ssrc: u32) -> Result<Self, CodecError> {
+        let sample_rate = config.params.sample_rate;
+        let codec = participant_codec(params);
+        let mut encoder = codec.encoder(sample_rate, 1)?;
+        encoder.set_bitrate(params.bitrate)?;
+        encoder.set_fec(params.fec, 0)?;
+        Ok(Self {
+            decoder: codec.decoder(sample_rate, 1)?,
+            jitter: JitterBuffer::new(config.jitter_depth),
+            encoder,
+            header: RtpHeader {
+                marker: true,
+                payload_type: codec.payload_type(),
+                sequence: 0,
+                timestamp: 0,
+                ssrc,
+            },
+            frame: vec![0.0; frame_samples(&config.params)],
+            limiter: Limiter::new(sample_rate),
+        })
+    }
+
+    /// Decodes the participant's next frame, concealing losses and filling gaps with silence.
+    fn decode(&mut self) {
+        let decoded = match self.jitter.pop() {
+            Playout::Packet(packet) => self.decoder.decode(&packet, &mut self.frame),
+            Playout::Lost => self.decoder.decode(&[], &mut self.frame),
+            Playout::Empty => Ok(0),
+        };
+        let samples = decoded.unwrap_or_else(|err| {
+            Stage::Decode
+                .span()
+                .in_scope(|| warn!(error = %err, "failed to decode packet"));
+            0
+        });
+        self.frame[samples..].fill(0.0);
+    }
+
+    /// Encodes `pcm` into an RTP packet for this participant.
+    fn encode(&mut self, pcm: &[f32], frame_ms: u8) -> Option<Vec<u8>> {
+        let mut packet = vec![0u8; MAX_PACKET];
+        let size = match self.encoder.encode(pcm, &mut packet[HEADER_LEN..]) {
+            Ok(size) => size,
+            Err(err) => {
+                Stage::Encode
+                    .span()
+                    .in_scope(|| warn!(error = %err, "failed to encode mix"));
+                return None;
+            }
+        };
+        self.header.write(&mut packet);
+        packet.truncate(HEADER_LEN + size);
+        self.header.marker = false;
+        self.header.sequence = self.header.sequence.wrapping_add(1);
+        self.header.timestamp = self
+            .header
+            .timestamp
+            .wrapping_add(self.encoder.clock_rate() * frame_ms as u32 / 1000);
+        Some(packet)
+    }
+}
+
+struct Participant {
+    ssrc: u32,
+    /// SSRC we answered with. In mix mode it identifies the mix sent to this participant.
+    server_ssrc: u32,
+    params: CodecParams,
+    last_heard: Instant,
+    mix: Option<MixState>,
+}
+
+/// Rooms and their participants.
+pub struct Conference {
+    config: ConferenceConfig,
+    rooms: HashMap<u32, HashMap<SocketAddr, Participant>>,
+    /// The room each address is in.
+    members: HashMap<SocketAddr, u32>,
+    next_mix: Option<Instant>,
+}
+
+impl Conference {
+    pub fn new(config: ConferenceConfig) -> Self {
+        Self {
+            config,
+            rooms: HashMap::new(),
+            members: HashMap::new(),
+            next_mix: None,
+        }
+    }
+
+    pub fn config(&self) -> &ConferenceConfig {
+        &self.config
+    }
+
+    /// Rooms with at least one participant.
+    pub fn rooms(&self) -> Vec<u32> {
+        let mut rooms: Vec<u32> = self.rooms.keys().copied().collect();
+        rooms.sort_unstable();
+        rooms
+    }
+
+    /// Addresses of everybody in `room`.
+    pub fn participants(&self, room: u32) -> Vec<SocketAddr> {
+        let mut participants: Vec<SocketAddr> = self
+            .rooms
+            .get(&room)
+            .map(|room| room.keys().copied().collect())
+            .unwrap_or_default();
+        participants.sort_unstable();
+        participants
+    }
+
+    /// Handles a packet from `from`: signaling, RTCP or media.
+    pub fn receive(&mut self, packet: &[u8], from: SocketAddr, now: Instant) -> Vec<Action> {
+        if Message::is_signaling(packet) {
+            return match Message::decode(packet) {
+                Some(message) => self.signal(message, from, now),
+                None => Vec::new(),
+            };
+        }
+        let Some(&room) = self.members.get(&from) else {
+            return Vec::new();
+        };
+        let participants = self.rooms.get_mut(&room).expect("member of a missing room");
+        let sender = participants
+            .get_mut(&from)
+            .expect("member missing from room");
+        sender.last_heard = now;
+
+        if RtcpPacket::is_rtcp(packet) {
+            // Reports describe the streams between two participants, which only exist when
+            // forwarding. In mix mode every stream ends at the server.
+            return match self.config.mode {
+                Mode::Forward => forward(participants, from, packet),
+                Mode::Mix => Vec::new(),
+            };
+        }
+        let Some((header, payload)) = RtpHeader::parse(packet) else {
+            return Vec::new();
+        };
+        if header.payload_type != participant_codec(&sender.params).payload_type()
+            || header.ssrc != sender.ssrc
+        {
+            return Vec::new();
+        }
+        match &mut sender.mix {
+            Some(mix) => {
+                mix.jitter.push(header.sequence, payload.to_vec());
+                Vec::new()
+            }
+            None => forward(participants, from, packet),
+        }
+    }
+
+    /// Drops silent participants and, in mix mode, sends every mix that is due.
+    pub fn tick(&mut self, now: Instant) -> Vec<Action> {
+        let idle_timeout = self.config.idle_timeout;
+        let silent: Vec<SocketAddr> = self
+            .rooms
+            .values()
+            .flat_map(|room| room.iter())
+            .filter(|(_, participant)| {
+                now.saturating_duration_since(participant.last_heard) >= idle_timeout
+            })
+            .map(|(&peer, _)| peer)
+            .collect();
+        let mut actions = Vec::new();
+        for peer in silent {
+            actions.extend(self.leave(peer, EndReason::Timeout, true));
+        }
+
+        if self.config.mode == Mode::Mix {
+            let frame = Duration::from_millis(self.config.params.frame_ms as u64);
+            let next_mix = self.next_mix.get_or_insert(now + frame);
+            while now >= *next_mix {
+                *next_mix += frame;
+                for participants in self.rooms.values_mut() {
+                    actions.extend(mix(participants, self.config.params.frame_ms));
+                }
+            }
+            if self.rooms.is_empty() {
+                self.next_mix = None;
+            }
+        }
+        actions
+    }
+
+    /// Says goodbye to everybody.
+    pub fn shutdown(&mut self) -> Vec<Action> {
+        let everybody: Vec<SocketAddr> = self.members.keys().copied().collect();
+        everybody
+            .into_iter()
+            .flat_map(|peer| self.leave(peer, EndReason::Hangup, true))
+            .collect()
+    }
+
+    fn signal(&mut self, message: Message, from: SocketAddr, now: Instant) -> Vec<Action> {
+        let current = self.members.get(&from).copied();
+        if let Some(room) = current
+            && room == message.call_id()
+            && let Some(participant) = self
+                .rooms
+                .get_mut(&room)
+                .and_then(|room| room.get_mut(&from))
+        {
+            participant.last_heard = now;
+        }
+
+        match message {
+            Message::Invite {
+                call_id: room,
+                ssrc,
+                params,
+                public_key,
+            } => match current {
+                None => self.join(room, from, ssrc, &params, public_key.is_some(), now),
+                Some(current) if current == room => {
+                    // Our accept was lost; the participant is retransmitting its invite.
+                    let participant = &self.rooms[&room][&from];
+                    vec![send(from, accept(room, participant))]
+                }
+                Some(_) => reject(from, room, RejectReason::Busy),
+            },
+            Message::Bye { call_id } if current == Some(call_id) => {
+                self.leave(from, EndReason::RemoteHangup, false)
+            }
+            Message::Keepalive { call_id } if current == Some(call_id) => {
+                vec![send(from, Message::Keepalive { call_id })]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn join(
+        &mut self,
+        room: u32,
+        peer: SocketAddr,
+        ssrc: u32,
+        offer: &CodecParams,
+        encrypted: bool,
+        now: Instant,
+    ) -> Vec<Action> {
+        let participants = self.rooms.get(&room);
+        if participants.is_some_and(|room| room.len() >= self.config.max_participants) {
+            return reject(peer, room, RejectReason::Busy);
+        }
+        // Forwarded packets are played as they are, so a room sticks to the codec, format and
+        // frame size of whoever joined it first.
+        let first = participants
+            .and_then(|room| room.values().next())
+            .filter(|_| self.config.mode == Mode::Forward)
+            .map(|first| first.params);
+        let limits = match first {
+            Some(first) => CodecParams {
+                bitrate: self.config.params.bitrate,
+                fec: self.config.params.fec,
+                ..first
+            },
+            None => match self.config.mode {
+                Mode::Forward => self.config.params,
+                Mode::Mix => CodecParams {
+                    channels: 1,
+                    ..self.config.params
+                },
+            },
+        };
+        let params = match limits.negotiate(offer) {
+            Ok(params) => params,
+            Err(reason) => return reject(peer, room, reason),
+        };
+        let format = |params: &CodecParams| {
+            (
+                params.codecs,
+                params.sample_rate,
+                params.channels,
+                params.frame_ms,
+            )
+        };
+        let forward_mismatch = first.is_some_and(|first| format(&first) != format(&params));
+        // Listeners tell forwarded streams apart by SSRC, and mixes have a fixed frame size
+        // and sample rate.
+        let ssrc_taken = participants
+            .is_some_and(|room| room.values().any(|participant| participant.ssrc == ssrc));
+        let mix_rate = self.config.params.sample_rate;
+        let mix_mismatch = self.config.mode == Mode::Mix
+            && (params.frame_ms != self.config.params.frame_ms
+                || participant_codec(&params).format(mix_rate, 1).0 != mix_rate);
+        if encrypted || ssrc_taken || mix_mismatch || forward_mismatch {
+            return reject(peer, room, RejectReason::Incompatible);
+        }
+
+        let server_ssrc = rtp::random_ssrc();
+        let mix = match self.config.mode {
+            Mode::Forward => None,
+            Mode::Mix => match MixState::new(&self.config, &params, server_ssrc) {
+                Ok(mix) => Some(mix),
+                Err(err) => {
+                    warn!(error = %err, %peer, "failed to set up mixing");
+                    return reject(peer, room, RejectReason::Incompatible);
+                }
+            },
+        };
+        let participant = Participant {
+            ssrc,
+            server_ssrc,
+            params,
+            last_heard: now,
+            mix,
+        };
+        let message = accept(room, &participant);
+        self.rooms
+            .entry(room)
+            .or_default()
+            .insert(peer, participant);
+        self.members.insert(peer, room);
+        vec![
+            send(peer, message),
+            Action::Event(Event::Joined { room, peer, ssrc }),
+        ]
+    }
+
+    /// Removes `peer` from its room, telling it so if `bye` is set.
+    fn leave(&mut self, peer: SocketAddr, reason: EndReason, bye: bool) -> Vec<Action> {
+        let Some(room) = self.members.remove(&peer) else {
+            return Vec::new();
+        };
+        if let Some(participants) = self.rooms.get_mut(&room) {
+            participants.remove(&peer);
+            if participants.is_empty() {
+                self.rooms.remove(&room);
+            }
+        }
+        let mut actions = Vec::new();
+        if bye {
+            actions.push(send(peer, Message::Bye { call_id: room }));
+        }
+        actions.push(Action::Event(Event::Left { room, peer, reason }));
+        actions
+    }
+}
+
+/// Sends `packet` from `from` to everybody else in the room.
+fn forward(
+    participants: &HashMap<SocketAddr, Participant>,
+    from: SocketAddr,
+    packet: &[u8],
+) -> Vec<Action> {
+    participants
+        .keys()
+        .filter(|&&peer| peer != from)
+        .map(|&to| Action::Send {
+            to,
+            packet: packet.to_vec(),
+        })
+        .collect()
+}
+
+/// Decodes one frame from everybody in a room and sends each of them the rest of the room.
+fn mix(participants: &mut HashMap<SocketAddr, Participant>, frame_ms: u8) -> Vec<Action> {
+    let mut total: Vec<f32> = Vec::new();
+    for mix in participants.values_mut().filter_map(|p| p.mix.as_mut()) {
+        mix.decode();
+        total.resize(mix.frame.len(), 0.0);
+        for (sum, sample) in total.iter_mut().zip(&mix.frame) {
+            *sum += sample;
+        }
+    }
+    // Somebody alone in a room would only get silence.
+    if participants.len() < 2 {
+        return Vec::new();
+    }
+
+    let mut actions = Vec::new();
+    let mut pcm = vec![0f32; total.len()];
+    for (&to, participant) in participants.iter_mut() {
+        let Some(mix) = &mut participant.mix else {
+            continue;
+        };
+        for ((out, sum), own) in pcm.iter_mut().zip(&total).zip(&mix.frame) {
+            *out = sum - own;
+        }
+        mix.limiter.process(&mut pcm, 1);
+        if let Some(packet) = mix.encode(&pcm, frame_ms) {
+            actions.push(Action::Send { to, packet });
+        }
+    }
+    actions
+}
+
+fn accept(room: u32, participant: &Participant) -> Message {
+    Message::Accept {
+        call_id: room,
+        ssrc: participant.server_ssrc,
+        params: participant.params,
+        public_key: None,
+    }
+}
+
+fn reject(peer: SocketAddr, room: u32, reason: RejectReason) -> Vec<Action> {
+    vec![send(
+        peer,
+        Message::Reject {
+            call_id: room,
+            reason,
+        },
+    )]
+}
+
+fn send(to: SocketAddr, message: Message) -> Action {
+    Action::Send {
+        to,
+        packet: message.encode(),
+    }
+}
+
+/// The codec agreed on with a participant.
+fn participant_codec(params: &CodecParams) -> Codec {
+    params.codec().unwrap_or(Codec::Opus)
+}
+
+/// Samples in one mono frame at the conference sample rate.
+fn frame_samples(params: &CodecParams) -> usize {
+    params.sample_rate as usize * params.frame_ms as usize / 1000
+}
+
+/// A [`Conference`] served on a UDP socket by a background thread.
+pub struct ConferenceServer {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    local_addr: SocketAddr,
+    events: mpsc::Receiver<Event>,
+}
+
+impl ConferenceServer {
+    pub fn start(socket: UdpSocket, config: ConferenceConfig) -> io::Result<ConferenceServer> {
+        let local_addr = socket.local_addr()?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let running = Arc::new(AtomicBool::new(true));
+        let (events_tx, events) = mpsc::channel();
+
+        let thread_running = Arc::clone(&running);
+        let conference = Conference::new(config);
+        let thread = thread::Builder::new()
+            .name("conference".into())
+            .spawn(move || {
+                Stage::Network
+                    .span()
+                    .in_scope(|| serve(socket, conference, events_tx, &thread_running))
+            })?;
+
+        Ok(ConferenceServer {
+            running,
+            thread: Some(thread),
+            local_addr,
+            events,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Waits up to `timeout` for somebody to join or leave.
+    pub fn next_event(&self, timeout: Duration) -> Option<Event> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Hangs up on every participant and waits for the server thread to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ConferenceServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn serve(
+    socket: UdpSocket,
+    mut conference: Conference,
+    events: mpsc::Sender<Event>,
+    running: &AtomicBool,
+) {
+    let perform = |actions: Vec<Action>| {
+        for action in actions {
+            match action {
+                Action::Send { to, packet } => {
+                    if let Err(err) = socket.send_to(&packet, to)
+                        && !is_transient(&err)
+                    {
+                        warn!(error = %err, %to, "failed to send packet");
+                    }
+                }
+                // Nobody may be listening for events.
+                Action::Event(event) => {
+                    let _ = events.send(event);
+                }
+            }
+        }
+    };
+
+    let mut packet = [0u8; MAX_PACKET];
+    while running.load(Ordering::Relaxed) {
+        match socket.recv_from(&mut packet) {
+            Ok((size, from)) => perform(conference.receive(&packet[..size], from, Instant::now())),
+            Err(err) if is_transient(&err) => {}
+            Err(err) => warn!(error = %err, "failed to receive packet"),
+        }
+        perform(conference.tick(Instant::now()));
+    }
+    perform(conference.shutdown());
+}
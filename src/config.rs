@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use serde::Deserialize;
+
+/// Name of the config file section shared by every binary.
+pub const COMMON_SECTION: &str = "network";
+/// Name of the config file section holding the main window's key bindings.
+pub const KEYS_SECTION: &str = "keys";
+
+/// Network options shared by the UDP binaries.
+#[derive(Debug, Clone, Default, Args)]
+pub struct NetArgs {
+    /// Local address to bind the UDP socket to, e.g. `0.0.0.0:5000` or `[::]:5000`.
+    #[arg(long, value_name = "ADDR")]
+    pub bind: Option<String>,
+
+    /// Remote address to send audio to. Host names are resolved.
+    #[arg(long, value_name = "ADDR")]
+    pub peer: Option<String>,
+
+    /// Multicast group to join on the bound socket.
+    #[arg(long, value_name = "GROUP")]
+    pub multicast: Option<IpAddr>,
+
+    /// Interface used for multicast: an IPv4 address, or an interface index for IPv6.
+    #[arg(long, value_name = "IFACE")]
+    pub interface: Option<String>,
+
+    /// Use IPv6 defaults for the bind and peer addresses.
+    #[arg(short = '6', long)]
+    pub ipv6: bool,
+
+    /// Extra config file layered over the user config file.
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+}
+
+/// Per-binary defaults used when neither the config files nor the command line set a value.
+#[derive(Debug, Clone, Copy)]
+pub struct NetDefaults {
+    pub bind_port: u16,
+    pub peer_port: Option<u16>,
+}
+
+/// One section of the config file. Every key is optional so sections can be layered, but
+/// misspelt ones are refused rather than ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileSection {
+    pub bind: Option<String>,
+    pub peer: Option<String>,
+    pub multicast: Option<IpAddr>,
+    pub interface: Option<String>,
+}
+
+/// A parsed config file: a `[network]` section plus optional per-binary sections. The
+/// `[keys]` section is left to [`KeyBindings`].
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    pub sections: BTreeMap<String, FileSection>,
+}
+
+/// Keys for the main window's talk controls, each a single keystroke in gpui's syntax
+/// such as `m` or `ctrl-shift-m`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    pub toggle_mute: String,
+    pub toggle_deafen: String,
+    /// Held to talk while in push-to-talk mode.
+    pub push_to_talk: String,
+    /// Switches between an open microphone and push-to-talk.
+    pub toggle_push_to_talk: String,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            toggle_mute: "m".into(),
+            toggle_deafen: "d".into(),
+            push_to_talk: "space".into(),
+            toggle_push_to_talk: "t".into(),
+        }
+    }
+}
+
+/// Fully resolved and validated network configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetConfig {
+    pub bind: SocketAddr,
+    pub peer: Option<SocketAddr>,
+    pub multicast: Option<Multicast>,
+}
+
+/// A multicast group together with the interface to join it on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multicast {
+    V4 {
+        group: Ipv4Addr,
+        interface: Ipv4Addr,
+    },
+    V6 {
+        group: Ipv6Addr,
+        interface: u32,
+    },
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    InvalidAddress(String),
+    MissingPeer,
+    UnspecifiedPeer(SocketAddr),
+    PeerIsSelf(SocketAddr),
+    FamilyMismatch { bind: SocketAddr, peer: SocketAddr },
+    NotMulticast(IpAddr),
+    MulticastFamilyMismatch { bind: SocketAddr, group: IpAddr },
+    InvalidInterface(String),
+    InterfaceWithoutMulticast,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => {
+                write!(f, "invalid config {}: {}", path.display(), err)
+            }
+            ConfigError::InvalidAddress(addr) => write!(f, "invalid address '{}'", addr),
+            ConfigError::MissingPeer => write!(f, "no peer address given (use --peer)"),
+            ConfigError::UnspecifiedPeer(addr) => {
+                write!(f, "peer address {} is not a routable destination", addr)
+            }
+            ConfigError::PeerIsSelf(addr) => {
+                write!(f, "peer address {} is the local bind address", addr)
+            }
+            ConfigError::FamilyMismatch { bind, peer } => write!(
+                f,
+                "bind address {} and peer address {} use different IP versions",
+                bind, peer
+            ),
+            ConfigError::NotMulticast(addr) => write!(f, "{} is not a multicast address", addr),
+            ConfigError::MulticastFamilyMismatch { bind, group } => write!(
+                f,
+                "cannot join multicast group {} on a socket bound to {}",
+                group, bind
+            ),
+            ConfigError::InvalidInterface(iface) => write!(f, "invalid interface '{}'", iface),
+            ConfigError::InterfaceWithoutMulticast => {
+                write!(
+                    f,
+                    "--interface is only meaningful together with --multicast"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(_, err) => Some(err),
+            ConfigError::Parse(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Reads and parses a config file.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.into(), err))?;
+        Self::parse(&text).map_err(|err| ConfigError::Parse(path.into(), err))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        let mut table: toml::Table = toml::from_str(text)?;
+        table.remove(KEYS_SECTION);
+        Ok(Self {
+            sections: toml::Value::Table(table).try_into()?,
+        })
+    }
+
+    /// Returns the `[network]` section overlaid with the section named after `binary`.
+    pub fn section(&self, binary: &str) -> FileSection {
+        let mut merged = FileSection::default();
+        for name in [COMMON_SECTION, binary] {
+            if let Some(section) = self.sections.get(name) {
+                merged.overlay(section);
+            }
+        }
+        merged
+    }
+}
+
+impl FileSection {
+    fn overlay(&mut self, other: &FileSection) {
+        if other.bind.is_some() {
+            self.bind = other.bind.clone();
+        }
+        if other.peer.is_some() {
+            self.peer = other.peer.clone();
+        }
+        if other.multicast.is_some() {
+            self.multicast = other.multicast;
+        }
+        if other.interface.is_some() {
+            self.interface = other.interface.clone();
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Reads the `[keys]` section of the user config file. Keys it leaves out keep their
+    /// defaults.
+    pub fn load() -> Result<Self, ConfigError> {
+        match user_config_path().filter(|path| path.exists()) {
+            Some(path) => {
+                let text =
+                    fs::read_to_string(&path).map_err(|err| ConfigError::Io(path.clone(), err))?;
+                Self::parse(&text).map_err(|err| ConfigError::Parse(path, err))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Picks the `[keys]` section out of a whole config file.
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        let mut table: toml::Table = toml::from_str(text)?;
+        match table.remove(KEYS_SECTION) {
+            Some(keys) => keys.try_into(),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+/// Location of the per-user config file, if a home directory is known.
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("test-gpui").join("config.toml"))
+}
+
+impl NetArgs {
+    /// Layers defaults, the user config file, `--config` and the command line flags, then
+    /// validates the result.
+    pub fn resolve(&self, binary: &str, defaults: NetDefaults) -> Result<NetConfig, ConfigError> {
+        let mut layered = FileSection::default();
+        if let Some(path) = user_config_path().filter(|path| path.exists()) {
+            layered.overlay(&ConfigFile::load(&path)?.section(binary));
+        }
+        if let Some(path) = &self.config {
+            layered.overlay(&ConfigFile::load(path)?.section(binary));
+        }
+        self.resolve_with(layered, defaults)
+    }
+
+    /// Like [`NetArgs::resolve`], but uses `file` instead of reading config files from disk.
+    pub fn resolve_with(
+        &self,
+        mut file: FileSection,
+        defaults: NetDefaults,
+    ) -> Result<NetConfig, ConfigError> {
+        file.overlay(&FileSection {
+            bind: self.bind.clone(),
+            peer: self.peer.clone(),
+            multicast: self.multicast,
+            interface: self.interface.clone(),
+        });
+
+        let unspecified: IpAddr = if self.ipv6 {
+            Ipv6Addr::UNSPECIFIED.into()
+        } else {
+            Ipv4Addr::UNSPECIFIED.into()
+        };
+        let loopback: IpAddr = if self.ipv6 {
+            Ipv6Addr::LOCALHOST.into()
+        } else {
+            Ipv4Addr::LOCALHOST.into()
+        };
+
+        let bind = match &file.bind {
+            Some(addr) => parse_bind(addr)?,
+            None => SocketAddr::new(unspecified, defaults.bind_port),
+        };
+        let peer = match (&file.peer, defaults.peer_port) {
+            (Some(addr), _) => Some(resolve_peer(addr, bind)?),
+            (None, Some(port)) => Some(SocketAddr::new(loopback, port)),
+            (None, None) => None,
+        };
+        let multicast = match file.multicast {
+            Some(group) => Some(multicast(group, file.interface.as_deref(), bind)?),
+            None if file.interface.is_some() => return Err(ConfigError::InterfaceWithoutMulticast),
+            None => None,
+        };
+
+        let config = NetConfig {
+            bind,
+            peer,
+            multicast,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl NetConfig {
+    /// Rejects combinations that would bind or send somewhere nonsensical.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(peer) = self.peer {
+            if peer.ip().is_unspecified() || peer.port() == 0 {
+                return Err(ConfigError::UnspecifiedPeer(peer));
+            }
+            if peer.is_ipv4() != self.bind.is_ipv4() {
+                return Err(ConfigError::FamilyMismatch {
+                    bind: self.bind,
+                    peer,
+                });
+            }
+            if peer == self.bind || (peer.ip().is_loopback() && peer.port() == self.bind.port()) {
+                return Err(ConfigError::PeerIsSelf(peer));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the peer address or an error for binaries that must send somewhere.
+    pub fn require_peer(&self) -> Result<SocketAddr, ConfigError> {
+        self.peer.ok_or(ConfigError::MissingPeer)
+    }
+}
+
+fn parse_bind(addr: &str) -> Result<SocketAddr, ConfigError> {
+    addr.parse()
+        .map_err(|_| ConfigError::InvalidAddress(addr.to_string()))
+}
+
+fn resolve_peer(addr: &str, bind: SocketAddr) -> Result<SocketAddr, ConfigError> {
+    if let Ok(addr) = addr.parse() {
+        return Ok(addr);
+    }
+    let candidates: Vec<SocketAddr> = addr
+        .to_socket_addrs()
+        .map_err(|_| ConfigError::InvalidAddress(addr.to_string()))?
+        .collect();
+    // Prefer an address of the same family as the socket so the result is usable.
+    candidates
+        .iter()
+        .find(|candidate| candidate.is_ipv4() == bind.is_ipv4())
+        .or(candidates.first())
+        .copied()
+        .ok_or_else(|| ConfigError::InvalidAddress(addr.to_string()))
+}
+
+fn multicast(
+    group: IpAddr,
+    interface: Option<&str>,
+    bind: SocketAddr,
+) -> Result<Multicast, ConfigError> {
+    if !group.is_multicast() {
+        return Err(ConfigError::NotMulticast(group));
+    }
+    let invalid = |iface: &str| ConfigError::InvalidInterface(iface.to_string());
+    match (group, bind) {
+        (IpAddr::V4(group), SocketAddr::V4(_)) => {
+            let interface = match interface {
+                Some(iface) => iface.parse().map_err(|_| invalid(iface))?,
+                None => Ipv4Addr::UNSPECIFIED,
+            };
+            Ok(Multicast::V4 { group, interface })
+        }
+        (IpAddr::V6(group), SocketAddr::V6(_)) => {
+            let interface = match interface {
+                Some(iface) => iface.parse().map_err(|_| invalid(iface))?,
+                None => 0,
+            };
+            Ok(Multicast::V6 { group, interface })
+        }
+        (group, bind) => Err(ConfigError::MulticastFamilyMismatch { bind, group }),
+    }
+}
@@ -0,0 +1,126 @@
+//! Opening audio devices, with failures reported as [`Error`]s instead of panics.
+//!
+//! Streams opened here always deal in f32 samples. Devices that only offer 16-bit formats
+//! are converted to and from in the callback, so callers never need a case per format.
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{
+    Device, FromSample, Host, InputCallbackInfo, OutputCallbackInfo, Sample, SampleFormat,
+    SizedSample, Stream, StreamConfig, StreamError, SupportedStreamConfig,
+};
+
+use crate::error::{Direction, Error, Result};
+
+/// The host's default input device and its preferred config.
+pub fn default_input(host: &Host) -> Result<(Device, SupportedStreamConfig)> {
+    let device = host
+        .default_input_device()
+        .ok_or(Error::NoDevice(Direction::Input))?;
+    let config = device.default_input_config()?;
+    Ok((device, config))
+}
+
+/// The host's default output device and its preferred config.
+pub fn default_output(host: &Host) -> Result<(Device, SupportedStreamConfig)> {
+    let device = host
+        .default_output_device()
+        .ok_or(Error::NoDevice(Direction::Output))?;
+    let config = device.default_output_config()?;
+    Ok((device, config))
+}
+
+/// Opens an input stream that hands `callback` the captured interleaved samples as f32,
+/// whichever of f32, i16 or u16 the device delivers as `format`. The stream still has to be
+/// played.
+pub fn open_input(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    mut callback: impl FnMut(&[f32]) + Send + 'static,
+    on_error: impl FnMut(StreamError) + Send + 'static,
+) -> Result<Stream> {
+    let stream = match format {
+        SampleFormat::F32 => device.build_input_stream(
+            config,
+            move |data: &[f32], _: &InputCallbackInfo| callback(data),
+            on_error,
+            None,
+        ),
+        SampleFormat::I16 => open_converted_input::<i16>(device, config, callback, on_error),
+        SampleFormat::U16 => open_converted_input::<u16>(device, config, callback, on_error),
+        format => return Err(Error::UnsupportedFormat(format)),
+    };
+    Ok(stream?)
+}
+
+/// Opens an output stream that lets `callback` fill interleaved f32 samples, converted to
+/// whichever of f32, i16 or u16 the device takes as `format`. The stream still has to be
+/// played.
+pub fn open_output(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    mut callback: impl FnMut(&mut [f32]) + Send + 'static,
+    on_error: impl FnMut(StreamError) + Send + 'static,
+) -> Result<Stream> {
+    let stream = match format {
+        SampleFormat::F32 => device.build_output_stream(
+            config,
+            move |data: &mut [f32], _: &OutputCallbackInfo| callback(data),
+            on_error,
+            None,
+        ),
+        SampleFormat::I16 => open_converted_output::<i16>(device, config, callback, on_error),
+        SampleFormat::U16 => open_converted_output::<u16>(device, config, callback, on_error),
+        format => return Err(Error::UnsupportedFormat(format)),
+    };
+    Ok(stream?)
+}
+
+fn open_converted_input<T>(
+    device: &Device,
+    config: &StreamConfig,
+    mut callback: impl FnMut(&[f32]) + Send + 'static,
+    on_error: impl FnMut(StreamError) + Send + 'static,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let mut converted = Vec::new();
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &InputCallbackInfo| {
+            converted.clear();
+            converted.extend(data.iter().map(|&sample| f32::from_sample(sample)));
+            callback(&converted);
+        },
+        on_error,
+        None,
+    )
+}
+
+fn open_converted_output<T>(
+    device: &Device,
+    config: &StreamConfig,
+    mut callback: impl FnMut(&mut [f32]) + Send + 'static,
+    on_error: impl FnMut(StreamError) + Send + 'static,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let mut samples = Vec::new();
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &OutputCallbackInfo| {
+            samples.clear();
+            samples.resize(data.len(), 0.0);
+            callback(&mut samples);
+            for (out, &sample) in data.iter_mut().zip(&samples) {
+                *out = T::from_sample(sample);
+            }
+        },
+        on_error,
+        None,
+    )
+}
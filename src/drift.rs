@@ -0,0 +1,233 @@
+//! Keeping audio flowing between two devices whose clocks disagree.
+//!
+//! A microphone and a speaker both nominally at 48 kHz are each driven by a crystal of their
+//! own, and rarely tick at exactly the same rate: a buffer between them slowly fills up or
+//! runs dry. A [`DriftCompensator`] reads such a buffer through a [`Resampler`] whose ratio
+//! a [`DriftEstimator`] nudges, by a thousandth at most, to hold the buffer at a constant
+//! fill and so the latency constant.
+
+use std::time::Duration;
+
+use crate::audio::{AudioSource, SharedBuffer};
+
+/// Largest correction applied to the rate, in parts per million. Real devices drift by
+/// tens of ppm; the pitch change at the limit is under two cents.
+pub const MAX_CORRECTION_PPM: f64 = 1000.0;
+/// How quickly the buffer is brought back to its target. Slower hides the correction
+/// better, faster lets the buffer wander less.
+const RESPONSE_TIME: f64 = 10.0;
+/// How long the fill level is averaged over, so bursty device callbacks don't show.
+const SMOOTHING_TIME: f64 = 1.0;
+
+/// Estimates the drift between a producer and a consumer from how full the buffer between
+/// them is, and works out the rate at which the consumer should read to correct it.
+///
+/// Time is counted in frames read, which is to say by the consumer's clock.
+#[derive(Debug, Clone)]
+pub struct DriftEstimator {
+    sample_rate: f64,
+    target: f64,
+    /// Smoothed fill level, in seconds.
+    fill: Option<f64>,
+    /// Drift between the two clocks, learnt from how the fill level kept moving.
+    drift: f64,
+    ratio: f64,
+}
+
+impl DriftEstimator {
+    /// Holds the buffer at `target` frames.
+    pub fn new(sample_rate: u32, target: usize) -> Self {
+        Self {
+            sample_rate: sample_rate as f64,
+            target: target as f64 / sample_rate as f64,
+            fill: None,
+            drift: 0.0,
+            ratio: 1.0,
+        }
+    }
+
+    /// Updates the estimate with the buffer holding `fill` frames, `elapsed` frames after
+    /// the last update, and returns the new ratio.
+    pub fn update(&mut self, fill: usize, elapsed: usize) -> f64 {
+        let measured = fill as f64 / self.sample_rate;
+        let elapsed = elapsed as f64 / self.sample_rate;
+        let fill = match self.fill {
+            Some(smoothed) => {
+                smoothed + (measured - smoothed) * elapsed / (SMOOTHING_TIME + elapsed)
+            }
+            None => measured,
+        };
+        self.fill = Some(fill);
+
+        // A critically damped PI controller: the integral settles on the drift itself.
+        let error = fill - self.target;
+        let limit = MAX_CORRECTION_PPM * 1e-6;
+        self.drift =
+            (self.drift + error * elapsed / (RESPONSE_TIME * RESPONSE_TIME)).clamp(-limit, limit);
+        let correction = (self.drift + 2.0 * error / RESPONSE_TIME).clamp(-limit, limit);
+        self.ratio = 1.0 + correction;
+        self.ratio
+    }
+
+    /// Input frames to read per output frame: above 1 when the producer runs fast.
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// How much faster the producer's clock runs than the consumer's, in ppm.
+    pub fn drift_ppm(&self) -> f64 {
+        self.drift * 1e6
+    }
+
+    /// Forgets the fill level, as after the buffer ran dry, but not the drift learnt.
+    pub fn reset(&mut self) {
+        self.fill = None;
+    }
+}
+
+/// Resamples interleaved audio by a ratio that can change from one read to the next, with
+/// cubic interpolation.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    channels: usize,
+    ratio: f64,
+    /// Input read from the source but not used up yet, from one frame before `position`.
+    input: Vec<f32>,
+    /// Where the next output frame falls in `input`, in frames.
+    position: f64,
+}
+
+impl Resampler {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            channels,
+            ratio: 1.0,
+            // One frame of silence before the first, which interpolation looks back at.
+            input: vec![0.0; channels],
+            position: 1.0,
+        }
+    }
+
+    /// Sets how many input frames each output frame advances by.
+    pub fn set_ratio(&mut self, ratio: f64) {
+        self.ratio = ratio;
+    }
+
+    /// Input frames held that have not been played yet.
+    pub fn buffered(&self) -> usize {
+        (self.input.len() / self.channels).saturating_sub(self.position as usize)
+    }
+
+    /// Fills `out` with audio read from `source`, and returns how many samples were written:
+    /// fewer than asked when the source runs short.
+    pub fn read(&mut self, source: &mut impl AudioSource, out: &mut [f32]) -> usize {
+        let channels = self.channels;
+        let wanted = out.len() / channels;
+        if wanted == 0 {
+            return 0;
+        }
+        // Each output frame looks at two input frames past its position.
+        let last = self.position + (wanted - 1) as f64 * self.ratio;
+        let needed = (last as usize + 3) * channels;
+        if needed > self.input.len() {
+            let start = self.input.len();
+            self.input.resize(needed, 0.0);
+            let read = source.read(&mut self.input[start..]);
+            self.input.truncate(start + read);
+        }
+
+        let available = self.input.len() / channels;
+        let mut produced = 0;
+        while produced < wanted {
+            let index = self.position as usize;
+            if index + 2 >= available {
+                break;
+            }
+            let t = (self.position - index as f64) as f32;
+            for channel in 0..channels {
+                let sample = |frame: usize| self.input[frame * channels + channel];
+                out[produced * channels + channel] = hermite(
+                    sample(index - 1),
+                    sample(index),
+                    sample(index + 1),
+                    sample(index + 2),
+                    t,
+                );
+            }
+            self.position += self.ratio;
+            produced += 1;
+        }
+
+        let used = (self.position as usize - 1).min(available);
+        self.input.drain(..used * channels);
+        self.position -= used as f64;
+        produced * channels
+    }
+}
+
+/// Catmull-Rom interpolation between `y1` and `y2`, `t` of the way.
+fn hermite(y0: f32, y1: f32, y2: f32, y3: f32, t: f32) -> f32 {
+    let c1 = 0.5 * (y2 - y0);
+    let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+    let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+    ((c3 * t + c2) * t + c1) * t + y1
+}
+
+/// Reads a buffer filled by another device's clock, resampled to hold it at a constant
+/// fill. Stands in for the buffer wherever it is read: in an output callback, or in a
+/// [`PacedSource`](crate::pipeline::PacedSource).
+///
+/// Nothing is read until the buffer first reaches its target, or again after it ran dry,
+/// so playback starts with the latency it is meant to keep.
+pub struct DriftCompensator {
+    buffer: SharedBuffer,
+    channels: usize,
+    target: usize,
+    estimator: DriftEstimator,
+    resampler: Resampler,
+    primed: bool,
+}
+
+impl DriftCompensator {
+    /// Reads `buffer`, holding `target` of audio in it.
+    pub fn new(buffer: SharedBuffer, sample_rate: u32, channels: usize, target: Duration) -> Self {
+        let target = (target.as_secs_f64() * sample_rate as f64) as usize;
+        Self {
+            buffer,
+            channels,
+            target,
+            estimator: DriftEstimator::new(sample_rate, target),
+            resampler: Resampler::new(channels),
+            primed: false,
+        }
+    }
+
+    pub fn estimator(&self) -> &DriftEstimator {
+        &self.estimator
+    }
+
+    /// Frames waiting to be played, in the buffer and in the resampler.
+    pub fn fill(&self) -> usize {
+        self.buffer.len() / self.channels + self.resampler.buffered()
+    }
+}
+
+impl AudioSource for DriftCompensator {
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        let fill = self.fill();
+        if !self.primed {
+            if fill < self.target {
+                return 0;
+            }
+            self.primed = true;
+            self.estimator.reset();
+        }
+        let ratio = self.estimator.update(fill, buf.len() / self.channels);
+        self.resampler.set_ratio(ratio);
+        let read = self.resampler.read(&mut self.buffer, buf);
+        if read < buf.len() {
+            self.primed = false;
+        }
+        read
+    }
+}
@@ -0,0 +1,369 @@
+//! Discontinuous transmission (DTX) and comfort noise.
+//!
+//! A sender that stops sending while nobody talks saves most of its bandwidth, but a
+//! receiver that then plays nothing sounds as if the call dropped. Opus does the sending half
+//! itself once DTX is on. For codecs that can't, a [`Dtx`] tells speech from background and
+//! sends an RFC 3389 [`NoiseDescription`] of the background now and then. On the receiving
+//! side, [`ComfortNoise`] plays noise of the same level and spectrum through the gaps. It
+//! learns that noise from such descriptions, or from the audio heard before the gap.
+
+use std::time::Duration;
+
+/// Opus marks frames not worth sending under DTX by shrinking them to this many bytes or
+/// fewer.
+pub const OPUS_DTX_PACKET_LEN: usize = 2;
+/// Spectral detail in a noise description. RFC 3389 allows any order; speech codecs use ten.
+pub const ORDER: usize = 10;
+/// How often a silent sender refreshes the receiver's description of the background, as
+/// often as Opus does.
+pub const SID_INTERVAL: Duration = Duration::from_millis(400);
+/// Quietest level a description can carry, in dBov.
+const MIN_LEVEL: f32 = -127.0;
+/// Frames quieter than this are never speech, however quiet the background, in dBov.
+const SILENCE_LEVEL: f32 = -70.0;
+/// How far above the background a frame must be to count as speech, in dB.
+const SPEECH_MARGIN: f32 = 9.0;
+/// How long speech is assumed to go on after the last loud frame, so the ends of words
+/// and short pauses are still sent.
+const HANGOVER: Duration = Duration::from_millis(200);
+/// How fast the background estimate rises while the signal stays above it, in dB per
+/// second, so a fan switched on becomes background in a few seconds. It falls at once.
+const FLOOR_RISE: f32 = 3.0;
+/// How long descriptions learnt from the audio itself are averaged over.
+const LEARN_TIME: f32 = 0.5;
+
+/// Level of `samples` in dBov: 0 for a full-scale sine, down to -127 for silence.
+pub fn level_dbov(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return MIN_LEVEL;
+    }
+    let power = samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32;
+    // A full-scale sine has a power of one half.
+    (10.0 * (2.0 * power).log10()).clamp(MIN_LEVEL, 0.0)
+}
+
+/// Level and spectral shape of background noise, as an RFC 3389 comfort noise payload
+/// carries them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoiseDescription {
+    /// Level in dBov.
+    pub level: f32,
+    /// Reflection coefficients of the all-pole filter shaping the noise, in -1..1. None for
+    /// white noise.
+    pub reflection: Vec<f32>,
+}
+
+impl NoiseDescription {
+    /// Describes the noise in interleaved `samples`, with [`ORDER`] coefficients.
+    pub fn analyze(samples: &[f32], channels: usize) -> Self {
+        let mono: Vec<f32> = samples
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect();
+        let correlation: Vec<f32> = (0..=ORDER.min(mono.len().saturating_sub(1)))
+            .map(|lag| {
+                mono.iter()
+                    .zip(&mono[lag..])
+                    .map(|(a, b)| a * b)
+                    .sum::<f32>()
+            })
+            .collect();
+        Self {
+            level: level_dbov(samples),
+            reflection: reflection_coefficients(&correlation),
+        }
+    }
+
+    /// Encodes the payload: the level in -dBov, then each coefficient quantized uniformly
+    /// over -1..1 to 0..=254.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![(-self.level).round().clamp(0.0, 127.0) as u8];
+        payload.extend(
+            self.reflection
+                .iter()
+                .map(|k| (127.0 + k * 127.0).round().clamp(0.0, 254.0) as u8),
+        );
+        payload
+    }
+
+    /// Parses a payload. Returns `None` when it is empty.
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let (&level, coefficients) = payload.split_first()?;
+        Some(Self {
+            level: -((level & 0x7f) as f32),
+            reflection: coefficients
+                .iter()
+                .map(|&k| ((k as f32 - 127.0) / 127.0).clamp(-1.0, 1.0))
+                .collect(),
+        })
+    }
+
+    /// Moves `weight` of the way towards `other`.
+    fn blend(&mut self, other: &NoiseDescription, weight: f32) {
+        self.level += (other.level - self.level) * weight;
+        self.reflection
+            .resize(self.reflection.len().max(other.reflection.len()), 0.0);
+        for (index, k) in self.reflection.iter_mut().enumerate() {
+            let target = other.reflection.get(index).copied().unwrap_or_default();
+            *k += (target - *k) * weight;
+        }
+    }
+}
+
+/// Levinson-Durbin recursion from an autocorrelation to reflection coefficients, kept a
+/// little inside -1..1 so the synthesis filter stays stable after quantization.
+fn reflection_coefficients(correlation: &[f32]) -> Vec<f32> {
+    let Some(&energy) = correlation.first() else {
+        return Vec::new();
+    };
+    if energy <= f32::EPSILON {
+        return Vec::new();
+    }
+    // A touch of white noise keeps nearly pure tones well conditioned.
+    let mut error = energy * 1.0001;
+    let mut predictor = vec![0.0f32; correlation.len()];
+    let mut reflection = Vec::with_capacity(correlation.len() - 1);
+    for order in 1..correlation.len() {
+        let mut acc = correlation[order];
+        for lag in 1..order {
+            acc += predictor[lag] * correlation[order - lag];
+        }
+        let k = (-acc / error).clamp(-0.99, 0.99);
+        let previous = predictor.clone();
+        for lag in 1..order {
+            predictor[lag] = previous[lag] + k * previous[order - lag];
+        }
+        predictor[order] = k;
+        error *= 1.0 - k * k;
+        reflection.push(k);
+    }
+    reflection
+}
+
+/// Tells speech from background by how far a frame rises above the quietest level heard
+/// recently.
+#[derive(Debug, Clone)]
+pub struct VoiceDetector {
+    sample_rate: u32,
+    channels: usize,
+    /// Background level in dBov.
+    floor: Option<f32>,
+    /// Frames left of the hangover after the last loud frame.
+    hangover: usize,
+}
+
+impl VoiceDetector {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            floor: None,
+            hangover: 0,
+        }
+    }
+
+    /// Background level in dBov, once anything was heard.
+    pub fn floor(&self) -> Option<f32> {
+        self.floor
+    }
+
+    /// Returns whether interleaved `samples` hold speech, and updates the background level.
+    pub fn process(&mut self, samples: &[f32]) -> bool {
+        let frames = samples.len() / self.channels;
+        let seconds = frames as f32 / self.sample_rate as f32;
+        let level = level_dbov(samples);
+        let floor = match self.floor {
+            Some(floor) if level > floor => (floor + FLOOR_RISE * seconds).min(level),
+            _ => level,
+        };
+        self.floor = Some(floor);
+
+        if level > floor + SPEECH_MARGIN && level > SILENCE_LEVEL {
+            self.hangover = (HANGOVER.as_secs_f32() * self.sample_rate as f32) as usize;
+            return true;
+        }
+        let talking = self.hangover > 0;
+        self.hangover = self.hangover.saturating_sub(frames);
+        talking
+    }
+}
+
+/// What a sender does with one frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transmit {
+    /// Encode and send it.
+    Speech,
+    /// Send this description of the background in a comfort noise packet instead.
+    Noise(NoiseDescription),
+    /// Send nothing.
+    Nothing,
+}
+
+/// Decides frame by frame what a sender without native DTX sends: speech as usual, and
+/// while nobody talks a description of the background when silence starts and every
+/// [`SID_INTERVAL`] after.
+#[derive(Debug, Clone)]
+pub struct Dtx {
+    detector: VoiceDetector,
+    sample_rate: u32,
+    channels: usize,
+    /// Background averaged over the silence so far.
+    background: Option<NoiseDescription>,
+    /// Frames since the background was last sent, none while talking.
+    since_sent: Option<usize>,
+}
+
+impl Dtx {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            detector: VoiceDetector::new(sample_rate, channels),
+            sample_rate,
+            channels,
+            background: None,
+            since_sent: None,
+        }
+    }
+
+    /// Whether the last frame was speech.
+    pub fn talking(&self) -> bool {
+        self.since_sent.is_none()
+    }
+
+    /// Decides what to send for one frame of interleaved `samples`.
+    pub fn process(&mut self, samples: &[f32]) -> Transmit {
+        if self.detector.process(samples) {
+            self.since_sent = None;
+            return Transmit::Speech;
+        }
+        let frames = samples.len() / self.channels;
+        let heard = NoiseDescription::analyze(samples, self.channels);
+        learn(&mut self.background, heard, frames, self.sample_rate);
+        let interval = (SID_INTERVAL.as_secs_f32() * self.sample_rate as f32) as usize;
+        match self.since_sent {
+            Some(since) if since + frames < interval => {
+                self.since_sent = Some(since + frames);
+                Transmit::Nothing
+            }
+            _ => {
+                self.since_sent = Some(0);
+                Transmit::Noise(self.background.clone().unwrap_or(NoiseDescription {
+                    level: MIN_LEVEL,
+                    reflection: Vec::new(),
+                }))
+            }
+        }
+    }
+}
+
+/// Averages `heard`, lasting `frames`, into `description`.
+fn learn(
+    description: &mut Option<NoiseDescription>,
+    heard: NoiseDescription,
+    frames: usize,
+    sample_rate: u32,
+) {
+    let seconds = frames as f32 / sample_rate as f32;
+    match description {
+        Some(description) => description.blend(&heard, seconds / (LEARN_TIME + seconds)),
+        None => *description = Some(heard),
+    }
+}
+
+/// Plays noise like the sender's background through the gaps in what it sends.
+///
+/// The noise is learnt from descriptions the sender sends, or for Opus, which sends none,
+/// from the quiet parts of what was heard. Until either is known it plays silence.
+#[derive(Debug, Clone)]
+pub struct ComfortNoise {
+    sample_rate: u32,
+    channels: usize,
+    detector: VoiceDetector,
+    description: Option<NoiseDescription>,
+    /// Scale of the white noise driving the filter, for the described level.
+    gain: f32,
+    /// Backward errors of the lattice filter, one per coefficient.
+    state: Vec<f32>,
+    seed: u32,
+}
+
+impl ComfortNoise {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            detector: VoiceDetector::new(sample_rate, channels),
+            description: None,
+            gain: 0.0,
+            state: Vec::new(),
+            seed: 1,
+        }
+    }
+
+    /// The noise being played, once known.
+    pub fn description(&self) -> Option<&NoiseDescription> {
+        self.description.as_ref()
+    }
+
+    /// Plays the noise a sender described from now on.
+    pub fn update(&mut self, description: NoiseDescription) {
+        self.description = Some(description);
+        self.configure();
+    }
+
+    /// Learns the background from interleaved `samples` just played, ignoring speech.
+    pub fn learn(&mut self, samples: &[f32]) {
+        if samples.is_empty() || self.detector.process(samples) {
+            return;
+        }
+        let heard = NoiseDescription::analyze(samples, self.channels);
+        learn(
+            &mut self.description,
+            heard,
+            samples.len() / self.channels,
+            self.sample_rate,
+        );
+        self.configure();
+    }
+
+    /// Fills interleaved `out` with comfort noise, the same in every channel.
+    pub fn generate(&mut self, out: &mut [f32]) {
+        let Some(description) = &self.description else {
+            out.fill(0.0);
+            return;
+        };
+        let reflection = &description.reflection;
+        for frame in out.chunks_mut(self.channels) {
+            // Uniform white noise with unit variance.
+            self.seed = self
+                .seed
+                .wrapping_mul(1_664_525)
+                .wrapping_add(1_013_904_223);
+            let white = ((self.seed >> 8) as f32 / (1 << 23) as f32 - 1.0) * 3f32.sqrt();
+
+            // All-pole lattice, run from the last coefficient down to the first.
+            let mut forward = white * self.gain;
+            for index in (0..reflection.len()).rev() {
+                forward -= reflection[index] * self.state[index];
+                if index + 1 < self.state.len() {
+                    self.state[index + 1] = self.state[index] + reflection[index] * forward;
+                }
+            }
+            if let Some(first) = self.state.first_mut() {
+                *first = forward;
+            }
+            frame.fill(forward);
+        }
+    }
+
+    /// Works out the filter gain that gives the described level.
+    fn configure(&mut self) {
+        let Some(description) = &self.description else {
+            return;
+        };
+        let rms = 10f32.powf(description.level / 20.0) / 2f32.sqrt();
+        // The filter amplifies white noise by the inverse of its prediction gain.
+        let prediction_error: f32 = description.reflection.iter().map(|k| 1.0 - k * k).product();
+        self.gain = rms * prediction_error.sqrt();
+        self.state.resize(description.reflection.len(), 0.0);
+    }
+}
@@ -0,0 +1,180 @@
+//! The error type of the library's device, codec and network operations.
+//!
+//! Narrower error types such as [`CallError`] or [`ConfigError`] convert into [`Error`], so
+//! an application can handle everything through one type and show it to the user instead of
+//! panicking. [`Context`] says what was being done when something failed.
+
+use std::fmt;
+use std::io;
+
+use crate::call::CallError;
+use crate::codec::CodecError;
+use crate::config::ConfigError;
+use crate::sdp::SdpError;
+use crate::srtp::CryptoError;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Which way audio flows through a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Input => write!(f, "input"),
+            Direction::Output => write!(f, "output"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The host has no default device for this direction.
+    NoDevice(Direction),
+    /// The device only offers a sample format we cannot convert.
+    UnsupportedFormat(cpal::SampleFormat),
+    DeviceConfig(cpal::DefaultStreamConfigError),
+    SupportedConfigs(cpal::SupportedStreamConfigsError),
+    BuildStream(cpal::BuildStreamError),
+    PlayStream(cpal::PlayStreamError),
+    Codec(CodecError),
+    Io(io::Error),
+    Config(ConfigError),
+    Sdp(SdpError),
+    Crypto(CryptoError),
+    Call(CallError),
+    /// `source` happened while doing what the message says.
+    Context(String, Box<Error>),
+}
+
+impl Error {
+    /// The innermost error, without any context.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context(_, source) => source.root(),
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoDevice(direction) => write!(f, "no default {} device", direction),
+            Error::UnsupportedFormat(format) => {
+                write!(f, "unsupported sample format '{}'", format)
+            }
+            Error::DeviceConfig(err) => write!(f, "no usable device config: {}", err),
+            Error::SupportedConfigs(err) => write!(f, "failed to query device configs: {}", err),
+            Error::BuildStream(err) => write!(f, "failed to open audio stream: {}", err),
+            Error::PlayStream(err) => write!(f, "failed to start audio stream: {}", err),
+            Error::Codec(err) => write!(f, "codec error: {}", err),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Config(err) => write!(f, "{}", err),
+            Error::Sdp(err) => write!(f, "invalid session description: {}", err),
+            Error::Crypto(err) => write!(f, "{}", err),
+            Error::Call(err) => write!(f, "{}", err),
+            Error::Context(context, source) => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NoDevice(_) | Error::UnsupportedFormat(_) => None,
+            Error::DeviceConfig(err) => Some(err),
+            Error::SupportedConfigs(err) => Some(err),
+            Error::BuildStream(err) => Some(err),
+            Error::PlayStream(err) => Some(err),
+            Error::Codec(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::Config(err) => Some(err),
+            Error::Sdp(err) => Some(err),
+            Error::Crypto(err) => Some(err),
+            Error::Call(err) => Some(err),
+            Error::Context(_, source) => Some(&**source),
+        }
+    }
+}
+
+impl From<cpal::DefaultStreamConfigError> for Error {
+    fn from(err: cpal::DefaultStreamConfigError) -> Self {
+        Error::DeviceConfig(err)
+    }
+}
+
+impl From<cpal::SupportedStreamConfigsError> for Error {
+    fn from(err: cpal::SupportedStreamConfigsError) -> Self {
+        Error::SupportedConfigs(err)
+    }
+}
+
+impl From<cpal::BuildStreamError> for Error {
+    fn from(err: cpal::BuildStreamError) -> Self {
+        Error::BuildStream(err)
+    }
+}
+
+impl From<cpal::PlayStreamError> for Error {
+    fn from(err: cpal::PlayStreamError) -> Self {
+        Error::PlayStream(err)
+    }
+}
+
+impl From<CodecError> for Error {
+    fn from(err: CodecError) -> Self {
+        Error::Codec(err)
+    }
+}
+
+impl From<opus::Error> for Error {
+    fn from(err: opus::Error) -> Self {
+        Error::Codec(CodecError::Opus(err))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err)
+    }
+}
+
+impl From<SdpError> for Error {
+    fn from(err: SdpError) -> Self {
+        Error::Sdp(err)
+    }
+}
+
+impl From<CryptoError> for Error {
+    fn from(err: CryptoError) -> Self {
+        Error::Crypto(err)
+    }
+}
+
+impl From<CallError> for Error {
+    fn from(err: CallError) -> Self {
+        Error::Call(err)
+    }
+}
+
+/// Adds what was being done to the error of a failed operation.
+pub trait Context<T> {
+    fn context(self, context: impl Into<String>) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.map_err(|err| Error::Context(context.into(), Box::new(err.into())))
+    }
+}
@@ -0,0 +1,206 @@
+//! G.711 μ-law and A-law (ITU-T G.711), the codecs every telephone speaks.
+//!
+//! Each sample is companded on its own to eight bits, so there is no state and no
+//! look-ahead. The conversions follow the ITU-T G.191 reference implementation bit for bit:
+//! μ-law works on the top 14 bits of a 16-bit sample and A-law on the top 13.
+
+use crate::codec::{AudioDecoder, AudioEncoder, Codec, CodecError};
+use crate::util::float_into_i16;
+
+/// G.711 samples at 8 kHz, in mono.
+pub const SAMPLE_RATE: u32 = 8_000;
+
+/// Added to μ-law magnitudes so every segment starts at a power of two.
+const ULAW_BIAS: i32 = 33;
+
+/// Compresses a sample to μ-law.
+pub fn ulaw_encode(sample: i16) -> u8 {
+    let sample = sample as i32;
+    // Ones' complement for negative samples, as the reference does.
+    let magnitude = if sample < 0 {
+        !sample >> 2
+    } else {
+        sample >> 2
+    };
+    let magnitude = (magnitude + ULAW_BIAS).min(0x1fff);
+
+    let mut segment = 1;
+    let mut rest = magnitude >> 6;
+    while rest != 0 {
+        segment += 1;
+        rest >>= 1;
+    }
+    let high = 8 - segment;
+    let low = 0x0f - ((magnitude >> segment) & 0x0f);
+    let code = (high << 4) | low;
+    if sample >= 0 {
+        (code | 0x80) as u8
+    } else {
+        code as u8
+    }
+}
+
+/// Expands a μ-law code to a 16-bit sample.
+pub fn ulaw_decode(code: u8) -> i16 {
+    let inverted = !code;
+    let exponent = ((inverted >> 4) & 0x07) as i32;
+    let mantissa = (inverted & 0x0f) as i32;
+    let step = 4 << (exponent + 1);
+    let magnitude = (0x80 << exponent) + step * mantissa + step / 2 - 4 * ULAW_BIAS;
+    if code < 0x80 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+/// Compresses a sample to A-law.
+pub fn alaw_encode(sample: i16) -> u8 {
+    let sample = sample as i32;
+    let mut magnitude = if sample < 0 {
+        !sample >> 4
+    } else {
+        sample >> 4
+    };
+    if magnitude > 15 {
+        let mut exponent = 1;
+        while magnitude > 16 + 15 {
+            magnitude >>= 1;
+            exponent += 1;
+        }
+        magnitude -= 16;
+        magnitude += exponent << 4;
+    }
+    if sample >= 0 {
+        magnitude |= 0x80;
+    }
+    // Every other bit is inverted on the line.
+    (magnitude ^ 0x55) as u8
+}
+
+/// Expands an A-law code to a 16-bit sample.
+pub fn alaw_decode(code: u8) -> i16 {
+    let code = code ^ 0x55;
+    let exponent = ((code & 0x7f) >> 4) as i32;
+    let mut mantissa = (code & 0x0f) as i32;
+    if exponent > 0 {
+        mantissa += 16;
+    }
+    mantissa = (mantissa << 4) + 0x08;
+    if exponent > 1 {
+        mantissa <<= exponent - 1;
+    }
+    if code & 0x80 != 0 {
+        mantissa as i16
+    } else {
+        -mantissa as i16
+    }
+}
+
+/// Which companding law a G.711 codec uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Law {
+    /// μ-law, as in North America and Japan.
+    Mu,
+    /// A-law, as everywhere else.
+    A,
+}
+
+impl Law {
+    fn codec(self) -> Codec {
+        match self {
+            Law::Mu => Codec::Pcmu,
+            Law::A => Codec::Pcma,
+        }
+    }
+
+    fn encode(self, sample: i16) -> u8 {
+        match self {
+            Law::Mu => ulaw_encode(sample),
+            Law::A => alaw_encode(sample),
+        }
+    }
+
+    fn decode(self, code: u8) -> i16 {
+        match self {
+            Law::Mu => ulaw_decode(code),
+            Law::A => alaw_decode(code),
+        }
+    }
+}
+
+/// Encodes 8 kHz mono audio to G.711, one byte per sample.
+#[derive(Debug, Clone)]
+pub struct G711Encoder {
+    law: Law,
+}
+
+impl G711Encoder {
+    pub fn new(law: Law) -> Self {
+        Self { law }
+    }
+}
+
+impl AudioEncoder for G711Encoder {
+    fn codec(&self) -> Codec {
+        self.law.codec()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn channels(&self) -> usize {
+        1
+    }
+
+    fn encode(&mut self, pcm: &[f32], out: &mut [u8]) -> Result<usize, CodecError> {
+        let out = out.get_mut(..pcm.len()).ok_or(CodecError::BufferTooSmall)?;
+        for (code, sample) in out.iter_mut().zip(pcm) {
+            *code = self.law.encode(float_into_i16(sample));
+        }
+        Ok(pcm.len())
+    }
+}
+
+/// Decodes G.711 to 8 kHz mono audio.
+///
+/// Lost packets are played as silence: G.711 has nothing to conceal them with.
+#[derive(Debug, Clone)]
+pub struct G711Decoder {
+    law: Law,
+}
+
+impl G711Decoder {
+    pub fn new(law: Law) -> Self {
+        Self { law }
+    }
+}
+
+impl AudioDecoder for G711Decoder {
+    fn codec(&self) -> Codec {
+        self.law.codec()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn channels(&self) -> usize {
+        1
+    }
+
+    fn decode(&mut self, packet: &[u8], out: &mut [f32]) -> Result<usize, CodecError> {
+        if packet.is_empty() {
+            out.fill(0.0);
+            return Ok(out.len());
+        }
+        let out = out
+            .get_mut(..packet.len())
+            .ok_or(CodecError::BufferTooSmall)?;
+        for (sample, &code) in out.iter_mut().zip(packet) {
+            *sample = self.law.decode(code) as f32 / i16::MAX as f32;
+        }
+        Ok(packet.len())
+    }
+}
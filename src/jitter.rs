@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+/// What the jitter buffer has for the next playout slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Playout {
+    /// The packet due for this slot.
+    Packet(Vec<u8>),
+    /// The packet due for this slot never arrived; conceal it.
+    Lost,
+    /// Still buffering, play silence.
+    Empty,
+}
+
+/// Reorders packets by RTP sequence number and releases them one slot at a time.
+///
+/// Playout starts once `depth` packets are buffered and stops again on underrun so the
+/// buffer can refill.
+pub struct JitterBuffer {
+    packets: BTreeMap<u64, Vec<u8>>,
+    depth: usize,
+    capacity: usize,
+    next: Option<u64>,
+    highest: Option<u64>,
+    playing: bool,
+}
+
+impl JitterBuffer {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            packets: BTreeMap::new(),
+            depth: depth.max(1),
+            capacity: depth.max(1) * 4,
+            next: None,
+            highest: None,
+            playing: false,
+        }
+    }
+
+    /// Number of packets currently buffered.
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Inserts a packet. Packets older than the playout position are dropped.
+    pub fn push(&mut self, sequence: u16, payload: Vec<u8>) {
+        let index = self.extend(sequence);
+        if self.next.is_some_and(|next| index < next) {
+            return;
+        }
+        self.highest = Some(self.highest.map_or(index, |highest| highest.max(index)));
+        self.packets.insert(index, payload);
+
+        while self.packets.len() > self.capacity {
+            if let Some((oldest, _)) = self.packets.pop_first() {
+                self.next = Some(oldest + 1);
+            }
+        }
+    }
+
+    /// Returns the packet for the next playout slot.
+    pub fn pop(&mut self) -> Playout {
+        if !self.playing {
+            if self.packets.len() < self.depth {
+                return Playout::Empty;
+            }
+            self.playing = true;
+            if self.next.is_none() {
+                self.next = self.packets.keys().next().copied();
+            }
+        }
+        if self.packets.is_empty() {
+            self.playing = false;
+            return Playout::Empty;
+        }
+
+        let next = self.next.unwrap_or_default();
+        self.next = Some(next + 1);
+        match self.packets.remove(&next) {
+            Some(packet) => Playout::Packet(packet),
+            None => Playout::Lost,
+        }
+    }
+
+    /// Unwraps a 16-bit sequence number relative to the highest one seen so far.
+    fn extend(&self, sequence: u16) -> u64 {
+        let Some(reference) = self.highest else {
+            return (1 << 32) + sequence as u64;
+        };
+        let delta = sequence.wrapping_sub(reference as u16) as i16;
+        reference.wrapping_add_signed(delta as i64)
+    }
+}
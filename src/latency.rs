@@ -0,0 +1,420 @@
+//! Measuring how long audio takes to get from one point of the path to another.
+//!
+//! An [`Injector`] mixes a short chirp into the audio where it enters, once per interval,
+//! and notes when. [`Detector`]s further along find the chirp again by cross-correlation
+//! and note when it passed them. A [`LatencyProbe`] matches the two up, so every stage's
+//! delay and the total show in one [`LatencyReport`].
+//!
+//! Both work on blocks of samples with the time they pass, so they go equally in a device
+//! callback, as pipeline [`Transform`]s, or in an [`Offline`](crate::offline::Offline) run
+//! on a [`VirtualClock`](crate::clock::VirtualClock).
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+use crate::pipeline::{Frame, Transform};
+
+/// How often a marker is sent when not told otherwise. Latencies must be shorter than the
+/// interval to be matched to the right marker.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+const MARKER_DURATION: Duration = Duration::from_millis(30);
+/// The chirp stays in the band every codec and speaker passes.
+const MARKER_LOW_HZ: f32 = 300.0;
+const MARKER_HIGH_HZ: f32 = 3400.0;
+const MARKER_AMPLITUDE: f32 = 0.5;
+/// Markers are looked for at about this rate, which is plenty for their band and keeps the
+/// correlation cheap.
+const DETECTION_RATE: u32 = 16_000;
+/// Normalized correlation above which the marker is taken to be there.
+const THRESHOLD: f32 = 0.6;
+/// Injections remembered for matching, more than any sane latency spans.
+const HISTORY: usize = 16;
+
+/// A linear chirp across the voice band at `sample_rate`, faded in and out.
+pub fn marker(sample_rate: u32) -> Vec<f32> {
+    let len = (MARKER_DURATION.as_secs_f64() * sample_rate as f64) as usize;
+    let duration = MARKER_DURATION.as_secs_f32();
+    let sweep = (MARKER_HIGH_HZ - MARKER_LOW_HZ) / duration;
+    (0..len)
+        .map(|n| {
+            let t = n as f32 / sample_rate as f32;
+            let phase = 2.0 * PI * (MARKER_LOW_HZ * t + 0.5 * sweep * t * t);
+            let fade = 0.5 - 0.5 * (2.0 * PI * n as f32 / len as f32).cos();
+            MARKER_AMPLITUDE * fade * phase.sin()
+        })
+        .collect()
+}
+
+/// Where markers are sent from and found, and what was measured between them. Clones share
+/// the measurements, so injectors and detectors can live on different threads.
+#[derive(Clone)]
+pub struct LatencyProbe {
+    interval: Duration,
+    measurements: Arc<Mutex<Measurements>>,
+}
+
+#[derive(Default)]
+struct Measurements {
+    injected: VecDeque<Instant>,
+    stages: Vec<StageLatency>,
+}
+
+impl LatencyProbe {
+    /// Sends a marker every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            measurements: Arc::default(),
+        }
+    }
+
+    /// Sends markers into audio at `sample_rate`, on `clock`'s time.
+    pub fn injector<C: Clock>(&self, sample_rate: u32, clock: C) -> Injector<C> {
+        Injector {
+            probe: self.clone(),
+            clock,
+            sample_rate,
+            marker: marker(sample_rate),
+            interval: (self.interval.as_secs_f64() * sample_rate as f64) as u64,
+            position: 0,
+            next: 0,
+        }
+    }
+
+    /// Finds markers in audio at `sample_rate` as they pass `stage`, on `clock`'s time.
+    /// Stages are reported in the order their detectors were made.
+    pub fn detector<C: Clock>(
+        &self,
+        stage: impl Into<String>,
+        sample_rate: u32,
+        clock: C,
+    ) -> Detector<C> {
+        let stage = stage.into();
+        let mut measurements = self.measurements.lock().unwrap();
+        let index = match measurements.stages.iter().position(|s| s.name == stage) {
+            Some(index) => index,
+            None => {
+                measurements.stages.push(StageLatency::new(stage));
+                measurements.stages.len() - 1
+            }
+        };
+        let decimation = (sample_rate / DETECTION_RATE).max(1) as usize;
+        let template = marker(sample_rate / decimation as u32);
+        let energy = template.iter().map(|sample| sample * sample).sum::<f32>();
+        Detector {
+            probe: self.clone(),
+            stage: index,
+            clock,
+            sample_rate,
+            decimation,
+            template_norm: energy.sqrt(),
+            window: VecDeque::from(vec![0.0; template.len()]),
+            template,
+            accumulated: 0.0,
+            accumulated_frames: 0,
+            decimated: 0,
+            position: 0,
+            blocks: VecDeque::new(),
+            peak: None,
+            quiet_until: 0,
+        }
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            stages: self.measurements.lock().unwrap().stages.clone(),
+        }
+    }
+
+    fn injected(&self, at: Instant) {
+        let mut measurements = self.measurements.lock().unwrap();
+        measurements.injected.push_back(at);
+        if measurements.injected.len() > HISTORY {
+            measurements.injected.pop_front();
+        }
+    }
+
+    fn detected(&self, stage: usize, at: Instant) {
+        let mut measurements = self.measurements.lock().unwrap();
+        let sent = measurements
+            .injected
+            .iter()
+            .rev()
+            .find(|&&sent| sent <= at)
+            .copied();
+        // Nothing sent yet: a marker left over from before, or something that sounds like one.
+        if let Some(sent) = sent {
+            measurements.stages[stage].record(at - sent);
+        }
+    }
+}
+
+impl Default for LatencyProbe {
+    fn default() -> Self {
+        Self::new(DEFAULT_INTERVAL)
+    }
+}
+
+/// Mixes a marker into the audio passing through it every interval.
+pub struct Injector<C> {
+    probe: LatencyProbe,
+    clock: C,
+    sample_rate: u32,
+    marker: Vec<f32>,
+    /// Frames between markers.
+    interval: u64,
+    /// Frames passed so far.
+    position: u64,
+    /// Where the next marker starts.
+    next: u64,
+}
+
+impl<C: Clock> Injector<C> {
+    /// Adds markers to the interleaved `samples`, on every channel, taking them to pass now.
+    pub fn process(&mut self, samples: &mut [f32], channels: usize) {
+        let now = self.clock.now();
+        let frames = (samples.len() / channels) as u64;
+        let start = self.position;
+        // A marker that began in an earlier block carries on into this one.
+        let mut marker_start = self.next.saturating_sub(self.interval);
+        if marker_start + self.marker.len() as u64 <= start {
+            marker_start = self.next;
+        }
+        while marker_start < start + frames {
+            if marker_start == self.next {
+                let offset = marker_start.saturating_sub(start);
+                self.probe
+                    .injected(now + frames_to_time(offset, self.sample_rate));
+                self.next += self.interval;
+            }
+            for (frame, sample) in self.marker.iter().enumerate() {
+                let Some(at) = (marker_start + frame as u64).checked_sub(start) else {
+                    continue;
+                };
+                if at >= frames {
+                    break;
+                }
+                for value in &mut samples[at as usize * channels..][..channels] {
+                    *value += sample;
+                }
+            }
+            marker_start = self.next;
+        }
+        self.position += frames;
+    }
+}
+
+impl<C: Clock> Transform for Injector<C> {
+    type Input = Frame;
+    type Output = Frame;
+
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) {
+        Injector::process(self, &mut frame.samples, frame.channels);
+        out.push(frame);
+    }
+}
+
+/// Finds markers in the audio passing through it and reports when each one did.
+pub struct Detector<C> {
+    probe: LatencyProbe,
+    stage: usize,
+    clock: C,
+    sample_rate: u32,
+    /// Frames averaged into each sample correlated.
+    decimation: usize,
+    template: Vec<f32>,
+    template_norm: f32,
+    /// The latest samples at the detection rate, as long as the template.
+    window: VecDeque<f32>,
+    accumulated: f32,
+    accumulated_frames: usize,
+    /// Samples at the detection rate so far.
+    decimated: u64,
+    /// Frames passed so far.
+    position: u64,
+    /// Where recent blocks began, in frames, and when they passed.
+    blocks: VecDeque<(u64, Instant)>,
+    /// The best match so far of a marker being found: its correlation and where it starts,
+    /// in detection samples.
+    peak: Option<(f32, u64)>,
+    /// No new marker is looked for before this detection sample.
+    quiet_until: u64,
+}
+
+impl<C: Clock> Detector<C> {
+    /// Looks for markers in the interleaved `samples`, taking them to pass now.
+    pub fn process(&mut self, samples: &[f32], channels: usize) {
+        let now = self.clock.now();
+        self.blocks.push_back((self.position, now));
+        for frame in samples.chunks_exact(channels) {
+            self.accumulated += frame.iter().sum::<f32>() / channels as f32;
+            self.accumulated_frames += 1;
+            if self.accumulated_frames == self.decimation {
+                let sample = self.accumulated / self.decimation as f32;
+                self.accumulated = 0.0;
+                self.accumulated_frames = 0;
+                self.correlate(sample);
+            }
+        }
+        self.position += (samples.len() / channels) as u64;
+
+        // Blocks are kept as far back as a marker being found could have started.
+        let horizon = (2 * self.template.len() * self.decimation) as u64;
+        while self.blocks.len() > 1 && self.blocks[1].0 + horizon < self.position {
+            self.blocks.pop_front();
+        }
+    }
+
+    fn correlate(&mut self, sample: f32) {
+        self.window.pop_front();
+        self.window.push_back(sample);
+        self.decimated += 1;
+        let start = self.decimated.saturating_sub(self.template.len() as u64);
+
+        let (front, back) = self.window.as_slices();
+        let (template_front, template_back) = self.template.split_at(front.len());
+        let dot = dot(front, template_front) + dot(back, template_back);
+        let energy = dot_self(front) + dot_self(back);
+        let correlation = match energy > 0.0 {
+            true => dot / (energy.sqrt() * self.template_norm),
+            false => 0.0,
+        };
+
+        match self.peak {
+            // The correlation peaks where the marker lines up; a marker's length past the
+            // first good match, the best one is it.
+            Some((best, at)) => {
+                if correlation > best {
+                    self.peak = Some((correlation, start));
+                } else if start >= at + self.template.len() as u64 {
+                    self.peak = None;
+                    self.quiet_until = at + 2 * self.template.len() as u64;
+                    self.found(at);
+                }
+            }
+            None if correlation >= THRESHOLD && start >= self.quiet_until => {
+                self.peak = Some((correlation, start));
+            }
+            None => {}
+        }
+    }
+
+    /// Reports a marker starting at detection sample `at`.
+    fn found(&mut self, at: u64) {
+        let frame = at * self.decimation as u64;
+        let Some(&(block_start, passed)) =
+            self.blocks.iter().rev().find(|(start, _)| *start <= frame)
+        else {
+            return;
+        };
+        let at = passed + frames_to_time(frame - block_start, self.sample_rate);
+        self.probe.detected(self.stage, at);
+    }
+}
+
+impl<C: Clock> Transform for Detector<C> {
+    type Input = Frame;
+    type Output = Frame;
+
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) {
+        Detector::process(self, &frame.samples, frame.channels);
+        out.push(frame);
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn dot_self(a: &[f32]) -> f32 {
+    dot(a, a)
+}
+
+fn frames_to_time(frames: u64, sample_rate: u32) -> Duration {
+    Duration::from_secs_f64(frames as f64 / sample_rate as f64)
+}
+
+/// How long markers took to reach one stage, from when they were sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageLatency {
+    pub name: String,
+    /// Markers found.
+    pub count: usize,
+    pub last: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    total: Duration,
+}
+
+impl StageLatency {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            count: 0,
+            last: Duration::ZERO,
+            min: Duration::MAX,
+            max: Duration::ZERO,
+            total: Duration::ZERO,
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        self.count += 1;
+        self.last = latency;
+        self.min = self.min.min(latency);
+        self.max = self.max.max(latency);
+        self.total += latency;
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count as u32)
+    }
+}
+
+/// Latency to every stage, in the order of the path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyReport {
+    pub stages: Vec<StageLatency>,
+}
+
+impl LatencyReport {
+    pub fn stage(&self, name: &str) -> Option<&StageLatency> {
+        self.stages.iter().find(|stage| stage.name == name)
+    }
+
+    /// Mean latency to the last stage markers were found at.
+    pub fn total(&self) -> Option<Duration> {
+        self.stages.iter().rev().find_map(StageLatency::mean)
+    }
+}
+
+impl fmt::Display for LatencyReport {
+    /// Each stage's mean latency, and how much of it the stage added to the one before.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut before = Duration::ZERO;
+        for (index, stage) in self.stages.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            match stage.mean() {
+                Some(mean) => {
+                    let added = mean.as_secs_f64() - before.as_secs_f64();
+                    write!(
+                        f,
+                        "{} {:.1} ms ({:+.1} ms)",
+                        stage.name,
+                        mean.as_secs_f64() * 1000.0,
+                        added * 1000.0
+                    )?;
+                    before = mean;
+                }
+                None => write!(f, "{} -", stage.name)?,
+            }
+        }
+        Ok(())
+    }
+}
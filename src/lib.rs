@@ -1 +1,35 @@
+pub mod analysis;
+pub mod audio;
+pub mod bitrate;
+pub mod call;
+pub mod capture;
+pub mod clock;
+pub mod codec;
+pub mod conference;
+pub mod config;
+pub mod device;
+pub mod drift;
+pub mod dtx;
+pub mod error;
+pub mod g711;
+pub mod jitter;
+pub mod latency;
+pub mod logging;
+pub mod mixer;
+pub mod multistream;
+pub mod net;
+pub mod offline;
+pub mod ogg;
+pub mod pipeline;
+pub mod recorder;
+pub mod roster;
+pub mod rtcp;
+pub mod rtp;
+pub mod sdp;
+pub mod signaling;
+pub mod spatial;
+pub mod srtp;
+pub mod stats;
+pub mod talk;
 pub mod util;
+pub mod wav;
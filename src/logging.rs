@@ -0,0 +1,220 @@
+//! Diagnostics through `tracing`, including from realtime audio callbacks.
+//!
+//! Work is tagged with the [`Stage`] of the audio path it belongs to. Device callbacks must
+//! not format, lock or block, so they [`RealtimeLog::record`] an [`Event`] into a bounded
+//! lock-free queue instead, and a [`Drain`] on an ordinary thread hands it to `tracing`.
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use clap::{Args, ValueEnum};
+use tracing::{Level, Span, debug, error, error_span, warn};
+
+use crate::codec::CodecError;
+
+/// Events a realtime log holds before dropping: several per buffer between polls.
+pub const REALTIME_CAPACITY: usize = 1024;
+/// How often a drain thread looks for new events. Recording never wakes it, since waking a
+/// thread can take a lock.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A step audio goes through between the microphone and the speakers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Capture,
+    Encode,
+    Network,
+    Decode,
+    Playback,
+}
+
+impl Stage {
+    /// A span tagging everything logged inside it with this stage. Spans are enabled at every
+    /// level, so warnings and errors always say where they came from.
+    pub fn span(self) -> Span {
+        match self {
+            Stage::Capture => error_span!("capture"),
+            Stage::Encode => error_span!("encode"),
+            Stage::Network => error_span!("network"),
+            Stage::Decode => error_span!("decode"),
+            Stage::Playback => error_span!("playback"),
+        }
+    }
+}
+
+/// Something worth reporting from a realtime thread.
+#[derive(Debug)]
+pub enum Event {
+    /// Samples arrived from the input device.
+    Captured {
+        samples: usize,
+    },
+    /// Samples went to the output device.
+    Played {
+        samples: usize,
+    },
+    /// The output device wanted more samples than were buffered.
+    Underrun {
+        wanted: usize,
+        available: usize,
+    },
+    Encoded {
+        bytes: usize,
+    },
+    Decoded {
+        samples: usize,
+    },
+    EncodeFailed(CodecError),
+    DecodeFailed(CodecError),
+    SendFailed(io::Error),
+    /// The input device reported a problem with its stream.
+    InputFailed(cpal::StreamError),
+    /// The output device reported a problem with its stream.
+    OutputFailed(cpal::StreamError),
+}
+
+impl Event {
+    pub fn stage(&self) -> Stage {
+        match self {
+            Event::Captured { .. } | Event::InputFailed(_) => Stage::Capture,
+            Event::Encoded { .. } | Event::EncodeFailed(_) => Stage::Encode,
+            Event::SendFailed(_) => Stage::Network,
+            Event::Decoded { .. } | Event::DecodeFailed(_) => Stage::Decode,
+            Event::Played { .. } | Event::Underrun { .. } | Event::OutputFailed(_) => {
+                Stage::Playback
+            }
+        }
+    }
+
+    /// Logs the event inside the span of its stage.
+    fn emit(self) {
+        let _entered = self.stage().span().entered();
+        match self {
+            Event::Captured { samples } => debug!(samples, "captured"),
+            Event::Played { samples } => debug!(samples, "played"),
+            Event::Underrun { wanted, available } => warn!(wanted, available, "underrun"),
+            Event::Encoded { bytes } => debug!(bytes, "encoded"),
+            Event::Decoded { samples } => debug!(samples, "decoded"),
+            Event::EncodeFailed(err) => warn!(error = %err, "failed to encode"),
+            Event::DecodeFailed(err) => warn!(error = %err, "failed to decode"),
+            Event::SendFailed(err) => warn!(error = %err, "failed to send"),
+            Event::InputFailed(err) => error!(error = %err, "input stream failed"),
+            Event::OutputFailed(err) => error!(error = %err, "output stream failed"),
+        }
+    }
+}
+
+/// Creates a log for realtime threads holding up to `capacity` events, and the drain that
+/// passes them on to `tracing`.
+pub fn realtime_log(capacity: usize) -> (RealtimeLog, Drain) {
+    let (events, receiver) = mpsc::sync_channel(capacity.max(1));
+    let dropped = Arc::new(AtomicUsize::new(0));
+    (
+        RealtimeLog {
+            events,
+            dropped: Arc::clone(&dropped),
+        },
+        Drain { receiver, dropped },
+    )
+}
+
+/// The recording end of a realtime log. Clones record into the same log, so every callback
+/// of a program can share one drain.
+#[derive(Clone)]
+pub struct RealtimeLog {
+    events: SyncSender<Event>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl RealtimeLog {
+    /// Queues `event` without blocking, allocating or formatting. When the queue is full
+    /// the event is only counted, and the drain reports how many went missing.
+    pub fn record(&self, event: Event) {
+        if let Err(TrySendError::Full(_)) = self.events.try_send(event) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The end of a realtime log that logs its events.
+pub struct Drain {
+    receiver: Receiver<Event>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Drain {
+    /// Logs every event recorded so far and returns how many there were.
+    pub fn flush(&self) -> usize {
+        let mut count = 0;
+        while let Ok(event) = self.receiver.try_recv() {
+            event.emit();
+            count += 1;
+        }
+        self.report_dropped();
+        count
+    }
+
+    /// Logs events on a thread of its own until every [`RealtimeLog`] is gone.
+    pub fn spawn(self) -> JoinHandle<()> {
+        thread::spawn(move || {
+            loop {
+                match self.receiver.try_recv() {
+                    Ok(event) => event.emit(),
+                    Err(TryRecvError::Empty) => {
+                        self.report_dropped();
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                    Err(TryRecvError::Disconnected) => {
+                        self.report_dropped();
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    fn report_dropped(&self) {
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!(dropped, "realtime log overflowed");
+        }
+    }
+}
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// One human-readable line per event.
+    Text,
+    /// One JSON object per event, with its fields and spans.
+    Json,
+}
+
+/// Logging options shared by the binaries.
+#[derive(Debug, Clone, Args)]
+pub struct LogArgs {
+    /// Least severe messages to show: error, warn, info, debug or trace.
+    #[arg(long, value_name = "LEVEL", default_value_t = Level::INFO)]
+    pub log_level: Level,
+
+    /// Write logs as text or JSON lines.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+impl LogArgs {
+    /// Installs the global subscriber. Logs go to stderr, leaving stdout to the program.
+    pub fn init(&self) {
+        let logs = tracing_subscriber::fmt()
+            .with_max_level(self.log_level)
+            .with_writer(io::stderr);
+        match self.log_format {
+            LogFormat::Text => logs.init(),
+            LogFormat::Json => logs.json().init(),
+        }
+    }
+}
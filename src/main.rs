@@ -1,22 +1,744 @@
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Sample, SampleFormat};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use clap::Parser;
+use cpal::traits::StreamTrait;
+use futures::StreamExt;
+use futures::channel::mpsc;
 use gpui::{
-    App, Application, Bounds, Context, SharedString, Window, WindowBounds, WindowOptions, div,
-    prelude::*, px, rgb, size,
+    App, Application, Bounds, Context, Entity, FocusHandle, Hsla, KeyBinding, KeyUpEvent,
+    Keystroke, SharedString, Subscription, Window, WindowBounds, WindowOptions, actions, canvas,
+    div, fill, hsla, point, prelude::*, px, rgb, size,
 };
+use test_gpui::analysis::{self, Scope, Spectrum, Tap};
+use test_gpui::config::KeyBindings;
+use test_gpui::device;
+use test_gpui::error::{Context as _, Result};
+use test_gpui::logging::LogArgs;
+use test_gpui::mixer::{InputId, Mixer};
+use test_gpui::recorder::{RecordFormat, Recorder, Track};
+use test_gpui::roster::{Participant, ParticipantId, Quality, Roster, RosterEvent, SpeechDetector};
+use test_gpui::spatial::{self, Renderer};
+use test_gpui::talk::{Gate, TalkControl, TalkMode};
+use test_gpui::wav::WavSpec;
+use tracing::warn;
+
+actions!(
+    talk,
+    [ToggleMute, ToggleDeafen, PushToTalk, TogglePushToTalk]
+);
+
+/// What the mixer runs at when there is no output device to match.
+const FALLBACK_SAMPLE_RATE: u32 = 48_000;
+const FALLBACK_CHANNELS: usize = 2;
+/// Size of the window, in pixels.
+const WINDOW_WIDTH: f32 = 640.0;
+const WINDOW_HEIGHT: f32 = 860.0;
+/// Test voices placed on the stage: a name, a colour and the pitch of their beep.
+const VOICES: [(&str, fn() -> Hsla, f32); 3] = [
+    ("Low", gpui::red, 220.0),
+    ("Mid", gpui::green, 440.0),
+    ("High", gpui::blue, 880.0),
+];
+/// How far one click moves a voice, in degrees.
+const STEP: f32 = 15.0;
+/// Size of the stage and radius of the arc voices sit on, in pixels.
+const STAGE_WIDTH: f32 = 240.0;
+const STAGE_HEIGHT: f32 = 130.0;
+const STAGE_RADIUS: f32 = 100.0;
+/// Steps of the volume slider, and the volume of the last one.
+const VOLUME_STEPS: usize = 8;
+const MAX_VOLUME: f32 = 2.0;
+/// Audio each scope keeps, and how much of it the waveform shows.
+const SCOPE_LENGTH: Duration = Duration::from_millis(500);
+const WAVEFORM_LENGTH: Duration = Duration::from_millis(250);
+const FFT_SIZE: usize = 1024;
+/// Frequency bands shown, spaced logarithmically between these frequencies in Hz.
+const BANDS: usize = 24;
+const LOWEST_BAND: f32 = 60.0;
+const HIGHEST_BAND: f32 = 12_000.0;
+/// Levels drawn, in dBFS; anything quieter is drawn as nothing.
+const DISPLAY_FLOOR: f32 = -90.0;
+const SPECTROGRAM_COLUMNS: usize = 64;
+const WAVEFORM_COLUMNS: usize = 100;
+const TRACE_HEIGHT: f32 = 32.0;
+
+struct Voice {
+    name: SharedString,
+    color: Hsla,
+    input: InputId,
+    azimuth: f32,
+}
+
+/// Shows where every voice sits around the listener and lets the user move them.
+struct Stage {
+    mixer: Arc<Mutex<Mixer>>,
+    voices: Vec<Voice>,
+}
+
+impl Stage {
+    fn move_voice(&mut self, index: usize, degrees: f32) {
+        let voice = &mut self.voices[index];
+        voice.azimuth = (voice.azimuth + degrees).clamp(-90.0, 90.0);
+        if let Some(input) = self.mixer.lock().unwrap().input(voice.input) {
+            input.set_azimuth(Some(voice.azimuth));
+        }
+    }
+
+    fn toggle_renderer(&mut self) {
+        let mut mixer = self.mixer.lock().unwrap();
+        let renderer = match mixer.renderer() {
+            Renderer::EqualPower => Renderer::Binaural,
+            Renderer::Binaural => Renderer::EqualPower,
+        };
+        mixer.set_renderer(renderer);
+    }
+}
+
+fn button(id: (&'static str, usize), label: impl Into<SharedString>) -> gpui::Stateful<gpui::Div> {
+    div()
+        .id(id)
+        .px_2()
+        .bg(rgb(0x303030))
+        .hover(|style| style.bg(rgb(0x404040)))
+        .cursor_pointer()
+        .child(label.into())
+}
+
+impl Render for Stage {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let renderer = self.mixer.lock().unwrap().renderer();
+        // The listener sits at the bottom centre, facing up; voices sit on an arc around it.
+        let (center_x, center_y) = (STAGE_WIDTH / 2.0, STAGE_HEIGHT - 10.0);
+        let marker = |x: f32, y: f32, color: Hsla| {
+            div()
+                .absolute()
+                .left(px(x - 8.0))
+                .top(px(y - 8.0))
+                .size_4()
+                .rounded_full()
+                .bg(color)
+        };
+
+        div()
+            .flex()
+            .flex_col()
+            .gap_2()
+            .text_sm()
+            .child(
+                div()
+                    .relative()
+                    .w(px(STAGE_WIDTH))
+                    .h(px(STAGE_HEIGHT))
+                    .bg(rgb(0x202020))
+                    .child(marker(center_x, center_y, gpui::white()))
+                    .children(self.voices.iter().map(|voice| {
+                        let angle = voice.azimuth.to_radians();
+                        marker(
+                            center_x + STAGE_RADIUS * angle.sin(),
+                            center_y - STAGE_RADIUS * angle.cos(),
+                            voice.color,
+                        )
+                    })),
+            )
+            .children(self.voices.iter().enumerate().map(|(index, voice)| {
+                div()
+                    .flex()
+                    .gap_2()
+                    .items_center()
+                    .child(div().size_3().rounded_full().bg(voice.color))
+                    .child(div().w(px(48.0)).child(voice.name.clone()))
+                    .child(button(("left", index), "<").on_click(cx.listener(
+                        move |stage, _, _, cx| {
+                            stage.move_voice(index, -STEP);
+                            cx.notify();
+                        },
+                    )))
+                    .child(div().w(px(48.0)).child(format!("{:+.0}°", voice.azimuth)))
+                    .child(button(("right", index), ">").on_click(cx.listener(
+                        move |stage, _, _, cx| {
+                            stage.move_voice(index, STEP);
+                            cx.notify();
+                        },
+                    )))
+            }))
+            .child(
+                button(
+                    ("renderer", 0),
+                    match renderer {
+                        Renderer::EqualPower => "Speakers (equal power)",
+                        Renderer::Binaural => "Headphones (binaural)",
+                    },
+                )
+                .on_click(cx.listener(|stage, _, _, cx| {
+                    stage.toggle_renderer();
+                    cx.notify();
+                })),
+            )
+    }
+}
+
+/// Lists everyone in the call and lets the user adjust how they hear each of them.
+///
+/// Follows the roster's events rather than polling it, so it only redraws when something it
+/// shows has changed.
+struct CallView {
+    roster: Roster,
+    mixer: Arc<Mutex<Mixer>>,
+    inputs: Vec<(ParticipantId, InputId)>,
+    participants: Vec<Participant>,
+}
+
+impl CallView {
+    fn new(
+        roster: Roster,
+        mixer: Arc<Mutex<Mixer>>,
+        inputs: Vec<(ParticipantId, InputId)>,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        // The roster calls its listeners from the audio and network threads; hand the events
+        // over to the UI thread. Once the view is gone, sending fails and the listener is
+        // dropped.
+        let (events_tx, mut events_rx) = mpsc::unbounded();
+        roster.subscribe(move |event| events_tx.unbounded_send(event.clone()).is_ok());
+        cx.spawn(async move |view, cx| {
+            while let Some(event) = events_rx.next().await {
+                let applied = view.update(cx, |view, cx| {
+                    view.apply(event);
+                    cx.notify();
+                });
+                if applied.is_err() {
+                    break;
+                }
+            }
+        })
+        .detach();
+
+        Self {
+            roster,
+            mixer,
+            inputs,
+            participants: Vec::new(),
+        }
+    }
+
+    fn apply(&mut self, event: RosterEvent) {
+        match event {
+            RosterEvent::Joined(participant) => self.participants.push(participant),
+            RosterEvent::Changed(participant) => {
+                if let Some(shown) = self
+                    .participants
+                    .iter_mut()
+                    .find(|p| p.id == participant.id)
+                {
+                    *shown = participant;
+                }
+            }
+            RosterEvent::Left(id) => self.participants.retain(|p| p.id != id),
+        }
+    }
+
+    fn input(&self, id: ParticipantId) -> Option<InputId> {
+        self.inputs
+            .iter()
+            .find(|(participant, _)| *participant == id)
+            .map(|&(_, input)| input)
+    }
+
+    fn set_volume(&mut self, id: ParticipantId, volume: f32) {
+        if let Some(input) = self.input(id)
+            && let Some(input) = self.mixer.lock().unwrap().input(input)
+        {
+            input.set_gain(volume);
+        }
+        self.roster.set_volume(id, volume);
+    }
+
+    fn set_muted(&mut self, id: ParticipantId, muted: bool) {
+        if let Some(input) = self.input(id)
+            && let Some(input) = self.mixer.lock().unwrap().input(input)
+        {
+            input.set_muted(muted);
+        }
+        self.roster.set_muted(id, muted);
+    }
+}
+
+/// Signal bars for a connection, grey while nothing is known.
+fn quality_icon(quality: Quality) -> gpui::Div {
+    let (bars, color) = match quality {
+        Quality::Unknown => (0, rgb(0x808080)),
+        Quality::Bad => (1, rgb(0xe04040)),
+        Quality::Poor => (2, rgb(0xe0a040)),
+        Quality::Good => (3, rgb(0x80c040)),
+        Quality::Excellent => (4, rgb(0x40c040)),
+    };
+    div()
+        .flex()
+        .items_end()
+        .gap_0p5()
+        .h(px(12.0))
+        .children((0..4).map(|bar| {
+            div()
+                .w(px(3.0))
+                .h(px(3.0 * (bar + 1) as f32))
+                .bg(if bar < bars { color } else { rgb(0x303030) })
+        }))
+}
+
+impl Render for CallView {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        div()
+            .flex()
+            .flex_col()
+            .gap_1()
+            .text_sm()
+            .children(self.participants.iter().map(|participant| {
+                let id = participant.id;
+                let muted = participant.muted;
+                let speaking = if participant.speaking && !muted {
+                    gpui::green()
+                } else {
+                    gpui::black()
+                };
+                let slider = (0..VOLUME_STEPS).map(|step| {
+                    let volume = MAX_VOLUME * (step + 1) as f32 / VOLUME_STEPS as f32;
+                    div()
+                        .id(("volume", id as usize * VOLUME_STEPS + step))
+                        .w(px(8.0))
+                        .h(px(12.0))
+                        .cursor_pointer()
+                        .bg(if volume <= participant.volume + 1e-3 {
+                            rgb(0x4080e0)
+                        } else {
+                            rgb(0x303030)
+                        })
+                        .on_click(cx.listener(move |view, _, _, _| view.set_volume(id, volume)))
+                });
+
+                div()
+                    .flex()
+                    .gap_2()
+                    .items_center()
+                    .child(div().size_3().rounded_full().bg(speaking))
+                    .child(div().w(px(64.0)).child(participant.name.clone()))
+                    .child(quality_icon(participant.quality))
+                    .child(
+                        button(("mute", id as usize), if muted { "Unmute" } else { "Mute" })
+                            .on_click(cx.listener(move |view, _, _, _| view.set_muted(id, !muted))),
+                    )
+                    .child(div().flex().gap_0p5().children(slider))
+                    .child(format!("{:.0}%", participant.volume * 100.0))
+            }))
+    }
+}
+
+/// Shows whether the microphone is live, prominently, with buttons for what the keys do.
+struct TalkView {
+    control: TalkControl,
+    keys: KeyBindings,
+    /// Whether the user is speaking into a live microphone, as published by the capture
+    /// callback.
+    speaking: bool,
+}
+
+impl TalkView {
+    fn new(
+        control: TalkControl,
+        keys: KeyBindings,
+        mut speaking_rx: mpsc::UnboundedReceiver<bool>,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        cx.spawn(async move |view, cx| {
+            while let Some(speaking) = speaking_rx.next().await {
+                let applied = view.update(cx, |view, cx| {
+                    view.speaking = speaking;
+                    cx.notify();
+                });
+                if applied.is_err() {
+                    break;
+                }
+            }
+        })
+        .detach();
+
+        Self {
+            control,
+            keys,
+            speaking: false,
+        }
+    }
+
+    fn toggle_mute(&mut self, cx: &mut Context<Self>) {
+        self.control.toggle_mute();
+        cx.notify();
+    }
+
+    fn toggle_deafen(&mut self, cx: &mut Context<Self>) {
+        self.control.toggle_deafen();
+        cx.notify();
+    }
+
+    fn toggle_mode(&mut self, cx: &mut Context<Self>) {
+        self.control.toggle_mode();
+        cx.notify();
+    }
+
+    fn set_pressed(&mut self, pressed: bool, cx: &mut Context<Self>) {
+        if self.control.state().pressed != pressed {
+            self.control.set_pressed(pressed);
+            cx.notify();
+        }
+    }
+}
+
+impl Render for TalkView {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let state = self.control.state();
+        let keys = &self.keys;
+        let (status, color): (SharedString, _) = if state.deafened {
+            ("Deafened".into(), rgb(0xc03030))
+        } else if state.muted {
+            ("Muted".into(), rgb(0xc03030))
+        } else if !state.transmitting() {
+            (
+                format!("Hold {} to talk", keys.push_to_talk).into(),
+                rgb(0x606060),
+            )
+        } else {
+            ("Live".into(), rgb(0x30a030))
+        };
+
+        div()
+            .flex()
+            .flex_col()
+            .items_center()
+            .gap_2()
+            .child(
+                div()
+                    .px_6()
+                    .py_1()
+                    .rounded_md()
+                    .border_2()
+                    .border_color(if self.speaking && state.transmitting() {
+                        gpui::white()
+                    } else {
+                        color.into()
+                    })
+                    .bg(color)
+                    .text_2xl()
+                    .child(status),
+            )
+            .child(
+                div()
+                    .flex()
+                    .gap_2()
+                    .text_sm()
+                    .child(
+                        button(
+                            ("talk-mute", 0),
+                            format!(
+                                "{} ({})",
+                                if state.muted { "Unmute" } else { "Mute" },
+                                keys.toggle_mute
+                            ),
+                        )
+                        .on_click(cx.listener(|view, _, _, cx| view.toggle_mute(cx))),
+                    )
+                    .child(
+                        button(
+                            ("talk-deafen", 0),
+                            format!(
+                                "{} ({})",
+                                if state.deafened { "Undeafen" } else { "Deafen" },
+                                keys.toggle_deafen
+                            ),
+                        )
+                        .on_click(cx.listener(|view, _, _, cx| view.toggle_deafen(cx))),
+                    )
+                    .child(
+                        button(
+                            ("talk-mode", 0),
+                            format!(
+                                "{} ({})",
+                                match state.mode {
+                                    TalkMode::Open => "Open mic",
+                                    TalkMode::PushToTalk => "Push to talk",
+                                },
+                                keys.toggle_push_to_talk
+                            ),
+                        )
+                        .on_click(cx.listener(|view, _, _, cx| view.toggle_mode(cx))),
+                    ),
+            )
+    }
+}
+
+/// Starts and stops recording the call, and shows where the recording goes.
+struct RecordView {
+    recorder: Recorder,
+    /// Every recording gets a directory of its own in here.
+    directory: PathBuf,
+    /// Why the last recording could not start or finish.
+    error: Option<SharedString>,
+}
+
+impl RecordView {
+    fn toggle(&mut self, cx: &mut Context<Self>) {
+        let result = if self.recorder.is_recording() {
+            self.recorder.stop()
+        } else {
+            let started = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            let directory = self.directory.join(format!("call-{}", started.as_secs()));
+            self.recorder.start(directory, Instant::now())
+        };
+        self.error = result
+            .err()
+            .map(|err| format!("Recording failed: {}", err).into());
+        cx.notify();
+    }
+}
+
+impl Render for RecordView {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let recording = self.recorder.directory();
+        div()
+            .flex()
+            .items_center()
+            .gap_2()
+            .text_sm()
+            .child(
+                button(
+                    ("record", 0),
+                    if recording.is_some() {
+                        "Stop recording"
+                    } else {
+                        "Record"
+                    },
+                )
+                .on_click(cx.listener(|view, _, _, cx| view.toggle(cx))),
+            )
+            .children(recording.map(|directory| {
+                div()
+                    .text_color(gpui::red())
+                    .child(format!("Recording to {}", directory.display()))
+            }))
+            .children(
+                self.error
+                    .clone()
+                    .map(|error| div().text_color(gpui::red()).child(error)),
+            )
+    }
+}
+
+/// What the visualizer shows of one stream.
+struct Trace {
+    name: SharedString,
+    color: Hsla,
+    scope: Scope,
+    /// The latest snapshot of the scope and how many samples had been written when it was
+    /// taken.
+    samples: Vec<f32>,
+    seen: usize,
+    spectrum: Spectrum,
+    bands: [f32; BANDS],
+    /// Band levels of past snapshots, oldest first.
+    spectrogram: VecDeque<[f32; BANDS]>,
+}
+
+impl Trace {
+    fn new(name: impl Into<SharedString>, color: Hsla, scope: Scope) -> Self {
+        let length = (WAVEFORM_LENGTH.as_secs_f32() * scope.sample_rate() as f32) as usize;
+        Self {
+            name: name.into(),
+            color,
+            scope,
+            samples: vec![0.0; length.max(FFT_SIZE)],
+            seen: 0,
+            spectrum: Spectrum::new(FFT_SIZE),
+            bands: [analysis::FLOOR_DB; BANDS],
+            spectrogram: VecDeque::with_capacity(SPECTROGRAM_COLUMNS),
+        }
+    }
+
+    /// Takes a fresh snapshot and analyzes it, unless nothing new has been written.
+    fn update(&mut self) {
+        let written = self.scope.latest(&mut self.samples);
+        if written == self.seen {
+            return;
+        }
+        self.seen = written;
+        self.spectrum.analyze(&self.samples);
+        self.spectrum.bands(
+            self.scope.sample_rate(),
+            LOWEST_BAND,
+            HIGHEST_BAND,
+            &mut self.bands,
+        );
+        if self.spectrogram.len() == SPECTROGRAM_COLUMNS {
+            self.spectrogram.pop_front();
+        }
+        self.spectrogram.push_back(self.bands);
+    }
+}
+
+/// Where a level falls between the display floor (0) and full scale (1).
+fn level_fraction(level: f32) -> f32 {
+    ((level - DISPLAY_FLOOR) / -DISPLAY_FLOOR).clamp(0.0, 1.0)
+}
+
+/// The recent waveform, one vertical bar per column from its lowest to its highest sample.
+fn waveform(samples: &[f32], color: Hsla) -> impl IntoElement {
+    let mut peaks = vec![(0.0, 0.0); WAVEFORM_COLUMNS];
+    analysis::peaks(samples, &mut peaks);
+    canvas(
+        move |_, _, _| peaks,
+        move |bounds, peaks, window, _| {
+            let (width, height) = (f32::from(bounds.size.width), f32::from(bounds.size.height));
+            let column = width / peaks.len() as f32;
+            for (index, (low, high)) in peaks.into_iter().enumerate() {
+                let top = height / 2.0 * (1.0 - high.clamp(-1.0, 1.0));
+                let bottom = height / 2.0 * (1.0 - low.clamp(-1.0, 1.0));
+                let origin = bounds.origin + point(px(column * index as f32), px(top));
+                let extent = size(px(column), px((bottom - top).max(1.0)));
+                window.paint_quad(fill(Bounds::new(origin, extent), color));
+            }
+        },
+    )
+    .w(px(200.0))
+    .h(px(TRACE_HEIGHT))
+}
+
+/// The current level of each band as bars rising from the bottom.
+fn spectrum(bands: [f32; BANDS], color: Hsla) -> impl IntoElement {
+    canvas(
+        |_, _, _| {},
+        move |bounds, _, window, _| {
+            let (width, height) = (f32::from(bounds.size.width), f32::from(bounds.size.height));
+            let column = width / BANDS as f32;
+            for (index, level) in bands.into_iter().enumerate() {
+                let bar = height * level_fraction(level);
+                let origin = bounds.origin + point(px(column * index as f32), px(height - bar));
+                let extent = size(px(column - 1.0), px(bar));
+                window.paint_quad(fill(Bounds::new(origin, extent), color));
+            }
+        },
+    )
+    .w(px(96.0))
+    .h(px(TRACE_HEIGHT))
+}
+
+/// Past band levels scrolling from right to left, louder being brighter, low bands at the
+/// bottom.
+fn spectrogram(history: Vec<[f32; BANDS]>, color: Hsla) -> impl IntoElement {
+    canvas(
+        |_, _, _| {},
+        move |bounds, _, window, _| {
+            let (width, height) = (f32::from(bounds.size.width), f32::from(bounds.size.height));
+            let (column, row) = (width / SPECTROGRAM_COLUMNS as f32, height / BANDS as f32);
+            let start = SPECTROGRAM_COLUMNS - history.len();
+            for (index, bands) in history.iter().enumerate() {
+                for (band, &level) in bands.iter().enumerate() {
+                    let lightness = 0.05 + 0.6 * level_fraction(level);
+                    let x = column * (start + index) as f32;
+                    let y = height - row * (band + 1) as f32;
+                    let origin = bounds.origin + point(px(x), px(y));
+                    let cell = hsla(color.h, color.s, lightness, 1.0);
+                    window.paint_quad(fill(Bounds::new(origin, size(px(column), px(row))), cell));
+                }
+            }
+        },
+    )
+    .w(px(128.0))
+    .h(px(TRACE_HEIGHT))
+}
+
+/// Draws each stream's waveform, spectrum and spectrogram.
+///
+/// Reads the scopes the audio threads publish to once per displayed frame. Reading never
+/// waits for the audio, and the audio never waits for it.
+struct Visualizer {
+    traces: Vec<Trace>,
+}
+
+impl Render for Visualizer {
+    fn render(&mut self, window: &mut Window, _cx: &mut Context<Self>) -> impl IntoElement {
+        window.request_animation_frame();
+        for trace in &mut self.traces {
+            trace.update();
+        }
+
+        div()
+            .flex()
+            .flex_col()
+            .gap_1()
+            .text_sm()
+            .children(self.traces.iter().map(|trace| {
+                div()
+                    .flex()
+                    .gap_2()
+                    .items_center()
+                    .child(div().w(px(48.0)).child(trace.name.clone()))
+                    .child(waveform(&trace.samples, trace.color))
+                    .child(spectrum(trace.bands, trace.color))
+                    .child(spectrogram(
+                        trace.spectrogram.iter().copied().collect(),
+                        trace.color,
+                    ))
+            }))
+    }
+}
 
 struct HelloWorld {
     text: SharedString,
+    focus_handle: FocusHandle,
+    /// Key whose release ends push-to-talk.
+    push_to_talk: Keystroke,
+    talk: Entity<TalkView>,
+    record: Entity<RecordView>,
+    call: Entity<CallView>,
+    stage: Entity<Stage>,
+    visualizer: Entity<Visualizer>,
+    /// Audio problems, shown instead of ending the program.
+    errors: Vec<SharedString>,
+    /// Audio streams stop when dropped, so they live as long as the window.
+    _streams: Vec<cpal::Stream>,
+    _activation: Subscription,
 }
 
 impl Render for HelloWorld {
-    fn render(&mut self, _window: &mut Window, _cx: &mut Context<Self>) -> impl IntoElement {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
         div()
+            .track_focus(&self.focus_handle)
+            .on_action(cx.listener(|this, _: &ToggleMute, _, cx| {
+                this.talk.update(cx, |talk, cx| talk.toggle_mute(cx));
+            }))
+            .on_action(cx.listener(|this, _: &ToggleDeafen, _, cx| {
+                this.talk.update(cx, |talk, cx| talk.toggle_deafen(cx));
+            }))
+            .on_action(cx.listener(|this, _: &TogglePushToTalk, _, cx| {
+                this.talk.update(cx, |talk, cx| talk.toggle_mode(cx));
+            }))
+            .on_action(cx.listener(|this, _: &PushToTalk, _, cx| {
+                this.talk.update(cx, |talk, cx| talk.set_pressed(true, cx));
+            }))
+            .on_key_up(cx.listener(|this, event: &KeyUpEvent, _, cx| {
+                if event.keystroke.key == this.push_to_talk.key {
+                    this.talk.update(cx, |talk, cx| talk.set_pressed(false, cx));
+                }
+            }))
             .flex()
             .flex_col()
             .gap_3()
             .bg(rgb(0x505050))
-            .size(px(500.0))
+            .w(px(WINDOW_WIDTH))
+            .h(px(WINDOW_HEIGHT))
             .justify_center()
             .items_center()
             .shadow_lg()
@@ -25,78 +747,330 @@ impl Render for HelloWorld {
             .text_xl()
             .text_color(rgb(0xffffff))
             .child(format!("Hello, {}!", &self.text))
-            .child(
-                div()
-                    .flex()
-                    .gap_2()
-                    .child(div().size_8().bg(gpui::red()))
-                    .child(div().size_8().bg(gpui::green()))
-                    .child(div().size_8().bg(gpui::blue()))
-                    .child(div().size_8().bg(gpui::yellow()))
-                    .child(div().size_8().bg(gpui::black()))
-                    .child(div().size_8().bg(gpui::white())),
+            .children(
+                self.errors
+                    .iter()
+                    .map(|error| div().text_sm().text_color(gpui::red()).child(error.clone())),
             )
+            .child(self.talk.clone())
+            .child(self.record.clone())
+            .child(self.visualizer.clone())
+            .child(self.call.clone())
+            .child(self.stage.clone())
+    }
+}
+
+/// Feeds every voice a short beep once a second, staggered so they can be told apart,
+/// reports how loud each of them is playing to the roster and records what each one says.
+fn feed_voices(
+    mixer: Arc<Mutex<Mixer>>,
+    roster: Roster,
+    recorder: Recorder,
+    voices: Vec<(ParticipantId, InputId, f32)>,
+    sample_rate: u32,
+) {
+    let spec = WavSpec {
+        sample_rate,
+        channels: 1,
+    };
+    const CHUNK: Duration = Duration::from_millis(20);
+    let rate = sample_rate as usize;
+    let (beep, stagger) = (rate / 5, rate / 3);
+    let mut samples = vec![0f32; rate / 50];
+    let mut position = 0;
+    let mut next = Instant::now();
+    loop {
+        {
+            let mut mixer = mixer.lock().unwrap();
+            let now = Instant::now();
+            for (index, &(id, input, frequency)) in voices.iter().enumerate() {
+                for (offset, sample) in samples.iter_mut().enumerate() {
+                    let n = position + offset;
+                    let t = (n + index * stagger) % rate;
+                    *sample = if t < beep {
+                        let envelope = (PI * t as f32 / beep as f32).sin();
+                        0.2 * envelope * (2.0 * PI * frequency * n as f32 / rate as f32).sin()
+                    } else {
+                        0.0
+                    };
+                }
+                if let Some(input) = mixer.input(input) {
+                    input.push(&samples);
+                    roster.update_level(id, input.level(), now);
+                }
+                recorder.write(Track::Participant(id), &samples, spec, now);
+            }
+        }
+        position += samples.len();
+        next += CHUNK;
+        thread::sleep(next.saturating_duration_since(Instant::now()));
     }
 }
 
-fn write_silence<T: Sample>(data: &mut [T], _: &cpal::OutputCallbackInfo) {
-    for sample in data.iter_mut() {
-        *sample = Sample::EQUILIBRIUM;
+/// Watches the microphone through the talk gate, publishing when the user starts and stops
+/// speaking and what is heard to a scope, and recording it. Nothing is sent anywhere yet;
+/// all three show what would be.
+fn watch_microphone(
+    host: &cpal::Host,
+    talk: TalkControl,
+    recorder: Recorder,
+    speaking_tx: mpsc::UnboundedSender<bool>,
+    errors_tx: mpsc::UnboundedSender<SharedString>,
+) -> Result<(cpal::Stream, Scope)> {
+    let (device, config) = device::default_input(host)?;
+    let channels = config.channels() as usize;
+    let spec = WavSpec {
+        sample_rate: config.sample_rate().0,
+        channels: config.channels(),
+    };
+    let mut gate = Gate::new(config.sample_rate().0);
+    let mut detector = SpeechDetector::new();
+    let (tap, scope) = new_scope(config.sample_rate().0);
+    let stream = device::open_input(
+        &device,
+        &config.config(),
+        config.sample_format(),
+        move |data| {
+            let mut samples = data.to_vec();
+            gate.process(&mut samples, channels, talk.state().transmitting());
+            tap.push(&samples, channels);
+            recorder.write(Track::Microphone, &samples, spec, Instant::now());
+            let energy = samples.iter().map(|sample| sample * sample).sum::<f32>();
+            let level = (energy / samples.len().max(1) as f32).sqrt();
+            let was_speaking = detector.is_speaking();
+            if detector.update(level, Instant::now()) != was_speaking {
+                let _ = speaking_tx.unbounded_send(!was_speaking);
+            }
+        },
+        move |err| report(&errors_tx, format!("Microphone failed: {}", err)),
+    )
+    .context("opening the input stream")?;
+    stream.play().context("starting the input stream")?;
+    Ok((stream, scope))
+}
+
+/// Plays the mix on `device`, silenced while deafened, and records it as mixed.
+fn play_mix(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    mixer: Arc<Mutex<Mixer>>,
+    talk: TalkControl,
+    recorder: Recorder,
+    errors_tx: mpsc::UnboundedSender<SharedString>,
+) -> Result<cpal::Stream> {
+    let channels = mixer.lock().unwrap().channels();
+    let spec = WavSpec {
+        sample_rate: config.sample_rate().0,
+        channels: channels as u16,
+    };
+    let mut gate = Gate::new(config.sample_rate().0);
+    let stream = device::open_output(
+        device,
+        &config.config(),
+        config.sample_format(),
+        move |data| {
+            mixer.lock().unwrap().mix(data);
+            recorder.write(Track::Mix, data, spec, Instant::now());
+            gate.process(data, channels, talk.state().hearing());
+        },
+        move |err| report(&errors_tx, format!("Speakers failed: {}", err)),
+    )
+    .context("opening the output stream")?;
+    stream.play().context("starting the output stream")?;
+    Ok(stream)
+}
+
+/// Shows an audio problem in the window, and on the terminal in case the window never opens.
+fn report(errors_tx: &mpsc::UnboundedSender<SharedString>, message: String) {
+    warn!("{}", message);
+    let _ = errors_tx.unbounded_send(message.into());
+}
+
+/// A scope keeping enough audio for the visualizer.
+fn new_scope(sample_rate: u32) -> (Tap, Scope) {
+    let capacity = (SCOPE_LENGTH.as_secs_f32() * sample_rate as f32) as usize;
+    analysis::scope(capacity, sample_rate)
+}
+
+/// Reads the key bindings, falling back to the default for any key gpui cannot parse.
+fn key_bindings() -> KeyBindings {
+    let mut keys = KeyBindings::load().unwrap_or_else(|err| {
+        warn!(error = %err, "using the default keys");
+        KeyBindings::default()
+    });
+    let defaults = KeyBindings::default();
+    for (key, default) in [
+        (&mut keys.toggle_mute, defaults.toggle_mute),
+        (&mut keys.toggle_deafen, defaults.toggle_deafen),
+        (&mut keys.push_to_talk, defaults.push_to_talk),
+        (&mut keys.toggle_push_to_talk, defaults.toggle_push_to_talk),
+    ] {
+        if Keystroke::parse(key).is_err() {
+            warn!(%key, %default, "invalid key, using the default");
+            *key = default;
+        }
     }
+    keys
+}
+
+/// A voice chat test bench: test voices on a stage, a call roster and live audio views.
+#[derive(Parser)]
+struct Args {
+    /// Where recordings are saved, each in a directory of its own.
+    #[arg(long, value_name = "DIR", default_value = "recordings")]
+    recordings: PathBuf,
+
+    /// Record as WAV, or as Ogg Opus.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = RecordFormat::Wav)]
+    record_format: RecordFormat,
+
+    #[command(flatten)]
+    log: LogArgs,
 }
 
 fn main() {
-    Application::new().run(|cx: &mut App| {
+    let args = Args::parse();
+    args.log.init();
+    Application::new().run(move |cx: &mut App| {
         let audio_host = cpal::default_host();
-        let audio_device = audio_host
-            .default_output_device()
-            .expect("No output device found.");
-        let mut audio_config_range = audio_device
-            .supported_output_configs()
-            .expect("Error while querying audio configs.");
-        let audio_config = audio_config_range
-            .next()
-            .expect("No supported config!?")
-            .with_max_sample_rate();
-        let err_fn = |err| eprintln!("an error occurred on the output audio stream: {}", err);
-        let sample_format = audio_config.sample_format();
-        let config = audio_config.into();
-        let stream = match sample_format {
-            SampleFormat::F32 => {
-                audio_device.build_output_stream(
-                    &config,
-                    move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                        // react to stream events and read or write stream data here.
-                        for sample in data.iter_mut() {
-                            *sample = 1.0.to_sample();
-                        }
-                    },
-                    err_fn,
-                    None,
-                )
-            }
-            SampleFormat::I16 => {
-                audio_device.build_output_stream(&config, write_silence::<i16>, err_fn, None)
+        let (errors_tx, mut errors_rx) = mpsc::unbounded();
+        let output = device::default_output(&audio_host);
+        // Without speakers the window still opens, mixing for nobody.
+        let (sample_rate, channels) = match &output {
+            Ok((_, config)) => (config.sample_rate().0, config.channels() as usize),
+            Err(_) => (FALLBACK_SAMPLE_RATE, FALLBACK_CHANNELS),
+        };
+        let mixer = Arc::new(Mutex::new(Mixer::new(sample_rate, channels)));
+
+        let roster = Roster::new();
+        let mut voices = Vec::new();
+        let mut feeds = Vec::new();
+        let mut inputs = Vec::new();
+        let mut traces = Vec::new();
+        let positions = VOICES.into_iter().zip(spatial::spread(VOICES.len()));
+        for (id, ((name, color, frequency), azimuth)) in (0..).zip(positions) {
+            let mut mixer = mixer.lock().unwrap();
+            let input = mixer.add_input(1);
+            let (tap, scope) = new_scope(sample_rate);
+            if let Some(input) = mixer.input(input) {
+                input.set_azimuth(Some(azimuth));
+                input.set_tap(Some(tap));
             }
-            SampleFormat::U16 => {
-                audio_device.build_output_stream(&config, write_silence::<u16>, err_fn, None)
+            traces.push(Trace::new(name, color(), scope));
+            voices.push(Voice {
+                name: name.into(),
+                color: color(),
+                input,
+                azimuth,
+            });
+            feeds.push((id, input, frequency));
+            inputs.push((id, input));
+            roster.join(id, name);
+        }
+        let recorder = Recorder::new(args.record_format);
+        let (feed_mixer, feed_roster, feed_recorder) =
+            (Arc::clone(&mixer), roster.clone(), recorder.clone());
+        thread::spawn(move || {
+            feed_voices(feed_mixer, feed_roster, feed_recorder, feeds, sample_rate)
+        });
+
+        let talk = TalkControl::default();
+        let mut streams = Vec::new();
+        let playing = output.and_then(|(device, config)| {
+            play_mix(
+                &device,
+                &config,
+                Arc::clone(&mixer),
+                talk.clone(),
+                recorder.clone(),
+                errors_tx.clone(),
+            )
+        });
+        match playing {
+            Ok(stream) => streams.push(stream),
+            Err(err) => report(&errors_tx, format!("No sound: {}", err)),
+        }
+
+        let (speaking_tx, speaking_rx) = mpsc::unbounded();
+        let watching = watch_microphone(
+            &audio_host,
+            talk.clone(),
+            recorder.clone(),
+            speaking_tx,
+            errors_tx.clone(),
+        );
+        match watching {
+            Ok((stream, scope)) => {
+                streams.push(stream);
+                traces.insert(0, Trace::new("You", gpui::white(), scope));
             }
-            sample_format => panic!("Unsupported sample format '{sample_format}'"),
+            Err(err) => report(&errors_tx, format!("No microphone: {}", err)),
         }
-        .unwrap();
 
-        stream.play().unwrap();
+        let keys = key_bindings();
+        cx.bind_keys([
+            KeyBinding::new(&keys.toggle_mute, ToggleMute, None),
+            KeyBinding::new(&keys.toggle_deafen, ToggleDeafen, None),
+            KeyBinding::new(&keys.push_to_talk, PushToTalk, None),
+            KeyBinding::new(&keys.toggle_push_to_talk, TogglePushToTalk, None),
+        ]);
+        let push_to_talk = Keystroke::parse(&keys.push_to_talk).unwrap();
 
         // Generating the window with gpui-rs
-        let bounds = Bounds::centered(None, size(px(500.), px(500.0)), cx);
+        let bounds = Bounds::centered(None, size(px(WINDOW_WIDTH), px(WINDOW_HEIGHT)), cx);
         cx.open_window(
             WindowOptions {
                 window_bounds: Some(WindowBounds::Windowed(bounds)),
                 ..Default::default()
             },
-            |_, cx| {
-                cx.new(|_| HelloWorld {
-                    text: "World".into(),
+            |window, cx| {
+                let talk = cx.new(|cx| TalkView::new(talk, keys, speaking_rx, cx));
+                let record = cx.new(|_| RecordView {
+                    recorder,
+                    directory: args.recordings,
+                    error: None,
+                });
+                let call = cx.new(|cx| CallView::new(roster, Arc::clone(&mixer), inputs, cx));
+                let stage = cx.new(|_| Stage { mixer, voices });
+                let visualizer = cx.new(|_| Visualizer { traces });
+                cx.new(|cx| {
+                    cx.spawn(async move |view, cx| {
+                        while let Some(error) = errors_rx.next().await {
+                            let applied = view.update(cx, |view: &mut HelloWorld, cx| {
+                                // Stream errors tend to repeat; once is enough.
+                                if !view.errors.contains(&error) {
+                                    view.errors.push(error);
+                                    cx.notify();
+                                }
+                            });
+                            if applied.is_err() {
+                                break;
+                            }
+                        }
+                    })
+                    .detach();
+                    let focus_handle = cx.focus_handle();
+                    window.focus(&focus_handle);
+                    // Releasing the key in another window would go unseen, so stop talking
+                    // whenever this one loses focus.
+                    let activation = cx.observe_window_activation(window, |this, window, cx| {
+                        if !window.is_window_active() {
+                            this.talk.update(cx, |talk, cx| talk.set_pressed(false, cx));
+                        }
+                    });
+                    HelloWorld {
+                        text: "World".into(),
+                        focus_handle,
+                        push_to_talk,
+                        talk,
+                        record,
+                        call,
+                        stage,
+                        visualizer,
+                        errors: Vec::new(),
+                        _streams: streams,
+                        _activation: activation,
+                    }
                 })
             },
         )
@@ -0,0 +1,396 @@
+//! Mixes several decoded streams into one output.
+//!
+//! Every [`Input`] has its own bounded buffer, so a late or stalled stream only silences
+//! itself: it starts playing once enough audio is buffered, fades out when it runs dry and
+//! fades back in when it recovers. [`Mixer::mix`] never allocates, so it can run inside an
+//! audio device callback; adding inputs does allocate and belongs on another thread.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::analysis::Tap;
+use crate::audio::AudioSource;
+use crate::spatial::{Renderer, Spatializer};
+
+/// Length of the fades applied when an input starts or runs dry.
+const FADE: Duration = Duration::from_millis(5);
+/// Time constant gain and pan changes are smoothed with, so they do not click.
+const SMOOTHING: Duration = Duration::from_millis(10);
+
+/// Level the limiter keeps peaks under, about -1 dBFS.
+const LIMIT: f32 = 0.9;
+/// Time constant the limiter recovers with once peaks are gone.
+const RELEASE: Duration = Duration::from_millis(100);
+
+/// Identifies an input of a [`Mixer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InputId(u32);
+
+/// Whether an input is currently heard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputState {
+    /// Waiting for enough audio to start, or to restart after running dry.
+    Buffering,
+    Playing,
+}
+
+/// One stream fed into a [`Mixer`].
+pub struct Input {
+    id: InputId,
+    channels: usize,
+    /// Interleaved samples waiting to be mixed, never grown past its initial capacity.
+    buffer: VecDeque<f32>,
+    capacity: usize,
+    state: InputState,
+    finished: bool,
+    gain: f32,
+    muted: bool,
+    pan: f32,
+    /// Gain and pan as currently applied, moving towards the settings above.
+    current_gain: f32,
+    current_pan: f32,
+    /// Frames into the fade-in after (re)starting.
+    faded_in: usize,
+    /// RMS level of the last block mixed, before gain.
+    level: f32,
+    /// Publishes what the input plays, before gain, for display.
+    tap: Option<Tap>,
+    /// Renders the input at an azimuth instead of panning it.
+    spatializer: Option<Spatializer>,
+    renderer: Renderer,
+    sample_rate: u32,
+}
+
+impl Input {
+    pub fn id(&self) -> InputId {
+        self.id
+    }
+
+    pub fn state(&self) -> InputState {
+        self.state
+    }
+
+    /// RMS level of the audio mixed in the last call to [`Mixer::mix`], before gain and
+    /// muting are applied, so it tells whether the source is making any sound at all.
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Audio waiting to be mixed, in frames.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len() / self.channels
+    }
+
+    /// Appends interleaved samples in the input's own channel layout.
+    ///
+    /// When the buffer is full, the oldest audio is dropped so latency stays bounded.
+    pub fn push(&mut self, samples: &[f32]) {
+        let whole = samples.len() - samples.len() % self.channels;
+        let samples = &samples[whole.saturating_sub(self.capacity)..whole];
+        let overflow = (self.buffer.len() + samples.len()).saturating_sub(self.capacity);
+        self.buffer.drain(..overflow);
+        self.buffer.extend(samples);
+    }
+
+    /// Linear gain applied to the input.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain.max(0.0);
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Position between the left (-1) and right (1) output channels. At 0 both get the
+    /// input at full level; moving away from the centre turns the other side down.
+    pub fn set_pan(&mut self, pan: f32) {
+        self.pan = pan.clamp(-1.0, 1.0);
+    }
+
+    /// Places the input at `azimuth` degrees around the listener, from -90 (left) to 90
+    /// (right), using the mixer's renderer. `None` goes back to plain panning.
+    pub fn set_azimuth(&mut self, azimuth: Option<f32>) {
+        self.spatializer = match (azimuth, self.spatializer.take()) {
+            (None, _) => None,
+            (Some(azimuth), Some(mut spatializer)) => {
+                spatializer.set_azimuth(azimuth);
+                Some(spatializer)
+            }
+            (Some(azimuth), None) => {
+                Some(Spatializer::new(self.renderer, self.sample_rate).with_azimuth(azimuth))
+            }
+        };
+    }
+
+    pub fn azimuth(&self) -> Option<f32> {
+        self.spatializer.as_ref().map(Spatializer::azimuth)
+    }
+
+    /// Publishes the input's audio as it is mixed, downmixed and before gain, to a scope.
+    pub fn set_tap(&mut self, tap: Option<Tap>) {
+        self.tap = tap;
+    }
+
+    /// Plays out whatever is buffered, without waiting to fill up, then removes the input.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    fn target_gain(&self) -> f32 {
+        if self.muted { 0.0 } else { self.gain }
+    }
+
+    /// Adds as much of this input as is buffered into `out`.
+    fn mix_into(&mut self, out: &mut [f32], out_channels: usize, mixer: &Params) {
+        let frames = out.len() / out_channels;
+        let available = self.buffered();
+        self.level = 0.0;
+        if self.state == InputState::Buffering {
+            if available == 0 || (available < mixer.prebuffer && !self.finished) {
+                return;
+            }
+            self.state = InputState::Playing;
+            self.faded_in = 0;
+        }
+
+        // Running dry: fade out over the last frames we have rather than stopping dead.
+        let playing = frames.min(available);
+        let fade_out_from = if playing < frames {
+            self.state = InputState::Buffering;
+            playing.saturating_sub(mixer.fade)
+        } else {
+            usize::MAX
+        };
+
+        let target_gain = self.target_gain();
+        let mut energy = 0.0;
+        for (index, frame) in out.chunks_exact_mut(out_channels).take(playing).enumerate() {
+            let (left, right) = match self.channels {
+                1 => {
+                    let sample = self.buffer.pop_front().unwrap_or_default();
+                    (sample, sample)
+                }
+                channels => {
+                    let left = self.buffer.pop_front().unwrap_or_default();
+                    let right = self.buffer.pop_front().unwrap_or_default();
+                    self.buffer.drain(..(channels - 2).min(self.buffer.len()));
+                    (left, right)
+                }
+            };
+            energy += (left * left + right * right) * 0.5;
+            if let Some(tap) = &self.tap {
+                tap.write((left + right) * 0.5);
+            }
+
+            self.current_gain += (target_gain - self.current_gain) * mixer.smoothing;
+            self.current_pan += (self.pan - self.current_pan) * mixer.smoothing;
+            let mut envelope = 1.0;
+            if self.faded_in < mixer.fade {
+                self.faded_in += 1;
+                envelope = self.faded_in as f32 / mixer.fade as f32;
+            }
+            if index >= fade_out_from {
+                envelope *= (playing - index) as f32 / (playing - fade_out_from + 1) as f32;
+            }
+            let gain = self.current_gain * envelope;
+
+            if out_channels == 1 {
+                frame[0] += gain * (left + right) * 0.5;
+            } else if let Some(spatializer) = &mut self.spatializer {
+                let (left, right) = spatializer.render((left + right) * 0.5);
+                frame[0] += gain * left;
+                frame[1] += gain * right;
+            } else {
+                frame[0] += gain * (1.0 - self.current_pan).min(1.0) * left;
+                frame[1] += gain * (1.0 + self.current_pan).min(1.0) * right;
+            }
+        }
+        if frames > 0 {
+            self.level = (energy / frames as f32).sqrt();
+        }
+    }
+}
+
+/// Keeps a sum of several sources out of clipping.
+///
+/// Peaks above the limit turn the gain down at once, and the gain recovers smoothly once the
+/// signal gets quieter, so loud passages are compressed instead of clipped.
+pub struct Limiter {
+    gain: f32,
+    release: f32,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            gain: 1.0,
+            release: coefficient(RELEASE, sample_rate),
+        }
+    }
+
+    /// Limits interleaved `samples` in place.
+    pub fn process(&mut self, samples: &mut [f32], channels: usize) {
+        for frame in samples.chunks_exact_mut(channels.max(1)) {
+            let peak = frame
+                .iter()
+                .fold(0f32, |peak, sample| peak.max(sample.abs()));
+            let target = if peak > LIMIT { LIMIT / peak } else { 1.0 };
+            if target < self.gain {
+                self.gain = target;
+            } else {
+                self.gain += (target - self.gain) * self.release;
+            }
+            for sample in frame {
+                *sample = (*sample * self.gain).clamp(-1.0, 1.0);
+            }
+        }
+    }
+}
+
+/// Per-sample constants shared by every input.
+struct Params {
+    /// Frames an input buffers before it starts playing.
+    prebuffer: usize,
+    fade: usize,
+    smoothing: f32,
+}
+
+/// Sums any number of inputs into one interleaved output.
+///
+/// Mono inputs go to the first two output channels, stereo inputs keep their sides, and
+/// channels past the first two are left silent. Inputs placed at an azimuth are downmixed and
+/// rendered by a [`Spatializer`]. A mono output gets every input downmixed.
+pub struct Mixer {
+    sample_rate: u32,
+    channels: usize,
+    renderer: Renderer,
+    inputs: Vec<Input>,
+    next_id: u32,
+    /// Frames an input may buffer before the oldest are dropped.
+    capacity: usize,
+    params: Params,
+    limiter: Limiter,
+}
+
+impl Mixer {
+    /// Mixes into `channels` interleaved channels at `sample_rate`, buffering 20 ms before
+    /// an input starts and keeping at most half a second queued per input.
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            renderer: Renderer::default(),
+            inputs: Vec::new(),
+            next_id: 0,
+            capacity: frames(Duration::from_millis(500), sample_rate),
+            params: Params {
+                prebuffer: frames(Duration::from_millis(20), sample_rate),
+                fade: frames(FADE, sample_rate).max(1),
+                smoothing: coefficient(SMOOTHING, sample_rate),
+            },
+            limiter: Limiter::new(sample_rate),
+        }
+    }
+
+    /// Changes how much audio an input buffers before it starts, and how much at most.
+    pub fn with_buffering(mut self, prebuffer: Duration, max: Duration) -> Self {
+        self.params.prebuffer = frames(prebuffer, self.sample_rate);
+        self.capacity = frames(max, self.sample_rate)
+            .max(self.params.prebuffer)
+            .max(1);
+        self
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    /// Switches how inputs placed at an azimuth are rendered, e.g. when headphones are
+    /// plugged in.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+        for input in &mut self.inputs {
+            input.renderer = renderer;
+            if let Some(azimuth) = input.azimuth() {
+                input.spatializer =
+                    Some(Spatializer::new(renderer, self.sample_rate).with_azimuth(azimuth));
+            }
+        }
+    }
+
+    /// Adds an input with `channels` interleaved channels, at unity gain and centred.
+    pub fn add_input(&mut self, channels: usize) -> InputId {
+        let channels = channels.max(1);
+        let id = InputId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+        let capacity = self.capacity * channels;
+        self.inputs.push(Input {
+            id,
+            channels,
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
+            state: InputState::Buffering,
+            finished: false,
+            gain: 1.0,
+            muted: false,
+            pan: 0.0,
+            current_gain: 1.0,
+            current_pan: 0.0,
+            faded_in: 0,
+            level: 0.0,
+            tap: None,
+            spatializer: None,
+            renderer: self.renderer,
+            sample_rate: self.sample_rate,
+        });
+        id
+    }
+
+    /// Removes an input at once, dropping whatever it had buffered.
+    pub fn remove_input(&mut self, id: InputId) -> bool {
+        let count = self.inputs.len();
+        self.inputs.retain(|input| input.id != id);
+        self.inputs.len() != count
+    }
+
+    pub fn input(&mut self, id: InputId) -> Option<&mut Input> {
+        self.inputs.iter_mut().find(|input| input.id == id)
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = &Input> {
+        self.inputs.iter()
+    }
+
+    /// Overwrites `out` with the next interleaved frames of the mix.
+    pub fn mix(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+        let whole = out.len() - out.len() % self.channels;
+        let out = &mut out[..whole];
+        for input in &mut self.inputs {
+            input.mix_into(out, self.channels, &self.params);
+        }
+        self.inputs
+            .retain(|input| !(input.finished && input.buffer.is_empty()));
+        self.limiter.process(out, self.channels);
+    }
+}
+
+impl AudioSource for Mixer {
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        self.mix(buf);
+        buf.len()
+    }
+}
+
+fn frames(duration: Duration, sample_rate: u32) -> usize {
+    (duration.as_secs_f64() * sample_rate as f64).round() as usize
+}
+
+/// Per-sample weight of a one-pole smoother with time constant `tau`.
+fn coefficient(tau: Duration, sample_rate: u32) -> f32 {
+    1.0 - (-1.0 / (tau.as_secs_f32() * sample_rate as f32)).exp()
+}
@@ -0,0 +1,563 @@
+//! Opus multistream (RFC 7845, section 5.1.1): more channels than one Opus stream holds.
+//!
+//! The channels are coded as several mono and stereo ("coupled") streams, whose packets for
+//! one frame travel together as one. Every stream but the last uses the self-delimiting
+//! framing of RFC 6716, appendix B, so a decoder can tell where the next one starts. A
+//! [`ChannelMapping`] says which decoded channel each output channel plays, and is what an
+//! `OpusHead` carries from byte 18 on.
+//!
+//! Audio in and out is in WAV channel order, as devices and WAV files have it. Mapping
+//! family 1 orders channels the way Vorbis does, so they are reordered on the way.
+
+use opus::{Application, Bitrate, Channels};
+
+use crate::codec::{AudioDecoder, AudioEncoder, Codec, CodecError, OPUS_FRAME_MS};
+use crate::rtp::OPUS_CLOCK_RATE;
+
+/// Most channels mapping family 1 has a layout for: 7.1 surround.
+pub const MAX_SURROUND_CHANNELS: usize = 8;
+/// Mapping entry for an output channel that plays silence.
+pub const SILENT: u8 = 255;
+
+/// Largest frame in one Opus packet, in bytes (RFC 6716, section 3.2.1).
+const MAX_FRAME_BYTES: usize = 1275;
+/// Output buffer size recommended for each stream's encoder.
+const MAX_STREAM_PAYLOAD: usize = 4000;
+
+/// Streams, coupled streams and mapping of family 1, by channel count (RFC 7845,
+/// section 5.1.1.2).
+const VORBIS_LAYOUTS: [(u8, u8, &[u8]); MAX_SURROUND_CHANNELS] = [
+    (1, 0, &[0]),
+    (1, 1, &[0, 1]),
+    (2, 1, &[0, 2, 1]),
+    (2, 2, &[0, 1, 2, 3]),
+    (3, 2, &[0, 4, 1, 2, 3]),
+    (4, 2, &[0, 4, 1, 2, 3, 5]),
+    (4, 3, &[0, 4, 1, 2, 3, 5, 6]),
+    (5, 3, &[0, 6, 1, 2, 3, 4, 5, 7]),
+];
+
+/// For each channel in Vorbis order, the WAV channel it is, by channel count. WAV puts the
+/// centre after both fronts and the LFE right after them, Vorbis puts the centre between the
+/// fronts and the LFE last.
+const WAV_CHANNELS: [&[usize]; MAX_SURROUND_CHANNELS] = [
+    &[0],
+    &[0, 1],
+    &[0, 2, 1],
+    &[0, 1, 2, 3],
+    &[0, 2, 1, 3, 4],
+    &[0, 2, 1, 4, 5, 3],
+    &[0, 2, 1, 5, 6, 4, 3],
+    &[0, 2, 1, 6, 7, 4, 5, 3],
+];
+
+/// How channels are split into streams, as an `OpusHead` describes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelMapping {
+    /// 0 for mono and stereo, 1 for the Vorbis surround layouts, 255 for channels without a
+    /// layout.
+    pub family: u8,
+    pub streams: u8,
+    /// Streams coding two channels. They come first.
+    pub coupled: u8,
+    /// For each output channel, the decoded channel it plays: left and right of every
+    /// coupled stream, then one for every other stream. [`SILENT`] plays nothing.
+    pub mapping: Vec<u8>,
+}
+
+impl ChannelMapping {
+    /// The standard layout for `channels`: family 0 up to stereo, family 1 up to 7.1.
+    pub fn surround(channels: usize) -> Option<Self> {
+        let (streams, coupled, mapping) = *VORBIS_LAYOUTS.get(channels.checked_sub(1)?)?;
+        Some(Self {
+            family: if channels <= 2 { 0 } else { 1 },
+            streams,
+            coupled,
+            mapping: mapping.to_vec(),
+        })
+    }
+
+    /// Every channel in a mono stream of its own, in family 255, for channels that are not
+    /// speakers around a listener, such as the microphones of an array.
+    pub fn discrete(channels: usize) -> Option<Self> {
+        if !(1..=255).contains(&channels) {
+            return None;
+        }
+        Some(Self {
+            family: 255,
+            streams: channels as u8,
+            coupled: 0,
+            mapping: (0..channels as u8).collect(),
+        })
+    }
+
+    /// The standard layout for `channels` if there is one, or else discrete channels.
+    pub fn for_channels(channels: usize) -> Option<Self> {
+        Self::surround(channels).or_else(|| Self::discrete(channels))
+    }
+
+    pub fn channels(&self) -> usize {
+        self.mapping.len()
+    }
+
+    /// Channels the streams decode to, before mapping.
+    pub fn coded_channels(&self) -> usize {
+        self.streams as usize + self.coupled as usize
+    }
+
+    /// Channels in `stream`.
+    pub fn stream_channels(&self, stream: usize) -> usize {
+        if stream < self.coupled as usize { 2 } else { 1 }
+    }
+
+    /// The `OpusHead` fields from the mapping family on. Family 0 has no table.
+    pub fn head(&self) -> Vec<u8> {
+        let mut head = vec![self.family];
+        if self.family != 0 {
+            head.push(self.streams);
+            head.push(self.coupled);
+            head.extend_from_slice(&self.mapping);
+        }
+        head
+    }
+
+    /// The stream a decoded channel comes from, and its channel in that stream.
+    fn locate(&self, coded: usize) -> (usize, usize) {
+        let coupled = self.coupled as usize;
+        if coded < 2 * coupled {
+            (coded / 2, coded % 2)
+        } else {
+            (coded - coupled, 0)
+        }
+    }
+
+    /// For each mapping entry, the WAV channel it is.
+    fn wav_channels(&self) -> Vec<usize> {
+        match self.family {
+            1 => WAV_CHANNELS[self.channels() - 1].to_vec(),
+            _ => (0..self.channels()).collect(),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.streams > 0
+            && self.coupled <= self.streams
+            && self.coded_channels() <= 255
+            && !self.mapping.is_empty()
+            && (self.family != 1 || self.channels() <= MAX_SURROUND_CHANNELS)
+            && self
+                .mapping
+                .iter()
+                .all(|&coded| coded == SILENT || (coded as usize) < self.coded_channels())
+    }
+}
+
+/// Encodes any number of channels as Opus, one stream at a time.
+pub struct MultistreamEncoder {
+    encoders: Vec<opus::Encoder>,
+    mapping: ChannelMapping,
+    /// For each decoded channel, the input channel coded into it, if any.
+    sources: Vec<Option<usize>>,
+    sample_rate: u32,
+    stream_pcm: Vec<f32>,
+    stream_packet: Vec<u8>,
+}
+
+impl MultistreamEncoder {
+    pub fn new(
+        sample_rate: u32,
+        mapping: ChannelMapping,
+        application: Application,
+    ) -> Result<Self, CodecError> {
+        if !mapping.is_valid() {
+            return Err(CodecError::Channels(mapping.channels()));
+        }
+        let encoders = (0..mapping.streams as usize)
+            .map(|stream| {
+                let channels = match mapping.stream_channels(stream) {
+                    2 => Channels::Stereo,
+                    _ => Channels::Mono,
+                };
+                opus::Encoder::new(sample_rate, channels, application)
+            })
+            .collect::<Result<_, _>>()?;
+        let mut sources = vec![None; mapping.coded_channels()];
+        for (&coded, input) in mapping.mapping.iter().zip(mapping.wav_channels()) {
+            if let Some(source) = sources.get_mut(coded as usize) {
+                source.get_or_insert(input);
+            }
+        }
+        Ok(Self {
+            encoders,
+            mapping,
+            sources,
+            sample_rate,
+            stream_pcm: Vec::new(),
+            stream_packet: vec![0; MAX_STREAM_PAYLOAD],
+        })
+    }
+
+    pub fn mapping(&self) -> &ChannelMapping {
+        &self.mapping
+    }
+}
+
+impl AudioEncoder for MultistreamEncoder {
+    fn codec(&self) -> Codec {
+        Codec::Opus
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.mapping.channels()
+    }
+
+    fn clock_rate(&self) -> u32 {
+        OPUS_CLOCK_RATE
+    }
+
+    fn frame_len(&self, frame_ms: u32) -> Option<usize> {
+        let len = self.sample_rate as usize * frame_ms as usize / 1000 * self.channels();
+        OPUS_FRAME_MS.contains(&frame_ms).then_some(len)
+    }
+
+    fn supports_fec(&self) -> bool {
+        true
+    }
+
+    fn supports_dtx(&self) -> bool {
+        true
+    }
+
+    fn encode(&mut self, pcm: &[f32], out: &mut [u8]) -> Result<usize, CodecError> {
+        let channels = self.channels();
+        let frames = pcm.len() / channels;
+        let mut written = 0;
+        let mut coded = 0;
+        let streams = self.encoders.len();
+        for (stream, encoder) in self.encoders.iter_mut().enumerate() {
+            let stream_channels = self.mapping.stream_channels(stream);
+            let sources = &self.sources[coded..coded + stream_channels];
+            coded += stream_channels;
+            self.stream_pcm.clear();
+            for frame in pcm.chunks_exact(channels).take(frames) {
+                self.stream_pcm.extend(
+                    sources
+                        .iter()
+                        .map(|source| source.map_or(0.0, |i| frame[i])),
+                );
+            }
+
+            let size = encoder.encode_float(&self.stream_pcm, &mut self.stream_packet)?;
+            let packet = &self.stream_packet[..size];
+            let last = stream + 1 == streams;
+            let framed = match last {
+                true => packet.to_vec(),
+                false => self_delimit(packet)?,
+            };
+            out.get_mut(written..written + framed.len())
+                .ok_or(CodecError::BufferTooSmall)?
+                .copy_from_slice(&framed);
+            written += framed.len();
+        }
+        Ok(written)
+    }
+
+    /// Shares `bitrate` out by channel, so a coupled stream gets twice what a mono one does.
+    fn set_bitrate(&mut self, bitrate: u32) -> Result<(), CodecError> {
+        let coded = self.mapping.coded_channels() as u32;
+        for (stream, encoder) in self.encoders.iter_mut().enumerate() {
+            let share = bitrate * self.mapping.stream_channels(stream) as u32 / coded;
+            encoder.set_bitrate(Bitrate::Bits(share as i32))?;
+        }
+        Ok(())
+    }
+
+    fn set_fec(&mut self, fec: bool, expected_loss: u8) -> Result<(), CodecError> {
+        for encoder in &mut self.encoders {
+            encoder.set_inband_fec(fec)?;
+            encoder.set_packet_loss_perc(expected_loss as i32)?;
+        }
+        Ok(())
+    }
+
+    fn set_dtx(&mut self, dtx: bool) -> Result<(), CodecError> {
+        for encoder in &mut self.encoders {
+            encoder.set_dtx(dtx)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes multistream Opus packets into any number of channels.
+pub struct MultistreamDecoder {
+    decoders: Vec<opus::Decoder>,
+    mapping: ChannelMapping,
+    /// For each output channel, where in which stream it is decoded, or `None` for silence.
+    targets: Vec<Option<(usize, usize)>>,
+    sample_rate: u32,
+    stream_pcm: Vec<Vec<f32>>,
+}
+
+impl MultistreamDecoder {
+    pub fn new(sample_rate: u32, mapping: ChannelMapping) -> Result<Self, CodecError> {
+        if !mapping.is_valid() {
+            return Err(CodecError::Channels(mapping.channels()));
+        }
+        let decoders = (0..mapping.streams as usize)
+            .map(|stream| {
+                let channels = match mapping.stream_channels(stream) {
+                    2 => Channels::Stereo,
+                    _ => Channels::Mono,
+                };
+                opus::Decoder::new(sample_rate, channels)
+            })
+            .collect::<Result<_, _>>()?;
+        let mut targets = vec![None; mapping.channels()];
+        for (&coded, output) in mapping.mapping.iter().zip(mapping.wav_channels()) {
+            if coded != SILENT {
+                targets[output] = Some(mapping.locate(coded as usize));
+            }
+        }
+        Ok(Self {
+            stream_pcm: vec![Vec::new(); mapping.streams as usize],
+            decoders,
+            mapping,
+            targets,
+            sample_rate,
+        })
+    }
+
+    pub fn mapping(&self) -> &ChannelMapping {
+        &self.mapping
+    }
+
+    /// Decodes every stream of `packet`, or conceals them all if it is empty, and
+    /// interleaves the output channels into `out`.
+    fn decode_streams(
+        &mut self,
+        packet: &[u8],
+        fec: bool,
+        out: &mut [f32],
+    ) -> Result<usize, CodecError> {
+        let channels = self.channels();
+        let frames = out.len() / channels;
+        let streams = self.decoders.len();
+        let mut rest = packet;
+        let mut decoded = frames;
+        for (stream, decoder) in self.decoders.iter_mut().enumerate() {
+            let stream_packet = match rest.is_empty() {
+                true if packet.is_empty() => Vec::new(),
+                true => return Err(CodecError::InvalidPacket),
+                false if stream + 1 == streams => std::mem::take(&mut rest).to_vec(),
+                false => {
+                    let (stream_packet, next) = undelimit(rest)?;
+                    rest = next;
+                    stream_packet
+                }
+            };
+            let pcm = &mut self.stream_pcm[stream];
+            pcm.resize(frames * self.mapping.stream_channels(stream), 0.0);
+            let samples = decoder.decode_float(&stream_packet, pcm, fec)?;
+            decoded = decoded.min(samples);
+        }
+
+        for (frame, samples) in out.chunks_exact_mut(channels).take(decoded).enumerate() {
+            for (sample, target) in samples.iter_mut().zip(&self.targets) {
+                *sample = match *target {
+                    Some((stream, channel)) => {
+                        let stream_channels = self.mapping.stream_channels(stream);
+                        self.stream_pcm[stream][frame * stream_channels + channel]
+                    }
+                    None => 0.0,
+                };
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+impl AudioDecoder for MultistreamDecoder {
+    fn codec(&self) -> Codec {
+        Codec::Opus
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.mapping.channels()
+    }
+
+    fn supports_plc(&self) -> bool {
+        true
+    }
+
+    fn supports_fec(&self) -> bool {
+        true
+    }
+
+    fn decode(&mut self, packet: &[u8], out: &mut [f32]) -> Result<usize, CodecError> {
+        self.decode_streams(packet, false, out)
+    }
+
+    fn decode_fec(&mut self, packet: &[u8], out: &mut [f32]) -> Result<usize, CodecError> {
+        self.decode_streams(packet, true, out)
+    }
+}
+
+/// Reads a frame length: one byte below 252, two otherwise. Returns it and the bytes read.
+fn read_len(data: &[u8]) -> Option<(usize, usize)> {
+    let first = *data.first()? as usize;
+    if first < 252 {
+        return Some((first, 1));
+    }
+    Some((first + 4 * *data.get(1)? as usize, 2))
+}
+
+fn write_len(len: usize, out: &mut Vec<u8>) -> Result<(), CodecError> {
+    if len > MAX_FRAME_BYTES {
+        return Err(CodecError::InvalidPacket);
+    }
+    if len < 252 {
+        out.push(len as u8);
+    } else {
+        let first = 252 + (len & 0x03);
+        out.push(first as u8);
+        out.push(((len - first) >> 2) as u8);
+    }
+    Ok(())
+}
+
+/// Frame count, VBR flag, and where the frame lengths and padding of a code 3 packet are:
+/// returns (frames, vbr, offset of the first length, padding bytes at the end).
+fn code3_layout(data: &[u8]) -> Option<(usize, bool, usize, usize)> {
+    let count = *data.get(1)?;
+    let (vbr, padded, frames) = (
+        count & 0x80 != 0,
+        count & 0x40 != 0,
+        (count & 0x3f) as usize,
+    );
+    let mut pos = 2;
+    let mut padding = 0;
+    // Each padding length byte of 255 adds 254 bytes and another length byte.
+    let mut more = padded;
+    while more {
+        let byte = *data.get(pos)? as usize;
+        pos += 1;
+        padding += byte.min(254);
+        more = byte == 255;
+    }
+    (frames > 0).then_some((frames, vbr, pos, padding))
+}
+
+/// Rewrites a packet in the self-delimiting framing, which adds the length of one more
+/// frame so that another packet can follow.
+fn self_delimit(packet: &[u8]) -> Result<Vec<u8>, CodecError> {
+    let &toc = packet.first().ok_or(CodecError::InvalidPacket)?;
+    let mut out = vec![toc];
+    let (split, len) = match toc & 0x03 {
+        0 => (1, packet.len() - 1),
+        1 => (1, (packet.len() - 1) / 2),
+        2 => {
+            let (first, used) = read_len(&packet[1..]).ok_or(CodecError::InvalidPacket)?;
+            let second = (packet.len() - 1 - used)
+                .checked_sub(first)
+                .ok_or(CodecError::InvalidPacket)?;
+            (1 + used, second)
+        }
+        _ => {
+            let (frames, vbr, mut pos, padding) =
+                code3_layout(packet).ok_or(CodecError::InvalidPacket)?;
+            let mut data = packet
+                .len()
+                .checked_sub(pos + padding)
+                .ok_or(CodecError::InvalidPacket)?;
+            if vbr {
+                for _ in 1..frames {
+                    let (len, used) = read_len(&packet[pos..]).ok_or(CodecError::InvalidPacket)?;
+                    pos += used;
+                    data = data
+                        .checked_sub(len + used)
+                        .ok_or(CodecError::InvalidPacket)?;
+                }
+                (pos, data)
+            } else {
+                (pos, data / frames)
+            }
+        }
+    };
+    out.extend_from_slice(&packet[1..split]);
+    write_len(len, &mut out)?;
+    out.extend_from_slice(&packet[split..]);
+    Ok(out)
+}
+
+/// Splits the self-delimited packet `data` starts with off the rest, and returns it in the
+/// normal framing.
+fn undelimit(data: &[u8]) -> Result<(Vec<u8>, &[u8]), CodecError> {
+    let &toc = data.first().ok_or(CodecError::InvalidPacket)?;
+    // Where the added length is, and how many bytes of frames and padding follow it.
+    let (pos, body) = match toc & 0x03 {
+        0 => (1, read_len(&data[1..]).ok_or(CodecError::InvalidPacket)?.0),
+        1 => (
+            1,
+            2 * read_len(&data[1..]).ok_or(CodecError::InvalidPacket)?.0,
+        ),
+        2 => {
+            let (first, used) = read_len(&data[1..]).ok_or(CodecError::InvalidPacket)?;
+            let (second, _) = read_len(&data[1 + used..]).ok_or(CodecError::InvalidPacket)?;
+            (1 + used, first + second)
+        }
+        _ => {
+            let (frames, vbr, mut pos, padding) =
+                code3_layout(data).ok_or(CodecError::InvalidPacket)?;
+            let (last, _) =
+                read_len(data.get(pos..).unwrap_or_default()).ok_or(CodecError::InvalidPacket)?;
+            if !vbr {
+                (pos, frames * last + padding)
+            } else {
+                let mut body = padding;
+                for _ in 1..frames {
+                    let (len, used) = read_len(data.get(pos..).unwrap_or_default())
+                        .ok_or(CodecError::InvalidPacket)?;
+                    pos += used;
+                    body += len;
+                }
+                let (last, _) = read_len(data.get(pos..).unwrap_or_default())
+                    .ok_or(CodecError::InvalidPacket)?;
+                (pos, body + last)
+            }
+        }
+    };
+    let (_, used) =
+        read_len(data.get(pos..).unwrap_or_default()).ok_or(CodecError::InvalidPacket)?;
+    let end = pos + used + body;
+    let rest = data.get(end..).ok_or(CodecError::InvalidPacket)?;
+    let mut packet = data[..pos].to_vec();
+    packet.extend_from_slice(&data[pos + used..end]);
+    Ok((packet, rest))
+}
+
+/// A packet with no audio in any stream, from the TOC byte of one made by
+/// [`ogg::silence`](crate::ogg::silence). For one stream it is that packet.
+pub fn silence(mapping: &ChannelMapping, toc: u8) -> Vec<u8> {
+    let mut packet = Vec::new();
+    for stream in 0..mapping.streams as usize {
+        let stereo = if mapping.stream_channels(stream) == 2 {
+            0x04
+        } else {
+            0
+        };
+        packet.push(toc & !0x04 | stereo);
+        // No frame data: the self-delimited length of every stream but the last is zero.
+        if stream + 1 < mapping.streams as usize {
+            packet.push(0);
+        }
+    }
+    packet
+}
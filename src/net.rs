@@ -0,0 +1,31 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+use crate::config::{Multicast, NetConfig};
+
+/// Binds a UDP socket for `config`, joining its multicast group if one is configured.
+pub fn bind_socket(config: &NetConfig) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(config.bind)?;
+    match config.multicast {
+        Some(Multicast::V4 { group, interface }) => {
+            socket.join_multicast_v4(&group, &interface)?;
+        }
+        Some(Multicast::V6 { group, interface }) => {
+            socket.join_multicast_v6(&group, interface)?;
+        }
+        None => {}
+    }
+    Ok(socket)
+}
+
+/// The address a peer at `peer` can reach us on: the bound one, or for a wildcard bind, that
+/// of the interface the system routes `peer` through.
+pub fn advertised_ip(bind: SocketAddr, peer: SocketAddr) -> io::Result<IpAddr> {
+    if !bind.ip().is_unspecified() {
+        return Ok(bind.ip());
+    }
+    // Connecting a UDP socket sends nothing, but picks the route and so the source address.
+    let probe = UdpSocket::bind(SocketAddr::new(bind.ip(), 0))?;
+    probe.connect(peer)?;
+    Ok(probe.local_addr()?.ip())
+}
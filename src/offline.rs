@@ -0,0 +1,122 @@
+//! Running audio processing offline, faster than realtime.
+//!
+//! An [`Offline`] run pulls its source in fixed-size blocks on the calling thread, as fast
+//! as the processing allows, and keeps a [`VirtualClock`] in step with the audio. A minute
+//! of call goes through in milliseconds, with output that can be asserted on.
+
+use std::time::Duration;
+
+use crate::audio::AudioSource;
+use crate::clock::VirtualClock;
+use crate::pipeline::{Frame, Sink, Transform};
+
+/// A source, the processing of its audio and where the result goes, driven block by block.
+pub struct Offline<S, T: Transform, K> {
+    source: S,
+    transform: T,
+    sink: K,
+    clock: VirtualClock,
+    channels: usize,
+    block_len: usize,
+    block_duration: Duration,
+    output: Vec<T::Output>,
+}
+
+impl<S, T, K> Offline<S, T, K>
+where
+    S: AudioSource,
+    T: Transform<Input = Frame>,
+    K: Sink<Input = T::Output>,
+{
+    pub fn new(
+        source: S,
+        sample_rate: u32,
+        channels: usize,
+        block_ms: u32,
+        transform: T,
+        sink: K,
+    ) -> Self {
+        Self::with_clock(
+            source,
+            sample_rate,
+            channels,
+            block_ms,
+            transform,
+            sink,
+            VirtualClock::new(),
+        )
+    }
+
+    /// Keeps `clock` in step, such as one already handed to the nodes that need the time.
+    pub fn with_clock(
+        source: S,
+        sample_rate: u32,
+        channels: usize,
+        block_ms: u32,
+        transform: T,
+        sink: K,
+        clock: VirtualClock,
+    ) -> Self {
+        Self {
+            source,
+            transform,
+            sink,
+            clock,
+            channels,
+            block_len: (sample_rate * block_ms / 1000) as usize * channels,
+            block_duration: Duration::from_millis(block_ms as u64),
+            output: Vec::new(),
+        }
+    }
+
+    /// The time the run has reached.
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// Processes one block, padded with silence if the source runs short, and returns how
+    /// many samples the source had.
+    pub fn step(&mut self) -> usize {
+        let mut samples = vec![0.0; self.block_len];
+        let read = self.source.read(&mut samples);
+        self.transform.process(
+            Frame {
+                samples,
+                channels: self.channels,
+            },
+            &mut self.output,
+        );
+        for item in self.output.drain(..) {
+            self.sink.push(item);
+        }
+        self.clock.advance(self.block_duration);
+        read
+    }
+
+    /// Processes `duration` of audio, rounded up to whole blocks, and returns how many
+    /// blocks that took.
+    pub fn run_for(&mut self, duration: Duration) -> usize {
+        let blocks = duration.as_nanos().div_ceil(self.block_duration.as_nanos()) as usize;
+        for _ in 0..blocks {
+            self.step();
+        }
+        blocks
+    }
+
+    /// Processes blocks until the source runs dry, and returns how many that took. Never
+    /// returns for endless sources such as a tone.
+    pub fn run_to_end(&mut self) -> usize {
+        let mut blocks = 0;
+        loop {
+            let read = self.step();
+            blocks += 1;
+            if read < self.block_len {
+                return blocks;
+            }
+        }
+    }
+
+    pub fn into_sink(self) -> K {
+        self.sink
+    }
+}
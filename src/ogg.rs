@@ -0,0 +1,231 @@
+//! Writing Opus packets to Ogg files (RFC 7845), as `.opus` players expect them.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::multistream::{self, ChannelMapping};
+use crate::rtp;
+
+/// Sample rate Ogg Opus positions are counted in, whatever the encoder ran at.
+pub const GRANULE_RATE: u32 = 48_000;
+/// Audio gathered on one page before it is written: about a second, as is usual.
+const PAGE_DURATION: u64 = GRANULE_RATE as u64;
+/// Lacing values a page has room for: one per 255 bytes of each packet, plus one.
+const MAX_LACING: usize = 255;
+const VENDOR: &str = "test-gpui";
+
+const FIRST_PAGE: u8 = 0x02;
+const LAST_PAGE: u8 = 0x04;
+
+/// Samples per channel in an Opus packet at 48 kHz, from its TOC byte (RFC 6716, 3.1).
+pub fn packet_samples(packet: &[u8]) -> Option<usize> {
+    let toc = *packet.first()?;
+    let config = toc >> 3;
+    // Frame sizes in units of 2.5 ms, which is 120 samples.
+    let frame = match config {
+        0..=11 => [4, 8, 16, 24][config as usize % 4],
+        12..=15 => [4, 8][config as usize % 2],
+        _ => [1, 2, 4, 8][config as usize % 4],
+    };
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => (*packet.get(1)? & 0x3f) as usize,
+    };
+    Some(frames * frame * 120)
+}
+
+/// Packets carrying no audio, which decoders conceal like lost ones, covering `samples` at
+/// 48 kHz in as few packets as the Opus frame sizes allow.
+pub fn silence(samples: usize, stereo: bool) -> Vec<[u8; 1]> {
+    let stereo = if stereo { 0x04 } else { 0 };
+    let mut remaining = samples / 120;
+    let mut packets = Vec::new();
+    // CELT-only configurations 31 to 28: 20, 10, 5 and 2.5 ms.
+    for (config, frame) in [(31u8, 8), (30, 4), (29, 2), (28, 1)] {
+        while remaining >= frame {
+            packets.push([config << 3 | stereo]);
+            remaining -= frame;
+        }
+    }
+    packets
+}
+
+/// Streams Opus packets into an Ogg file, one logical stream laid out as its
+/// [`ChannelMapping`] says.
+pub struct OggOpusWriter {
+    file: BufWriter<File>,
+    serial: u32,
+    sequence: u32,
+    mapping: ChannelMapping,
+    /// Samples at 48 kHz in every packet written so far.
+    position: u64,
+    /// Packets waiting to go out on the next page.
+    packets: Vec<Vec<u8>>,
+    page_start: u64,
+    finished: bool,
+}
+
+impl OggOpusWriter {
+    /// Creates `path` and writes the Opus headers, for packets coded in the layout
+    /// [`ChannelMapping::for_channels`] picks, as [`Codec::encoder`](crate::codec::Codec::encoder)
+    /// codes them. `input_rate` is what the audio was encoded from, which players may
+    /// resample back to.
+    pub fn create(path: impl AsRef<Path>, channels: u8, input_rate: u32) -> io::Result<Self> {
+        let mapping = ChannelMapping::for_channels(channels as usize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no channels"))?;
+        Self::with_mapping(path, mapping, input_rate)
+    }
+
+    /// Creates `path` and writes the Opus headers, with the channel mapping table of
+    /// `mapping` for multistream packets.
+    pub fn with_mapping(
+        path: impl AsRef<Path>,
+        mapping: ChannelMapping,
+        input_rate: u32,
+    ) -> io::Result<Self> {
+        let channels = mapping.channels() as u8;
+        let head_mapping = mapping.head();
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            serial: rtp::random_ssrc(),
+            sequence: 0,
+            mapping,
+            position: 0,
+            packets: Vec::new(),
+            page_start: 0,
+            finished: false,
+        };
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(channels);
+        // No pre-skip: packets are stored as they were sent, encoder delay included.
+        head.extend_from_slice(&0u16.to_le_bytes());
+        head.extend_from_slice(&input_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes());
+        head.extend_from_slice(&head_mapping);
+        writer.write_page(&[head], 0, FIRST_PAGE)?;
+
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+        tags.extend_from_slice(VENDOR.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        writer.write_page(&[tags], 0, 0)?;
+        Ok(writer)
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.mapping.channels() as u8
+    }
+
+    pub fn mapping(&self) -> &ChannelMapping {
+        &self.mapping
+    }
+
+    /// Samples at 48 kHz written so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Appends one packet. Packets whose duration cannot be read are skipped.
+    pub fn write_packet(&mut self, packet: &[u8]) -> io::Result<()> {
+        let Some(samples) = packet_samples(packet) else {
+            return Ok(());
+        };
+        // The page before always ends on a packet, so the last page has one to flag.
+        let lacing: usize = self.packets.iter().map(|packet| lacing_len(packet)).sum();
+        if self.position - self.page_start >= PAGE_DURATION
+            || lacing + lacing_len(packet) > MAX_LACING
+        {
+            self.flush_page(0)?;
+        }
+        self.packets.push(packet.to_vec());
+        self.position += samples as u64;
+        Ok(())
+    }
+
+    /// Appends packets of silence until `position` samples at 48 kHz are written.
+    pub fn pad_to(&mut self, position: u64) -> io::Result<()> {
+        let missing = position.saturating_sub(self.position) as usize;
+        for [toc] in silence(missing, false) {
+            self.write_packet(&multistream::silence(&self.mapping, toc))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last page and flushes the file.
+    pub fn finalize(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.flush_page(LAST_PAGE)?;
+        self.file.flush()
+    }
+
+    fn flush_page(&mut self, flags: u8) -> io::Result<()> {
+        let packets = std::mem::take(&mut self.packets);
+        self.page_start = self.position;
+        self.write_page(&packets, self.position, flags)
+    }
+
+    fn write_page(&mut self, packets: &[Vec<u8>], granule: u64, flags: u8) -> io::Result<()> {
+        let mut lacing = Vec::new();
+        for packet in packets {
+            lacing.extend(std::iter::repeat_n(255, packet.len() / 255));
+            lacing.push((packet.len() % 255) as u8);
+        }
+        // A page without packets has no position.
+        let granule = if packets.is_empty() {
+            u64::MAX
+        } else {
+            granule
+        };
+
+        let mut page = b"OggS".to_vec();
+        page.push(0);
+        page.push(flags);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&0u32.to_le_bytes());
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        for packet in packets {
+            page.extend_from_slice(packet);
+        }
+        let checksum = crc(&page);
+        page[22..26].copy_from_slice(&checksum.to_le_bytes());
+
+        self.sequence += 1;
+        self.file.write_all(&page)
+    }
+}
+
+impl Drop for OggOpusWriter {
+    fn drop(&mut self) {
+        let _ = self.finalize();
+    }
+}
+
+fn lacing_len(packet: &[u8]) -> usize {
+    packet.len() / 255 + 1
+}
+
+/// The CRC-32 of Ogg pages: polynomial 0x04c11db7, not reflected, no initial or final XOR.
+pub fn crc(bytes: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in bytes {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
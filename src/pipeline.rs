@@ -0,0 +1,694 @@
+//! Composing audio processing out of nodes connected by typed ports.
+//!
+//! A [`Source`] produces items, a [`Transform`] turns each item into zero or more others and
+//! a [`Sink`] consumes them. Items are PCM [`Frame`]s, encoded [`Packet`]s or anything else
+//! that can cross threads, and the [`Builder`] only connects ports of matching types, so
+//! capture, DSP, encoding, transport, decoding and playback can be put together in any
+//! order that makes sense.
+//!
+//! Once started, every node runs on a worker thread of its own, linked to the next by a
+//! bounded queue. Device callbacks are realtime and stay outside: they exchange audio with
+//! the pipeline through a [`SharedBuffer`], read by a [`PacedSource`] or written by a
+//! [`SinkWriter`].
+//!
+//! [`SharedBuffer`]: crate::audio::SharedBuffer
+
+use std::io;
+use std::marker::PhantomData;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tracing::{error_span, warn};
+
+use crate::audio::{AudioSink, AudioSource};
+use crate::call::is_transient;
+use crate::clock::{Clock, SystemClock};
+use crate::codec::{AudioDecoder, AudioEncoder, CodecError};
+use crate::rtp::{HEADER_LEN, RtpHeader};
+use crate::util::convert_channels;
+
+/// Items a link between two nodes holds before the node feeding it waits.
+pub const QUEUE_CAPACITY: usize = 32;
+/// Longest a source should block, so a stopped pipeline winds down promptly.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Largest frame per channel we decode: 120 ms at 48 kHz, the longest Opus packet.
+const MAX_FRAME: usize = 48_000 * 120 / 1000;
+/// Output buffer size recommended for the Opus encoder, and plenty for the others.
+const MAX_PAYLOAD: usize = 4000;
+const MAX_PACKET: usize = HEADER_LEN + MAX_PAYLOAD;
+/// Most lost packets concealed at once; longer gaps are treated as a new start.
+const MAX_CONCEALED: u16 = 5;
+
+/// A block of interleaved PCM audio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub samples: Vec<f32>,
+    pub channels: usize,
+}
+
+/// One encoded frame and its place in the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub sequence: u16,
+    /// When the first sample plays, in RTP clock units.
+    pub timestamp: u32,
+    pub payload: Vec<u8>,
+}
+
+/// The start of a pipeline: a microphone, a socket, a file.
+pub trait Source: Send + 'static {
+    type Output: Send + 'static;
+
+    /// Adds whatever is available to `out`, waiting up to about [`POLL_INTERVAL`] when
+    /// nothing is. Returns false once there will never be more.
+    fn pull(&mut self, out: &mut Vec<Self::Output>) -> bool;
+}
+
+/// A step in the middle of a pipeline: a filter, an encoder, a decoder.
+pub trait Transform: Send + 'static {
+    type Input: Send + 'static;
+    type Output: Send + 'static;
+
+    /// Turns `input` into zero or more items added to `out`.
+    fn process(&mut self, input: Self::Input, out: &mut Vec<Self::Output>);
+
+    /// Combines this step with `next` into one, run on the same thread.
+    fn chain<N: Transform<Input = Self::Output>>(self, next: N) -> Chain<Self, N>
+    where
+        Self: Sized,
+    {
+        Chain {
+            first: self,
+            second: next,
+            between: Vec::new(),
+        }
+    }
+}
+
+/// The end of a pipeline: speakers, a socket, a recording.
+pub trait Sink: Send + 'static {
+    type Input: Send + 'static;
+
+    fn push(&mut self, input: Self::Input);
+}
+
+type Worker = Box<dyn FnOnce(&AtomicBool) + Send>;
+
+struct Node {
+    name: String,
+    worker: Worker,
+}
+
+/// A pipeline under construction, whose last node outputs `T`.
+pub struct Builder<T> {
+    nodes: Vec<Node>,
+    output: Receiver<T>,
+}
+
+/// A complete pipeline, from source to sink, ready to start.
+pub struct Pipeline {
+    nodes: Vec<Node>,
+}
+
+impl Pipeline {
+    /// Begins a pipeline at `source`. Node names show in logs and thread names.
+    pub fn source<S: Source>(name: impl Into<String>, mut source: S) -> Builder<S::Output> {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let worker = move |running: &AtomicBool| {
+            let mut out = Vec::new();
+            while running.load(Ordering::Relaxed) {
+                let more = source.pull(&mut out);
+                if !forward(&mut out, &tx) || !more {
+                    break;
+                }
+            }
+        };
+        Builder {
+            nodes: vec![Node {
+                name: name.into(),
+                worker: Box::new(worker),
+            }],
+            output: rx,
+        }
+    }
+
+    /// Names of the nodes from source to sink.
+    pub fn nodes(&self) -> impl Iterator<Item = &str> {
+        self.nodes.iter().map(|node| node.name.as_str())
+    }
+
+    /// Starts every node on a worker thread of its own.
+    pub fn start(self) -> io::Result<Running> {
+        let mut running = Running {
+            running: Arc::new(AtomicBool::new(true)),
+            threads: Vec::new(),
+        };
+        for node in self.nodes {
+            let flag = Arc::clone(&running.running);
+            let span = error_span!("node", name = %node.name);
+            let worker = node.worker;
+            // If a thread cannot start, dropping `running` stops the ones that did.
+            let thread = thread::Builder::new()
+                .name(node.name)
+                .spawn(move || span.in_scope(|| worker(&flag)))?;
+            running.threads.push(thread);
+        }
+        Ok(running)
+    }
+}
+
+impl<T: Send + 'static> Builder<T> {
+    /// Passes everything through `node` next.
+    pub fn then<N: Transform<Input = T>>(
+        self,
+        name: impl Into<String>,
+        mut node: N,
+    ) -> Builder<N::Output> {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let input = self.output;
+        let worker = move |_: &AtomicBool| {
+            let mut out = Vec::new();
+            for item in input {
+                node.process(item, &mut out);
+                if !forward(&mut out, &tx) {
+                    break;
+                }
+            }
+        };
+        extend(self.nodes, name.into(), Box::new(worker), rx)
+    }
+
+    /// Passes everything through `f` next.
+    pub fn map<O: Send + 'static>(
+        self,
+        name: impl Into<String>,
+        f: impl FnMut(T) -> O + Send + 'static,
+    ) -> Builder<O> {
+        self.then(
+            name,
+            Map {
+                f,
+                types: PhantomData,
+            },
+        )
+    }
+
+    /// Hands a copy of everything to `sink` on the way, such as a recording of what is
+    /// sent.
+    pub fn tee<S: Sink<Input = T>>(self, name: impl Into<String>, mut sink: S) -> Builder<T>
+    where
+        T: Clone,
+    {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let input = self.output;
+        let worker = move |_: &AtomicBool| {
+            for item in input {
+                sink.push(item.clone());
+                if tx.send(item).is_err() {
+                    break;
+                }
+            }
+        };
+        extend(self.nodes, name.into(), Box::new(worker), rx)
+    }
+
+    /// Ends the pipeline at `sink`.
+    pub fn sink<S: Sink<Input = T>>(self, name: impl Into<String>, mut sink: S) -> Pipeline {
+        let input = self.output;
+        let worker = move |_: &AtomicBool| {
+            for item in input {
+                sink.push(item);
+            }
+        };
+        let mut nodes = self.nodes;
+        nodes.push(Node {
+            name: name.into(),
+            worker: Box::new(worker),
+        });
+        Pipeline { nodes }
+    }
+}
+
+/// Adds the node producing `output` to the end of `nodes`.
+fn extend<U>(
+    mut nodes: Vec<Node>,
+    name: String,
+    worker: Worker,
+    output: Receiver<U>,
+) -> Builder<U> {
+    nodes.push(Node { name, worker });
+    Builder { nodes, output }
+}
+
+/// Sends everything in `out` downstream. Returns false once nobody is listening.
+fn forward<T>(out: &mut Vec<T>, tx: &SyncSender<T>) -> bool {
+    out.drain(..).all(|item| tx.send(item).is_ok())
+}
+
+/// A started pipeline. Dropping it stops it.
+pub struct Running {
+    running: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Running {
+    /// Whether any node is still at work. A pipeline also ends by itself once its source
+    /// runs dry.
+    pub fn is_running(&self) -> bool {
+        self.threads.iter().any(|thread| !thread.is_finished())
+    }
+
+    /// Stops the source, lets what it already produced drain through and waits for every
+    /// node to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    /// Waits for the pipeline to end by itself.
+    pub fn wait(mut self) {
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+struct Map<F, I, O> {
+    f: F,
+    types: PhantomData<fn(I) -> O>,
+}
+
+impl<F, I, O> Transform for Map<F, I, O>
+where
+    F: FnMut(I) -> O + Send + 'static,
+    I: Send + 'static,
+    O: Send + 'static,
+{
+    type Input = I;
+    type Output = O;
+
+    fn process(&mut self, input: I, out: &mut Vec<O>) {
+        out.push((self.f)(input));
+    }
+}
+
+/// Two transforms run one after the other, made by [`Transform::chain`].
+pub struct Chain<A: Transform, B> {
+    first: A,
+    second: B,
+    between: Vec<A::Output>,
+}
+
+impl<A: Transform, B: Transform<Input = A::Output>> Transform for Chain<A, B> {
+    type Input = A::Input;
+    type Output = B::Output;
+
+    fn process(&mut self, input: A::Input, out: &mut Vec<B::Output>) {
+        self.first.process(input, &mut self.between);
+        for item in self.between.drain(..) {
+            self.second.process(item, out);
+        }
+    }
+}
+
+/// Items sent from elsewhere in the program, until every sender is gone.
+impl<T: Send + 'static> Source for Receiver<T> {
+    type Output = T;
+
+    fn pull(&mut self, out: &mut Vec<T>) -> bool {
+        match self.recv_timeout(POLL_INTERVAL) {
+            Ok(item) => {
+                out.push(item);
+                true
+            }
+            Err(RecvTimeoutError::Timeout) => true,
+            Err(RecvTimeoutError::Disconnected) => false,
+        }
+    }
+}
+
+/// Hands items to elsewhere in the program, dropping them once nobody receives.
+impl<T: Send + 'static> Sink for mpsc::Sender<T> {
+    type Input = T;
+
+    fn push(&mut self, input: T) {
+        let _ = self.send(input);
+    }
+}
+
+/// Reads an [`AudioSource`] one frame at a time at the pace it plays, filling in silence
+/// for whatever it does not have.
+pub struct PacedSource<S, C = SystemClock> {
+    source: S,
+    clock: C,
+    channels: usize,
+    frame_len: usize,
+    frame_duration: Duration,
+    next_frame: Option<Instant>,
+}
+
+impl<S: AudioSource + Send + 'static> PacedSource<S> {
+    pub fn new(source: S, sample_rate: u32, channels: usize, frame_ms: u32) -> Self {
+        Self::with_clock(source, sample_rate, channels, frame_ms, SystemClock)
+    }
+}
+
+impl<S: AudioSource + Send + 'static, C: Clock> PacedSource<S, C> {
+    /// Paces by `clock`, such as a [`VirtualClock`](crate::clock::VirtualClock) to run
+    /// faster than realtime.
+    pub fn with_clock(
+        source: S,
+        sample_rate: u32,
+        channels: usize,
+        frame_ms: u32,
+        clock: C,
+    ) -> Self {
+        Self {
+            source,
+            clock,
+            channels,
+            frame_len: (sample_rate * frame_ms / 1000) as usize * channels,
+            frame_duration: Duration::from_millis(frame_ms as u64),
+            next_frame: None,
+        }
+    }
+}
+
+impl<S: AudioSource + Send + 'static, C: Clock> Source for PacedSource<S, C> {
+    type Output = Frame;
+
+    fn pull(&mut self, out: &mut Vec<Frame>) -> bool {
+        let next_frame = *self.next_frame.get_or_insert_with(|| self.clock.now());
+        self.clock.sleep_until(next_frame);
+        self.next_frame = Some(next_frame + self.frame_duration);
+
+        let mut samples = vec![0.0; self.frame_len];
+        self.source.read(&mut samples);
+        out.push(Frame {
+            samples,
+            channels: self.channels,
+        });
+        true
+    }
+}
+
+/// Writes frames to an [`AudioSink`], such as the buffer an output device plays.
+pub struct SinkWriter<S> {
+    sink: S,
+}
+
+impl<S: AudioSink + Send + 'static> SinkWriter<S> {
+    pub fn new(sink: S) -> Self {
+        Self { sink }
+    }
+}
+
+impl<S: AudioSink + Send + 'static> Sink for SinkWriter<S> {
+    type Input = Frame;
+
+    fn push(&mut self, frame: Frame) {
+        self.sink.write(&frame.samples);
+    }
+}
+
+/// Changes how many channels frames have, mixing down or copying channels as needed.
+pub struct ConvertChannels {
+    channels: usize,
+}
+
+impl ConvertChannels {
+    pub fn new(channels: usize) -> Self {
+        Self { channels }
+    }
+}
+
+impl Transform for ConvertChannels {
+    type Input = Frame;
+    type Output = Frame;
+
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) {
+        if frame.channels == self.channels {
+            out.push(frame);
+            return;
+        }
+        out.push(Frame {
+            samples: convert_channels(&frame.samples, frame.channels, self.channels),
+            channels: self.channels,
+        });
+    }
+}
+
+/// Encodes frames of any size into packets of a fixed duration.
+pub struct Encode {
+    encoder: Box<dyn AudioEncoder>,
+    channels: usize,
+    frame_len: usize,
+    /// RTP clock units per packet.
+    timestamp_step: u32,
+    pending: Vec<f32>,
+    sequence: u16,
+    timestamp: u32,
+}
+
+impl Encode {
+    pub fn new(encoder: Box<dyn AudioEncoder>, frame_ms: u32) -> Result<Self, CodecError> {
+        let frame_len = encoder
+            .frame_len(frame_ms)
+            .ok_or(CodecError::FrameSize(frame_ms))?;
+        Ok(Self {
+            channels: encoder.channels(),
+            frame_len,
+            timestamp_step: encoder.clock_rate() * frame_ms / 1000,
+            encoder,
+            pending: Vec::new(),
+            sequence: 0,
+            timestamp: 0,
+        })
+    }
+
+    /// The encoder, to adjust its bitrate, FEC and so on.
+    pub fn encoder_mut(&mut self) -> &mut dyn AudioEncoder {
+        self.encoder.as_mut()
+    }
+}
+
+impl Transform for Encode {
+    type Input = Frame;
+    type Output = Packet;
+
+    fn process(&mut self, frame: Frame, out: &mut Vec<Packet>) {
+        if frame.channels == self.channels {
+            self.pending.extend_from_slice(&frame.samples);
+        } else {
+            self.pending.extend(convert_channels(
+                &frame.samples,
+                frame.channels,
+                self.channels,
+            ));
+        }
+
+        while self.pending.len() >= self.frame_len {
+            let mut payload = vec![0u8; MAX_PAYLOAD];
+            match self
+                .encoder
+                .encode(&self.pending[..self.frame_len], &mut payload)
+            {
+                Ok(size) => {
+                    payload.truncate(size);
+                    out.push(Packet {
+                        sequence: self.sequence,
+                        timestamp: self.timestamp,
+                        payload,
+                    });
+                    self.sequence = self.sequence.wrapping_add(1);
+                }
+                Err(err) => warn!(error = %err, "failed to encode frame"),
+            }
+            // Time moves on even for a frame that could not be sent.
+            self.timestamp = self.timestamp.wrapping_add(self.timestamp_step);
+            self.pending.drain(..self.frame_len);
+        }
+    }
+}
+
+/// Decodes packets into frames, concealing short runs of lost packets.
+///
+/// When only the packet before one that arrived is missing and the codec has FEC, it is
+/// recovered from the copy the later packet carries.
+pub struct Decode {
+    decoder: Box<dyn AudioDecoder>,
+    channels: usize,
+    pcm: Vec<f32>,
+    /// Samples in the last decoded frame, which is how much a lost one is concealed with.
+    last_len: usize,
+    expected: Option<u16>,
+}
+
+impl Decode {
+    pub fn new(decoder: Box<dyn AudioDecoder>) -> Self {
+        let channels = decoder.channels();
+        Self {
+            decoder,
+            channels,
+            pcm: vec![0.0; MAX_FRAME * channels],
+            last_len: 0,
+            expected: None,
+        }
+    }
+
+    fn decode(&mut self, payload: &[u8], fec: bool, out: &mut Vec<Frame>) {
+        // A lost frame is as long as the last one that was not.
+        let lost = payload.is_empty() || fec;
+        let pcm = match lost {
+            true => &mut self.pcm[..self.last_len],
+            false => &mut self.pcm[..],
+        };
+        let decoded = match fec {
+            true => self.decoder.decode_fec(payload, pcm),
+            false => self.decoder.decode(payload, pcm),
+        };
+        match decoded {
+            Ok(samples) => {
+                let len = samples * self.channels;
+                if !lost {
+                    self.last_len = len;
+                }
+                out.push(Frame {
+                    samples: self.pcm[..len].to_vec(),
+                    channels: self.channels,
+                });
+            }
+            Err(err) => warn!(error = %err, "failed to decode packet"),
+        }
+    }
+}
+
+impl Transform for Decode {
+    type Input = Packet;
+    type Output = Frame;
+
+    fn process(&mut self, packet: Packet, out: &mut Vec<Frame>) {
+        if let Some(expected) = self.expected {
+            let missing = packet.sequence.wrapping_sub(expected);
+            // Packets from before the one expected arrived too late to play.
+            if missing >= u16::MAX / 2 {
+                return;
+            }
+            if missing <= MAX_CONCEALED && self.last_len > 0 {
+                let recovered = missing > 0 && self.decoder.supports_fec();
+                for _ in 0..missing - recovered as u16 {
+                    self.decode(&[], false, out);
+                }
+                if recovered {
+                    self.decode(&packet.payload, true, out);
+                }
+            }
+        }
+        self.expected = Some(packet.sequence.wrapping_add(1));
+        self.decode(&packet.payload, false, out);
+    }
+}
+
+/// Sends packets to a peer as RTP.
+pub struct RtpSend {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    payload_type: u8,
+    ssrc: u32,
+    first: bool,
+    buffer: Vec<u8>,
+}
+
+impl RtpSend {
+    pub fn new(socket: UdpSocket, peer: SocketAddr, payload_type: u8, ssrc: u32) -> Self {
+        Self {
+            socket,
+            peer,
+            payload_type,
+            ssrc,
+            first: true,
+            buffer: Vec::with_capacity(MAX_PACKET),
+        }
+    }
+}
+
+impl Sink for RtpSend {
+    type Input = Packet;
+
+    fn push(&mut self, packet: Packet) {
+        let header = RtpHeader {
+            marker: self.first,
+            payload_type: self.payload_type,
+            sequence: packet.sequence,
+            timestamp: packet.timestamp,
+            ssrc: self.ssrc,
+        };
+        self.first = false;
+        self.buffer.clear();
+        self.buffer.resize(HEADER_LEN, 0);
+        header.write(&mut self.buffer);
+        self.buffer.extend_from_slice(&packet.payload);
+        if let Err(err) = self.socket.send_to(&self.buffer, self.peer)
+            && !is_transient(&err)
+        {
+            warn!(error = %err, "failed to send packet");
+        }
+    }
+}
+
+/// Receives RTP packets of one payload type, from anyone.
+pub struct RtpReceive {
+    socket: UdpSocket,
+    payload_type: u8,
+    buffer: Vec<u8>,
+}
+
+impl RtpReceive {
+    pub fn new(socket: UdpSocket, payload_type: u8) -> io::Result<Self> {
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(Self {
+            socket,
+            payload_type,
+            buffer: vec![0; MAX_PACKET],
+        })
+    }
+}
+
+impl Source for RtpReceive {
+    type Output = Packet;
+
+    fn pull(&mut self, out: &mut Vec<Packet>) -> bool {
+        match self.socket.recv_from(&mut self.buffer) {
+            Ok((size, _)) => {
+                if let Some((header, payload)) = RtpHeader::parse(&self.buffer[..size])
+                    && header.payload_type == self.payload_type
+                {
+                    out.push(Packet {
+                        sequence: header.sequence,
+                        timestamp: header.timestamp,
+                        payload: payload.to_vec(),
+                    });
+                }
+            }
+            Err(err) if is_transient(&err) => {}
+            Err(err) => warn!(error = %err, "failed to receive packet"),
+        }
+        true
+    }
+}
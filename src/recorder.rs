@@ -0,0 +1,486 @@
+//! Recording calls to disk, one file per track.
+//!
+//! A [`Recorder`] writes the local microphone, every remote participant and the final mix
+//! to separate files in one directory, as WAV or as Ogg Opus. Every track is laid on the
+//! same timeline, starting when the recording did, so the files line up when played
+//! together. Files are written on a thread of their own: audio threads only queue what
+//! they hear and never wait for the disk.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use opus::Application;
+use tracing::warn;
+
+use crate::codec::AudioEncoder;
+use crate::multistream::{ChannelMapping, MultistreamEncoder};
+use crate::ogg::{self, GRANULE_RATE, OggOpusWriter};
+use crate::roster::ParticipantId;
+use crate::util::convert_channels;
+use crate::wav::{WavSpec, WavWriter};
+
+/// Writes queued before more are dropped: seconds of audio for a handful of tracks.
+pub const QUEUE_CAPACITY: usize = 1024;
+/// How late audio may arrive before the gap is filled with silence, so small scheduling
+/// jitter does not stretch a track.
+const GAP_TOLERANCE: Duration = Duration::from_millis(100);
+/// Length of the frames PCM is encoded in for Ogg recordings.
+const FRAME_MS: usize = 20;
+/// Output buffer size recommended for each stream of the Opus encoder.
+const MAX_PAYLOAD: usize = 4000;
+
+/// What recordings are written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RecordFormat {
+    /// 16-bit PCM, as heard.
+    Wav,
+    /// Opus in Ogg. Packets sent and received are written as they are, without
+    /// re-encoding; audio that only exists as PCM, such as the mix, is encoded.
+    Ogg,
+}
+
+impl RecordFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Wav => "wav",
+            RecordFormat::Ogg => "opus",
+        }
+    }
+}
+
+/// One file of a recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Track {
+    /// What the local microphone sent.
+    Microphone,
+    /// What one remote participant sent.
+    Participant(ParticipantId),
+    /// Everything played, mixed.
+    Mix,
+}
+
+impl Track {
+    pub fn file_name(self, format: RecordFormat) -> String {
+        let stem = match self {
+            Track::Microphone => "microphone".to_string(),
+            Track::Participant(id) => format!("participant-{}", id),
+            Track::Mix => "mix".to_string(),
+        };
+        format!("{}.{}", stem, format.extension())
+    }
+}
+
+enum Write {
+    Audio {
+        track: Track,
+        samples: Vec<f32>,
+        spec: WavSpec,
+        at: Instant,
+    },
+    Packet {
+        track: Track,
+        sequence: u16,
+        payload: Vec<u8>,
+        spec: WavSpec,
+        at: Instant,
+    },
+}
+
+/// Records tracks while started. Clones control the same recording, so every audio thread
+/// can hold one.
+///
+/// Each track keeps the sample rate and channel count of the first audio written to it;
+/// later audio is converted to that channel count.
+#[derive(Clone)]
+pub struct Recorder {
+    format: RecordFormat,
+    active: Arc<Mutex<Option<Active>>>,
+}
+
+struct Active {
+    writes: SyncSender<Write>,
+    dropped: Arc<AtomicUsize>,
+    directory: PathBuf,
+    thread: JoinHandle<io::Result<()>>,
+}
+
+impl Recorder {
+    /// A stopped recorder.
+    pub fn new(format: RecordFormat) -> Self {
+        Self {
+            format,
+            active: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn format(&self) -> RecordFormat {
+        self.format
+    }
+
+    /// Whether tracks that exist as Opus packets should be fed with
+    /// [`write_packet`](Self::write_packet) rather than [`write`](Self::write).
+    pub fn wants_packets(&self) -> bool {
+        self.format == RecordFormat::Ogg
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.active.lock().unwrap().is_some()
+    }
+
+    /// Where the current recording goes.
+    pub fn directory(&self) -> Option<PathBuf> {
+        let active = self.active.lock().unwrap();
+        active.as_ref().map(|active| active.directory.clone())
+    }
+
+    /// Starts recording into `directory`, created if needed, with `now` as the start of
+    /// every track. A recording already running is finished first.
+    pub fn start(&self, directory: impl Into<PathBuf>, now: Instant) -> io::Result<()> {
+        self.stop()?;
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        let (writes, queue) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let writer = Writer {
+            directory: directory.clone(),
+            format: self.format,
+            started: now,
+            tracks: HashMap::new(),
+            error: None,
+        };
+        let thread = thread::Builder::new()
+            .name("recorder".into())
+            .spawn(move || writer.run(queue))?;
+        *self.active.lock().unwrap() = Some(Active {
+            writes,
+            dropped: Arc::new(AtomicUsize::new(0)),
+            directory,
+            thread,
+        });
+        Ok(())
+    }
+
+    /// Finishes the recording, waiting for every file to be complete. Returns the first
+    /// error any track ran into.
+    pub fn stop(&self) -> io::Result<()> {
+        let Some(active) = self.active.lock().unwrap().take() else {
+            return Ok(());
+        };
+        drop(active.writes);
+        let result = active
+            .thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("recorder thread panicked")));
+        let dropped = active.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!(
+                dropped,
+                "recording fell behind; gaps were filled with silence"
+            );
+        }
+        result
+    }
+
+    /// Records interleaved PCM heard on `track` at `now`. Does nothing unless recording.
+    pub fn write(&self, track: Track, samples: &[f32], spec: WavSpec, now: Instant) {
+        self.queue(|| Write::Audio {
+            track,
+            samples: samples.to_vec(),
+            spec,
+            at: now,
+        });
+    }
+
+    /// Records an Opus packet sent or received on `track` at `now`, encoded from audio in
+    /// `spec`. Gaps in `sequence` are filled with silence. Does nothing unless recording.
+    pub fn write_packet(
+        &self,
+        track: Track,
+        sequence: u16,
+        payload: &[u8],
+        spec: WavSpec,
+        now: Instant,
+    ) {
+        self.queue(|| Write::Packet {
+            track,
+            sequence,
+            payload: payload.to_vec(),
+            spec,
+            at: now,
+        });
+    }
+
+    fn queue(&self, write: impl FnOnce() -> Write) {
+        let active = self.active.lock().unwrap();
+        if let Some(active) = &*active
+            && let Err(TrySendError::Full(_)) = active.writes.try_send(write())
+        {
+            active.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The files of one recording, owned by the recorder thread.
+struct Writer {
+    directory: PathBuf,
+    format: RecordFormat,
+    started: Instant,
+    /// `None` for tracks that failed, so they are not reopened over what they wrote.
+    tracks: HashMap<Track, Option<TrackWriter>>,
+    error: Option<io::Error>,
+}
+
+impl Writer {
+    fn run(mut self, queue: Receiver<Write>) -> io::Result<()> {
+        for write in queue {
+            let (track, spec) = match &write {
+                Write::Audio { track, spec, .. } | Write::Packet { track, spec, .. } => {
+                    (*track, *spec)
+                }
+            };
+            if !self.tracks.contains_key(&track) {
+                let opened = self.open(track, spec, matches!(write, Write::Packet { .. }));
+                let opened = self.check(track, opened);
+                self.tracks.insert(track, opened);
+            }
+            let Some(Some(writer)) = self.tracks.get_mut(&track) else {
+                continue;
+            };
+            let written = writer.write(write, self.started);
+            if self.check(track, written).is_none() {
+                self.tracks.insert(track, None);
+            }
+        }
+
+        for (track, writer) in self.tracks.drain() {
+            if let Some(writer) = writer
+                && let Err(err) = writer.finish()
+            {
+                warn!(?track, error = %err, "failed to finish recording");
+                self.error.get_or_insert(err);
+            }
+        }
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn open(&self, track: Track, spec: WavSpec, packets: bool) -> io::Result<TrackWriter> {
+        let path = self.directory.join(track.file_name(self.format));
+        let WavSpec {
+            sample_rate,
+            channels,
+        } = spec;
+        let channels = channels.max(1);
+        match self.format {
+            RecordFormat::Wav => Ok(TrackWriter::Wav {
+                writer: WavWriter::create(
+                    path,
+                    WavSpec {
+                        sample_rate,
+                        channels,
+                    },
+                )?,
+                frames: 0,
+            }),
+            RecordFormat::Ogg if packets => Ok(TrackWriter::Packets {
+                writer: OggOpusWriter::create(path, channels as u8, sample_rate)?,
+                expected: None,
+            }),
+            RecordFormat::Ogg => {
+                // More than two channels go in a multistream, surround if they have a layout.
+                let channels = channels as usize;
+                let mapping = ChannelMapping::for_channels(channels).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "too many channels")
+                })?;
+                let encoder =
+                    MultistreamEncoder::new(sample_rate, mapping.clone(), Application::Audio)
+                        .map_err(io::Error::other)?;
+                Ok(TrackWriter::Encoded {
+                    writer: OggOpusWriter::with_mapping(path, mapping, sample_rate)?,
+                    encoder,
+                    sample_rate,
+                    channels,
+                    frame_len: sample_rate as usize * FRAME_MS / 1000 * channels,
+                    pending: Vec::new(),
+                    frames: 0,
+                })
+            }
+        }
+    }
+
+    /// Keeps the first error for [`Recorder::stop`] to return.
+    fn check<T>(&mut self, track: Track, result: io::Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                warn!(?track, error = %err, "failed to record track");
+                self.error.get_or_insert(err);
+                None
+            }
+        }
+    }
+}
+
+enum TrackWriter {
+    Wav {
+        writer: WavWriter,
+        /// Frames written, silence included.
+        frames: u64,
+    },
+    /// Packets written as they are.
+    Packets {
+        writer: OggOpusWriter,
+        expected: Option<u16>,
+    },
+    /// PCM encoded on the way.
+    Encoded {
+        writer: OggOpusWriter,
+        encoder: MultistreamEncoder,
+        sample_rate: u32,
+        channels: usize,
+        frame_len: usize,
+        pending: Vec<f32>,
+        /// Frames taken in, silence included.
+        frames: u64,
+    },
+}
+
+impl TrackWriter {
+    fn write(&mut self, write: Write, started: Instant) -> io::Result<()> {
+        match (self, write) {
+            (
+                TrackWriter::Wav { writer, frames },
+                Write::Audio {
+                    samples, spec, at, ..
+                },
+            ) => {
+                let WavSpec {
+                    sample_rate,
+                    channels,
+                } = writer.spec();
+                let channels = channels as usize;
+                let target = frames_at(at, started, sample_rate);
+                if let Some(missing) = gap(*frames, target, sample_rate) {
+                    writer.write_samples(&vec![0.0; missing as usize * channels])?;
+                    *frames += missing;
+                }
+                let samples = convert_channels(&samples, spec.channels as usize, channels);
+                writer.write_samples(&samples)?;
+                *frames += (samples.len() / channels) as u64;
+                Ok(())
+            }
+            (
+                TrackWriter::Packets { writer, expected },
+                Write::Packet {
+                    sequence,
+                    payload,
+                    at,
+                    ..
+                },
+            ) => {
+                if let Some(expected) = *expected {
+                    let missing = sequence.wrapping_sub(expected);
+                    // Too late: the packet's place in the file is already taken.
+                    if missing >= u16::MAX / 2 {
+                        return Ok(());
+                    }
+                    let lost = ogg::packet_samples(&payload).unwrap_or(0);
+                    writer.pad_to(writer.position() + missing as u64 * lost as u64)?;
+                }
+                *expected = Some(sequence.wrapping_add(1));
+                let target = frames_at(at, started, GRANULE_RATE);
+                if let Some(missing) = gap(writer.position(), target, GRANULE_RATE) {
+                    writer.pad_to(writer.position() + missing)?;
+                }
+                writer.write_packet(&payload)
+            }
+            (
+                TrackWriter::Encoded {
+                    writer,
+                    encoder,
+                    sample_rate,
+                    channels,
+                    frame_len,
+                    pending,
+                    frames,
+                },
+                Write::Audio {
+                    samples, spec, at, ..
+                },
+            ) => {
+                let target = frames_at(at, started, *sample_rate);
+                if let Some(missing) = gap(*frames, target, *sample_rate) {
+                    pending.resize(pending.len() + missing as usize * *channels, 0.0);
+                    *frames += missing;
+                }
+                let samples = convert_channels(&samples, spec.channels as usize, *channels);
+                *frames += (samples.len() / *channels) as u64;
+                pending.extend(samples);
+                encode(writer, encoder, pending, *frame_len)
+            }
+            // Each track is fed one kind of write.
+            _ => Ok(()),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            TrackWriter::Wav { mut writer, .. } => writer.finalize(),
+            TrackWriter::Packets { mut writer, .. } => writer.finalize(),
+            TrackWriter::Encoded {
+                mut writer,
+                mut encoder,
+                frame_len,
+                mut pending,
+                ..
+            } => {
+                if !pending.is_empty() {
+                    pending.resize(pending.len().next_multiple_of(frame_len), 0.0);
+                }
+                encode(&mut writer, &mut encoder, &mut pending, frame_len)?;
+                writer.finalize()
+            }
+        }
+    }
+}
+
+/// Encodes every whole frame of `pending` into `writer`.
+fn encode(
+    writer: &mut OggOpusWriter,
+    encoder: &mut MultistreamEncoder,
+    pending: &mut Vec<f32>,
+    frame_len: usize,
+) -> io::Result<()> {
+    let mut payload = vec![0u8; MAX_PAYLOAD * encoder.mapping().streams as usize];
+    while pending.len() >= frame_len {
+        let size = encoder
+            .encode(&pending[..frame_len], &mut payload)
+            .map_err(io::Error::other)?;
+        writer.write_packet(&payload[..size])?;
+        pending.drain(..frame_len);
+    }
+    Ok(())
+}
+
+/// Frames at `rate` between the start of the recording and `at`.
+fn frames_at(at: Instant, started: Instant, rate: u32) -> u64 {
+    (at.saturating_duration_since(started).as_secs_f64() * rate as f64) as u64
+}
+
+/// Frames of silence needed for a track at `written` to catch up with `target`: all of
+/// them before the first audio, otherwise only once it is noticeably behind.
+fn gap(written: u64, target: u64, rate: u32) -> Option<u64> {
+    let tolerance = (GAP_TOLERANCE.as_secs_f64() * rate as f64) as u64;
+    let behind = target.saturating_sub(written);
+    (behind > 0 && (written == 0 || behind > tolerance)).then_some(behind)
+}
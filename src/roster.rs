@@ -0,0 +1,262 @@
+//! Who is in a call and how they are doing, for whoever displays it.
+//!
+//! The audio and network threads report membership, levels and statistics to a [`Roster`].
+//! It works out what actually changed and tells its subscribers, so a user interface is
+//! driven by events instead of polling, and only hears about a participant when something
+//! it would show is different.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::stats::CallStats;
+
+/// Level a participant has to go over to count as speaking, about -34 dBFS RMS.
+const SPEAKING_LEVEL: f32 = 0.02;
+/// Level they have to drop under to stop, lower so a voice near the threshold does not flicker.
+const QUIET_LEVEL: f32 = 0.01;
+/// How long someone still counts as speaking after going quiet, to bridge gaps between words.
+const HANG: Duration = Duration::from_millis(300);
+
+/// Identifies a participant, usually by the SSRC of their stream.
+pub type ParticipantId = u32;
+
+/// How well a participant's connection is doing, worst first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Quality {
+    /// Nothing received yet.
+    #[default]
+    Unknown,
+    Bad,
+    Poor,
+    Good,
+    Excellent,
+}
+
+impl Quality {
+    /// Rates what we receive from a participant.
+    ///
+    /// Uses the simplified ITU-T G.107 E-model: delay (half the round trip plus the jitter
+    /// buffer) and loss each take away from a best-case rating, which is then bucketed roughly
+    /// where listeners go from satisfied to dissatisfied.
+    pub fn from_stats(stats: &CallStats) -> Quality {
+        if stats.packets_received == 0 {
+            return Quality::Unknown;
+        }
+        let round_trip = stats.round_trip_time.unwrap_or_default().as_secs_f32() * 1000.0;
+        let jitter = stats.jitter.as_secs_f32() * 1000.0;
+        let delay = round_trip / 2.0 + 2.0 * jitter + 10.0;
+        let delay_penalty = if delay < 160.0 {
+            delay / 40.0
+        } else {
+            (delay - 120.0) / 10.0
+        };
+        let rating = 93.2 - delay_penalty - 2.5 * 100.0 * stats.fraction_lost;
+        match rating {
+            rating if rating >= 80.0 => Quality::Excellent,
+            rating if rating >= 70.0 => Quality::Good,
+            rating if rating >= 50.0 => Quality::Poor,
+            _ => Quality::Bad,
+        }
+    }
+}
+
+/// Decides whether somebody is speaking from their audio level.
+///
+/// Starting takes a louder level than carrying on, and a short pause does not count as
+/// stopping, so an indicator driven by it follows sentences rather than syllables.
+#[derive(Debug, Clone, Default)]
+pub struct SpeechDetector {
+    speaking: bool,
+    last_loud: Option<Instant>,
+}
+
+impl SpeechDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    /// Takes the RMS `level` of the latest audio and returns whether they are speaking.
+    pub fn update(&mut self, level: f32, now: Instant) -> bool {
+        let threshold = if self.speaking {
+            QUIET_LEVEL
+        } else {
+            SPEAKING_LEVEL
+        };
+        if level >= threshold {
+            self.speaking = true;
+            self.last_loud = Some(now);
+        } else if let Some(last_loud) = self.last_loud
+            && now.saturating_duration_since(last_loud) >= HANG
+        {
+            self.speaking = false;
+        }
+        self.speaking
+    }
+}
+
+/// What a user interface shows about one participant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Participant {
+    pub id: ParticipantId,
+    pub name: String,
+    pub speaking: bool,
+    /// Whether we have muted them locally.
+    pub muted: bool,
+    /// Linear playback gain we apply to them, 1 by default.
+    pub volume: f32,
+    pub quality: Quality,
+}
+
+/// A change to the roster, as told to subscribers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RosterEvent {
+    Joined(Participant),
+    /// Something about the participant changed; carries their new state.
+    Changed(Participant),
+    Left(ParticipantId),
+}
+
+type Listener = Box<dyn FnMut(&RosterEvent) -> bool + Send>;
+
+#[derive(Default)]
+struct State {
+    participants: Vec<(Participant, SpeechDetector)>,
+    listeners: Vec<Listener>,
+}
+
+impl State {
+    fn publish(&mut self, event: RosterEvent) {
+        self.listeners.retain_mut(|listener| listener(&event));
+    }
+
+    /// Applies `change` to a participant and publishes them if it made a difference.
+    fn update(
+        &mut self,
+        id: ParticipantId,
+        change: impl FnOnce(&mut Participant, &mut SpeechDetector),
+    ) {
+        let Some((participant, detector)) = self
+            .participants
+            .iter_mut()
+            .find(|(participant, _)| participant.id == id)
+        else {
+            return;
+        };
+        let before = participant.clone();
+        change(participant, detector);
+        if *participant != before {
+            let event = RosterEvent::Changed(participant.clone());
+            self.publish(event);
+        }
+    }
+}
+
+/// The participants of a call, shared between the threads that update it and the views
+/// that subscribe to it.
+///
+/// Cloning gives another handle to the same roster.
+#[derive(Clone, Default)]
+pub struct Roster {
+    state: Arc<Mutex<State>>,
+}
+
+impl Roster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `listener` with every change from now on, starting with a
+    /// [`RosterEvent::Joined`] for everybody already present.
+    ///
+    /// The listener returns false once it is no longer interested, e.g. because the view it
+    /// feeds has gone, and is then dropped. It runs on whichever thread made the change with
+    /// the roster locked, so it should hand the event on rather than act on it, and must not
+    /// call back into the roster.
+    pub fn subscribe(&self, listener: impl FnMut(&RosterEvent) -> bool + Send + 'static) {
+        let mut state = self.state.lock().unwrap();
+        let mut listener: Listener = Box::new(listener);
+        for (participant, _) in &state.participants {
+            if !listener(&RosterEvent::Joined(participant.clone())) {
+                return;
+            }
+        }
+        state.listeners.push(listener);
+    }
+
+    pub fn participants(&self) -> Vec<Participant> {
+        let state = self.state.lock().unwrap();
+        state
+            .participants
+            .iter()
+            .map(|(participant, _)| participant.clone())
+            .collect()
+    }
+
+    /// Adds a participant, or renames one already present.
+    pub fn join(&self, id: ParticipantId, name: impl Into<String>) {
+        let name = name.into();
+        let mut state = self.state.lock().unwrap();
+        if state
+            .participants
+            .iter()
+            .any(|(participant, _)| participant.id == id)
+        {
+            state.update(id, |participant, _| participant.name = name);
+            return;
+        }
+        let participant = Participant {
+            id,
+            name,
+            speaking: false,
+            muted: false,
+            volume: 1.0,
+            quality: Quality::Unknown,
+        };
+        state
+            .participants
+            .push((participant.clone(), SpeechDetector::new()));
+        state.publish(RosterEvent::Joined(participant));
+    }
+
+    pub fn leave(&self, id: ParticipantId) {
+        let mut state = self.state.lock().unwrap();
+        let count = state.participants.len();
+        state
+            .participants
+            .retain(|(participant, _)| participant.id != id);
+        if state.participants.len() != count {
+            state.publish(RosterEvent::Left(id));
+        }
+    }
+
+    /// Reports the RMS level of a participant's latest audio, e.g. from
+    /// [`Input::level`](crate::mixer::Input::level).
+    pub fn update_level(&self, id: ParticipantId, level: f32, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.update(id, |participant, detector| {
+            participant.speaking = detector.update(level, now);
+        });
+    }
+
+    /// Reports the latest statistics of the stream received from a participant.
+    pub fn update_stats(&self, id: ParticipantId, stats: &CallStats) {
+        let quality = Quality::from_stats(stats);
+        let mut state = self.state.lock().unwrap();
+        state.update(id, |participant, _| participant.quality = quality);
+    }
+
+    pub fn set_muted(&self, id: ParticipantId, muted: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.update(id, |participant, _| participant.muted = muted);
+    }
+
+    pub fn set_volume(&self, id: ParticipantId, volume: f32) {
+        let volume = volume.max(0.0);
+        let mut state = self.state.lock().unwrap();
+        state.update(id, |participant, _| participant.volume = volume);
+    }
+}
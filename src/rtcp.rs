@@ -0,0 +1,490 @@
+//! RTCP (RFC 3550 section 6), multiplexed with RTP on the media socket as in RFC 5761.
+//!
+//! Only the packet types a two-party call needs are understood: sender and receiver
+//! reports, the CNAME item of source descriptions, and BYE. Other packet types in a
+//! compound packet are skipped.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::rtp::RtpHeader;
+use crate::stats::{CallStats, ReceptionStats};
+
+pub const SENDER_REPORT: u8 = 200;
+pub const RECEIVER_REPORT: u8 = 201;
+pub const SOURCE_DESCRIPTION: u8 = 202;
+pub const BYE: u8 = 203;
+
+/// Time between reports. With two participants the bandwidth-scaled interval of RFC 3550
+/// always comes out below the recommended five second minimum.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+const VERSION: u8 = 2;
+const CNAME: u8 = 1;
+const REPORT_BLOCK_LEN: usize = 24;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Reception quality of one source, as carried in sender and receiver reports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReportBlock {
+    pub ssrc: u32,
+    /// Fraction of packets lost since the previous report, in 1/256ths.
+    pub fraction_lost: u8,
+    /// Packets lost since the start of reception; negative when duplicates arrived.
+    pub cumulative_lost: i32,
+    /// Highest sequence number received, extended with the number of wraps.
+    pub highest_sequence: u32,
+    /// Interarrival jitter in RTP timestamp units.
+    pub jitter: u32,
+    /// Middle 32 bits of the NTP timestamp of the last sender report received.
+    pub last_sr: u32,
+    /// Delay since that sender report was received, in 1/65536 seconds.
+    pub delay_since_last_sr: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RtcpPacket {
+    SenderReport {
+        ssrc: u32,
+        ntp_timestamp: u64,
+        rtp_timestamp: u32,
+        packet_count: u32,
+        octet_count: u32,
+        reports: Vec<ReportBlock>,
+    },
+    ReceiverReport {
+        ssrc: u32,
+        reports: Vec<ReportBlock>,
+    },
+    /// Source description carrying only the canonical name of each source.
+    SourceDescription { chunks: Vec<(u32, String)> },
+    Bye {
+        sources: Vec<u32>,
+        reason: Option<String>,
+    },
+}
+
+impl RtcpPacket {
+    /// Appends the packet to `buf`.
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        match self {
+            RtcpPacket::SenderReport {
+                ssrc,
+                ntp_timestamp,
+                rtp_timestamp,
+                packet_count,
+                octet_count,
+                reports,
+            } => {
+                write_header(buf, reports.len(), SENDER_REPORT);
+                buf.extend_from_slice(&ssrc.to_be_bytes());
+                buf.extend_from_slice(&ntp_timestamp.to_be_bytes());
+                buf.extend_from_slice(&rtp_timestamp.to_be_bytes());
+                buf.extend_from_slice(&packet_count.to_be_bytes());
+                buf.extend_from_slice(&octet_count.to_be_bytes());
+                reports.iter().for_each(|report| write_block(buf, report));
+            }
+            RtcpPacket::ReceiverReport { ssrc, reports } => {
+                write_header(buf, reports.len(), RECEIVER_REPORT);
+                buf.extend_from_slice(&ssrc.to_be_bytes());
+                reports.iter().for_each(|report| write_block(buf, report));
+            }
+            RtcpPacket::SourceDescription { chunks } => {
+                write_header(buf, chunks.len(), SOURCE_DESCRIPTION);
+                for (ssrc, cname) in chunks {
+                    buf.extend_from_slice(&ssrc.to_be_bytes());
+                    let cname = &cname.as_bytes()[..cname.len().min(255)];
+                    buf.push(CNAME);
+                    buf.push(cname.len() as u8);
+                    buf.extend_from_slice(cname);
+                    // The item list ends with at least one null byte, padded to a word.
+                    buf.push(0);
+                    pad(buf);
+                }
+            }
+            RtcpPacket::Bye { sources, reason } => {
+                write_header(buf, sources.len(), BYE);
+                for ssrc in sources {
+                    buf.extend_from_slice(&ssrc.to_be_bytes());
+                }
+                if let Some(reason) = reason {
+                    let reason = &reason.as_bytes()[..reason.len().min(255)];
+                    buf.push(reason.len() as u8);
+                    buf.extend_from_slice(reason);
+                    pad(buf);
+                }
+            }
+        }
+        let words = ((buf.len() - start) / 4 - 1) as u16;
+        buf[start + 2..start + 4].copy_from_slice(&words.to_be_bytes());
+    }
+
+    /// Whether a datagram on the media socket is RTCP rather than RTP (RFC 5761 section 4).
+    pub fn is_rtcp(packet: &[u8]) -> bool {
+        packet.len() >= 8 && packet[0] >> 6 == VERSION && (192..=223).contains(&packet[1])
+    }
+
+    /// Encodes several packets into one compound packet.
+    pub fn encode(packets: &[RtcpPacket]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for packet in packets {
+            packet.write(&mut buf);
+        }
+        buf
+    }
+
+    /// Parses a compound packet, skipping packet types we do not understand.
+    pub fn decode(mut data: &[u8]) -> Option<Vec<RtcpPacket>> {
+        let mut packets = Vec::new();
+        while !data.is_empty() {
+            if data.len() < 4 || data[0] >> 6 != VERSION {
+                return None;
+            }
+            let len = (u16::from_be_bytes([data[2], data[3]]) as usize + 1) * 4;
+            let packet = data.get(..len)?;
+            data = &data[len..];
+
+            let mut body = &packet[4..];
+            if packet[0] & 0x20 != 0 {
+                body = &body[..body.len().checked_sub(*packet.last()? as usize)?];
+            }
+            let count = (packet[0] & 0x1f) as usize;
+            match packet[1] {
+                SENDER_REPORT => {
+                    let fixed = body.get(..24)?;
+                    packets.push(RtcpPacket::SenderReport {
+                        ssrc: read_u32(fixed, 0),
+                        ntp_timestamp: (read_u32(fixed, 4) as u64) << 32
+                            | read_u32(fixed, 8) as u64,
+                        rtp_timestamp: read_u32(fixed, 12),
+                        packet_count: read_u32(fixed, 16),
+                        octet_count: read_u32(fixed, 20),
+                        reports: read_blocks(&body[24..], count)?,
+                    });
+                }
+                RECEIVER_REPORT => {
+                    let fixed = body.get(..4)?;
+                    packets.push(RtcpPacket::ReceiverReport {
+                        ssrc: read_u32(fixed, 0),
+                        reports: read_blocks(&body[4..], count)?,
+                    });
+                }
+                SOURCE_DESCRIPTION => packets.push(RtcpPacket::SourceDescription {
+                    chunks: read_chunks(body, count)?,
+                }),
+                BYE => {
+                    let sources = body.get(..count * 4)?;
+                    let sources = (0..count).map(|i| read_u32(sources, i * 4)).collect();
+                    let rest = &body[count * 4..];
+                    let reason = match rest.first() {
+                        Some(&len) => {
+                            let text = rest.get(1..1 + len as usize)?;
+                            Some(String::from_utf8_lossy(text).into_owned())
+                        }
+                        None => None,
+                    };
+                    packets.push(RtcpPacket::Bye { sources, reason });
+                }
+                _ => {}
+            }
+        }
+        Some(packets)
+    }
+}
+
+fn write_header(buf: &mut Vec<u8>, count: usize, packet_type: u8) {
+    buf.push(VERSION << 6 | count.min(31) as u8);
+    buf.push(packet_type);
+    // Length is filled in once the body is written.
+    buf.extend_from_slice(&[0, 0]);
+}
+
+fn write_block(buf: &mut Vec<u8>, report: &ReportBlock) {
+    let lost = report.cumulative_lost.clamp(-0x80_0000, 0x7f_ffff) as u32 & 0xff_ffff;
+    buf.extend_from_slice(&report.ssrc.to_be_bytes());
+    buf.extend_from_slice(&((report.fraction_lost as u32) << 24 | lost).to_be_bytes());
+    buf.extend_from_slice(&report.highest_sequence.to_be_bytes());
+    buf.extend_from_slice(&report.jitter.to_be_bytes());
+    buf.extend_from_slice(&report.last_sr.to_be_bytes());
+    buf.extend_from_slice(&report.delay_since_last_sr.to_be_bytes());
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+fn read_blocks(data: &[u8], count: usize) -> Option<Vec<ReportBlock>> {
+    let data = data.get(..count * REPORT_BLOCK_LEN)?;
+    let blocks = data
+        .chunks_exact(REPORT_BLOCK_LEN)
+        .map(|block| {
+            let lost = read_u32(block, 4);
+            ReportBlock {
+                ssrc: read_u32(block, 0),
+                fraction_lost: (lost >> 24) as u8,
+                // Sign-extend the 24-bit count.
+                cumulative_lost: ((lost << 8) as i32) >> 8,
+                highest_sequence: read_u32(block, 8),
+                jitter: read_u32(block, 12),
+                last_sr: read_u32(block, 16),
+                delay_since_last_sr: read_u32(block, 20),
+            }
+        })
+        .collect();
+    Some(blocks)
+}
+
+fn read_chunks(mut data: &[u8], count: usize) -> Option<Vec<(u32, String)>> {
+    let mut chunks = Vec::with_capacity(count);
+    for _ in 0..count {
+        let ssrc = read_u32(data.get(..4)?, 0);
+        let mut at = 4;
+        let mut cname = None;
+        loop {
+            let kind = *data.get(at)?;
+            if kind == 0 {
+                break;
+            }
+            let len = *data.get(at + 1)? as usize;
+            let text = data.get(at + 2..at + 2 + len)?;
+            if kind == CNAME {
+                cname = Some(String::from_utf8_lossy(text).into_owned());
+            }
+            at += 2 + len;
+        }
+        // Skip the terminating null and the padding up to the next word.
+        data = data.get((at / 4 + 1) * 4..)?;
+        if let Some(cname) = cname {
+            chunks.push((ssrc, cname));
+        }
+    }
+    Some(chunks)
+}
+
+/// Converts a wall clock time to a 64-bit NTP timestamp.
+pub fn ntp_timestamp(time: SystemTime) -> u64 {
+    let since_unix = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_unix.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((since_unix.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    seconds << 32 | fraction
+}
+
+/// Middle 32 bits of an NTP timestamp, the format used for round-trip calculations.
+pub fn compact_ntp(ntp: u64) -> u32 {
+    (ntp >> 16) as u32
+}
+
+/// Converts a duration to the 1/65536 second units of compact NTP times.
+pub(crate) fn to_compact(duration: Duration) -> u32 {
+    (duration.as_secs_f64() * 65_536.0) as u32
+}
+
+fn from_compact(units: u32) -> Duration {
+    Duration::from_secs_f64(units as f64 / 65_536.0)
+}
+
+/// RTCP state for one side of a call: what we sent, what we received from the peer and
+/// what the peer told us about our stream.
+///
+/// All times are passed in, so the session can be driven by a synthetic clock.
+pub struct RtcpSession {
+    ssrc: u32,
+    cname: String,
+    clock_rate: u32,
+    /// Wall clock time at `epoch`, so NTP timestamps follow the monotonic clock.
+    epoch: Instant,
+    epoch_ntp: u64,
+    packets_sent: u32,
+    octets_sent: u32,
+    /// RTP timestamp of the last packet sent and when it was sent.
+    last_sent: Option<(u32, Instant)>,
+    sent_since_report: bool,
+    remote: Option<(u32, ReceptionStats)>,
+    stats: CallStats,
+    remote_left: bool,
+    next_report: Instant,
+}
+
+impl RtcpSession {
+    pub fn new(ssrc: u32, clock_rate: u32, now: Instant) -> Self {
+        Self {
+            ssrc,
+            cname: format!("{:08x}@test-gpui", ssrc),
+            clock_rate,
+            epoch: now,
+            epoch_ntp: ntp_timestamp(SystemTime::now()),
+            packets_sent: 0,
+            octets_sent: 0,
+            last_sent: None,
+            sent_since_report: false,
+            remote: None,
+            stats: CallStats::default(),
+            remote_left: false,
+            // The first report goes out early so round-trip times are known quickly.
+            next_report: now + REPORT_INTERVAL / 5,
+        }
+    }
+
+    /// Records an RTP packet we sent.
+    pub fn sent(&mut self, rtp_timestamp: u32, payload_len: usize, now: Instant) {
+        self.packets_sent = self.packets_sent.wrapping_add(1);
+        self.octets_sent = self.octets_sent.wrapping_add(payload_len as u32);
+        self.last_sent = Some((rtp_timestamp, now));
+        self.sent_since_report = true;
+        self.stats.packets_sent += 1;
+        self.stats.octets_sent += payload_len as u64;
+    }
+
+    /// Records an RTP packet from the peer. Packets from other sources are ignored.
+    pub fn received(&mut self, header: &RtpHeader, now: Instant) {
+        let clock_rate = self.clock_rate;
+        let (ssrc, stats) = self
+            .remote
+            .get_or_insert_with(|| (header.ssrc, ReceptionStats::new(clock_rate)));
+        if *ssrc == header.ssrc {
+            stats.record(header.sequence, header.timestamp, now);
+        }
+    }
+
+    /// Handles a compound RTCP packet from the peer.
+    pub fn receive(&mut self, data: &[u8], now: Instant) {
+        let Some(packets) = RtcpPacket::decode(data) else {
+            return;
+        };
+        for packet in packets {
+            match packet {
+                RtcpPacket::SenderReport {
+                    ssrc,
+                    ntp_timestamp,
+                    reports,
+                    ..
+                } => {
+                    if let Some((remote, stats)) = &mut self.remote
+                        && *remote == ssrc
+                    {
+                        stats.sender_report(ntp_timestamp, now);
+                    }
+                    self.report_received(&reports, now);
+                }
+                RtcpPacket::ReceiverReport { reports, .. } => self.report_received(&reports, now),
+                RtcpPacket::SourceDescription { .. } => {}
+                RtcpPacket::Bye { sources, .. } => {
+                    if let Some((remote, _)) = self.remote
+                        && sources.contains(&remote)
+                    {
+                        self.remote_left = true;
+                    }
+                }
+            }
+        }
+    }
+
+    fn report_received(&mut self, reports: &[ReportBlock], now: Instant) {
+        let Some(report) = reports.iter().find(|report| report.ssrc == self.ssrc) else {
+            return;
+        };
+        self.stats.reports_received += 1;
+        self.stats.remote_fraction_lost = report.fraction_lost as f32 / 256.0;
+        self.stats.remote_packets_lost = report.cumulative_lost as i64;
+        self.stats.remote_jitter =
+            Duration::from_secs_f64(report.jitter as f64 / self.clock_rate as f64);
+        // A report that has not seen our sender report yet says nothing about the round trip.
+        if report.last_sr != 0 {
+            let arrival = compact_ntp(self.ntp(now));
+            let rtt = arrival
+                .wrapping_sub(report.last_sr)
+                .wrapping_sub(report.delay_since_last_sr);
+            // Clock jumps can make the difference negative; ignore those reports.
+            if rtt < 0x8000_0000 {
+                self.stats.round_trip_time = Some(from_compact(rtt));
+            }
+        }
+    }
+
+    /// Whether the peer said goodbye with an RTCP BYE.
+    pub fn remote_left(&self) -> bool {
+        self.remote_left
+    }
+
+    /// Returns a report if one is due.
+    pub fn poll(&mut self, now: Instant) -> Option<Vec<u8>> {
+        if now < self.next_report {
+            return None;
+        }
+        self.next_report = now + REPORT_INTERVAL;
+        Some(RtcpPacket::encode(&self.report(now)))
+    }
+
+    /// A final report followed by a BYE for our stream.
+    pub fn bye(&mut self, now: Instant) -> Vec<u8> {
+        let mut packets = self.report(now);
+        packets.push(RtcpPacket::Bye {
+            sources: vec![self.ssrc],
+            reason: None,
+        });
+        RtcpPacket::encode(&packets)
+    }
+
+    /// A sender report if we sent media since the last report, otherwise a receiver report,
+    /// followed by our CNAME.
+    fn report(&mut self, now: Instant) -> Vec<RtcpPacket> {
+        let reports: Vec<_> = self
+            .remote
+            .iter_mut()
+            .map(|(ssrc, stats)| stats.report_block(*ssrc, now))
+            .collect();
+        if let Some((_, stats)) = &self.remote {
+            self.stats.update_reception(stats);
+        }
+
+        let report = match self.last_sent {
+            Some((rtp_timestamp, sent_at)) if self.sent_since_report => {
+                // Extrapolate the RTP clock to the moment the report is generated.
+                let elapsed = now.saturating_duration_since(sent_at).as_secs_f64();
+                let ticks = (elapsed * self.clock_rate as f64) as u32;
+                RtcpPacket::SenderReport {
+                    ssrc: self.ssrc,
+                    ntp_timestamp: self.ntp(now),
+                    rtp_timestamp: rtp_timestamp.wrapping_add(ticks),
+                    packet_count: self.packets_sent,
+                    octet_count: self.octets_sent,
+                    reports,
+                }
+            }
+            _ => RtcpPacket::ReceiverReport {
+                ssrc: self.ssrc,
+                reports,
+            },
+        };
+        self.sent_since_report = false;
+        vec![
+            report,
+            RtcpPacket::SourceDescription {
+                chunks: vec![(self.ssrc, self.cname.clone())],
+            },
+        ]
+    }
+
+    /// Statistics as of the last report plus live counters.
+    pub fn stats(&self) -> CallStats {
+        let mut stats = self.stats;
+        if let Some((_, reception)) = &self.remote {
+            stats.update_reception(reception);
+        }
+        stats
+    }
+
+    fn ntp(&self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.epoch);
+        let ticks = (elapsed.as_nanos() << 32) / 1_000_000_000;
+        self.epoch_ntp.wrapping_add(ticks as u64)
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// Size of an RTP header without CSRCs or extensions.
+pub const HEADER_LEN: usize = 12;
+
+/// Dynamic payload type conventionally used for Opus.
+pub const OPUS_PAYLOAD_TYPE: u8 = 111;
+
+/// Static payload type of comfort noise (RFC 3389) at an 8 kHz clock (RFC 3551).
+pub const CN_PAYLOAD_TYPE: u8 = 13;
+
+/// Opus RTP timestamps count at 48 kHz whatever the sampling rate (RFC 7587 section 4.1).
+pub const OPUS_CLOCK_RATE: u32 = 48_000;
+
+const VERSION: u8 = 2;
+
+/// Fixed part of an RTP header (RFC 3550 section 5.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpHeader {
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+}
+
+impl RtpHeader {
+    /// Writes the header into the first [`HEADER_LEN`] bytes of `buf`.
+    pub fn write(&self, buf: &mut [u8]) {
+        buf[0] = VERSION << 6;
+        buf[1] = (u8::from(self.marker) << 7) | (self.payload_type & 0x7f);
+        buf[2..4].copy_from_slice(&self.sequence.to_be_bytes());
+        buf[4..8].copy_from_slice(&self.timestamp.to_be_bytes());
+        buf[8..12].copy_from_slice(&self.ssrc.to_be_bytes());
+    }
+
+    /// Parses an RTP packet, returning the header and the payload.
+    pub fn parse(packet: &[u8]) -> Option<(RtpHeader, &[u8])> {
+        if packet.len() < HEADER_LEN || packet[0] >> 6 != VERSION {
+            return None;
+        }
+        let padding = packet[0] & 0x20 != 0;
+
+        let header = RtpHeader {
+            marker: packet[1] & 0x80 != 0,
+            payload_type: packet[1] & 0x7f,
+            sequence: u16::from_be_bytes([packet[2], packet[3]]),
+            timestamp: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            ssrc: u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+        };
+
+        let start = header_len(packet)?;
+        let mut end = packet.len();
+        if padding {
+            end = end.checked_sub(*packet.last()? as usize)?;
+        }
+        if start > end {
+            return None;
+        }
+        Some((header, &packet[start..end]))
+    }
+}
+
+/// Length of the header of `packet`, including CSRCs and any header extension.
+pub fn header_len(packet: &[u8]) -> Option<usize> {
+    let first = *packet.first()?;
+    let mut len = HEADER_LEN + (first & 0x0f) as usize * 4;
+    if first & 0x10 != 0 {
+        let words = packet.get(len + 2..len + 4)?;
+        len += 4 + u16::from_be_bytes([words[0], words[1]]) as usize * 4;
+    }
+    (len <= packet.len()).then_some(len)
+}
+
+/// Picks a random SSRC for a new stream.
+pub fn random_ssrc() -> u32 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or_default(),
+    );
+    hasher.finish() as u32
+}
@@ -0,0 +1,477 @@
+//! SDP (RFC 8866) offers and answers describing an Opus audio stream (RFC 7587).
+
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::codec::{AudioEncoder, Codec, CodecError, CodecSet};
+use crate::signaling::{CodecParams, FRAME_SIZES_MS, OPUS_SAMPLE_RATES};
+
+/// Opus always uses a 48 kHz RTP clock and advertises two channels in the rtpmap.
+const OPUS_RTPMAP: &str = "opus/48000/2";
+/// The `maxaveragebitrate` range RFC 7587 allows.
+const OPUS_BITRATES: std::ops::RangeInclusive<u32> = 6_000..=510_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SdpError {
+    /// A line is not of the form `<type>=<value>` or a field could not be parsed.
+    InvalidLine(usize, String),
+    /// A required line such as `v=`, `o=` or `s=` is missing.
+    Missing(&'static str),
+    /// No audio media section offers Opus.
+    NoOpus,
+    /// The ptime is not a frame duration we packetize with.
+    UnsupportedPtime(u32),
+    /// Opus in SDP is mono or stereo only.
+    TooManyChannels(u8),
+}
+
+impl fmt::Display for SdpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SdpError::InvalidLine(line, text) => write!(f, "invalid SDP line {}: {}", line, text),
+            SdpError::Missing(field) => write!(f, "SDP is missing its {} line", field),
+            SdpError::NoOpus => write!(f, "SDP does not offer Opus audio"),
+            SdpError::UnsupportedPtime(ptime) => write!(f, "unsupported ptime of {} ms", ptime),
+            SdpError::TooManyChannels(channels) => {
+                write!(f, "SDP cannot describe {} channels", channels)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SdpError {}
+
+/// An `a=` line. Property attributes such as `a=sendrecv` have no value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Attribute {
+    pub fn new(name: &str, value: Option<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            value,
+        }
+    }
+}
+
+/// The `o=` line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub username: String,
+    pub session_id: u64,
+    pub session_version: u64,
+    pub address: IpAddr,
+}
+
+/// One `m=` section and everything up to the next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaDescription {
+    pub media: String,
+    pub port: u16,
+    pub protocol: String,
+    pub formats: Vec<String>,
+    pub connection: Option<IpAddr>,
+    pub attributes: Vec<Attribute>,
+}
+
+impl MediaDescription {
+    /// Values of every attribute called `name`.
+    pub fn attributes<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.attributes
+            .iter()
+            .filter(move |attribute| attribute.name == name)
+            .filter_map(|attribute| attribute.value.as_deref())
+    }
+
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.attributes
+            .iter()
+            .any(|attribute| attribute.name == name)
+    }
+
+    /// Payload type mapped to Opus by an `a=rtpmap` line.
+    pub fn opus_payload_type(&self) -> Option<u8> {
+        self.attributes("rtpmap").find_map(|value| {
+            let (payload_type, encoding) = value.split_once(' ')?;
+            if encoding.trim().eq_ignore_ascii_case(OPUS_RTPMAP) {
+                payload_type.parse().ok()
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Parameters of the `a=fmtp` line for `payload_type`.
+    pub fn fmtp(&self, payload_type: u8) -> Vec<(String, String)> {
+        let prefix = payload_type.to_string();
+        self.attributes("fmtp")
+            .filter_map(|value| {
+                let (format, params) = value.split_once(' ')?;
+                (format == prefix).then_some(params)
+            })
+            .flat_map(|params| params.split(';'))
+            .filter_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                Some((key.trim().to_ascii_lowercase(), value.trim().to_string()))
+            })
+            .collect()
+    }
+}
+
+/// A whole SDP document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionDescription {
+    pub origin: Origin,
+    pub session_name: String,
+    pub connection: Option<IpAddr>,
+    pub attributes: Vec<Attribute>,
+    pub media: Vec<MediaDescription>,
+}
+
+/// How we send and want to receive Opus, in the terms of RFC 7587.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusConfig {
+    pub payload_type: u8,
+    /// Encoder sample rate, advertised as `maxplaybackrate`.
+    pub sample_rate: u32,
+    pub stereo: bool,
+    pub ptime: u32,
+    pub max_average_bitrate: Option<u32>,
+    pub fec: bool,
+    pub dtx: bool,
+}
+
+impl Default for OpusConfig {
+    /// The configuration `opus-sender` has always used: 48 kHz mono in 20 ms frames.
+    fn default() -> Self {
+        Self {
+            payload_type: crate::rtp::OPUS_PAYLOAD_TYPE,
+            sample_rate: 48_000,
+            stereo: false,
+            ptime: 20,
+            max_average_bitrate: None,
+            fec: false,
+            dtx: false,
+        }
+    }
+}
+
+impl OpusConfig {
+    /// Reads the Opus parameters of the first audio section that offers Opus.
+    pub fn from_sdp(sdp: &SessionDescription) -> Result<Self, SdpError> {
+        let media = sdp
+            .media
+            .iter()
+            .filter(|media| media.media == "audio")
+            .find(|media| media.opus_payload_type().is_some())
+            .ok_or(SdpError::NoOpus)?;
+        let payload_type = media.opus_payload_type().ok_or(SdpError::NoOpus)?;
+
+        let mut config = OpusConfig {
+            payload_type,
+            ..OpusConfig::default()
+        };
+        for (key, value) in media.fmtp(payload_type) {
+            let flag = value == "1";
+            match key.as_str() {
+                "stereo" => config.stereo = flag,
+                "useinbandfec" => config.fec = flag,
+                "usedtx" => config.dtx = flag,
+                "maxaveragebitrate" => config.max_average_bitrate = value.parse().ok(),
+                "maxplaybackrate" => {
+                    if let Ok(rate) = value.parse::<u32>() {
+                        config.sample_rate = supported_rate(rate);
+                    }
+                }
+                "ptime" => config.ptime = value.parse().unwrap_or(config.ptime),
+                _ => {}
+            }
+        }
+        if let Some(ptime) = media.attributes("ptime").next() {
+            config.ptime = ptime.trim().parse().unwrap_or(config.ptime);
+        }
+        Ok(config)
+    }
+
+    /// The `a=fmtp` parameters describing this configuration.
+    pub fn fmtp(&self) -> String {
+        let mut params = vec![
+            format!("maxplaybackrate={}", self.sample_rate),
+            format!("stereo={}", u8::from(self.stereo)),
+            format!("sprop-stereo={}", u8::from(self.stereo)),
+            format!("useinbandfec={}", u8::from(self.fec)),
+            format!("usedtx={}", u8::from(self.dtx)),
+        ];
+        if let Some(bitrate) = self.max_average_bitrate {
+            params.push(format!("maxaveragebitrate={}", bitrate));
+        }
+        params.join(";")
+    }
+
+    /// Narrows our configuration to what the remote side asked for.
+    pub fn answer(&self, offer: &OpusConfig) -> OpusConfig {
+        let max_average_bitrate = match (self.max_average_bitrate, offer.max_average_bitrate) {
+            (Some(ours), Some(theirs)) => Some(ours.min(theirs)),
+            (ours, theirs) => ours.or(theirs),
+        };
+        OpusConfig {
+            payload_type: offer.payload_type,
+            sample_rate: self.sample_rate.min(offer.sample_rate),
+            stereo: self.stereo && offer.stereo,
+            ptime: offer.ptime,
+            max_average_bitrate,
+            fec: self.fec && offer.fec,
+            dtx: self.dtx && offer.dtx,
+        }
+    }
+
+    /// Configures an encoder to match. Without a bitrate limit, the encoder keeps choosing
+    /// its own; a limit outside what RFC 7587 allows is clamped into range.
+    pub fn apply(&self, encoder: &mut dyn AudioEncoder) -> Result<(), CodecError> {
+        encoder.set_fec(self.fec, 0)?;
+        encoder.set_dtx(self.dtx)?;
+        match self.max_average_bitrate {
+            Some(bitrate) => {
+                encoder.set_bitrate(bitrate.clamp(*OPUS_BITRATES.start(), *OPUS_BITRATES.end()))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Converts to the parameters exchanged by [`crate::signaling`], refusing a ptime that
+    /// is not one of [`FRAME_SIZES_MS`].
+    pub fn to_params(&self) -> Result<CodecParams, SdpError> {
+        let frame_ms = FRAME_SIZES_MS
+            .into_iter()
+            .find(|&frame_ms| frame_ms as u32 == self.ptime)
+            .ok_or(SdpError::UnsupportedPtime(self.ptime))?;
+        Ok(CodecParams {
+            sample_rate: self.sample_rate,
+            channels: if self.stereo { 2 } else { 1 },
+            frame_ms,
+            bitrate: self
+                .max_average_bitrate
+                .unwrap_or(CodecParams::default().bitrate),
+            fec: self.fec,
+            codecs: CodecSet::only(Codec::Opus),
+        })
+    }
+
+    /// Converts from the parameters exchanged by [`crate::signaling`], which can carry more
+    /// channels than SDP can describe.
+    pub fn from_params(params: &CodecParams) -> Result<Self, SdpError> {
+        if params.channels > 2 {
+            return Err(SdpError::TooManyChannels(params.channels));
+        }
+        Ok(Self {
+            sample_rate: params.sample_rate,
+            stereo: params.channels == 2,
+            ptime: params.frame_ms as u32,
+            max_average_bitrate: Some(params.bitrate),
+            fec: params.fec,
+            ..Self::default()
+        })
+    }
+}
+
+/// Rounds `rate` down to a sample rate the Opus encoder supports.
+fn supported_rate(rate: u32) -> u32 {
+    OPUS_SAMPLE_RATES
+        .iter()
+        .rev()
+        .copied()
+        .find(|&supported| supported <= rate)
+        .unwrap_or(OPUS_SAMPLE_RATES[0])
+}
+
+impl SessionDescription {
+    /// Builds an offer for a single sendrecv Opus stream on `address:port`.
+    pub fn offer(address: IpAddr, port: u16, session_id: u64, opus: &OpusConfig) -> Self {
+        let pt = opus.payload_type;
+        SessionDescription {
+            origin: Origin {
+                username: "-".into(),
+                session_id,
+                session_version: 1,
+                address,
+            },
+            session_name: "-".into(),
+            connection: Some(address),
+            attributes: Vec::new(),
+            media: vec![MediaDescription {
+                media: "audio".into(),
+                port,
+                protocol: "RTP/AVP".into(),
+                formats: vec![pt.to_string()],
+                connection: None,
+                attributes: vec![
+                    Attribute::new("rtpmap", Some(format!("{} {}", pt, OPUS_RTPMAP))),
+                    Attribute::new("fmtp", Some(format!("{} {}", pt, opus.fmtp()))),
+                    Attribute::new("ptime", Some(opus.ptime.to_string())),
+                    Attribute::new("sendrecv", None),
+                ],
+            }],
+        }
+    }
+
+    /// Answers `self` as an offer, narrowing `local` to what the offer allows.
+    pub fn answer(
+        &self,
+        address: IpAddr,
+        port: u16,
+        local: &OpusConfig,
+    ) -> Result<(SessionDescription, OpusConfig), SdpError> {
+        let offered = OpusConfig::from_sdp(self)?;
+        let agreed = local.answer(&offered);
+        let mut answer = Self::offer(address, port, self.origin.session_id, &agreed);
+        answer.origin.session_version = self.origin.session_version;
+        Ok((answer, agreed))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, SdpError> {
+        let mut version = false;
+        let mut origin = None;
+        let mut session_name = None;
+        let mut connection = None;
+        let mut attributes = Vec::new();
+        let mut media: Vec<MediaDescription> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || SdpError::InvalidLine(index + 1, line.to_string());
+            let (kind, value) = line.split_once('=').ok_or_else(invalid)?;
+            match (kind, media.last_mut()) {
+                ("v", _) if value == "0" => version = true,
+                ("v", _) => return Err(invalid()),
+                ("o", _) => origin = Some(parse_origin(value).ok_or_else(invalid)?),
+                ("s", _) => session_name = Some(value.to_string()),
+                ("m", _) => media.push(parse_media(value).ok_or_else(invalid)?),
+                ("c", Some(current)) => {
+                    current.connection = Some(parse_connection(value).ok_or_else(invalid)?)
+                }
+                ("c", None) => connection = Some(parse_connection(value).ok_or_else(invalid)?),
+                ("a", current) => {
+                    let attribute = match value.split_once(':') {
+                        Some((name, value)) => Attribute::new(name, Some(value.to_string())),
+                        None => Attribute::new(value, None),
+                    };
+                    match current {
+                        Some(current) => current.attributes.push(attribute),
+                        None => attributes.push(attribute),
+                    }
+                }
+                // Timing, bandwidth, and the rarer session fields are not needed.
+                (kind, _) if kind.len() == 1 => {}
+                _ => return Err(invalid()),
+            }
+        }
+
+        if !version {
+            return Err(SdpError::Missing("v="));
+        }
+        Ok(SessionDescription {
+            origin: origin.ok_or(SdpError::Missing("o="))?,
+            session_name: session_name.ok_or(SdpError::Missing("s="))?,
+            connection,
+            attributes,
+            media,
+        })
+    }
+}
+
+fn address_type(address: &IpAddr) -> &'static str {
+    match address {
+        IpAddr::V4(_) => "IP4",
+        IpAddr::V6(_) => "IP6",
+    }
+}
+
+fn parse_origin(value: &str) -> Option<Origin> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    let [username, session_id, session_version, "IN", _, address] = fields[..] else {
+        return None;
+    };
+    Some(Origin {
+        username: username.to_string(),
+        session_id: session_id.parse().ok()?,
+        session_version: session_version.parse().ok()?,
+        address: address.parse().ok()?,
+    })
+}
+
+fn parse_connection(value: &str) -> Option<IpAddr> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    let ["IN", _, address] = fields[..] else {
+        return None;
+    };
+    // Multicast connection addresses may carry a TTL suffix.
+    address.split('/').next()?.parse().ok()
+}
+
+fn parse_media(value: &str) -> Option<MediaDescription> {
+    let mut fields = value.split_whitespace();
+    let media = fields.next()?.to_string();
+    let port = fields.next()?.split('/').next()?.parse().ok()?;
+    let protocol = fields.next()?.to_string();
+    Some(MediaDescription {
+        media,
+        port,
+        protocol,
+        formats: fields.map(str::to_string).collect(),
+        connection: None,
+        attributes: Vec::new(),
+    })
+}
+
+fn write_attributes(f: &mut fmt::Formatter<'_>, attributes: &[Attribute]) -> fmt::Result {
+    for attribute in attributes {
+        match &attribute.value {
+            Some(value) => write!(f, "a={}:{}\r\n", attribute.name, value)?,
+            None => write!(f, "a={}\r\n", attribute.name)?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for SessionDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let origin = &self.origin;
+        write!(f, "v=0\r\n")?;
+        write!(
+            f,
+            "o={} {} {} IN {} {}\r\n",
+            origin.username,
+            origin.session_id,
+            origin.session_version,
+            address_type(&origin.address),
+            origin.address
+        )?;
+        write!(f, "s={}\r\n", self.session_name)?;
+        if let Some(address) = &self.connection {
+            write!(f, "c=IN {} {}\r\n", address_type(address), address)?;
+        }
+        write!(f, "t=0 0\r\n")?;
+        write_attributes(f, &self.attributes)?;
+        for media in &self.media {
+            write!(
+                f,
+                "m={} {} {} {}\r\n",
+                media.media,
+                media.port,
+                media.protocol,
+                media.formats.join(" ")
+            )?;
+            if let Some(address) = &media.connection {
+                write!(f, "c=IN {} {}\r\n", address_type(address), address)?;
+            }
+            write_attributes(f, &media.attributes)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,653 @@
+//! Call setup and teardown.
+//!
+//! Signaling messages share the media socket. They start with [`MAGIC`], which can never be
+//! the first byte of an RTP version 2 packet, so the two are easy to tell apart.
+//!
+//! [`Session`] is a pure state machine: it is fed messages, user commands and the current
+//! time, and answers with [`Action`]s for the caller to carry out.
+
+use std::time::{Duration, Instant};
+
+use crate::codec::{Codec, CodecSet};
+use crate::multistream::MAX_SURROUND_CHANNELS;
+
+pub const MAGIC: u8 = b'S';
+const VERSION: u8 = 2;
+
+const INVITE: u8 = 1;
+const ACCEPT: u8 = 2;
+const REJECT: u8 = 3;
+const BYE: u8 = 4;
+const KEEPALIVE: u8 = 5;
+
+/// Sample rates Opus can encode at.
+pub const OPUS_SAMPLE_RATES: [u32; 5] = [8_000, 12_000, 16_000, 24_000, 48_000];
+
+/// Frame durations we are willing to packetize with.
+pub const FRAME_SIZES_MS: [u8; 4] = [10, 20, 40, 60];
+
+const MIN_BITRATE: u32 = 6_000;
+
+/// An X25519 public key exchanged during call setup.
+pub type PublicKey = [u8; 32];
+
+/// Codec parameters offered in an invite or agreed on in an accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodecParams {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub frame_ms: u8,
+    pub bitrate: u32,
+    pub fec: bool,
+    /// Codecs offered, or the one agreed on.
+    pub codecs: CodecSet,
+}
+
+impl Default for CodecParams {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48_000,
+            channels: 1,
+            frame_ms: 20,
+            bitrate: 32_000,
+            fec: true,
+            codecs: CodecSet::all(),
+        }
+    }
+}
+
+impl CodecParams {
+    /// Answers a remote offer using `self` as the local limits, with the most preferred
+    /// codec both sides support.
+    pub fn negotiate(&self, offer: &CodecParams) -> Result<CodecParams, RejectReason> {
+        if !OPUS_SAMPLE_RATES.contains(&offer.sample_rate)
+            || !FRAME_SIZES_MS.contains(&offer.frame_ms)
+            || !(1..=MAX_SURROUND_CHANNELS as u8).contains(&offer.channels)
+            || offer.bitrate < MIN_BITRATE
+        {
+            return Err(RejectReason::Incompatible);
+        }
+        // The most preferred codec whose frames fit in a packet at the agreed format.
+        let (codec, (sample_rate, channels)) = self
+            .codecs
+            .intersection(offer.codecs)
+            .iter()
+            .map(|codec| {
+                let format = codec.format(
+                    self.sample_rate.min(offer.sample_rate),
+                    self.channels.min(offer.channels) as usize,
+                );
+                (codec, format)
+            })
+            .find(|(codec, (sample_rate, channels))| {
+                codec.fits(*sample_rate, *channels, offer.frame_ms as u32)
+            })
+            .ok_or(RejectReason::Incompatible)?;
+        Ok(CodecParams {
+            sample_rate,
+            channels: channels as u8,
+            frame_ms: offer.frame_ms,
+            bitrate: self.bitrate.min(offer.bitrate).max(MIN_BITRATE),
+            fec: self.fec && offer.fec,
+            codecs: CodecSet::only(codec),
+        })
+    }
+
+    /// The codec to use: the agreed one once negotiated.
+    pub fn codec(&self) -> Option<Codec> {
+        self.codecs.preferred()
+    }
+
+    /// Returns true if `answer` only narrows what `self` offered.
+    pub fn allows(&self, answer: &CodecParams) -> bool {
+        OPUS_SAMPLE_RATES.contains(&answer.sample_rate)
+            && answer.sample_rate <= self.sample_rate
+            && (1..=self.channels).contains(&answer.channels)
+            && answer.frame_ms == self.frame_ms
+            && (MIN_BITRATE..=self.bitrate).contains(&answer.bitrate)
+            && (self.fec || !answer.fec)
+            && answer.codecs.len() == 1
+            && self.codecs.intersection(answer.codecs) == answer.codecs
+            && answer.codec().is_some_and(|codec| {
+                let (_, channels) = codec.format(answer.sample_rate, answer.channels as usize);
+                channels == answer.channels as usize
+                    && codec.fits(
+                        answer.sample_rate,
+                        answer.channels as usize,
+                        answer.frame_ms as u32,
+                    )
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    Busy,
+    Declined,
+    Incompatible,
+}
+
+impl RejectReason {
+    fn code(self) -> u8 {
+        match self {
+            RejectReason::Busy => 1,
+            RejectReason::Declined => 2,
+            RejectReason::Incompatible => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(RejectReason::Busy),
+            2 => Some(RejectReason::Declined),
+            3 => Some(RejectReason::Incompatible),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Invite {
+        call_id: u32,
+        ssrc: u32,
+        params: CodecParams,
+        public_key: Option<PublicKey>,
+    },
+    Accept {
+        call_id: u32,
+        ssrc: u32,
+        params: CodecParams,
+        public_key: Option<PublicKey>,
+    },
+    Reject {
+        call_id: u32,
+        reason: RejectReason,
+    },
+    Bye {
+        call_id: u32,
+    },
+    Keepalive {
+        call_id: u32,
+    },
+}
+
+impl Message {
+    pub fn call_id(&self) -> u32 {
+        match self {
+            Message::Invite { call_id, .. }
+            | Message::Accept { call_id, .. }
+            | Message::Reject { call_id, .. }
+            | Message::Bye { call_id }
+            | Message::Keepalive { call_id } => *call_id,
+        }
+    }
+
+    /// Returns true for invites, accepts and rejects, which set a call up before there are
+    /// keys to sign them with. Byes and keepalives are signed once there are.
+    pub fn is_setup(&self) -> bool {
+        matches!(
+            self,
+            Message::Invite { .. } | Message::Accept { .. } | Message::Reject { .. }
+        )
+    }
+
+    /// Returns true if `packet` looks like a signaling message rather than media.
+    pub fn is_signaling(packet: &[u8]) -> bool {
+        packet.first() == Some(&MAGIC)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let kind = match self {
+            Message::Invite { .. } => INVITE,
+            Message::Accept { .. } => ACCEPT,
+            Message::Reject { .. } => REJECT,
+            Message::Bye { .. } => BYE,
+            Message::Keepalive { .. } => KEEPALIVE,
+        };
+        let mut buf = vec![MAGIC, VERSION, kind];
+        buf.extend_from_slice(&self.call_id().to_be_bytes());
+        match self {
+            Message::Invite {
+                ssrc,
+                params,
+                public_key,
+                ..
+            }
+            | Message::Accept {
+                ssrc,
+                params,
+                public_key,
+                ..
+            } => {
+                buf.extend_from_slice(&ssrc.to_be_bytes());
+                buf.extend_from_slice(&params.sample_rate.to_be_bytes());
+                buf.push(params.channels);
+                buf.push(params.frame_ms);
+                buf.extend_from_slice(&params.bitrate.to_be_bytes());
+                buf.push(u8::from(params.fec));
+                buf.push(params.codecs.bits());
+                if let Some(key) = public_key {
+                    buf.extend_from_slice(key);
+                }
+            }
+            Message::Reject { reason, .. } => buf.push(reason.code()),
+            Message::Bye { .. } | Message::Keepalive { .. } => {}
+        }
+        buf
+    }
+
+    pub fn decode(packet: &[u8]) -> Option<Message> {
+        if packet.len() < 7 || packet[0] != MAGIC || packet[1] != VERSION {
+            return None;
+        }
+        let call_id = u32::from_be_bytes(packet[3..7].try_into().ok()?);
+        let body = &packet[7..];
+        let message = match packet[2] {
+            INVITE | ACCEPT => {
+                if body.len() < 16 {
+                    return None;
+                }
+                let ssrc = u32::from_be_bytes(body[0..4].try_into().ok()?);
+                let params = CodecParams {
+                    sample_rate: u32::from_be_bytes(body[4..8].try_into().ok()?),
+                    channels: body[8],
+                    frame_ms: body[9],
+                    bitrate: u32::from_be_bytes(body[10..14].try_into().ok()?),
+                    fec: body[14] & 1 != 0,
+                    codecs: CodecSet::from_bits(body[15]),
+                };
+                let public_key = body.get(16..48).and_then(|key| key.try_into().ok());
+                if packet[2] == INVITE {
+                    Message::Invite {
+                        call_id,
+                        ssrc,
+                        params,
+                        public_key,
+                    }
+                } else {
+                    Message::Accept {
+                        call_id,
+                        ssrc,
+                        params,
+                        public_key,
+                    }
+                }
+            }
+            REJECT => Message::Reject {
+                call_id,
+                reason: RejectReason::from_code(*body.first()?)?,
+            },
+            BYE => Message::Bye { call_id },
+            KEEPALIVE => Message::Keepalive { call_id },
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+/// The agreed parameters of an established call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Media {
+    pub call_id: u32,
+    pub local_ssrc: u32,
+    pub remote_ssrc: u32,
+    pub params: CodecParams,
+    /// The peer's key, present when media is encrypted.
+    pub remote_key: Option<PublicKey>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndReason {
+    /// We hung up.
+    Hangup,
+    /// The peer sent a bye.
+    RemoteHangup,
+    Rejected(RejectReason),
+    /// The peer stopped responding.
+    Timeout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    /// We sent an invite and are waiting for an answer.
+    Inviting {
+        call_id: u32,
+        params: CodecParams,
+    },
+    /// We received an invite and are waiting for the user to accept or reject it.
+    Ringing {
+        call_id: u32,
+        remote_ssrc: u32,
+        params: CodecParams,
+        remote_key: Option<PublicKey>,
+    },
+    Active(Media),
+    Ended(EndReason),
+}
+
+/// Something the owner of a [`Session`] has to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Send(Message),
+    /// An invite arrived; accept or reject it.
+    Incoming(CodecParams),
+    /// Media can start flowing.
+    Connected(Media),
+    Ended(EndReason),
+}
+
+/// Retransmission and liveness intervals.
+#[derive(Debug, Clone, Copy)]
+pub struct Timers {
+    pub invite_retry: Duration,
+    pub invite_timeout: Duration,
+    pub keepalive: Duration,
+    pub idle_timeout: Duration,
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Self {
+            invite_retry: Duration::from_millis(500),
+            invite_timeout: Duration::from_secs(30),
+            keepalive: Duration::from_secs(1),
+            idle_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// One call's signaling state.
+pub struct Session {
+    state: State,
+    local: CodecParams,
+    local_ssrc: u32,
+    public_key: Option<PublicKey>,
+    timers: Timers,
+    started: Instant,
+    last_sent: Instant,
+    last_heard: Instant,
+}
+
+impl Session {
+    pub fn new(local: CodecParams, local_ssrc: u32, now: Instant) -> Self {
+        Self {
+            state: State::Idle,
+            local,
+            local_ssrc,
+            public_key: None,
+            timers: Timers::default(),
+            started: now,
+            last_sent: now,
+            last_heard: now,
+        }
+    }
+
+    pub fn with_timers(mut self, timers: Timers) -> Self {
+        self.timers = timers;
+        self
+    }
+
+    /// Offers `key` for encrypting media and refuses peers that do not offer one back.
+    pub fn with_public_key(mut self, key: PublicKey) -> Self {
+        self.public_key = Some(key);
+        self
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Returns the media parameters once the call is established.
+    pub fn media(&self) -> Option<Media> {
+        match self.state {
+            State::Active(media) => Some(media),
+            _ => None,
+        }
+    }
+
+    pub fn is_ended(&self) -> bool {
+        matches!(self.state, State::Ended(_))
+    }
+
+    /// Starts an outgoing call.
+    pub fn invite(&mut self, call_id: u32, now: Instant) -> Vec<Action> {
+        if self.state != State::Idle {
+            return Vec::new();
+        }
+        self.state = State::Inviting {
+            call_id,
+            params: self.local,
+        };
+        self.started = now;
+        self.last_heard = now;
+        vec![self.send(self.invite_message(call_id), now)]
+    }
+
+    /// Accepts a ringing incoming call.
+    pub fn accept(&mut self, now: Instant) -> Vec<Action> {
+        let State::Ringing {
+            call_id,
+            remote_ssrc,
+            params,
+            remote_key,
+        } = self.state
+        else {
+            return Vec::new();
+        };
+        let media = Media {
+            call_id,
+            local_ssrc: self.local_ssrc,
+            remote_ssrc,
+            params,
+            remote_key,
+        };
+        self.state = State::Active(media);
+        vec![
+            self.send(self.accept_message(&media), now),
+            Action::Connected(media),
+        ]
+    }
+
+    /// Turns down a ringing incoming call.
+    pub fn reject(&mut self, reason: RejectReason, now: Instant) -> Vec<Action> {
+        let State::Ringing { call_id, .. } = self.state else {
+            return Vec::new();
+        };
+        self.end_with(
+            EndReason::Rejected(reason),
+            Some(Message::Reject { call_id, reason }),
+            now,
+        )
+    }
+
+    /// Ends the call from our side, whatever stage it is in.
+    pub fn hangup(&mut self, now: Instant) -> Vec<Action> {
+        let call_id = match self.state {
+            State::Inviting { call_id, .. } | State::Active(Media { call_id, .. }) => call_id,
+            State::Ringing { call_id, .. } => {
+                let reason = RejectReason::Declined;
+                return self.end_with(
+                    EndReason::Hangup,
+                    Some(Message::Reject { call_id, reason }),
+                    now,
+                );
+            }
+            State::Idle => return self.end_with(EndReason::Hangup, None, now),
+            State::Ended(_) => return Vec::new(),
+        };
+        self.end_with(EndReason::Hangup, Some(Message::Bye { call_id }), now)
+    }
+
+    /// Records that media arrived from the peer, which counts as a sign of life.
+    pub fn media_received(&mut self, now: Instant) {
+        self.last_heard = now;
+    }
+
+    /// Handles a message from the peer.
+    ///
+    /// Nothing here checks who sent it: once keys are agreed, byes and keepalives should only
+    /// be passed on if their signature checks out, as [`Message::is_setup`] describes.
+    pub fn receive(&mut self, message: Message, now: Instant) -> Vec<Action> {
+        let current = match self.state {
+            State::Idle | State::Ended(_) => None,
+            State::Inviting { call_id, .. }
+            | State::Ringing { call_id, .. }
+            | State::Active(Media { call_id, .. }) => Some(call_id),
+        };
+        if current == Some(message.call_id()) {
+            self.last_heard = now;
+        }
+
+        match (self.state, message) {
+            (
+                State::Idle,
+                Message::Invite {
+                    call_id,
+                    ssrc,
+                    params,
+                    public_key,
+                },
+            ) => match self.negotiate(&params, public_key) {
+                Ok(params) => {
+                    self.state = State::Ringing {
+                        call_id,
+                        remote_ssrc: ssrc,
+                        params,
+                        remote_key: public_key,
+                    };
+                    self.started = now;
+                    self.last_heard = now;
+                    vec![Action::Incoming(params)]
+                }
+                Err(reason) => vec![self.send(Message::Reject { call_id, reason }, now)],
+            },
+            (_, Message::Invite { call_id, .. }) if current != Some(call_id) => {
+                let reason = RejectReason::Busy;
+                vec![self.send(Message::Reject { call_id, reason }, now)]
+            }
+            // Our accept was lost; the caller is retransmitting its invite.
+            (State::Active(media), Message::Invite { .. }) => {
+                vec![self.send(self.accept_message(&media), now)]
+            }
+            (
+                State::Inviting {
+                    call_id,
+                    params: offer,
+                },
+                Message::Accept {
+                    call_id: answered,
+                    ssrc,
+                    params,
+                    public_key,
+                },
+            ) if call_id == answered => {
+                let insecure = self.public_key.is_some() && public_key.is_none();
+                if insecure || !offer.allows(&params) {
+                    return self.end_with(
+                        EndReason::Rejected(RejectReason::Incompatible),
+                        Some(Message::Bye { call_id }),
+                        now,
+                    );
+                }
+                let media = Media {
+                    call_id,
+                    local_ssrc: self.local_ssrc,
+                    remote_ssrc: ssrc,
+                    params,
+                    remote_key: public_key,
+                };
+                self.state = State::Active(media);
+                vec![Action::Connected(media)]
+            }
+            (State::Inviting { .. }, Message::Reject { call_id, reason })
+                if current == Some(call_id) =>
+            {
+                self.end_with(EndReason::Rejected(reason), None, now)
+            }
+            (
+                State::Inviting { .. } | State::Ringing { .. } | State::Active(_),
+                Message::Bye { call_id },
+            ) if current == Some(call_id) => self.end_with(EndReason::RemoteHangup, None, now),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Retransmits, sends keepalives and detects a silent peer.
+    pub fn tick(&mut self, now: Instant) -> Vec<Action> {
+        let since_sent = now.saturating_duration_since(self.last_sent);
+        let since_heard = now.saturating_duration_since(self.last_heard);
+        match self.state {
+            State::Inviting { call_id, .. } => {
+                if now.saturating_duration_since(self.started) >= self.timers.invite_timeout {
+                    self.end_with(EndReason::Timeout, Some(Message::Bye { call_id }), now)
+                } else if since_sent >= self.timers.invite_retry {
+                    vec![self.send(self.invite_message(call_id), now)]
+                } else {
+                    Vec::new()
+                }
+            }
+            State::Ringing { .. } if since_heard >= self.timers.invite_timeout => {
+                self.end_with(EndReason::Timeout, None, now)
+            }
+            State::Active(Media { call_id, .. }) => {
+                if since_heard >= self.timers.idle_timeout {
+                    self.end_with(EndReason::Timeout, Some(Message::Bye { call_id }), now)
+                } else if since_sent >= self.timers.keepalive {
+                    vec![self.send(Message::Keepalive { call_id }, now)]
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Answers an offer, insisting on a key if we offer encryption ourselves.
+    fn negotiate(
+        &self,
+        offer: &CodecParams,
+        public_key: Option<PublicKey>,
+    ) -> Result<CodecParams, RejectReason> {
+        if self.public_key.is_some() && public_key.is_none() {
+            return Err(RejectReason::Incompatible);
+        }
+        self.local.negotiate(offer)
+    }
+
+    fn invite_message(&self, call_id: u32) -> Message {
+        Message::Invite {
+            call_id,
+            ssrc: self.local_ssrc,
+            params: self.local,
+            public_key: self.public_key,
+        }
+    }
+
+    fn accept_message(&self, media: &Media) -> Message {
+        Message::Accept {
+            call_id: media.call_id,
+            ssrc: media.local_ssrc,
+            params: media.params,
+            public_key: self.public_key,
+        }
+    }
+
+    fn send(&mut self, message: Message, now: Instant) -> Action {
+        self.last_sent = now;
+        Action::Send(message)
+    }
+
+    fn end_with(
+        &mut self,
+        reason: EndReason,
+        message: Option<Message>,
+        now: Instant,
+    ) -> Vec<Action> {
+        self.state = State::Ended(reason);
+        let mut actions: Vec<Action> = message.map(|m| self.send(m, now)).into_iter().collect();
+        actions.push(Action::Ended(reason));
+        actions
+    }
+}
@@ -0,0 +1,193 @@
+//! Places mono voices around the listener.
+//!
+//! Positions are azimuths in degrees across the front half of the listener, from -90 (hard
+//! left) through 0 (straight ahead) to 90 (hard right). Two renderers are available:
+//!
+//! * [`Renderer::EqualPower`] only changes the level of each side, keeping the total power
+//!   constant so a voice is as loud in the middle as at the sides. Good for speakers.
+//! * [`Renderer::Binaural`] also delays the far ear by the time sound takes to get around
+//!   the head (Woodworth's formula) and filters each ear with the head-shadow model of Brown
+//!   and Duda, which dulls the far ear and brightens the near one. Much easier to localize
+//!   on headphones, and cheap enough to run per participant.
+
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+use std::time::Duration;
+
+/// Radius of an average head in meters.
+const HEAD_RADIUS: f32 = 0.0875;
+const SPEED_OF_SOUND: f32 = 343.0;
+/// Head-shadow gain straight away from the source, and the angle where it bottoms out.
+const SHADOW_MIN_GAIN: f32 = 0.1;
+const SHADOW_MIN_ANGLE: f32 = 5.0 * PI / 6.0;
+/// Enough delay line for the largest interaural delay, about 0.66 ms, at 192 kHz.
+const DELAY_LEN: usize = 256;
+/// Time constant position changes are smoothed with, so moving a voice does not click.
+const GLIDE: Duration = Duration::from_millis(50);
+
+/// How voices are positioned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Renderer {
+    /// Level differences only.
+    #[default]
+    EqualPower,
+    /// Time and level differences with a head-shadow filter, for headphones.
+    Binaural,
+}
+
+/// Left and right gains placing a voice at `azimuth` degrees with constant total power.
+pub fn equal_power(azimuth: f32) -> (f32, f32) {
+    let angle = (azimuth.clamp(-90.0, 90.0) / 90.0 + 1.0) * FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+/// Spreads `count` voices evenly across the front, from left to right.
+pub fn spread(count: usize) -> Vec<f32> {
+    const WIDTH: f32 = 120.0;
+    match count {
+        0 => Vec::new(),
+        1 => vec![0.0],
+        _ => (0..count)
+            .map(|index| -WIDTH / 2.0 + WIDTH * index as f32 / (count - 1) as f32)
+            .collect(),
+    }
+}
+
+/// Brown and Duda's one-pole, one-zero head-shadow filter for one ear.
+///
+/// Low frequencies pass unchanged; above about 1 kHz the gain goes to `alpha`, which is 2
+/// for a source right next to the ear and 0.1 for one on the far side of the head.
+#[derive(Debug, Clone, Copy, Default)]
+struct HeadShadow {
+    b0: f32,
+    b1: f32,
+    a1: f32,
+    x1: f32,
+    y1: f32,
+}
+
+impl HeadShadow {
+    /// Tunes the filter for a source `angle` radians away from the ear.
+    fn set_angle(&mut self, angle: f32, sample_rate: u32) {
+        let alpha = (1.0 + SHADOW_MIN_GAIN / 2.0)
+            + (1.0 - SHADOW_MIN_GAIN / 2.0) * (angle / SHADOW_MIN_ANGLE * PI).cos();
+        // Bilinear transform of (1 + alpha s / 2w0) / (1 + s / 2w0), with w0 = c / a.
+        let k = sample_rate as f32 * HEAD_RADIUS / SPEED_OF_SOUND;
+        self.b0 = (1.0 + alpha * k) / (1.0 + k);
+        self.b1 = (1.0 - alpha * k) / (1.0 + k);
+        self.a1 = (1.0 - k) / (1.0 + k);
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 - self.a1 * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Renders a mono voice at an azimuth into a left and right channel.
+///
+/// Holds no heap memory, so it can be created anywhere and run in an audio callback.
+#[derive(Debug, Clone)]
+pub struct Spatializer {
+    renderer: Renderer,
+    sample_rate: u32,
+    azimuth: f32,
+    /// The azimuth currently rendered, gliding towards `azimuth`.
+    current: f32,
+    glide: f32,
+    gains: (f32, f32),
+    /// Delay of each ear in samples.
+    delays: (f32, f32),
+    ears: [HeadShadow; 2],
+    history: [f32; DELAY_LEN],
+    position: usize,
+}
+
+impl Spatializer {
+    /// A voice straight ahead.
+    pub fn new(renderer: Renderer, sample_rate: u32) -> Self {
+        let mut spatializer = Self {
+            renderer,
+            sample_rate,
+            azimuth: 0.0,
+            current: 0.0,
+            glide: 1.0 - (-1.0 / (GLIDE.as_secs_f32() * sample_rate as f32)).exp(),
+            gains: (1.0, 1.0),
+            delays: (0.0, 0.0),
+            ears: [HeadShadow::default(); 2],
+            history: [0.0; DELAY_LEN],
+            position: 0,
+        };
+        spatializer.update();
+        spatializer
+    }
+
+    /// Starts the voice at `azimuth` degrees rather than gliding there.
+    pub fn with_azimuth(mut self, azimuth: f32) -> Self {
+        self.set_azimuth(azimuth);
+        self.current = self.azimuth;
+        self.update();
+        self
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    pub fn azimuth(&self) -> f32 {
+        self.azimuth
+    }
+
+    /// Moves the voice to `azimuth` degrees. The move is smoothed over a few milliseconds.
+    pub fn set_azimuth(&mut self, azimuth: f32) {
+        self.azimuth = azimuth.clamp(-90.0, 90.0);
+    }
+
+    /// Renders one mono sample into a left and right sample.
+    pub fn render(&mut self, sample: f32) -> (f32, f32) {
+        if (self.azimuth - self.current).abs() > 0.01 {
+            self.current += (self.azimuth - self.current) * self.glide;
+            self.update();
+        }
+        match self.renderer {
+            Renderer::EqualPower => (sample * self.gains.0, sample * self.gains.1),
+            Renderer::Binaural => {
+                self.position = (self.position + 1) % DELAY_LEN;
+                self.history[self.position] = sample;
+                let left = self.delayed(self.delays.0);
+                let right = self.delayed(self.delays.1);
+                (self.ears[0].process(left), self.ears[1].process(right))
+            }
+        }
+    }
+
+    /// Renders `mono` into interleaved stereo `out`, which must be twice as long.
+    pub fn render_into(&mut self, mono: &[f32], out: &mut [f32]) {
+        for (sample, frame) in mono.iter().zip(out.chunks_exact_mut(2)) {
+            let (left, right) = self.render(*sample);
+            frame[0] = left;
+            frame[1] = right;
+        }
+    }
+
+    /// Recomputes gains, delays and filters for the current azimuth.
+    fn update(&mut self) {
+        self.gains = equal_power(self.current);
+        let theta = self.current.to_radians();
+        let itd = HEAD_RADIUS / SPEED_OF_SOUND * (theta.abs() + theta.abs().sin());
+        let far = itd * self.sample_rate as f32;
+        self.delays = if theta < 0.0 { (0.0, far) } else { (far, 0.0) };
+        self.ears[0].set_angle((theta + FRAC_PI_2).abs(), self.sample_rate);
+        self.ears[1].set_angle((theta - FRAC_PI_2).abs(), self.sample_rate);
+    }
+
+    /// The sample `delay` samples ago, interpolating between neighbours.
+    fn delayed(&self, delay: f32) -> f32 {
+        let delay = delay.clamp(0.0, (DELAY_LEN - 2) as f32);
+        let whole = delay as usize;
+        let fraction = delay - whole as f32;
+        let at = |back: usize| self.history[(self.position + DELAY_LEN - back) % DELAY_LEN];
+        at(whole) * (1.0 - fraction) + at(whole + 1) * fraction
+    }
+}
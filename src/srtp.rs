@@ -0,0 +1,485 @@
+//! Authenticated encryption of RTP media, modelled on SRTP (RFC 3711).
+//!
+//! Both ends of a call exchange X25519 public keys in their invite and accept messages and
+//! derive one ChaCha20-Poly1305 key per direction from the shared secret with HKDF-SHA256.
+//! The RTP header stays readable and is authenticated as associated data; the payload is
+//! encrypted and followed by a 16-byte tag. The nonce combines the SSRC with the 48-bit
+//! packet index (rollover counter and sequence number), so no nonce repeats under a key,
+//! and the receiver keeps a sliding window of indices to drop replayed packets.
+//!
+//! RTCP is protected the same way under keys of its own, as SRTCP does: the first eight
+//! bytes stay readable, and an explicit 31-bit index follows the encrypted part, with the
+//! top bit set to say it is encrypted.
+//!
+//! Once a call is set up, its byes and keepalives are signed with keys of their own too.
+//! They stay readable, followed by a 32-bit index and a tag over both, so that nobody who
+//! can see the call id can hang it up or keep it alive.
+
+use std::fmt;
+
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::rtp;
+
+/// Bytes added to every packet by [`SrtpSender::protect`].
+pub const TAG_LEN: usize = 16;
+
+/// Bytes [`SrtcpSender::protect`] adds: the SRTCP index and the tag.
+pub const RTCP_TRAILER_LEN: usize = 4 + TAG_LEN;
+
+/// Bytes [`SignalingSender::sign`] adds: the index and the tag.
+pub const SIGNALING_TRAILER_LEN: usize = 4 + TAG_LEN;
+
+/// Number of packet indices behind the newest one that may still arrive.
+const REPLAY_WINDOW: u64 = 64;
+/// RTCP header bytes left readable: flags, packet type, length and sender SSRC.
+const RTCP_HEADER_LEN: usize = 8;
+/// Set in the SRTCP index word of encrypted packets.
+const ENCRYPTED: u32 = 0x8000_0000;
+
+const KEY_INFO: &[u8] = b"test-gpui media keys";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptoError {
+    /// The packet is too short or is not RTP.
+    Malformed,
+    /// The packet was not produced with the expected key or was modified in transit.
+    Authentication,
+    /// A packet with this index was already received.
+    Replay,
+    /// The packet is older than the replay window.
+    TooOld,
+    /// The peer's public key yields a predictable shared secret.
+    WeakKey,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::Malformed => write!(f, "malformed packet"),
+            CryptoError::Authentication => write!(f, "packet failed authentication"),
+            CryptoError::Replay => write!(f, "replayed packet"),
+            CryptoError::TooOld => write!(f, "packet is outside the replay window"),
+            CryptoError::WeakKey => write!(f, "peer sent a low-order public key"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+/// Which side of the call we are, so both ends agree on which key protects which direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Caller,
+    Callee,
+}
+
+/// Our half of an X25519 exchange, used once for a single call.
+pub struct KeyExchange {
+    secret: EphemeralSecret,
+    public: PublicKey,
+}
+
+impl Default for KeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyExchange {
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::random();
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    /// The key to send to the peer.
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    /// Combines our secret with the peer's public key into the RTP keys for both directions.
+    pub fn finish(
+        self,
+        remote: [u8; 32],
+        role: Role,
+        call_id: u32,
+    ) -> Result<(SrtpSender, SrtpReceiver), CryptoError> {
+        let keys = self.derive(remote, role, call_id)?;
+        Ok((keys.send, keys.receive))
+    }
+
+    /// Combines our secret with the peer's public key into every key of the call.
+    pub fn derive(
+        self,
+        remote: [u8; 32],
+        role: Role,
+        call_id: u32,
+    ) -> Result<CallKeys, CryptoError> {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(remote));
+        if !shared.was_contributory() {
+            return Err(CryptoError::WeakKey);
+        }
+        let (caller, callee) = match role {
+            Role::Caller => (self.public.to_bytes(), remote),
+            Role::Callee => (remote, self.public.to_bytes()),
+        };
+        let mut info = KEY_INFO.to_vec();
+        info.extend_from_slice(&caller);
+        info.extend_from_slice(&callee);
+
+        // RTP keys from the caller and the callee, then RTCP and signaling keys in the same
+        // order.
+        let mut okm = [0u8; 192];
+        Hkdf::<Sha256>::new(Some(&call_id.to_be_bytes()), shared.as_bytes())
+            .expand(&info, &mut okm)
+            .expect("192 bytes is a valid HKDF-SHA256 output length");
+        let key = |n: usize| -> [u8; 32] { okm[n * 32..(n + 1) * 32].try_into().unwrap() };
+        let (send, receive) = match role {
+            Role::Caller => (0, 1),
+            Role::Callee => (1, 0),
+        };
+        Ok(CallKeys {
+            send: SrtpSender::new(key(send)),
+            receive: SrtpReceiver::new(key(receive)),
+            send_rtcp: SrtcpSender::new(key(send + 2)),
+            receive_rtcp: SrtcpReceiver::new(key(receive + 2)),
+            send_signaling: SignalingSender::new(key(send + 4)),
+            receive_signaling: SignalingReceiver::new(key(receive + 4)),
+        })
+    }
+}
+
+/// The keys protecting a call, from [`KeyExchange::derive`].
+pub struct CallKeys {
+    pub send: SrtpSender,
+    pub receive: SrtpReceiver,
+    pub send_rtcp: SrtcpSender,
+    pub receive_rtcp: SrtcpReceiver,
+    pub send_signaling: SignalingSender,
+    pub receive_signaling: SignalingReceiver,
+}
+
+/// Encrypts outgoing RTP packets.
+pub struct SrtpSender {
+    cipher: ChaCha20Poly1305,
+    highest: Option<u64>,
+}
+
+impl SrtpSender {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            highest: None,
+        }
+    }
+
+    /// Returns `packet` with its payload encrypted and an authentication tag appended.
+    ///
+    /// Sequence numbers must keep increasing: protecting an index twice would reuse a nonce,
+    /// so older packets are refused with [`CryptoError::Replay`].
+    pub fn protect(&mut self, packet: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let (header_len, ssrc, sequence) = split(packet)?;
+        let index = estimate_index(self.highest, sequence).ok_or(CryptoError::Replay)?;
+        if self.highest.is_some_and(|highest| index <= highest) {
+            return Err(CryptoError::Replay);
+        }
+        self.highest = Some(index);
+
+        let mut protected = packet.to_vec();
+        let (header, payload) = protected.split_at_mut(header_len);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce(ssrc, index), header, payload)
+            .map_err(|_| CryptoError::Malformed)?;
+        protected.extend_from_slice(&tag);
+        Ok(protected)
+    }
+}
+
+/// Authenticates and decrypts incoming RTP packets, rejecting replays.
+pub struct SrtpReceiver {
+    cipher: ChaCha20Poly1305,
+    replay: ReplayWindow,
+}
+
+impl SrtpReceiver {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            replay: ReplayWindow::default(),
+        }
+    }
+
+    /// Returns the original RTP packet if `packet` is authentic and has not been seen before.
+    ///
+    /// The replay window only advances for packets that authenticate, so forged packets
+    /// cannot push genuine ones out of it.
+    pub fn unprotect(&mut self, packet: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let (header_len, ssrc, sequence) = split(packet)?;
+        if packet.len() < header_len + TAG_LEN {
+            return Err(CryptoError::Malformed);
+        }
+        let index = estimate_index(self.replay.highest, sequence).ok_or(CryptoError::TooOld)?;
+        self.replay.check(index)?;
+
+        let (body, tag) = packet.split_at(packet.len() - TAG_LEN);
+        let mut plain = body.to_vec();
+        let (header, payload) = plain.split_at_mut(header_len);
+        self.cipher
+            .decrypt_in_place_detached(&nonce(ssrc, index), header, payload, Tag::from_slice(tag))
+            .map_err(|_| CryptoError::Authentication)?;
+
+        self.replay.record(index);
+        Ok(plain)
+    }
+}
+
+/// Encrypts outgoing RTCP packets, numbering them with an index of their own.
+pub struct SrtcpSender {
+    cipher: ChaCha20Poly1305,
+    index: u32,
+}
+
+impl SrtcpSender {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            index: 0,
+        }
+    }
+
+    /// Returns `packet` with all but its first header encrypted, followed by the SRTCP
+    /// index and an authentication tag.
+    ///
+    /// Fails with [`CryptoError::Replay`] once the 31-bit index is used up, rather than reuse
+    /// a nonce.
+    pub fn protect(&mut self, packet: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let ssrc = split_rtcp(packet)?;
+        if self.index & ENCRYPTED != 0 {
+            return Err(CryptoError::Replay);
+        }
+        let word = (self.index | ENCRYPTED).to_be_bytes();
+        let nonce = nonce(ssrc, self.index as u64);
+        self.index += 1;
+
+        let mut protected = packet.to_vec();
+        let (header, payload) = protected.split_at_mut(RTCP_HEADER_LEN);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce, &[header, &word[..]].concat(), payload)
+            .map_err(|_| CryptoError::Malformed)?;
+        protected.extend_from_slice(&word);
+        protected.extend_from_slice(&tag);
+        Ok(protected)
+    }
+}
+
+/// Authenticates and decrypts incoming RTCP packets, rejecting replays.
+pub struct SrtcpReceiver {
+    cipher: ChaCha20Poly1305,
+    replay: ReplayWindow,
+}
+
+impl SrtcpReceiver {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            replay: ReplayWindow::default(),
+        }
+    }
+
+    /// Returns the original RTCP packet if `packet` is authentic and has not been seen
+    /// before. Packets sent without encryption do not authenticate.
+    pub fn unprotect(&mut self, packet: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let ssrc = split_rtcp(packet)?;
+        if packet.len() < RTCP_HEADER_LEN + RTCP_TRAILER_LEN {
+            return Err(CryptoError::Malformed);
+        }
+        let (body, trailer) = packet.split_at(packet.len() - RTCP_TRAILER_LEN);
+        let (word, tag) = trailer.split_at(4);
+        let word = u32::from_be_bytes(word.try_into().unwrap());
+        if word & ENCRYPTED == 0 {
+            return Err(CryptoError::Authentication);
+        }
+        let index = (word & !ENCRYPTED) as u64;
+        self.replay.check(index)?;
+
+        let mut plain = body.to_vec();
+        let (header, payload) = plain.split_at_mut(RTCP_HEADER_LEN);
+        let aad = [header, &word.to_be_bytes()[..]].concat();
+        self.cipher
+            .decrypt_in_place_detached(&nonce(ssrc, index), &aad, payload, Tag::from_slice(tag))
+            .map_err(|_| CryptoError::Authentication)?;
+
+        self.replay.record(index);
+        Ok(plain)
+    }
+}
+
+/// Signs outgoing signaling messages.
+pub struct SignalingSender {
+    cipher: ChaCha20Poly1305,
+    index: u32,
+}
+
+impl SignalingSender {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            index: 0,
+        }
+    }
+
+    /// Returns `message` followed by its index and a tag authenticating both.
+    ///
+    /// Fails with [`CryptoError::Replay`] once the index is used up, rather than reuse a
+    /// nonce.
+    pub fn sign(&mut self, message: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let word = self.index.to_be_bytes();
+        let nonce = nonce(0, self.index as u64);
+        self.index = self.index.checked_add(1).ok_or(CryptoError::Replay)?;
+
+        let mut signed = [message, &word[..]].concat();
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce, &signed, &mut [])
+            .map_err(|_| CryptoError::Malformed)?;
+        signed.extend_from_slice(&tag);
+        Ok(signed)
+    }
+}
+
+/// Checks incoming signaling messages, rejecting replays.
+pub struct SignalingReceiver {
+    cipher: ChaCha20Poly1305,
+    replay: ReplayWindow,
+}
+
+impl SignalingReceiver {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            replay: ReplayWindow::default(),
+        }
+    }
+
+    /// Returns the message `packet` carries if it is authentic and has not been seen
+    /// before.
+    pub fn verify<'a>(&mut self, packet: &'a [u8]) -> Result<&'a [u8], CryptoError> {
+        if packet.len() < SIGNALING_TRAILER_LEN {
+            return Err(CryptoError::Malformed);
+        }
+        let (signed, tag) = packet.split_at(packet.len() - TAG_LEN);
+        let (message, word) = signed.split_at(signed.len() - 4);
+        let index = u32::from_be_bytes(word.try_into().unwrap()) as u64;
+        self.replay.check(index)?;
+        self.cipher
+            .decrypt_in_place_detached(&nonce(0, index), signed, &mut [], Tag::from_slice(tag))
+            .map_err(|_| CryptoError::Authentication)?;
+        self.replay.record(index);
+        Ok(message)
+    }
+}
+
+/// The packet indices received lately, to tell replays from packets arriving late.
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    /// Bit `n` is set when index `highest - n` has been received.
+    window: u64,
+}
+
+impl ReplayWindow {
+    fn check(&self, index: u64) -> Result<(), CryptoError> {
+        let Some(highest) = self.highest else {
+            return Ok(());
+        };
+        if index > highest {
+            return Ok(());
+        }
+        let age = highest - index;
+        if age >= REPLAY_WINDOW {
+            Err(CryptoError::TooOld)
+        } else if self.window & (1 << age) != 0 {
+            Err(CryptoError::Replay)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn record(&mut self, index: u64) {
+        match self.highest {
+            Some(highest) if index <= highest => self.window |= 1 << (highest - index),
+            Some(highest) => {
+                let shift = index - highest;
+                self.window = if shift >= REPLAY_WINDOW {
+                    1
+                } else {
+                    (self.window << shift) | 1
+                };
+                self.highest = Some(index);
+            }
+            None => {
+                self.window = 1;
+                self.highest = Some(index);
+            }
+        }
+    }
+}
+
+/// Returns the sender SSRC of an RTCP packet.
+fn split_rtcp(packet: &[u8]) -> Result<u32, CryptoError> {
+    if packet.len() < RTCP_HEADER_LEN || packet[0] >> 6 != 2 {
+        return Err(CryptoError::Malformed);
+    }
+    Ok(u32::from_be_bytes([
+        packet[4], packet[5], packet[6], packet[7],
+    ]))
+}
+
+/// Returns the header length, SSRC and sequence number of an RTP packet.
+fn split(packet: &[u8]) -> Result<(usize, u32, u16), CryptoError> {
+    if packet.len() < rtp::HEADER_LEN || packet[0] >> 6 != 2 {
+        return Err(CryptoError::Malformed);
+    }
+    let header_len = rtp::header_len(packet).ok_or(CryptoError::Malformed)?;
+    let ssrc = u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]);
+    let sequence = u16::from_be_bytes([packet[2], packet[3]]);
+    Ok((header_len, ssrc, sequence))
+}
+
+/// Extends a 16-bit sequence number to a packet index (RFC 3711 section 3.3.1), guessing the
+/// rollover counter from the highest index seen so far.
+///
+/// Returns `None` for a packet from before the first rollover of a stream already past it.
+fn estimate_index(highest: Option<u64>, sequence: u16) -> Option<u64> {
+    let Some(highest) = highest else {
+        return Some(sequence as u64);
+    };
+    let roc = highest >> 16;
+    let last = highest as u16;
+    let roc = if last < 0x8000 {
+        if sequence > last + 0x8000 {
+            roc.checked_sub(1)?
+        } else {
+            roc
+        }
+    } else if sequence < last - 0x8000 {
+        roc + 1
+    } else {
+        roc
+    };
+    Some((roc << 16) | sequence as u64)
+}
+
+fn nonce(ssrc: u32, index: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..4].copy_from_slice(&ssrc.to_be_bytes());
+    nonce[4..].copy_from_slice(&index.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
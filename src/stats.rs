@@ -0,0 +1,228 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::rtcp::{self, ReportBlock};
+
+/// Largest forward jump in sequence numbers still treated as loss rather than a restart.
+const MAX_DROPOUT: u16 = 3000;
+
+/// Largest backward jump still treated as a late or duplicate packet.
+const MAX_MISORDER: u16 = 100;
+
+/// Quality of a call in both directions, updated from RTP traffic and RTCP reports.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CallStats {
+    pub packets_sent: u64,
+    pub octets_sent: u64,
+    pub packets_received: u64,
+    /// Packets from the peer that never arrived; negative when duplicates arrived.
+    pub packets_lost: i64,
+    /// Share of the peer's packets lost during the last report interval.
+    pub fraction_lost: f32,
+    /// Interarrival jitter of the peer's packets.
+    pub jitter: Duration,
+    /// Loss of our packets as reported by the peer.
+    pub remote_packets_lost: i64,
+    pub remote_fraction_lost: f32,
+    pub remote_jitter: Duration,
+    /// Known once the peer has answered one of our sender reports.
+    pub round_trip_time: Option<Duration>,
+    /// Reports about our stream received from the peer so far.
+    pub reports_received: u64,
+}
+
+impl CallStats {
+    pub(crate) fn update_reception(&mut self, reception: &ReceptionStats) {
+        self.packets_received = reception.received;
+        self.packets_lost = reception.lost();
+        self.fraction_lost = reception.fraction_lost as f32 / 256.0;
+        self.jitter = reception.jitter();
+    }
+}
+
+impl fmt::Display for CallStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent {} packets, received {}, lost {} ({:.1}%), jitter {:.1} ms; \
+             peer lost {} ({:.1}%), jitter {:.1} ms",
+            self.packets_sent,
+            self.packets_received,
+            self.packets_lost,
+            self.fraction_lost * 100.0,
+            self.jitter.as_secs_f64() * 1000.0,
+            self.remote_packets_lost,
+            self.remote_fraction_lost * 100.0,
+            self.remote_jitter.as_secs_f64() * 1000.0,
+        )?;
+        if let Some(rtt) = self.round_trip_time {
+            write!(f, "; rtt {:.1} ms", rtt.as_secs_f64() * 1000.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reception statistics for one incoming RTP stream (RFC 3550 appendices A.1, A.3 and A.8).
+pub struct ReceptionStats {
+    clock_rate: u32,
+    /// Arrival times are measured from the first packet.
+    epoch: Option<Instant>,
+    base_sequence: u32,
+    max_sequence: u16,
+    /// Sequence number wraps, shifted left by 16.
+    cycles: u32,
+    /// After a large jump, the sequence number that confirms the stream restarted.
+    bad_sequence: Option<u16>,
+    received: u64,
+    expected_prior: u64,
+    received_prior: u64,
+    fraction_lost: u8,
+    last_transit: Option<u32>,
+    /// Jitter estimate in RTP timestamp units, scaled by 16.
+    jitter: u32,
+    /// Compact NTP time of the last sender report and when it arrived.
+    last_sr: Option<(u32, Instant)>,
+}
+
+impl ReceptionStats {
+    pub fn new(clock_rate: u32) -> Self {
+        Self {
+            clock_rate,
+            epoch: None,
+            base_sequence: 0,
+            max_sequence: 0,
+            cycles: 0,
+            bad_sequence: None,
+            received: 0,
+            expected_prior: 0,
+            received_prior: 0,
+            fraction_lost: 0,
+            last_transit: None,
+            jitter: 0,
+            last_sr: None,
+        }
+    }
+
+    /// Records a packet arriving at `now`.
+    pub fn record(&mut self, sequence: u16, rtp_timestamp: u32, now: Instant) {
+        let epoch = match self.epoch {
+            Some(epoch) => {
+                if !self.update_sequence(sequence) {
+                    return;
+                }
+                epoch
+            }
+            None => {
+                self.epoch = Some(now);
+                self.restart(sequence);
+                now
+            }
+        };
+        self.received += 1;
+
+        // Interarrival jitter, computed in RTP timestamp units.
+        let arrival = now.saturating_duration_since(epoch).as_secs_f64() * self.clock_rate as f64;
+        let transit = (arrival as u64 as u32).wrapping_sub(rtp_timestamp);
+        if let Some(last) = self.last_transit {
+            let d = (transit.wrapping_sub(last) as i32).unsigned_abs();
+            self.jitter = (self.jitter as i64 + d as i64 - ((self.jitter as i64 + 8) >> 4)) as u32;
+        }
+        self.last_transit = Some(transit);
+    }
+
+    /// Tracks the highest sequence number and its wraps. Returns false for a packet that
+    /// jumped too far ahead to be counted until the stream confirms the jump.
+    fn update_sequence(&mut self, sequence: u16) -> bool {
+        let delta = sequence.wrapping_sub(self.max_sequence);
+        if delta < MAX_DROPOUT {
+            // In order, possibly with a gap.
+            if sequence < self.max_sequence {
+                self.cycles = self.cycles.wrapping_add(1 << 16);
+            }
+            self.max_sequence = sequence;
+        } else if delta <= u16::MAX - MAX_MISORDER {
+            if self.bad_sequence != Some(sequence) {
+                self.bad_sequence = Some(sequence.wrapping_add(1));
+                return false;
+            }
+            // Two packets in a row after the jump: the sender restarted.
+            self.restart(sequence);
+        }
+        // Anything else is a duplicate or late packet, which still counts as received.
+        self.bad_sequence = None;
+        true
+    }
+
+    fn restart(&mut self, sequence: u16) {
+        self.base_sequence = sequence as u32;
+        self.max_sequence = sequence;
+        self.cycles = 0;
+        self.received = 0;
+        self.expected_prior = 0;
+        self.received_prior = 0;
+        self.last_transit = None;
+        self.bad_sequence = None;
+    }
+
+    /// Remembers a sender report from this source so the next report block can echo it.
+    pub fn sender_report(&mut self, ntp_timestamp: u64, now: Instant) {
+        self.last_sr = Some((rtcp::compact_ntp(ntp_timestamp), now));
+    }
+
+    /// Highest sequence number received, extended with the number of wraps.
+    pub fn extended_max(&self) -> u32 {
+        self.cycles.wrapping_add(self.max_sequence as u32)
+    }
+
+    pub fn expected(&self) -> u64 {
+        if self.epoch.is_none() {
+            return 0;
+        }
+        (self.extended_max() as u64 + 1).saturating_sub(self.base_sequence as u64)
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Cumulative number of packets lost.
+    pub fn lost(&self) -> i64 {
+        self.expected() as i64 - self.received as i64
+    }
+
+    pub fn jitter(&self) -> Duration {
+        Duration::from_secs_f64((self.jitter >> 4) as f64 / self.clock_rate as f64)
+    }
+
+    /// Builds a report block for this source and starts a new reporting interval.
+    pub fn report_block(&mut self, ssrc: u32, now: Instant) -> ReportBlock {
+        let expected = self.expected();
+        let expected_interval = expected - self.expected_prior;
+        let received_interval = self.received - self.received_prior;
+        self.expected_prior = expected;
+        self.received_prior = self.received;
+        let lost_interval = expected_interval as i64 - received_interval as i64;
+        self.fraction_lost = if expected_interval == 0 || lost_interval <= 0 {
+            0
+        } else {
+            ((lost_interval << 8) / expected_interval as i64) as u8
+        };
+
+        let (last_sr, delay_since_last_sr) = match self.last_sr {
+            Some((ntp, arrived)) => (
+                ntp,
+                rtcp::to_compact(now.saturating_duration_since(arrived)),
+            ),
+            None => (0, 0),
+        };
+        ReportBlock {
+            ssrc,
+            fraction_lost: self.fraction_lost,
+            cumulative_lost: self.lost().clamp(-0x80_0000, 0x7f_ffff) as i32,
+            highest_sequence: self.extended_max(),
+            jitter: self.jitter >> 4,
+            last_sr,
+            delay_since_last_sr,
+        }
+    }
+}
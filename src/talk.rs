@@ -0,0 +1,242 @@
+//! Controls whether the microphone is sent and the call is heard.
+//!
+//! A [`TalkControl`] is shared between whatever the user operates, such as key bindings,
+//! and the audio threads, which apply it with a [`Gate`]. Gates fade over a few
+//! milliseconds instead of cutting, so muting and push-to-talk never click.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use crate::audio::{AudioSink, AudioSource};
+
+/// How long a gate takes to open or close.
+const FADE: Duration = Duration::from_millis(10);
+
+/// Bits of a [`TalkState`] as [`TalkControl`] stores it.
+const PUSH_TO_TALK: u8 = 1;
+const MUTED: u8 = 2;
+const DEAFENED: u8 = 4;
+const PRESSED: u8 = 8;
+
+/// When the microphone is live.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TalkMode {
+    /// Live unless muted.
+    #[default]
+    Open,
+    /// Live only while the push-to-talk key is held.
+    PushToTalk,
+}
+
+/// Everything the user has set about talking and listening.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TalkState {
+    pub mode: TalkMode,
+    pub muted: bool,
+    /// Hearing nothing from the call. Also keeps the microphone off, without forgetting
+    /// whether it was muted before.
+    pub deafened: bool,
+    /// Whether the push-to-talk key is held.
+    pub pressed: bool,
+}
+
+impl TalkState {
+    /// Whether the microphone is being sent.
+    pub fn transmitting(&self) -> bool {
+        !self.muted
+            && !self.deafened
+            && match self.mode {
+                TalkMode::Open => true,
+                TalkMode::PushToTalk => self.pressed,
+            }
+    }
+
+    /// Whether the call is being played.
+    pub fn hearing(&self) -> bool {
+        !self.deafened
+    }
+
+    fn bits(&self) -> u8 {
+        let flag = |on: bool, bit: u8| if on { bit } else { 0 };
+        flag(self.mode == TalkMode::PushToTalk, PUSH_TO_TALK)
+            | flag(self.muted, MUTED)
+            | flag(self.deafened, DEAFENED)
+            | flag(self.pressed, PRESSED)
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        Self {
+            mode: if bits & PUSH_TO_TALK != 0 {
+                TalkMode::PushToTalk
+            } else {
+                TalkMode::Open
+            },
+            muted: bits & MUTED != 0,
+            deafened: bits & DEAFENED != 0,
+            pressed: bits & PRESSED != 0,
+        }
+    }
+}
+
+/// The talk state shared between the user interface and the audio threads.
+///
+/// Cloning gives another handle to the same state. It is kept as bits of one atomic, so the
+/// audio threads read it on every buffer without taking a lock.
+#[derive(Debug, Clone, Default)]
+pub struct TalkControl {
+    state: Arc<AtomicU8>,
+}
+
+impl TalkControl {
+    pub fn new(state: TalkState) -> Self {
+        Self {
+            state: Arc::new(AtomicU8::new(state.bits())),
+        }
+    }
+
+    pub fn state(&self) -> TalkState {
+        TalkState::from_bits(self.state.load(Ordering::Relaxed))
+    }
+
+    pub fn set_mode(&self, mode: TalkMode) {
+        self.set(PUSH_TO_TALK, mode == TalkMode::PushToTalk);
+    }
+
+    pub fn toggle_mode(&self) {
+        self.state.fetch_xor(PUSH_TO_TALK, Ordering::Relaxed);
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.set(MUTED, muted);
+    }
+
+    pub fn toggle_mute(&self) {
+        self.state.fetch_xor(MUTED, Ordering::Relaxed);
+    }
+
+    pub fn set_deafened(&self, deafened: bool) {
+        self.set(DEAFENED, deafened);
+    }
+
+    pub fn toggle_deafen(&self) {
+        self.state.fetch_xor(DEAFENED, Ordering::Relaxed);
+    }
+
+    /// Reports the push-to-talk key going down or up.
+    pub fn set_pressed(&self, pressed: bool) {
+        self.set(PRESSED, pressed);
+    }
+
+    fn set(&self, bit: u8, on: bool) {
+        if on {
+            self.state.fetch_or(bit, Ordering::Relaxed);
+        } else {
+            self.state.fetch_and(!bit, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Fades audio in and out as it is switched on and off.
+#[derive(Debug, Clone)]
+pub struct Gate {
+    gain: f32,
+    /// Gain change per frame while fading.
+    step: f32,
+}
+
+impl Gate {
+    /// A closed gate, so audio fades in when it first opens.
+    pub fn new(sample_rate: u32) -> Self {
+        let frames = (FADE.as_secs_f32() * sample_rate as f32).max(1.0);
+        Self {
+            gain: 0.0,
+            step: 1.0 / frames,
+        }
+    }
+
+    /// Whether the gate is fully closed, letting nothing through.
+    pub fn is_closed(&self) -> bool {
+        self.gain == 0.0
+    }
+
+    /// Applies the gate to interleaved `samples` in place, fading towards `open`.
+    pub fn process(&mut self, samples: &mut [f32], channels: usize, open: bool) {
+        let target = if open { 1.0 } else { 0.0 };
+        if self.gain == target {
+            if !open {
+                samples.fill(0.0);
+            }
+            return;
+        }
+        for frame in samples.chunks_mut(channels.max(1)) {
+            self.gain = if open {
+                (self.gain + self.step).min(1.0)
+            } else {
+                (self.gain - self.step).max(0.0)
+            };
+            for sample in frame {
+                *sample *= self.gain;
+            }
+        }
+    }
+}
+
+/// Passes on a source's audio only while the control says to transmit.
+pub struct GatedSource<S> {
+    source: S,
+    control: TalkControl,
+    gate: Gate,
+    channels: usize,
+}
+
+impl<S: AudioSource> GatedSource<S> {
+    pub fn new(source: S, control: TalkControl, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            source,
+            control,
+            gate: Gate::new(sample_rate),
+            channels,
+        }
+    }
+}
+
+impl<S: AudioSource> AudioSource for GatedSource<S> {
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        let count = self.source.read(buf);
+        let open = self.control.state().transmitting();
+        self.gate.process(&mut buf[..count], self.channels, open);
+        count
+    }
+}
+
+/// Passes audio on to a sink only while the control is not deafened.
+pub struct GatedSink<S> {
+    sink: S,
+    control: TalkControl,
+    gate: Gate,
+    channels: usize,
+    scratch: Vec<f32>,
+}
+
+impl<S: AudioSink> GatedSink<S> {
+    pub fn new(sink: S, control: TalkControl, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            sink,
+            control,
+            gate: Gate::new(sample_rate),
+            channels,
+            scratch: Vec::new(),
+        }
+    }
+}
+
+impl<S: AudioSink> AudioSink for GatedSink<S> {
+    fn write(&mut self, samples: &[f32]) {
+        self.scratch.clear();
+        self.scratch.extend_from_slice(samples);
+        let open = self.control.state().hearing();
+        self.gate.process(&mut self.scratch, self.channels, open);
+        self.sink.write(&self.scratch);
+    }
+}
@@ -7,7 +7,8 @@ pub trait IntoChannels {
 }
 
 impl IntoChannels for u16 {
-    /// Converts u16 into a Channels type.
+    /// Converts u16 into a Channels type. One Opus stream holds at most two channels, so
+    /// anything more needs a [`MultistreamEncoder`](crate::multistream::MultistreamEncoder).
     fn into_channels(&self) -> Channels {
         match self {
             1 => Channels::Mono,
@@ -50,3 +51,30 @@ pub fn convert_to_mono(data: &[f32]) -> Vec<f32> {
         .map(|stereo| (stereo[0] + stereo[1]) * 0.5) // Average L+R
         .collect()
 }
+
+/// Converts interleaved audio between channel counts. Mono is duplicated into every output
+/// channel, any layout is averaged down to mono, and other layouts keep the channels they
+/// share.
+pub fn convert_channels(data: &[f32], from: usize, to: usize) -> Vec<f32> {
+    let mut result = Vec::with_capacity(data.len() / from * to);
+    extend_channels(&mut result, data, from, to);
+    result
+}
+
+/// Like [`convert_channels`], but appends to `out`, so audio callbacks can reuse a buffer
+/// rather than allocate.
+pub fn extend_channels(out: &mut Vec<f32>, data: &[f32], from: usize, to: usize) {
+    if from == to {
+        out.extend_from_slice(data);
+        return;
+    }
+    for frame in data.chunks_exact(from) {
+        if to == 1 {
+            out.push(frame.iter().sum::<f32>() / from as f32);
+        } else if from == 1 {
+            out.extend(std::iter::repeat_n(frame[0], to));
+        } else {
+            out.extend((0..to).map(|channel| frame.get(channel).copied().unwrap_or(0.0)));
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const HEADER_LEN: u32 = 44;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// Reads a 16-bit PCM or 32-bit float WAV file into interleaved f32 samples.
+pub fn read(path: impl AsRef<Path>) -> io::Result<(WavSpec, Vec<f32>)> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    decode(&bytes)
+}
+
+/// Parses the contents of a WAV file.
+pub fn decode(bytes: &[u8]) -> io::Result<(WavSpec, Vec<f32>)> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a RIFF/WAVE file"));
+    }
+
+    let mut format = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let body = &bytes[pos + 8..(pos + 8 + len).min(bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let tag = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let sample_rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                format = Some((
+                    tag,
+                    bits,
+                    WavSpec {
+                        sample_rate,
+                        channels,
+                    },
+                ));
+            }
+            b"data" => {
+                let (tag, bits, spec) = format.ok_or_else(|| invalid("data before fmt chunk"))?;
+                let samples = match (tag, bits) {
+                    (FORMAT_PCM, 16) => body
+                        .chunks_exact(2)
+                        .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / i16::MAX as f32)
+                        .collect(),
+                    (FORMAT_FLOAT, 32) => body
+                        .chunks_exact(4)
+                        .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+                        .collect(),
+                    _ => return Err(invalid("unsupported sample format")),
+                };
+                return Ok((spec, samples));
+            }
+            _ => {}
+        }
+        pos += 8 + len + (len & 1);
+    }
+    Err(invalid("missing data chunk"))
+}
+
+/// Streams 16-bit PCM samples to a WAV file, patching the header sizes when finished.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    spec: WavSpec,
+    data_len: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: impl AsRef<Path>, spec: WavSpec) -> io::Result<Self> {
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            spec,
+            data_len: 0,
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    pub fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    /// Appends interleaved samples, clamping them to [-1.0, 1.0].
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    /// Writes the final chunk sizes and flushes the file.
+    pub fn finalize(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.flush()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let block_align = self.spec.channels * 2;
+        let header = [
+            &b"RIFF"[..],
+            &(HEADER_LEN - 8 + self.data_len).to_le_bytes(),
+            b"WAVEfmt ",
+            &16u32.to_le_bytes(),
+            &FORMAT_PCM.to_le_bytes(),
+            &self.spec.channels.to_le_bytes(),
+            &self.spec.sample_rate.to_le_bytes(),
+            &(self.spec.sample_rate * block_align as u32).to_le_bytes(),
+            &block_align.to_le_bytes(),
+            &16u16.to_le_bytes(),
+            b"data",
+            &self.data_len.to_le_bytes(),
+        ];
+        for part in header {
+            self.file.write_all(part)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let _ = self.finalize();
+    }
+}
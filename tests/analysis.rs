@@ -0,0 +1,111 @@
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::thread;
+    use std::time::Duration;
+
+    use test_gpui::analysis::{self, FLOOR_DB, Spectrum};
+    use test_gpui::mixer::Mixer;
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    fn tone(frequency: f32, amplitude: f32, samples: usize) -> Vec<f32> {
+        (0..samples)
+            .map(|n| amplitude * (2.0 * PI * frequency * n as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_scope_snapshots() {
+        let (tap, scope) = analysis::scope(8, SAMPLE_RATE);
+        let mut out = [9.0; 4];
+        assert_eq!(scope.latest(&mut out), 0);
+        assert_eq!(out, [0.0; 4]);
+
+        tap.push(&[1.0, 3.0, 5.0, 7.0], 2);
+        assert_eq!(scope.latest(&mut out), 2);
+        assert_eq!(out, [0.0, 0.0, 2.0, 6.0]);
+
+        // Past the capacity, only the newest samples are kept.
+        for n in 0..20 {
+            tap.write(n as f32);
+        }
+        let mut out = [0.0; 10];
+        assert_eq!(scope.latest(&mut out), 22);
+        assert_eq!(
+            out,
+            [0.0, 0.0, 12.0, 13.0, 14.0, 15.0, 16.0, 17.0, 18.0, 19.0]
+        );
+
+        // Readers on other threads see everything published before they look.
+        let reader = scope.clone();
+        let handle = thread::spawn(move || {
+            let mut out = [0.0; 4];
+            while reader.written() < 1_000 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            reader.latest(&mut out);
+            out
+        });
+        for n in 22..1_000 {
+            tap.write(n as f32);
+        }
+        assert_eq!(handle.join().unwrap(), [996.0, 997.0, 998.0, 999.0]);
+    }
+
+    #[test]
+    fn test_spectrum() {
+        let mut spectrum = Spectrum::new(1024);
+        // 3 kHz sits exactly on bin 64 of a 1024-point FFT at 48 kHz.
+        let levels = spectrum.analyze(&tone(3_000.0, 1.0, 2_048)).to_vec();
+        assert_eq!(levels.len(), 513);
+        let loudest = (0..levels.len())
+            .max_by(|&a, &b| levels[a].total_cmp(&levels[b]))
+            .unwrap();
+        assert_eq!(loudest, 64);
+        assert!(levels[64].abs() < 0.1, "{}", levels[64]);
+        assert!(levels[200] < -60.0, "{}", levels[200]);
+
+        let levels = spectrum.analyze(&tone(3_000.0, 0.1, 1_024)).to_vec();
+        assert!((levels[64] + 20.0).abs() < 0.1, "{}", levels[64]);
+
+        // Silence, including too few samples, reads as the floor.
+        assert!(spectrum.analyze(&[]).iter().all(|&level| level == FLOOR_DB));
+
+        let mut bands = [0.0; 16];
+        spectrum.analyze(&tone(1_000.0, 1.0, 1_024));
+        spectrum.bands(SAMPLE_RATE, 100.0, 10_000.0, &mut bands);
+        let loudest = (0..bands.len())
+            .max_by(|&a, &b| bands[a].total_cmp(&bands[b]))
+            .unwrap();
+        // 1 kHz is half way up a log scale from 100 Hz to 10 kHz.
+        assert_eq!(loudest, 8);
+    }
+
+    #[test]
+    fn test_peaks() {
+        let mut out = [(0.0, 0.0); 2];
+        analysis::peaks(&[0.1, -0.5, 0.3, 0.2, 0.9, -0.1], &mut out);
+        assert_eq!(out, [(-0.5, 0.3), (-0.1, 0.9)]);
+        let mut out = [(1.0, 1.0); 3];
+        analysis::peaks(&[], &mut out);
+        assert_eq!(out, [(0.0, 0.0); 3]);
+    }
+
+    #[test]
+    fn test_mixer_input_tap() {
+        let mut mixer =
+            Mixer::new(SAMPLE_RATE, 2).with_buffering(Duration::ZERO, Duration::from_secs(1));
+        let input = mixer.add_input(2);
+        let (tap, scope) = analysis::scope(4_800, SAMPLE_RATE);
+        mixer.input(input).unwrap().set_tap(Some(tap));
+        mixer.input(input).unwrap().set_gain(0.0);
+        mixer.input(input).unwrap().push(&[0.2, 0.4].repeat(480));
+
+        let mut out = vec![0.0; 960];
+        mixer.mix(&mut out);
+        let mut seen = [0.0; 480];
+        assert_eq!(scope.latest(&mut seen), 480);
+        assert!(seen.iter().all(|&sample| (sample - 0.3).abs() < 1e-6));
+    }
+}
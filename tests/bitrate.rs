@@ -0,0 +1,148 @@
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use test_gpui::bitrate::{BitrateController, EncoderSettings, Feedback, RateLimits};
+
+    const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+    const BASE_RTT: Duration = Duration::from_millis(40);
+
+    fn report(fraction_lost: f32) -> Feedback {
+        Feedback {
+            fraction_lost,
+            jitter: Duration::from_millis(2),
+            round_trip_time: Some(BASE_RTT),
+        }
+    }
+
+    /// Feeds the same report `count` times, one report interval apart.
+    fn run(
+        controller: &mut BitrateController,
+        start: &mut Instant,
+        feedback: Feedback,
+        count: usize,
+    ) -> EncoderSettings {
+        for _ in 0..count {
+            *start += REPORT_INTERVAL;
+            controller.update(&feedback, *start);
+        }
+        controller.settings()
+    }
+
+    #[test]
+    fn test_backs_off_under_loss_and_recovers() {
+        let mut now = Instant::now();
+        let mut controller = BitrateController::new(RateLimits::default());
+        let initial = controller.settings();
+        assert_eq!(initial.bitrate, 32_000);
+        assert_eq!(initial.frame_ms, 20);
+
+        // Heavy loss: each report cuts the bitrate, FEC comes on and frames get longer.
+        controller.update(&report(0.0), now);
+        let mut previous = controller.settings().bitrate;
+        for _ in 0..3 {
+            now += REPORT_INTERVAL;
+            let settings = controller.update(&report(0.3), now).unwrap();
+            assert!(settings.bitrate <= previous * 86 / 100, "{:?}", settings);
+            previous = settings.bitrate;
+        }
+        let lossy = run(&mut controller, &mut now, report(0.3), 10);
+        assert_eq!(lossy.bitrate, 6_000);
+        assert!(lossy.fec);
+        assert!(lossy.expected_loss >= 25, "{:?}", lossy);
+        assert_eq!(lossy.frame_ms, 60);
+
+        // Moderate loss holds the bitrate where it is.
+        let held = run(&mut controller, &mut now, report(0.05), 3);
+        assert_eq!(held.bitrate, 6_000);
+
+        // Once the network is clean again everything returns to the start.
+        let recovered = run(&mut controller, &mut now, report(0.0), 40);
+        assert_eq!(recovered.bitrate, 64_000);
+        assert!(!recovered.fec);
+        assert_eq!(recovered.expected_loss, 0);
+        assert_eq!(recovered.frame_ms, 20);
+    }
+
+    #[test]
+    fn test_backs_off_on_delay() {
+        let mut now = Instant::now();
+        let mut controller = BitrateController::new(RateLimits::default());
+        controller.update(&report(0.0), now);
+        let start = controller.settings().bitrate;
+
+        // Queueing delay without loss still means the link is saturated.
+        now += REPORT_INTERVAL;
+        let queued = Feedback {
+            round_trip_time: Some(BASE_RTT + Duration::from_millis(250)),
+            ..report(0.0)
+        };
+        let settings = controller.update(&queued, now).unwrap();
+        assert_eq!(settings.bitrate, start * 85 / 100);
+
+        now += REPORT_INTERVAL;
+        let jittery = Feedback {
+            jitter: Duration::from_millis(50),
+            ..report(0.0)
+        };
+        let settings = controller.update(&jittery, now).unwrap();
+        assert!(settings.bitrate < start * 85 / 100);
+        assert!(!settings.fec);
+    }
+
+    #[test]
+    fn test_respects_limits() {
+        let limits = RateLimits {
+            min_bitrate: 12_000,
+            max_bitrate: 24_000,
+            start_bitrate: 100_000,
+            min_frame_ms: 20,
+            max_frame_ms: 40,
+            fec: false,
+        };
+        let mut now = Instant::now();
+        let mut controller = BitrateController::new(limits);
+        assert_eq!(controller.settings().bitrate, 24_000);
+
+        let lossy = run(&mut controller, &mut now, report(0.5), 20);
+        assert_eq!(lossy.bitrate, 12_000);
+        assert_eq!(lossy.frame_ms, 40);
+        assert!(!lossy.fec);
+
+        let clean = run(&mut controller, &mut now, report(0.0), 20);
+        assert_eq!(clean.bitrate, 24_000);
+    }
+
+    /// Sends through a simulated bottleneck that drops whatever exceeds its capacity and
+    /// checks the controller settles around the capacity instead of overshooting it.
+    #[test]
+    fn test_converges_to_bottleneck() {
+        let capacity = 20_000.0;
+        let mut now = Instant::now();
+        let mut controller = BitrateController::new(RateLimits::default());
+        let mut rates = Vec::new();
+        for _ in 0..120 {
+            let rate = controller.settings().bitrate as f32;
+            let loss = (1.0 - capacity / rate).max(0.0);
+            // The queue grows while we send faster than the link drains it.
+            let queueing = if loss > 0.0 { 60 } else { 0 };
+            let feedback = Feedback {
+                fraction_lost: loss,
+                jitter: Duration::from_millis(2),
+                round_trip_time: Some(BASE_RTT + Duration::from_millis(queueing)),
+            };
+            now += REPORT_INTERVAL;
+            controller.update(&feedback, now);
+            rates.push(controller.settings().bitrate as f32);
+        }
+
+        let settled = &rates[60..];
+        let mean = settled.iter().sum::<f32>() / settled.len() as f32;
+        assert!(
+            (0.7 * capacity..1.15 * capacity).contains(&mean),
+            "mean {}",
+            mean
+        );
+        assert!(settled.iter().all(|&rate| rate < 1.3 * capacity));
+    }
+}
@@ -0,0 +1,217 @@
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::net::UdpSocket;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::Duration;
+
+    use test_gpui::audio::{FileSink, FileSource, MemorySink};
+    use test_gpui::call::{Call, CallConfig};
+    use test_gpui::rtcp::RtcpPacket;
+    use test_gpui::rtp::RtpHeader;
+    use test_gpui::wav::{self, WavSpec, WavWriter};
+
+    const SPEC: WavSpec = WavSpec {
+        sample_rate: 48_000,
+        channels: 1,
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("test-gpui-{}-{}", std::process::id(), name))
+    }
+
+    fn write_tone(path: &PathBuf, frequency: f32, seconds: f32) {
+        let samples: Vec<f32> = (0..(SPEC.sample_rate as f32 * seconds) as usize)
+            .map(|n| 0.5 * (2.0 * PI * frequency * n as f32 / SPEC.sample_rate as f32).sin())
+            .collect();
+        let mut writer = WavWriter::create(path, SPEC).unwrap();
+        writer.write_samples(&samples).unwrap();
+        writer.finalize().unwrap();
+    }
+
+    /// Signal power at `frequency` using the Goertzel algorithm.
+    fn power_at(samples: &[f32], frequency: f32) -> f32 {
+        let coeff = 2.0 * (2.0 * PI * frequency / SPEC.sample_rate as f32).cos();
+        let (mut s1, mut s2) = (0.0, 0.0);
+        for sample in samples {
+            let s0 = sample + coeff * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        s1 * s1 + s2 * s2 - coeff * s1 * s2
+    }
+
+    #[test]
+    fn test_call_over_loopback() {
+        let (tone_a, tone_b) = (temp_path("a.wav"), temp_path("b.wav"));
+        let (heard_a, heard_b) = (temp_path("heard-a.wav"), temp_path("heard-b.wav"));
+        write_tone(&tone_a, 440.0, 2.0);
+        write_tone(&tone_b, 1000.0, 2.0);
+
+        let socket_a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket_b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr_a = socket_a.local_addr().unwrap();
+        let addr_b = socket_b.local_addr().unwrap();
+
+        let config = CallConfig::default();
+        let call_a = Call::start(
+            socket_a,
+            addr_b,
+            config,
+            Box::new(FileSource::open(&tone_a).unwrap()),
+            Box::new(FileSink::create(&heard_a, SPEC).unwrap()),
+        )
+        .unwrap();
+        let call_b = Call::start(
+            socket_b,
+            addr_a,
+            config,
+            Box::new(FileSource::open(&tone_b).unwrap()),
+            Box::new(FileSink::create(&heard_b, SPEC).unwrap()),
+        )
+        .unwrap();
+        assert!(call_a.is_active() && call_b.is_active());
+
+        thread::sleep(Duration::from_millis(1000));
+        let stats = call_a.stats();
+        assert!(stats.packets_sent > 25 && stats.packets_received > 25);
+        assert_eq!(stats.packets_lost, 0);
+        call_a.hangup();
+        call_b.hangup();
+
+        let (_, heard_by_a) = wav::read(&heard_a).unwrap();
+        let (_, heard_by_b) = wav::read(&heard_b).unwrap();
+        for path in [tone_a, tone_b, heard_a, heard_b] {
+            let _ = std::fs::remove_file(path);
+        }
+
+        assert!(heard_by_a.len() > SPEC.sample_rate as usize / 2);
+        assert!(heard_by_b.len() > SPEC.sample_rate as usize / 2);
+        assert!(power_at(&heard_by_a, 1000.0) > 10.0 * power_at(&heard_by_a, 440.0));
+        assert!(power_at(&heard_by_b, 440.0) > 10.0 * power_at(&heard_by_b, 1000.0));
+    }
+
+    #[test]
+    fn test_dial_answer_and_remote_hangup() {
+        let spec = SPEC;
+        let silence = move || Box::new(FileSource::from_samples(spec, Vec::new()));
+        // Each side gets a sink of its own.
+        let discard = || Box::new(MemorySink::new());
+
+        let callee_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let callee_addr = callee_socket.local_addr().unwrap();
+        let config = CallConfig::default();
+        let sink = discard();
+        let callee =
+            thread::spawn(move || Call::answer(callee_socket, config, silence(), sink).unwrap());
+
+        let caller_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let caller_addr = caller_socket.local_addr().unwrap();
+        let caller = Call::dial(caller_socket, callee_addr, config, silence(), discard()).unwrap();
+        let callee = callee.join().unwrap();
+        assert_eq!(callee.peer(), caller_addr);
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(caller.is_active() && callee.is_active());
+
+        caller.hangup();
+        for _ in 0..50 {
+            if !callee.is_active() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!callee.is_active());
+        callee.hangup();
+    }
+
+    #[test]
+    fn test_opus_timestamps_count_at_48k() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let spec = WavSpec {
+            sample_rate: 16_000,
+            ..SPEC
+        };
+        let config = CallConfig {
+            sample_rate: 16_000,
+            ..CallConfig::default()
+        };
+        let call = Call::start(
+            socket,
+            peer.local_addr().unwrap(),
+            config,
+            Box::new(FileSource::from_samples(spec, Vec::new())),
+            Box::new(FileSink::create(temp_path("discard-16k.wav"), spec).unwrap()),
+        )
+        .unwrap();
+
+        let mut timestamps = Vec::new();
+        let mut packet = [0u8; 1500];
+        while timestamps.len() < 2 {
+            let size = peer.recv(&mut packet).unwrap();
+            if let Some((header, _)) = RtpHeader::parse(&packet[..size])
+                && !RtcpPacket::is_rtcp(&packet[..size])
+            {
+                timestamps.push(header.timestamp);
+            }
+        }
+        call.hangup();
+        let _ = std::fs::remove_file(temp_path("discard-16k.wav"));
+        // 20 ms of a 16 kHz stream still steps the timestamp by 960.
+        assert_eq!(timestamps[1].wrapping_sub(timestamps[0]), 960);
+    }
+
+    #[test]
+    fn test_surround_caller_hears_mono_callee() {
+        let surround = WavSpec {
+            channels: 6,
+            ..SPEC
+        };
+        let (tone, heard) = (temp_path("mono-tone.wav"), temp_path("heard-surround.wav"));
+        write_tone(&tone, 1000.0, 2.0);
+
+        let callee_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let callee_addr = callee_socket.local_addr().unwrap();
+        let source = Box::new(FileSource::open(&tone).unwrap());
+        let sink = Box::new(FileSink::create(temp_path("discard-mono.wav"), SPEC).unwrap());
+        let callee = thread::spawn(move || {
+            Call::answer(callee_socket, CallConfig::default(), source, sink).unwrap()
+        });
+
+        // The callee answers mono, and the caller's six channels are remixed to and from it.
+        let config = CallConfig {
+            channels: 6,
+            ..CallConfig::default()
+        };
+        let silence = FileSource::from_samples(surround, Vec::new());
+        let sink = FileSink::create(&heard, surround).unwrap();
+        let caller_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let caller = Call::dial(
+            caller_socket,
+            callee_addr,
+            config,
+            Box::new(silence),
+            Box::new(sink),
+        )
+        .unwrap();
+        let callee = callee.join().unwrap();
+
+        thread::sleep(Duration::from_millis(600));
+        caller.hangup();
+        callee.hangup();
+
+        let (spec, samples) = wav::read(&heard).unwrap();
+        for path in [tone, heard, temp_path("discard-mono.wav")] {
+            let _ = std::fs::remove_file(path);
+        }
+        assert_eq!(spec.channels, 6);
+        let front_left: Vec<f32> = samples.chunks_exact(6).map(|frame| frame[0]).collect();
+        let rear_right: Vec<f32> = samples.chunks_exact(6).map(|frame| frame[5]).collect();
+        assert!(front_left.len() > SPEC.sample_rate as usize / 4);
+        assert!(power_at(&front_left, 1000.0) > 10.0 * power_at(&front_left, 440.0));
+        assert_eq!(front_left, rear_right);
+    }
+}
@@ -0,0 +1,191 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use test_gpui::capture::{
+        Capture, Datagram, PcapReader, PcapWriter, Replay, internet_checksum,
+    };
+    use test_gpui::clock::VirtualClock;
+    use test_gpui::pipeline::{POLL_INTERVAL, Source};
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn datagram(millis: u64, from: &str, to: &str, payload: &[u8]) -> Datagram {
+        Datagram {
+            time: UNIX_EPOCH + Duration::from_secs(1_700_000_000) + Duration::from_millis(millis),
+            from: from.parse().unwrap(),
+            to: to.parse().unwrap(),
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn write(datagrams: &[Datagram]) -> Vec<u8> {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for datagram in datagrams {
+            writer.write(datagram).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    fn read(bytes: &[u8]) -> Vec<Datagram> {
+        PcapReader::new(bytes)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let datagrams = [
+            datagram(0, "10.0.0.1:5000", "10.0.0.2:5001", &[0x80, 111, 0, 1]),
+            datagram(20, "10.0.0.2:5001", "10.0.0.1:5000", &[0x81, 201, 0, 1, 2]),
+            datagram(40, "[::1]:5000", "[fe80::2]:5001", &[]),
+        ];
+        let bytes = write(&datagrams);
+        assert_eq!(read(&bytes), datagrams);
+
+        // The IPv4 and UDP checksums of the first packet hold.
+        let packet = &bytes[24 + 16..24 + 16 + 20 + 8 + 4];
+        assert_eq!(internet_checksum(&[&packet[..20]]), 0);
+        let pseudo = [&packet[12..20], &[0, 17, 0, 12][..]].concat();
+        assert_eq!(internet_checksum(&[&pseudo, &packet[20..]]), 0);
+
+        // IPv4 sending to IPv6 comes back mapped.
+        let mixed = datagram(0, "127.0.0.1:5000", "[::1]:5001", &[1]);
+        let read_back = read(&write(std::slice::from_ref(&mixed)));
+        assert_eq!(
+            read_back[0].from,
+            "[::ffff:127.0.0.1]:5000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(read_back[0].to, mixed.to);
+    }
+
+    #[test]
+    fn test_reads_tcpdump_captures() {
+        // Big-endian, microsecond timestamps, Ethernet frames: a UDP packet and a TCP one.
+        let udp = write(&[datagram(0, "10.0.0.1:5000", "10.0.0.2:5001", &[7; 3])]);
+        let ip = &udp[24 + 16..];
+        let mut tcp = ip.to_vec();
+        tcp[9] = 6;
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0xa1b2_c3d4u32.to_be_bytes());
+        bytes.extend_from_slice(&[0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff]);
+        bytes.extend_from_slice(&1u32.to_be_bytes());
+        for (seconds, packet) in [(10u32, &tcp[..]), (11, ip)] {
+            bytes.extend_from_slice(&seconds.to_be_bytes());
+            bytes.extend_from_slice(&250_000u32.to_be_bytes());
+            let len = (14 + packet.len()) as u32;
+            bytes.extend_from_slice(&len.to_be_bytes());
+            bytes.extend_from_slice(&len.to_be_bytes());
+            bytes.extend_from_slice(&[0; 12]);
+            bytes.extend_from_slice(&[0x08, 0x00]);
+            bytes.extend_from_slice(packet);
+        }
+
+        let datagrams = read(&bytes);
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(
+            datagrams[0].time,
+            UNIX_EPOCH + Duration::from_millis(11_250)
+        );
+        assert_eq!(datagrams[0].payload, [7; 3]);
+
+        // A file cut short mid-record is an error, not a silent end.
+        let mut reader = PcapReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(reader.next().unwrap().is_err());
+        assert!(PcapReader::new(&b"not a capture at all"[..]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_corrupt_records() {
+        let bytes = write(&[datagram(0, "10.0.0.1:5000", "10.0.0.2:5001", &[1, 2, 3])]);
+
+        // A record claiming more than the snapshot length is refused before it is read.
+        let mut oversized = bytes.clone();
+        oversized[24 + 8..24 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = PcapReader::new(&oversized[..])
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // Microseconds past a second carry into the seconds rather than overflowing.
+        let mut late = bytes;
+        late[..4].copy_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        late[24 + 4..24 + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let datagrams = read(&late);
+        assert_eq!(
+            datagrams[0].time,
+            UNIX_EPOCH
+                + Duration::from_secs(1_700_000_000)
+                + Duration::from_micros(u32::MAX as u64)
+        );
+    }
+
+    #[test]
+    fn test_replay_keeps_timing() {
+        let datagrams = vec![
+            datagram(0, "10.0.0.1:5000", "10.0.0.2:5001", &[1]),
+            datagram(20, "10.0.0.1:5000", "10.0.0.2:5001", &[2]),
+            datagram(1020, "10.0.0.1:5000", "10.0.0.2:5001", &[3]),
+        ];
+        let clock = VirtualClock::new();
+        let mut replay = Replay::with_clock(datagrams.clone().into_iter().map(Ok), clock.clone());
+
+        let mut out = Vec::new();
+        let mut replayed_at = Vec::new();
+        loop {
+            let before = clock.elapsed();
+            if !replay.pull(&mut out) {
+                break;
+            }
+            // Nothing waits longer than a poll interval at a time.
+            assert!(clock.elapsed() - before <= POLL_INTERVAL);
+            if out.len() > replayed_at.len() {
+                replayed_at.push(clock.elapsed());
+            }
+        }
+        assert_eq!(out, datagrams);
+        assert_eq!(
+            replayed_at,
+            [0, 20, 1020].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_capture_file() {
+        let path = std::env::temp_dir().join(format!("test-gpui-{}.pcap", std::process::id()));
+        let capture = Capture::create(&path).unwrap();
+        let (local, peer): (SocketAddr, SocketAddr) = (
+            "127.0.0.1:5000".parse().unwrap(),
+            "127.0.0.1:5001".parse().unwrap(),
+        );
+        let before = SystemTime::now();
+        capture.clone().record(local, peer, b"sent");
+        capture.record(peer, local, b"received");
+        drop(capture);
+
+        // The file is flushed as soon as the writer catches up.
+        let started = Instant::now();
+        let datagrams = loop {
+            let datagrams: Vec<Datagram> = PcapReader::open(&path)
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect();
+            if datagrams.len() == 2 || started.elapsed() > TIMEOUT {
+                break datagrams;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!((datagrams[0].from, datagrams[0].to), (local, peer));
+        assert_eq!(datagrams[0].payload, b"sent");
+        assert_eq!((datagrams[1].from, datagrams[1].to), (peer, local));
+        assert!(datagrams[0].time >= before && datagrams[1].time >= datagrams[0].time);
+        let _ = std::fs::remove_file(&path);
+    }
+}
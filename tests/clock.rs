@@ -0,0 +1,32 @@
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use test_gpui::clock::{Clock, SystemClock, VirtualClock};
+
+    #[test]
+    fn test_virtual_clock() {
+        let clock = VirtualClock::new();
+        let shared = clock.clone();
+        let start = clock.now();
+
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(shared.now() - start, Duration::from_millis(20));
+
+        // Sleeping jumps ahead at once, and never back.
+        let started = Instant::now();
+        shared.sleep_until(start + Duration::from_secs(60));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(clock.elapsed(), Duration::from_secs(60));
+        shared.sleep_until(start);
+        assert_eq!(clock.elapsed(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_system_clock() {
+        let clock = SystemClock;
+        let start = clock.now();
+        clock.sleep_until(start + Duration::from_millis(10));
+        assert!(clock.now() - start >= Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,337 @@
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::net::UdpSocket;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use test_gpui::audio::{AudioSink, FileSource};
+    use test_gpui::call::{Call, CallConfig};
+    use test_gpui::codec::{Codec, CodecError, CodecSet};
+    use test_gpui::g711::{self, alaw_decode, alaw_encode, ulaw_decode, ulaw_encode};
+    use test_gpui::signaling::CodecParams;
+    use test_gpui::wav::WavSpec;
+
+    /// μ-law codes 0x80 to 0xFF as the ITU-T G.191 reference expands them. Codes below
+    /// 0x80 are the same magnitudes, negated.
+    const ULAW: [i16; 128] = [
+        32124, 31100, 30076, 29052, 28028, 27004, 25980, 24956, //
+        23932, 22908, 21884, 20860, 19836, 18812, 17788, 16764, //
+        15996, 15484, 14972, 14460, 13948, 13436, 12924, 12412, //
+        11900, 11388, 10876, 10364, 9852, 9340, 8828, 8316, //
+        7932, 7676, 7420, 7164, 6908, 6652, 6396, 6140, //
+        5884, 5628, 5372, 5116, 4860, 4604, 4348, 4092, //
+        3900, 3772, 3644, 3516, 3388, 3260, 3132, 3004, //
+        2876, 2748, 2620, 2492, 2364, 2236, 2108, 1980, //
+        1884, 1820, 1756, 1692, 1628, 1564, 1500, 1436, //
+        1372, 1308, 1244, 1180, 1116, 1052, 988, 924, //
+        876, 844, 812, 780, 748, 716, 684, 652, //
+        620, 588, 556, 524, 492, 460, 428, 396, //
+        372, 356, 340, 324, 308, 292, 276, 260, //
+        244, 228, 212, 196, 180, 164, 148, 132, //
+        120, 112, 104, 96, 88, 80, 72, 64, //
+        56, 48, 40, 32, 24, 16, 8, 0, //
+    ];
+
+    /// A-law codes 0x80 to 0xFF, as [`ULAW`]. The even bits are inverted on the line.
+    const ALAW: [i16; 128] = [
+        5504, 5248, 6016, 5760, 4480, 4224, 4992, 4736, //
+        7552, 7296, 8064, 7808, 6528, 6272, 7040, 6784, //
+        2752, 2624, 3008, 2880, 2240, 2112, 2496, 2368, //
+        3776, 3648, 4032, 3904, 3264, 3136, 3520, 3392, //
+        22016, 20992, 24064, 23040, 17920, 16896, 19968, 18944, //
+        30208, 29184, 32256, 31232, 26112, 25088, 28160, 27136, //
+        11008, 10496, 12032, 11520, 8960, 8448, 9984, 9472, //
+        15104, 14592, 16128, 15616, 13056, 12544, 14080, 13568, //
+        344, 328, 376, 360, 280, 264, 312, 296, //
+        472, 456, 504, 488, 408, 392, 440, 424, //
+        88, 72, 120, 104, 24, 8, 56, 40, //
+        216, 200, 248, 232, 152, 136, 184, 168, //
+        1376, 1312, 1504, 1440, 1120, 1056, 1248, 1184, //
+        1888, 1824, 2016, 1952, 1632, 1568, 1760, 1696, //
+        688, 656, 752, 720, 560, 528, 624, 592, //
+        944, 912, 1008, 976, 816, 784, 880, 848, //
+    ];
+
+    fn tone(sample_rate: u32, frequency: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|n| 0.5 * (2.0 * PI * frequency * n as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    /// Signal power at `frequency` using the Goertzel algorithm.
+    fn power_at(samples: &[f32], sample_rate: u32, frequency: f32) -> f32 {
+        let coeff = 2.0 * (2.0 * PI * frequency / sample_rate as f32).cos();
+        let (mut s1, mut s2) = (0.0, 0.0);
+        for sample in samples {
+            let s0 = sample + coeff * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        s1 * s1 + s2 * s2 - coeff * s1 * s2
+    }
+
+    #[test]
+    fn test_g711_matches_reference() {
+        for code in 0..=255u8 {
+            let (ulaw, alaw) = (ULAW[code as usize & 0x7f], ALAW[code as usize & 0x7f]);
+            let (ulaw, alaw) = if code < 0x80 {
+                (-ulaw, -alaw)
+            } else {
+                (ulaw, alaw)
+            };
+            assert_eq!(ulaw_decode(code), ulaw, "μ-law {:#04x}", code);
+            assert_eq!(alaw_decode(code), alaw, "A-law {:#04x}", code);
+
+            // Every code survives a round trip, but μ-law's negative zero comes back positive.
+            let expected = if code == 0x7f { 0xff } else { code };
+            assert_eq!(ulaw_encode(ulaw), expected, "μ-law {:#04x}", code);
+            assert_eq!(alaw_encode(alaw), code, "A-law {:#04x}", code);
+        }
+        assert_eq!(ulaw_encode(0), 0xff);
+        assert_eq!(alaw_encode(0), 0xd5);
+        assert_eq!(ulaw_encode(i16::MAX), 0x80);
+        assert_eq!(ulaw_encode(i16::MIN), 0x00);
+        assert_eq!(alaw_encode(i16::MAX), 0xaa);
+        assert_eq!(alaw_encode(i16::MIN), 0x2a);
+    }
+
+    #[test]
+    fn test_g711_quantizes_to_nearest_step() {
+        let mut previous = (i16::MIN, i16::MIN);
+        for sample in i16::MIN..=i16::MAX {
+            let ulaw = ulaw_decode(ulaw_encode(sample));
+            let alaw = alaw_decode(alaw_encode(sample));
+            // Louder samples get coarser steps, but never go backwards.
+            assert!(ulaw >= previous.0 && alaw >= previous.1, "{}", sample);
+            previous = (ulaw, alaw);
+
+            let allowed = 16 + (sample as i32).abs() / 16;
+            assert!(
+                (ulaw as i32 - sample as i32).abs() <= allowed,
+                "μ-law {}",
+                sample
+            );
+            assert!(
+                (alaw as i32 - sample as i32).abs() <= allowed,
+                "A-law {}",
+                sample
+            );
+        }
+    }
+
+    #[test]
+    fn test_g711_encoder_and_decoder() {
+        let frame = tone(g711::SAMPLE_RATE, 1000.0, 160);
+        for codec in [Codec::Pcmu, Codec::Pcma] {
+            assert_eq!(codec.format(48_000, 2), (8_000, 1));
+            let mut encoder = codec.encoder(8_000, 1).unwrap();
+            let mut decoder = codec.decoder(8_000, 1).unwrap();
+            assert_eq!(encoder.codec(), codec);
+
+            let mut packet = [0u8; 160];
+            assert_eq!(encoder.encode(&frame, &mut packet).unwrap(), 160);
+            assert!(matches!(
+                encoder.encode(&frame, &mut [0u8; 80]),
+                Err(CodecError::BufferTooSmall)
+            ));
+
+            let mut decoded = [0.0; 160];
+            assert_eq!(decoder.decode(&packet, &mut decoded).unwrap(), 160);
+            for (decoded, original) in decoded.iter().zip(&frame) {
+                assert!((decoded - original).abs() < 0.02);
+            }
+            // Nothing to conceal a loss with but silence.
+            assert_eq!(decoder.decode(&[], &mut decoded).unwrap(), 160);
+            assert!(decoded.iter().all(|&sample| sample == 0.0));
+        }
+    }
+
+    #[test]
+    fn test_l16_round_trip() {
+        let frame: Vec<f32> = tone(16_000, 440.0, 640);
+        let mut encoder = Codec::L16.encoder(16_000, 2).unwrap();
+        let mut decoder = Codec::L16.decoder(16_000, 2).unwrap();
+
+        let mut packet = [0u8; 1280];
+        assert_eq!(encoder.encode(&frame, &mut packet).unwrap(), 1280);
+        // Big-endian on the wire, full scale being i16::MAX.
+        let first = i16::from_be_bytes([packet[2], packet[3]]);
+        assert_eq!(first, (frame[1] * i16::MAX as f32) as i16);
+
+        let mut decoded = [0.0; 640];
+        assert_eq!(decoder.decode(&packet, &mut decoded).unwrap(), 320);
+        for (decoded, original) in decoded.iter().zip(&frame) {
+            assert!((decoded - original).abs() <= 1.0 / i16::MAX as f32);
+        }
+        assert!(matches!(
+            decoder.decode(&packet[..3], &mut decoded),
+            Err(CodecError::InvalidPacket)
+        ));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let opus = Codec::Opus.encoder(16_000, 2).unwrap();
+        assert_eq!(opus.clock_rate(), 48_000);
+        assert_eq!(opus.frame_len(20), Some(640));
+        assert_eq!(opus.frame_len(30), None);
+        assert!(opus.supports_fec() && opus.supports_dtx());
+        let decoder = Codec::Opus.decoder(16_000, 2).unwrap();
+        assert!(decoder.supports_plc() && decoder.supports_fec());
+
+        for codec in [Codec::Pcmu, Codec::Pcma, Codec::L16] {
+            let mut encoder = codec.encoder(8_000, 1).unwrap();
+            assert_eq!(encoder.clock_rate(), 8_000);
+            assert_eq!(encoder.frame_len(30), Some(240));
+            assert!(!encoder.supports_fec() && !encoder.supports_dtx());
+            // Fixed-rate codecs take bitrate changes and ignore them.
+            encoder.set_bitrate(6_000).unwrap();
+            let decoder = codec.decoder(8_000, 1).unwrap();
+            assert!(!decoder.supports_plc() && !decoder.supports_fec());
+        }
+    }
+
+    #[test]
+    fn test_codec_set() {
+        let all = CodecSet::all();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all.preferred(), Some(Codec::Opus));
+        assert_eq!(all.iter().collect::<Vec<_>>(), Codec::ALL);
+
+        let legacy: CodecSet = [Codec::L16, Codec::Pcma].into_iter().collect();
+        assert_eq!(legacy.preferred(), Some(Codec::Pcma));
+        assert_eq!(CodecSet::from_bits(legacy.bits() | 0xf0), legacy);
+        assert!(legacy.intersection(CodecSet::only(Codec::Opus)).is_empty());
+
+        for codec in Codec::ALL {
+            assert_eq!(Codec::from_payload_type(codec.payload_type()), Some(codec));
+        }
+        assert_eq!(Codec::Pcmu.payload_type(), 0);
+        assert_eq!(Codec::Pcma.payload_type(), 8);
+        assert_eq!(Codec::from_payload_type(13), None);
+    }
+
+    #[test]
+    fn test_negotiates_fallback() {
+        let callee = CodecParams::default();
+        let offer = CodecParams {
+            codecs: CodecSet::only(Codec::Pcmu),
+            ..CodecParams::default()
+        };
+        let answer = callee.negotiate(&offer).unwrap();
+        assert_eq!(answer.codec(), Some(Codec::Pcmu));
+        assert_eq!((answer.sample_rate, answer.channels), (8_000, 1));
+        assert!(offer.allows(&answer));
+
+        // L16 is refused where its frames would not fit in a packet.
+        let offer = CodecParams {
+            codecs: CodecSet::only(Codec::L16),
+            ..CodecParams::default()
+        };
+        assert!(callee.negotiate(&offer).is_err());
+        let offer = CodecParams {
+            sample_rate: 16_000,
+            ..offer
+        };
+        let answer = callee.negotiate(&offer).unwrap();
+        assert_eq!(answer.codec(), Some(Codec::L16));
+        assert!(offer.allows(&answer));
+    }
+
+    /// Collects what a call plays.
+    #[derive(Clone, Default)]
+    struct Heard(Arc<Mutex<Vec<f32>>>);
+
+    impl AudioSink for Heard {
+        fn write(&mut self, samples: &[f32]) {
+            self.0.lock().unwrap().extend_from_slice(samples);
+        }
+    }
+
+    /// Calls between two local sockets with `config` for a moment, playing a 1 kHz tone both
+    /// ways, and returns what the first side heard.
+    fn call_tone(config: CallConfig, spec: WavSpec) -> Vec<f32> {
+        let source = || {
+            let samples = tone(spec.sample_rate, 1000.0, spec.sample_rate as usize * 2);
+            Box::new(FileSource::from_samples(spec, samples))
+        };
+        let socket_a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket_b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr_a = socket_a.local_addr().unwrap();
+        let addr_b = socket_b.local_addr().unwrap();
+
+        let heard = Heard::default();
+        let call_a =
+            Call::start(socket_a, addr_b, config, source(), Box::new(heard.clone())).unwrap();
+        let call_b = Call::start(
+            socket_b,
+            addr_a,
+            config,
+            source(),
+            Box::new(Heard::default()),
+        )
+        .unwrap();
+        let (rate, _) = config.codec().format(config.sample_rate, config.channels);
+        assert_eq!(call_a.config().sample_rate, rate);
+        assert_eq!(call_a.config().codec(), config.codec());
+
+        thread::sleep(Duration::from_millis(600));
+        let stats = call_a.stats();
+        assert!(stats.packets_received > 15);
+        call_a.hangup();
+        call_b.hangup();
+
+        let heard = heard.0.lock().unwrap().clone();
+        assert!(heard.len() > spec.sample_rate as usize / 4);
+        let rate = spec.sample_rate;
+        assert!(power_at(&heard, rate, 1000.0) > 10.0 * power_at(&heard, rate, 440.0));
+        heard
+    }
+
+    #[test]
+    fn test_call_over_pcmu() {
+        // The devices stay at 48 kHz, and the call resamples to and from G.711's 8 kHz.
+        let spec = WavSpec {
+            sample_rate: 48_000,
+            channels: 1,
+        };
+        let config = CallConfig {
+            codecs: CodecSet::only(Codec::Pcmu),
+            ..CallConfig::default()
+        };
+        let heard = call_tone(config, spec);
+        assert!(power_at(&heard, 48_000, 1000.0) > 10.0 * power_at(&heard, 48_000, 6000.0));
+    }
+
+    #[test]
+    fn test_call_over_l16() {
+        // At 48 kHz a 20 ms frame takes 1920 bytes, more than a packet holds.
+        let config = CallConfig {
+            codecs: CodecSet::only(Codec::L16),
+            ..CallConfig::default()
+        };
+        assert!(!Codec::L16.fits(48_000, 1, 20));
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = socket.local_addr().unwrap();
+        let source = Box::new(FileSource::from_samples(
+            WavSpec {
+                sample_rate: 48_000,
+                channels: 1,
+            },
+            Vec::new(),
+        ));
+        assert!(Call::start(socket, peer, config, source, Box::new(Heard::default())).is_err());
+
+        let spec = WavSpec {
+            sample_rate: 16_000,
+            channels: 1,
+        };
+        let config = CallConfig {
+            sample_rate: spec.sample_rate,
+            ..config
+        };
+        call_tone(config, spec);
+    }
+}
@@ -0,0 +1,439 @@
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use opus::{Application, Channels, Decoder, Encoder};
+    use test_gpui::audio::{FileSource, MemorySink};
+    use test_gpui::call::{Call, CallConfig, CallError};
+    use test_gpui::codec::{Codec, CodecSet};
+    use test_gpui::conference::{
+        Action, Conference, ConferenceConfig, ConferenceServer, Event, Mode,
+    };
+    use test_gpui::rtp::{HEADER_LEN, OPUS_PAYLOAD_TYPE, RtpHeader};
+    use test_gpui::signaling::{
+        Action as SessionAction, CodecParams, EndReason, Media, Message, RejectReason, Session,
+    };
+    use test_gpui::wav::WavSpec;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const FRAME: usize = 960;
+    const FRAME_DURATION: Duration = Duration::from_millis(20);
+    const EVENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// A participant simulated with a plain socket.
+    struct Client {
+        socket: UdpSocket,
+        server: SocketAddr,
+        ssrc: u32,
+        header: RtpHeader,
+        encoder: Encoder,
+    }
+
+    impl Client {
+        fn new(server: SocketAddr, ssrc: u32) -> Client {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_millis(50)))
+                .unwrap();
+            Client {
+                socket,
+                server,
+                ssrc,
+                header: RtpHeader {
+                    marker: true,
+                    payload_type: OPUS_PAYLOAD_TYPE,
+                    sequence: 0,
+                    timestamp: 0,
+                    ssrc,
+                },
+                encoder: Encoder::new(SAMPLE_RATE, Channels::Mono, Application::Voip).unwrap(),
+            }
+        }
+
+        fn addr(&self) -> SocketAddr {
+            self.socket.local_addr().unwrap()
+        }
+
+        /// Invites the server into `room`, returning the media agreed on or how it ended.
+        fn join(&self, room: u32) -> Result<Media, EndReason> {
+            let now = Instant::now();
+            let mut session = Session::new(CodecParams::default(), self.ssrc, now);
+            let mut actions = session.invite(room, now);
+            let mut packet = [0u8; 1500];
+            loop {
+                for action in actions.drain(..) {
+                    match action {
+                        SessionAction::Send(message) => {
+                            self.socket.send_to(&message.encode(), self.server).unwrap();
+                        }
+                        SessionAction::Connected(media) => return Ok(media),
+                        SessionAction::Ended(reason) => return Err(reason),
+                        SessionAction::Incoming(_) => {}
+                    }
+                }
+                if let Ok((size, _)) = self.socket.recv_from(&mut packet)
+                    && let Some(message) = Message::decode(&packet[..size])
+                {
+                    actions.extend(session.receive(message, Instant::now()));
+                }
+                actions.extend(session.tick(Instant::now()));
+            }
+        }
+
+        fn leave(&self, room: u32) {
+            let bye = Message::Bye { call_id: room };
+            self.socket.send_to(&bye.encode(), self.server).unwrap();
+        }
+
+        fn send_frame(&mut self, pcm: &[f32]) {
+            let mut packet = [0u8; 1500];
+            let size = self
+                .encoder
+                .encode_float(pcm, &mut packet[HEADER_LEN..])
+                .unwrap();
+            self.header.write(&mut packet);
+            self.socket
+                .send_to(&packet[..HEADER_LEN + size], self.server)
+                .unwrap();
+            self.header.marker = false;
+            self.header.sequence = self.header.sequence.wrapping_add(1);
+            self.header.timestamp = self.header.timestamp.wrapping_add(FRAME as u32);
+        }
+
+        /// Everything that arrived until the socket went quiet.
+        fn drain(&self) -> Vec<Vec<u8>> {
+            let mut packets = Vec::new();
+            let mut packet = [0u8; 1500];
+            while let Ok((size, _)) = self.socket.recv_from(&mut packet) {
+                packets.push(packet[..size].to_vec());
+            }
+            packets
+        }
+    }
+
+    fn tone(frequency: f32, frame: usize) -> Vec<f32> {
+        (frame * FRAME..(frame + 1) * FRAME)
+            .map(|n| 0.3 * (2.0 * PI * frequency * n as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    /// Signal power at `frequency` using the Goertzel algorithm.
+    fn power_at(samples: &[f32], frequency: f32) -> f32 {
+        let coeff = 2.0 * (2.0 * PI * frequency / SAMPLE_RATE as f32).cos();
+        let (mut s1, mut s2) = (0.0, 0.0);
+        for sample in samples {
+            let s0 = sample + coeff * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        s1 * s1 + s2 * s2 - coeff * s1 * s2
+    }
+
+    fn start(mode: Mode, max_participants: usize) -> ConferenceServer {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ConferenceConfig {
+            mode,
+            max_participants,
+            ..ConferenceConfig::default()
+        };
+        ConferenceServer::start(socket, config).unwrap()
+    }
+
+    #[test]
+    fn test_forwarding_over_loopback() {
+        let server = start(Mode::Forward, 3);
+        let mut clients: Vec<Client> = (1..=4)
+            .map(|ssrc| Client::new(server.local_addr(), ssrc))
+            .collect();
+        for client in &clients[..3] {
+            let media = client.join(7).unwrap();
+            assert_eq!(media.call_id, 7);
+            assert_eq!(
+                server.next_event(EVENT_TIMEOUT),
+                Some(Event::Joined {
+                    room: 7,
+                    peer: client.addr(),
+                    ssrc: client.ssrc,
+                })
+            );
+        }
+        // The room is full, but other rooms are not.
+        assert_eq!(
+            clients[3].join(7),
+            Err(EndReason::Rejected(RejectReason::Busy))
+        );
+        assert!(clients[3].join(8).is_ok());
+        assert!(server.next_event(EVENT_TIMEOUT).is_some());
+
+        // Packets reach everybody else in the room untouched.
+        for frame in 0..5 {
+            clients[0].send_frame(&tone(440.0, frame));
+        }
+        let sent = clients[0].header.sequence;
+        for listener in &clients[1..3] {
+            let packets = listener.drain();
+            assert_eq!(packets.len(), sent as usize);
+            for (sequence, packet) in packets.iter().enumerate() {
+                let (header, _) = RtpHeader::parse(packet).unwrap();
+                assert_eq!(header.ssrc, 1);
+                assert_eq!(header.sequence, sequence as u16);
+            }
+        }
+        assert!(clients[0].drain().is_empty());
+        assert!(clients[3].drain().is_empty());
+
+        // Leaving frees the seat.
+        clients[1].leave(7);
+        assert_eq!(
+            server.next_event(EVENT_TIMEOUT),
+            Some(Event::Left {
+                room: 7,
+                peer: clients[1].addr(),
+                reason: EndReason::RemoteHangup,
+            })
+        );
+        let newcomer = Client::new(server.local_addr(), 5);
+        assert!(newcomer.join(7).is_ok());
+
+        // Stopping the server hangs up on everybody.
+        server.stop();
+        let bye = Message::Bye { call_id: 7 }.encode();
+        assert!(clients[0].drain().contains(&bye));
+    }
+
+    #[test]
+    fn test_mixing_over_loopback() {
+        let server = start(Mode::Mix, 8);
+        let frequencies = [440.0, 1000.0, 1500.0];
+        let mut clients: Vec<Client> = (1..=3)
+            .map(|ssrc| Client::new(server.local_addr(), ssrc))
+            .collect();
+        let mix_ssrcs: Vec<u32> = clients
+            .iter()
+            .map(|client| client.join(3).unwrap().remote_ssrc)
+            .collect();
+
+        let start = Instant::now();
+        for frame in 0..40 {
+            for (client, frequency) in clients.iter_mut().zip(frequencies) {
+                client.send_frame(&tone(frequency, frame));
+            }
+            let due = start + FRAME_DURATION * (frame as u32 + 1);
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+        thread::sleep(FRAME_DURATION * 5);
+        // Mixes keep coming while anybody is in the room.
+        for client in &clients {
+            client.leave(3);
+        }
+
+        // Everybody hears the other two, but not themselves.
+        for ((client, own), mix_ssrc) in clients.iter().zip(frequencies).zip(mix_ssrcs) {
+            let mut decoder = Decoder::new(SAMPLE_RATE, Channels::Mono).unwrap();
+            let mut heard = Vec::new();
+            for packet in client.drain() {
+                let Some((header, payload)) = RtpHeader::parse(&packet) else {
+                    continue;
+                };
+                assert_eq!(header.ssrc, mix_ssrc);
+                let mut pcm = [0f32; FRAME];
+                let samples = decoder.decode_float(payload, &mut pcm, false).unwrap();
+                heard.extend_from_slice(&pcm[..samples]);
+            }
+            assert!(heard.len() > 20 * FRAME, "heard {} samples", heard.len());
+
+            let own_power = power_at(&heard, own);
+            for other in frequencies.into_iter().filter(|&f| f != own) {
+                let power = power_at(&heard, other);
+                assert!(
+                    power > 100.0 * own_power,
+                    "{} Hz listener: {} Hz at {}, own voice at {}",
+                    own,
+                    other,
+                    power,
+                    own_power
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_calls_join_mixed_room() {
+        let server = start(Mode::Mix, 8);
+        let spec = WavSpec {
+            sample_rate: SAMPLE_RATE,
+            channels: 2,
+        };
+        let dial = |config: CallConfig, samples: Vec<f32>, sink: MemorySink| {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let source = Box::new(FileSource::from_samples(spec, samples));
+            Call::dial(socket, server.local_addr(), config, source, Box::new(sink))
+        };
+        // Stereo is narrowed to the mono of the mix, and the call remixes to match.
+        let config = CallConfig {
+            channels: 2,
+            encrypt: false,
+            call_id: Some(5),
+            ..CallConfig::default()
+        };
+
+        // The server reads the media, so it turns away calls that want to encrypt it.
+        let encrypted = dial(CallConfig::default(), Vec::new(), MemorySink::new());
+        assert!(matches!(
+            encrypted,
+            Err(CallError::Ended(EndReason::Rejected(
+                RejectReason::Incompatible
+            )))
+        ));
+
+        let talking: Vec<f32> = (0..50)
+            .flat_map(|frame| tone(1000.0, frame))
+            .flat_map(|sample| [sample, sample])
+            .collect();
+        let talker = dial(config, talking, MemorySink::new()).unwrap();
+        let heard = MemorySink::new();
+        let listener = dial(config, Vec::new(), heard.clone()).unwrap();
+        assert_eq!(listener.config().channels, 1);
+
+        thread::sleep(Duration::from_millis(800));
+        talker.hangup();
+        listener.hangup();
+        let left: Vec<f32> = heard.samples().chunks(2).map(|frame| frame[0]).collect();
+        assert!(left.len() > 10 * FRAME, "heard {} samples", left.len());
+        assert!(power_at(&left, 1000.0) > 10.0 * power_at(&left, 440.0));
+    }
+
+    fn offer(ssrc: u32) -> Message {
+        Message::Invite {
+            call_id: 1,
+            ssrc,
+            params: CodecParams::default(),
+            public_key: None,
+        }
+    }
+
+    fn replies(actions: &[Action]) -> Vec<Message> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Send { packet, .. } => Message::decode(packet),
+                Action::Event(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_room_management() {
+        let start = Instant::now();
+        let mut conference = Conference::new(ConferenceConfig::default());
+        let alice: SocketAddr = "127.0.0.1:4001".parse().unwrap();
+        let bob: SocketAddr = "127.0.0.1:4002".parse().unwrap();
+
+        let actions = conference.receive(&offer(1).encode(), alice, start);
+        assert!(matches!(
+            replies(&actions)[..],
+            [Message::Accept { call_id: 1, .. }]
+        ));
+        assert_eq!(conference.participants(1), vec![alice]);
+
+        // A retransmitted invite is answered again without joining twice.
+        let again = conference.receive(&offer(1).encode(), alice, start);
+        assert_eq!(replies(&again), replies(&actions));
+        assert!(
+            !again
+                .iter()
+                .any(|action| matches!(action, Action::Event(_)))
+        );
+
+        // Encrypted offers and SSRCs already in use are refused.
+        let encrypted = Message::Invite {
+            call_id: 1,
+            ssrc: 2,
+            params: CodecParams::default(),
+            public_key: Some([9; 32]),
+        };
+        let reject = Message::Reject {
+            call_id: 1,
+            reason: RejectReason::Incompatible,
+        };
+        let actions = conference.receive(&encrypted.encode(), bob, start);
+        assert_eq!(replies(&actions), vec![reject.clone()]);
+        let actions = conference.receive(&offer(1).encode(), bob, start);
+        assert_eq!(replies(&actions), vec![reject]);
+        conference.receive(&offer(2).encode(), bob, start);
+        assert_eq!(conference.participants(1).len(), 2);
+
+        // Keepalives are answered, and keep a participant in the room.
+        let keepalive = Message::Keepalive { call_id: 1 };
+        let later = start + Duration::from_secs(3);
+        let actions = conference.receive(&keepalive.encode(), alice, later);
+        assert_eq!(replies(&actions), vec![keepalive]);
+
+        // Bob went quiet, so he is dropped, and the room goes away once Alice leaves too.
+        let actions = conference.tick(start + Duration::from_secs(5));
+        assert_eq!(replies(&actions), vec![Message::Bye { call_id: 1 }]);
+        assert!(actions.contains(&Action::Event(Event::Left {
+            room: 1,
+            peer: bob,
+            reason: EndReason::Timeout,
+        })));
+        conference.receive(&Message::Bye { call_id: 1 }.encode(), alice, later);
+        assert!(conference.rooms().is_empty());
+    }
+
+    #[test]
+    fn test_forwarded_room_keeps_first_codec() {
+        let start = Instant::now();
+        let mut conference = Conference::new(ConferenceConfig::default());
+        let invite = |call_id, ssrc, codecs, sample_rate| Message::Invite {
+            call_id,
+            ssrc,
+            params: CodecParams {
+                sample_rate,
+                codecs,
+                ..CodecParams::default()
+            },
+            public_key: None,
+        };
+        let reject = Message::Reject {
+            call_id: 1,
+            reason: RejectReason::Incompatible,
+        };
+        let peer = |port: u16| -> SocketAddr { ([127, 0, 0, 1], port).into() };
+
+        let pcmu = CodecSet::only(Codec::Pcmu);
+        conference.receive(&invite(1, 1, pcmu, 48_000).encode(), peer(4001), start);
+        assert_eq!(conference.participants(1).len(), 1);
+
+        // Opus alone cannot join a PCMU room...
+        let opus = CodecSet::only(Codec::Opus);
+        let actions = conference.receive(&invite(1, 2, opus, 48_000).encode(), peer(4002), start);
+        assert_eq!(replies(&actions), vec![reject.clone()]);
+
+        // ...but offering both settles on PCMU.
+        let actions = conference.receive(
+            &invite(1, 3, CodecSet::all(), 48_000).encode(),
+            peer(4003),
+            start,
+        );
+        assert!(matches!(
+            replies(&actions)[..],
+            [Message::Accept { params, .. }] if params.codec() == Some(Codec::Pcmu)
+        ));
+
+        // An Opus room also keeps its sample rate.
+        conference.receive(&invite(2, 4, opus, 48_000).encode(), peer(4004), start);
+        let actions = conference.receive(&invite(2, 5, opus, 16_000).encode(), peer(4005), start);
+        assert_eq!(
+            replies(&actions),
+            vec![Message::Reject {
+                call_id: 2,
+                reason: RejectReason::Incompatible,
+            }]
+        );
+        assert_eq!(conference.participants(2).len(), 1);
+    }
+}
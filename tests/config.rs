@@ -0,0 +1,205 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use test_gpui::config::{
+        ConfigError, ConfigFile, FileSection, KeyBindings, Multicast, NetArgs, NetDefaults,
+    };
+
+    const SENDER: NetDefaults = NetDefaults {
+        bind_port: 5000,
+        peer_port: Some(5001),
+    };
+    const RECEIVER: NetDefaults = NetDefaults {
+        bind_port: 5001,
+        peer_port: None,
+    };
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = NetArgs::default()
+            .resolve_with(FileSection::default(), SENDER)
+            .unwrap();
+        assert_eq!(config.bind, addr("0.0.0.0:5000"));
+        assert_eq!(config.peer, Some(addr("127.0.0.1:5001")));
+
+        let args = NetArgs {
+            ipv6: true,
+            ..Default::default()
+        };
+        let config = args.resolve_with(FileSection::default(), SENDER).unwrap();
+        assert_eq!(config.bind, addr("[::]:5000"));
+        assert_eq!(config.peer, Some(addr("[::1]:5001")));
+    }
+
+    #[test]
+    fn test_layering() {
+        let file = ConfigFile::parse(
+            r#"
+            [network]
+            bind = "0.0.0.0:6000"
+            peer = "10.0.0.2:6001"
+
+            [opus-sender]
+            peer = "10.0.0.3:6001"
+            "#,
+        )
+        .unwrap();
+
+        let section = file.section("opus-sender");
+        let config = NetArgs::default()
+            .resolve_with(section.clone(), SENDER)
+            .unwrap();
+        assert_eq!(config.bind, addr("0.0.0.0:6000"));
+        assert_eq!(config.peer, Some(addr("10.0.0.3:6001")));
+
+        let args = NetArgs {
+            peer: Some("10.0.0.4:7000".into()),
+            ..Default::default()
+        };
+        let config = args.resolve_with(section, SENDER).unwrap();
+        assert_eq!(config.peer, Some(addr("10.0.0.4:7000")));
+
+        let config = NetArgs::default()
+            .resolve_with(file.section("opus-receiver"), RECEIVER)
+            .unwrap();
+        assert_eq!(config.peer, Some(addr("10.0.0.2:6001")));
+    }
+
+    #[test]
+    fn test_key_bindings() {
+        let text = r#"
+            [network]
+            bind = "0.0.0.0:6000"
+
+            [keys]
+            toggle_mute = "ctrl-m"
+            push_to_talk = "f1"
+            "#;
+        let keys = KeyBindings::parse(text).unwrap();
+        assert_eq!(keys.toggle_mute, "ctrl-m");
+        assert_eq!(keys.push_to_talk, "f1");
+        assert_eq!(keys.toggle_deafen, KeyBindings::default().toggle_deafen);
+
+        // The network settings are unaffected by the extra section.
+        let section = ConfigFile::parse(text).unwrap().section("opus-sender");
+        assert_eq!(section.bind.as_deref(), Some("0.0.0.0:6000"));
+        assert_eq!(KeyBindings::parse("").unwrap(), KeyBindings::default());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_refused() {
+        let typo = r#"
+            [network]
+            bnid = "0.0.0.0:6000"
+            "#;
+        assert!(ConfigFile::parse(typo).is_err());
+        // Key bindings only look at their own section.
+        assert_eq!(KeyBindings::parse(typo).unwrap(), KeyBindings::default());
+
+        let typo = r#"
+            [keys]
+            toggle_mut = "ctrl-m"
+            "#;
+        assert!(KeyBindings::parse(typo).is_err());
+        assert!(ConfigFile::parse(typo).unwrap().sections.is_empty());
+    }
+
+    #[test]
+    fn test_multicast() {
+        let args = NetArgs {
+            multicast: Some("239.1.2.3".parse().unwrap()),
+            interface: Some("192.168.1.5".into()),
+            ..Default::default()
+        };
+        let config = args.resolve_with(FileSection::default(), RECEIVER).unwrap();
+        assert_eq!(
+            config.multicast,
+            Some(Multicast::V4 {
+                group: Ipv4Addr::new(239, 1, 2, 3),
+                interface: Ipv4Addr::new(192, 168, 1, 5),
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_combinations() {
+        let resolve = |args: NetArgs, defaults| args.resolve_with(FileSection::default(), defaults);
+
+        let err = resolve(
+            NetArgs {
+                peer: Some("0.0.0.0:5001".into()),
+                ..Default::default()
+            },
+            SENDER,
+        );
+        assert!(matches!(err, Err(ConfigError::UnspecifiedPeer(_))));
+
+        let err = resolve(
+            NetArgs {
+                peer: Some("[::1]:5001".into()),
+                ..Default::default()
+            },
+            SENDER,
+        );
+        assert!(matches!(err, Err(ConfigError::FamilyMismatch { .. })));
+
+        let err = resolve(
+            NetArgs {
+                peer: Some("127.0.0.1:5000".into()),
+                ..Default::default()
+            },
+            SENDER,
+        );
+        assert!(matches!(err, Err(ConfigError::PeerIsSelf(_))));
+
+        let err = resolve(
+            NetArgs {
+                multicast: Some("10.0.0.1".parse().unwrap()),
+                ..Default::default()
+            },
+            RECEIVER,
+        );
+        assert!(matches!(err, Err(ConfigError::NotMulticast(_))));
+
+        let err = resolve(
+            NetArgs {
+                multicast: Some("ff02::1".parse().unwrap()),
+                ..Default::default()
+            },
+            RECEIVER,
+        );
+        assert!(matches!(
+            err,
+            Err(ConfigError::MulticastFamilyMismatch { .. })
+        ));
+
+        let err = resolve(
+            NetArgs {
+                interface: Some("eth0".into()),
+                ..Default::default()
+            },
+            RECEIVER,
+        );
+        assert!(matches!(err, Err(ConfigError::InterfaceWithoutMulticast)));
+
+        let err = resolve(
+            NetArgs {
+                bind: Some("not an address".into()),
+                ..Default::default()
+            },
+            RECEIVER,
+        );
+        assert!(matches!(err, Err(ConfigError::InvalidAddress(_))));
+
+        let config = resolve(NetArgs::default(), RECEIVER).unwrap();
+        assert!(matches!(
+            config.require_peer(),
+            Err(ConfigError::MissingPeer)
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use test_gpui::audio::{AudioSource, FileSource, SharedBuffer, ToneSource};
+    use test_gpui::clock::{Clock, VirtualClock};
+    use test_gpui::drift::{DriftCompensator, DriftEstimator, MAX_CORRECTION_PPM, Resampler};
+    use test_gpui::wav::WavSpec;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    /// Callback sizes of the simulated devices, which rarely match.
+    const MICROPHONE_BLOCK: usize = 512;
+    const SPEAKER_BLOCK: usize = 480;
+    const TARGET: Duration = Duration::from_millis(40);
+
+    fn ramp(frames: usize, channels: usize) -> FileSource {
+        let samples = (0..frames * channels).map(|i| i as f32 / 1000.0).collect();
+        let spec = WavSpec {
+            sample_rate: SAMPLE_RATE,
+            channels: channels as u16,
+        };
+        FileSource::from_samples(spec, samples)
+    }
+
+    #[test]
+    fn test_resampler_passes_through_at_unity() {
+        let mut source = ramp(100, 2);
+        let mut resampler = Resampler::new(2);
+        let mut out = vec![0.0; 2 * 98];
+        assert_eq!(resampler.read(&mut source, &mut out), out.len());
+        let expected: Vec<f32> = (0..2 * 98).map(|i| i as f32 / 1000.0).collect();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_resampler_follows_ratio() {
+        let mut source = ToneSource::new(440.0, 0.5, SAMPLE_RATE, 1);
+        let mut resampler = Resampler::new(1);
+        resampler.set_ratio(1.001);
+        let mut out = vec![0.0; SAMPLE_RATE as usize];
+        assert_eq!(resampler.read(&mut source, &mut out), out.len());
+        // Only what interpolation looks ahead at is read beyond what was played.
+        assert!(resampler.buffered() <= 3);
+        assert!(out.iter().all(|sample| sample.abs() <= 0.51));
+
+        // The tone comes out a thousandth higher: count upward zero crossings.
+        let crossings = out
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        assert!((440..=441).contains(&crossings), "{} crossings", crossings);
+    }
+
+    #[test]
+    fn test_estimator() {
+        let target = 1920;
+        let mut estimator = DriftEstimator::new(SAMPLE_RATE, target);
+        for _ in 0..100 {
+            assert_eq!(estimator.update(target, SPEAKER_BLOCK), 1.0);
+        }
+
+        // A buffer that stays too full is drained faster, but never by more than the limit.
+        for _ in 0..10_000 {
+            estimator.update(target * 10, SPEAKER_BLOCK);
+        }
+        let limit = 1.0 + MAX_CORRECTION_PPM * 1e-6;
+        assert!(estimator.ratio() > 1.0 && estimator.ratio() <= limit);
+        assert!(estimator.drift_ppm() <= MAX_CORRECTION_PPM);
+    }
+
+    /// A device moving `block` frames at a time by a clock of its own, `skew_ppm` fast.
+    struct Device {
+        block: Vec<f32>,
+        period: Duration,
+        next: Duration,
+    }
+
+    impl Device {
+        fn new(block: usize, skew_ppm: f64) -> Self {
+            let rate = SAMPLE_RATE as f64 * (1.0 + skew_ppm * 1e-6);
+            Self {
+                block: vec![0.0; block],
+                period: Duration::from_secs_f64(block as f64 / rate),
+                next: Duration::ZERO,
+            }
+        }
+    }
+
+    /// Runs a microphone and a speaker with skewed clocks for `duration`, and returns the
+    /// compensator with the fill levels the speaker found in the last quarter, and how many reads came
+    /// up short once playing.
+    fn simulate(
+        producer_ppm: f64,
+        consumer_ppm: f64,
+        duration: Duration,
+    ) -> (DriftCompensator, Vec<usize>, usize) {
+        let clock = VirtualClock::new();
+        let start = clock.now();
+        let buffer = SharedBuffer::new(SAMPLE_RATE as usize);
+        let mut compensator = DriftCompensator::new(buffer.clone(), SAMPLE_RATE, 1, TARGET);
+        let mut tone = ToneSource::new(440.0, 0.5, SAMPLE_RATE, 1);
+        let mut producer = Device::new(MICROPHONE_BLOCK, producer_ppm);
+        let mut consumer = Device::new(SPEAKER_BLOCK, consumer_ppm);
+
+        let (mut fills, mut underruns, mut playing) = (Vec::new(), 0, false);
+        while clock.elapsed() < duration {
+            if producer.next <= consumer.next {
+                clock.sleep_until(start + producer.next);
+                tone.read(&mut producer.block);
+                buffer.push(&producer.block);
+                producer.next += producer.period;
+            } else {
+                clock.sleep_until(start + consumer.next);
+                if clock.elapsed() > duration * 3 / 4 {
+                    fills.push(compensator.fill());
+                }
+                let read = compensator.read(&mut consumer.block);
+                playing |= read > 0;
+                if playing && read < SPEAKER_BLOCK {
+                    underruns += 1;
+                }
+                consumer.next += consumer.period;
+            }
+        }
+        (compensator, fills, underruns)
+    }
+
+    fn check_compensated(producer_ppm: f64, consumer_ppm: f64) {
+        let (compensator, fills, underruns) =
+            simulate(producer_ppm, consumer_ppm, Duration::from_secs(120));
+        assert_eq!(underruns, 0);
+
+        // Latency holds at the target, give or take a block either way.
+        let target = (TARGET.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+        let block = MICROPHONE_BLOCK.max(SPEAKER_BLOCK);
+        let (low, high) = (fills.iter().min().unwrap(), fills.iter().max().unwrap());
+        assert!(
+            *low + block >= target && *high <= target + block,
+            "fill ranged over {}..={} frames",
+            low,
+            high
+        );
+
+        let drift = ((1.0 + producer_ppm * 1e-6) / (1.0 + consumer_ppm * 1e-6) - 1.0) * 1e6;
+        let estimated = compensator.estimator().drift_ppm();
+        assert!(
+            (estimated - drift).abs() < 20.0,
+            "estimated {} ppm of {} ppm",
+            estimated,
+            drift
+        );
+    }
+
+    #[test]
+    fn test_fast_microphone() {
+        check_compensated(300.0, -200.0);
+    }
+
+    #[test]
+    fn test_slow_microphone() {
+        check_compensated(-150.0, 0.0);
+    }
+}
@@ -0,0 +1,162 @@
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use test_gpui::dtx::{ComfortNoise, Dtx, NoiseDescription, Transmit, level_dbov};
+
+    const SAMPLE_RATE: u32 = 48_000;
+    /// 20 ms frames.
+    const FRAME: usize = 960;
+
+    /// Low-pass filtered noise at roughly -40 dBov, like the hum of a room.
+    struct Background {
+        state: u32,
+        filtered: f32,
+    }
+
+    impl Background {
+        fn new() -> Self {
+            Self {
+                state: 1,
+                filtered: 0.0,
+            }
+        }
+
+        fn frame(&mut self) -> Vec<f32> {
+            (0..FRAME)
+                .map(|_| {
+                    self.state = self
+                        .state
+                        .wrapping_mul(1_664_525)
+                        .wrapping_add(1_013_904_223);
+                    let white = (self.state >> 8) as f32 / (1 << 23) as f32 - 1.0;
+                    self.filtered = 0.9 * self.filtered + 0.1 * white;
+                    self.filtered * 0.1
+                })
+                .collect()
+        }
+    }
+
+    fn tone(frame: usize) -> Vec<f32> {
+        (0..FRAME)
+            .map(|n| {
+                let t = (frame * FRAME + n) as f32 / SAMPLE_RATE as f32;
+                0.3 * (2.0 * PI * 440.0 * t).sin()
+            })
+            .collect()
+    }
+
+    /// Correlation between neighbouring samples: near 1 for low-pass noise, 0 for white.
+    fn smoothness(samples: &[f32]) -> f32 {
+        let lagged: f32 = samples.windows(2).map(|pair| pair[0] * pair[1]).sum();
+        lagged / samples.iter().map(|sample| sample * sample).sum::<f32>()
+    }
+
+    #[test]
+    fn test_description_round_trip() {
+        let description = NoiseDescription {
+            level: -42.3,
+            reflection: vec![-0.9, 0.0, 0.5],
+        };
+        let payload = description.encode();
+        assert_eq!(payload, [42, 13, 127, 191]);
+
+        let parsed = NoiseDescription::parse(&payload).unwrap();
+        assert_eq!(parsed.level, -42.0);
+        for (parsed, original) in parsed.reflection.iter().zip(&description.reflection) {
+            assert!((parsed - original).abs() <= 0.5 / 127.0);
+        }
+        assert!(NoiseDescription::parse(&[]).is_none());
+    }
+
+    #[test]
+    fn test_comfort_noise_matches_background() {
+        let mut background = Background::new();
+        let heard: Vec<f32> = (0..50).flat_map(|_| background.frame()).collect();
+        let description = NoiseDescription::analyze(&heard, 1);
+
+        let mut comfort = ComfortNoise::new(SAMPLE_RATE, 1);
+        let mut played = vec![1.0; 2 * SAMPLE_RATE as usize];
+        comfort.generate(&mut played[..FRAME]);
+        assert!(played[..FRAME].iter().all(|&sample| sample == 0.0));
+
+        // Sent through a payload, as a sender without native DTX would.
+        comfort.update(NoiseDescription::parse(&description.encode()).unwrap());
+        comfort.generate(&mut played);
+        let (expected, generated) = (level_dbov(&heard), level_dbov(&played));
+        assert!(
+            (expected - generated).abs() < 1.0,
+            "{} dBov for {} dBov",
+            generated,
+            expected
+        );
+        assert!((smoothness(&played) - smoothness(&heard)).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_learns_background_not_speech() {
+        let mut background = Background::new();
+        let mut comfort = ComfortNoise::new(SAMPLE_RATE, 2);
+        let stereo = |mono: Vec<f32>| -> Vec<f32> { mono.iter().flat_map(|&s| [s, s]).collect() };
+        for _ in 0..50 {
+            comfort.learn(&stereo(background.frame()));
+        }
+        let level = comfort.description().unwrap().level;
+        for frame in 0..50 {
+            comfort.learn(&stereo(tone(frame)));
+        }
+        assert!((comfort.description().unwrap().level - level).abs() < 1.0);
+
+        let mut played = vec![0.0; 2 * FRAME * 10];
+        comfort.generate(&mut played);
+        assert!(played.chunks(2).all(|frame| frame[0] == frame[1]));
+        assert!((level_dbov(&played) - level).abs() < 2.0);
+    }
+
+    #[test]
+    fn test_dtx_schedule() {
+        let mut background = Background::new();
+        let mut dtx = Dtx::new(SAMPLE_RATE, 1);
+        let mut decisions = Vec::new();
+        for _ in 0..25 {
+            dtx.process(&background.frame());
+        }
+        for frame in 0..25 {
+            decisions.push(dtx.process(&tone(frame)));
+        }
+        assert!(
+            decisions
+                .iter()
+                .all(|decision| *decision == Transmit::Speech)
+        );
+        assert!(dtx.talking());
+
+        // Quiet again: a 200 ms hangover, then the background every 400 ms.
+        decisions.clear();
+        for _ in 0..100 {
+            decisions.push(dtx.process(&background.frame()));
+        }
+        let noise: Vec<usize> = decisions
+            .iter()
+            .enumerate()
+            .filter(|(_, decision)| matches!(decision, Transmit::Noise(_)))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(noise, [10, 30, 50, 70, 90]);
+        assert!(decisions[..10].iter().all(|d| *d == Transmit::Speech));
+        assert_eq!(
+            decisions
+                .iter()
+                .filter(|d| **d == Transmit::Nothing)
+                .count(),
+            85
+        );
+        let Transmit::Noise(description) = &decisions[90] else {
+            unreachable!()
+        };
+        assert!((description.level - level_dbov(&background.frame())).abs() < 2.0);
+        assert!(!dtx.talking());
+
+        assert_eq!(dtx.process(&tone(0)), Transmit::Speech);
+    }
+}
@@ -0,0 +1,55 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+    use std::io;
+
+    use test_gpui::call::CallError;
+    use test_gpui::config::ConfigError;
+    use test_gpui::error::{Context, Direction, Error, Result};
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Error::NoDevice(Direction::Input).to_string(),
+            "no default input device"
+        );
+        assert_eq!(
+            Error::UnsupportedFormat(cpal::SampleFormat::I64).to_string(),
+            "unsupported sample format 'i64'"
+        );
+        assert_eq!(
+            Error::from(ConfigError::MissingPeer).to_string(),
+            ConfigError::MissingPeer.to_string()
+        );
+    }
+
+    #[test]
+    fn test_context() {
+        let result: Result<()> = Err(io::Error::new(io::ErrorKind::AddrInUse, "in use"))
+            .context("binding 0.0.0.0:5000")
+            .context("starting the server");
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "starting the server: binding 0.0.0.0:5000: in use"
+        );
+        assert!(matches!(err.root(), Error::Io(io) if io.kind() == io::ErrorKind::AddrInUse));
+
+        // The chain of sources leads through every context down to the I/O error.
+        let source = err.source().unwrap();
+        assert_eq!(source.to_string(), "binding 0.0.0.0:5000: in use");
+        assert_eq!(source.source().unwrap().to_string(), "in use");
+
+        let ok: std::result::Result<u8, CallError> = Ok(7);
+        assert_eq!(ok.context("starting the call").unwrap(), 7);
+    }
+
+    #[test]
+    fn test_from() {
+        let err = Error::from(CallError::Io(io::Error::other("closed")));
+        assert!(matches!(err, Error::Call(CallError::Io(_))));
+        assert_eq!(err.to_string(), "socket error: closed");
+        assert!(err.source().is_some());
+        assert!(Error::NoDevice(Direction::Output).source().is_none());
+    }
+}
@@ -0,0 +1,26 @@
+#[cfg(test)]
+mod tests {
+    use test_gpui::jitter::{JitterBuffer, Playout};
+
+    #[test]
+    fn test_reorders_and_conceals() {
+        let mut jitter = JitterBuffer::new(2);
+        jitter.push(65534, vec![1]);
+        assert_eq!(jitter.pop(), Playout::Empty);
+
+        // Out of order and across the sequence number wrap.
+        jitter.push(0, vec![3]);
+        jitter.push(65535, vec![2]);
+        jitter.push(2, vec![5]);
+
+        assert_eq!(jitter.pop(), Playout::Packet(vec![1]));
+        assert_eq!(jitter.pop(), Playout::Packet(vec![2]));
+        assert_eq!(jitter.pop(), Playout::Packet(vec![3]));
+        assert_eq!(jitter.pop(), Playout::Lost);
+
+        // Too late to be played.
+        jitter.push(1, vec![4]);
+        assert_eq!(jitter.pop(), Playout::Packet(vec![5]));
+        assert_eq!(jitter.pop(), Playout::Empty);
+    }
+}
@@ -0,0 +1,113 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use test_gpui::audio::ToneSource;
+    use test_gpui::clock::VirtualClock;
+    use test_gpui::codec::Codec;
+    use test_gpui::latency::{LatencyProbe, marker};
+    use test_gpui::offline::Offline;
+    use test_gpui::pipeline::{Decode, Encode, Transform};
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const BLOCK: usize = 480;
+
+    fn millis(ms: f64) -> Duration {
+        Duration::from_secs_f64(ms / 1000.0)
+    }
+
+    /// Deterministic noise in -amplitude..amplitude.
+    fn noise(state: &mut u32, amplitude: f32) -> f32 {
+        *state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (*state >> 8) as f32 / (1 << 23) as f32 * amplitude - amplitude
+    }
+
+    #[test]
+    fn test_marker() {
+        let marker = marker(SAMPLE_RATE);
+        assert_eq!(marker.len(), 1440);
+        assert!(marker.iter().all(|sample| sample.abs() <= 0.5));
+        // Faded in and out, so it starts and ends without a click.
+        assert!(marker[0].abs() < 1e-6 && marker[1439].abs() < 0.01);
+    }
+
+    #[test]
+    fn test_delay_line() {
+        let probe = LatencyProbe::new(Duration::from_millis(500));
+        let clock = VirtualClock::new();
+        let mut injector = probe.injector(SAMPLE_RATE, clock.clone());
+        let mut sent = probe.detector("sent", SAMPLE_RATE, clock.clone());
+        let mut delayed = probe.detector("delayed", SAMPLE_RATE, clock.clone());
+
+        // 37.5 ms of delay, under noise and a tone.
+        let mut line: VecDeque<f32> = VecDeque::from(vec![0.0; 1800 * 2]);
+        let mut state = 1;
+        for block in 0..300 {
+            let mut samples: Vec<f32> = (0..BLOCK)
+                .flat_map(|n| {
+                    let t = (block * BLOCK + n) as f32 / SAMPLE_RATE as f32;
+                    let sample = 0.2 * (2.0 * std::f32::consts::PI * 1000.0 * t).sin();
+                    [sample, sample]
+                })
+                .collect();
+            injector.process(&mut samples, 2);
+            sent.process(&samples, 2);
+            line.extend(samples.iter().map(|sample| sample + noise(&mut state, 0.1)));
+            let out: Vec<f32> = line.drain(..samples.len()).collect();
+            delayed.process(&out, 2);
+            clock.advance(Duration::from_millis(10));
+        }
+
+        let report = probe.report();
+        let (sent, delayed) = (
+            report.stage("sent").unwrap(),
+            report.stage("delayed").unwrap(),
+        );
+        // Three seconds hold six markers, found in both places.
+        assert_eq!(sent.count, 6);
+        assert_eq!(delayed.count, 6);
+        assert!(sent.max < millis(0.2), "{:?}", sent);
+        assert!(
+            delayed.min > millis(37.3) && delayed.max < millis(37.7),
+            "{:?}",
+            delayed
+        );
+        assert_eq!(report.total(), delayed.mean());
+    }
+
+    #[test]
+    fn test_offline_codec() {
+        let probe = LatencyProbe::default();
+        let clock = VirtualClock::new();
+        let path = probe
+            .injector(SAMPLE_RATE, clock.clone())
+            .chain(Encode::new(Codec::Opus.encoder(SAMPLE_RATE, 1).unwrap(), 20).unwrap())
+            .chain(Decode::new(Codec::Opus.decoder(SAMPLE_RATE, 1).unwrap()))
+            .chain(probe.detector("decode", SAMPLE_RATE, clock.clone()));
+        let (output, _played) = mpsc::channel();
+        let mut offline = Offline::with_clock(
+            ToneSource::new(440.0, 0.1, SAMPLE_RATE, 1),
+            SAMPLE_RATE,
+            1,
+            10,
+            path,
+            output,
+            clock,
+        );
+        offline.run_for(Duration::from_secs(5));
+
+        // Encoding waits for a whole 20 ms frame, which the block after the marker's
+        // completes, and Opus adds up to 6.5 ms of look-ahead.
+        let report = probe.report();
+        let decode = report.stage("decode").unwrap();
+        assert_eq!(decode.count, 5);
+        assert!(
+            decode.min >= millis(10.0) && decode.max <= millis(16.6),
+            "{:?}",
+            decode
+        );
+        assert!(report.to_string().starts_with("decode "));
+    }
+}
@@ -0,0 +1,121 @@
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use clap::Parser;
+    use test_gpui::logging::{self, Event, LogArgs, LogFormat, Stage};
+    use tracing::Level;
+
+    /// Log output collected in memory.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs `f` logging JSON at `level` and returns the lines written.
+    fn json_lines(level: Level, f: impl FnOnce()) -> Vec<String> {
+        let output = Output::default();
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_max_level(level)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(String::from).collect()
+    }
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        log: LogArgs,
+    }
+
+    #[test]
+    fn test_realtime_log() {
+        let (log, drain) = logging::realtime_log(16);
+        let callback = log.clone();
+        callback.record(Event::Captured { samples: 480 });
+        callback.record(Event::Underrun {
+            wanted: 960,
+            available: 100,
+        });
+        log.record(Event::SendFailed(io::Error::other("unreachable")));
+
+        let mut flushed = 0;
+        let lines = json_lines(Level::DEBUG, || flushed = drain.flush());
+        assert_eq!(flushed, 3);
+        assert_eq!(lines.len(), 3, "{:?}", lines);
+        assert!(lines[0].contains(r#""samples":480"#), "{}", lines[0]);
+        assert!(lines[0].contains(r#""name":"capture""#), "{}", lines[0]);
+        assert!(lines[1].contains(r#""level":"WARN""#), "{}", lines[1]);
+        assert!(lines[1].contains(r#""wanted":960"#), "{}", lines[1]);
+        assert!(lines[1].contains(r#""name":"playback""#), "{}", lines[1]);
+        assert!(lines[2].contains("unreachable"), "{}", lines[2]);
+        assert!(lines[2].contains(r#""name":"network""#), "{}", lines[2]);
+
+        // Nothing is left over.
+        assert_eq!(drain.flush(), 0);
+    }
+
+    #[test]
+    fn test_realtime_log_overflow() {
+        let (log, drain) = logging::realtime_log(2);
+        for _ in 0..5 {
+            log.record(Event::Decoded { samples: 960 });
+        }
+        let mut flushed = 0;
+        // Decoded is debug, so only the overflow shows at warn.
+        let lines = json_lines(Level::WARN, || flushed = drain.flush());
+        assert_eq!(flushed, 2);
+        assert_eq!(lines.len(), 1, "{:?}", lines);
+        assert!(lines[0].contains(r#""dropped":3"#), "{}", lines[0]);
+    }
+
+    #[test]
+    fn test_drain_thread() {
+        let (log, drain) = logging::realtime_log(4);
+        let handle = drain.spawn();
+        log.record(Event::Encoded { bytes: 80 });
+        // The thread finishes once nothing can record any more.
+        drop(log);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_stages() {
+        assert_eq!(Event::Captured { samples: 1 }.stage(), Stage::Capture);
+        assert_eq!(Event::Encoded { bytes: 1 }.stage(), Stage::Encode);
+        assert_eq!(
+            Event::SendFailed(io::Error::other("down")).stage(),
+            Stage::Network
+        );
+        assert_eq!(Event::Decoded { samples: 1 }.stage(), Stage::Decode);
+        assert_eq!(Event::Played { samples: 1 }.stage(), Stage::Playback);
+    }
+
+    #[test]
+    fn test_log_args() {
+        let cli = Cli::try_parse_from(["test"]).unwrap();
+        assert_eq!(cli.log.log_level, Level::INFO);
+        assert_eq!(cli.log.log_format, LogFormat::Text);
+
+        let cli =
+            Cli::try_parse_from(["test", "--log-level", "debug", "--log-format", "json"]).unwrap();
+        assert_eq!(cli.log.log_level, Level::DEBUG);
+        assert_eq!(cli.log.log_format, LogFormat::Json);
+
+        assert!(Cli::try_parse_from(["test", "--log-level", "loud"]).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use test_gpui::mixer::{InputState, Limiter, Mixer};
+
+    /// Counts allocations made by threads that asked for it.
+    struct CountingAllocator;
+
+    static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+    thread_local! {
+        static COUNTING: Cell<bool> = const { Cell::new(false) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if COUNTING.with(Cell::get) {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            }
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    /// A typical device callback: 10 ms of stereo.
+    const BLOCK: usize = 480 * 2;
+
+    fn mixer() -> Mixer {
+        Mixer::new(SAMPLE_RATE, 2).with_buffering(Duration::ZERO, Duration::from_secs(1))
+    }
+
+    /// Mixes `blocks` blocks and returns the last one.
+    fn run(mixer: &mut Mixer, blocks: usize) -> Vec<f32> {
+        let mut out = vec![0.0; BLOCK];
+        for _ in 0..blocks {
+            mixer.mix(&mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn test_gain_mute_and_pan() {
+        let mut mixer = mixer();
+        let a = mixer.add_input(1);
+        let b = mixer.add_input(2);
+        mixer.input(a).unwrap().push(&[0.25; 48_000]);
+        mixer.input(b).unwrap().push(&[0.1, 0.2].repeat(48_000));
+
+        // Past the fade-in, centred inputs add up at full level.
+        let out = run(&mut mixer, 2);
+        assert!((out[BLOCK - 2] - 0.35).abs() < 1e-4, "{}", out[BLOCK - 2]);
+        assert!((out[BLOCK - 1] - 0.45).abs() < 1e-4, "{}", out[BLOCK - 1]);
+
+        // Gain and pan changes glide over a few milliseconds instead of jumping.
+        mixer.input(a).unwrap().set_gain(2.0);
+        mixer.input(b).unwrap().set_pan(-1.0);
+        let out = run(&mut mixer, 1);
+        assert!(out[0] < 0.36 && out[1] < 0.46);
+        let out = run(&mut mixer, 10);
+        assert!((out[BLOCK - 2] - 0.6).abs() < 1e-3, "{}", out[BLOCK - 2]);
+        assert!((out[BLOCK - 1] - 0.5).abs() < 1e-3, "{}", out[BLOCK - 1]);
+
+        mixer.input(a).unwrap().set_muted(true);
+        let out = run(&mut mixer, 10);
+        assert!((out[BLOCK - 2] - 0.1).abs() < 1e-3, "{}", out[BLOCK - 2]);
+        assert!(out[BLOCK - 1].abs() < 1e-3, "{}", out[BLOCK - 1]);
+    }
+
+    #[test]
+    fn test_limiter_prevents_clipping() {
+        let mut mixer = mixer();
+        for _ in 0..4 {
+            let input = mixer.add_input(1);
+            let tone: Vec<f32> = (0..48_000).map(|n| 0.8 * (n as f32 * 0.05).sin()).collect();
+            mixer.input(input).unwrap().push(&tone);
+        }
+        let mut out = vec![0.0; BLOCK];
+        let mut peak = 0f32;
+        for _ in 0..50 {
+            mixer.mix(&mut out);
+            peak = out.iter().fold(peak, |peak, sample| peak.max(sample.abs()));
+        }
+        assert!(peak <= 0.9 + 1e-6, "peak {}", peak);
+        assert!(peak > 0.8, "peak {}", peak);
+
+        // Quiet audio passes untouched.
+        let mut limiter = Limiter::new(SAMPLE_RATE);
+        let mut quiet = vec![0.5, -0.5, 0.25, -0.25];
+        limiter.process(&mut quiet, 2);
+        assert_eq!(quiet, vec![0.5, -0.5, 0.25, -0.25]);
+    }
+
+    #[test]
+    fn test_inputs_underrun_independently() {
+        let mut mixer = Mixer::new(SAMPLE_RATE, 2)
+            .with_buffering(Duration::from_millis(20), Duration::from_secs(1));
+        let steady = mixer.add_input(1);
+        let bursty = mixer.add_input(1);
+        mixer.input(steady).unwrap().push(&[0.1; 48_000]);
+
+        // Too little to start: the bursty input waits while the steady one plays.
+        mixer.input(bursty).unwrap().push(&[0.2; 480]);
+        let out = run(&mut mixer, 2);
+        assert!((out[BLOCK - 1] - 0.1).abs() < 1e-4);
+        assert_eq!(mixer.input(bursty).unwrap().state(), InputState::Buffering);
+
+        // Enough to start, but it runs dry half way through the third block, fading out
+        // rather than stopping dead.
+        mixer.input(bursty).unwrap().push(&[0.2; 720]);
+        let out = run(&mut mixer, 2);
+        assert!((out[BLOCK - 1] - 0.3).abs() < 1e-4);
+        let out = run(&mut mixer, 1);
+        assert!((out[240 * 2 - 1] - 0.1).abs() < 0.01);
+        assert!(out[230 * 2] > 0.1 && out[230 * 2] < 0.3);
+        assert!((out[BLOCK - 1] - 0.1).abs() < 1e-4);
+        assert_eq!(mixer.input(bursty).unwrap().state(), InputState::Buffering);
+        assert_eq!(mixer.input(steady).unwrap().state(), InputState::Playing);
+
+        // Once it is done, it plays out what is left without waiting, then goes away.
+        mixer.input(bursty).unwrap().push(&[0.2; 240]);
+        mixer.input(bursty).unwrap().finish();
+        run(&mut mixer, 1);
+        assert!(mixer.input(bursty).is_none());
+        assert_eq!(mixer.inputs().count(), 1);
+        assert!(mixer.remove_input(steady));
+        assert!(run(&mut mixer, 1).iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_buffer_is_bounded() {
+        let mut mixer =
+            Mixer::new(SAMPLE_RATE, 1).with_buffering(Duration::ZERO, Duration::from_millis(100));
+        let input = mixer.add_input(2);
+        let input = mixer.input(input).unwrap();
+        for _ in 0..10 {
+            input.push(&[0.5; 4_800]);
+        }
+        assert_eq!(input.buffered(), 4_800);
+        input.push(&[0.5; 48_001]);
+        assert_eq!(input.buffered(), 4_800);
+    }
+
+    #[test]
+    fn test_mix_does_not_allocate() {
+        let mut mixer = mixer();
+        let inputs: Vec<_> = (0..8).map(|n| mixer.add_input(1 + n % 2)).collect();
+        let mut out = vec![0.0; BLOCK];
+        let frame = vec![0.3; 1_920];
+
+        COUNTING.with(|counting| counting.set(true));
+        for block in 0..200 {
+            for (n, &input) in inputs.iter().enumerate() {
+                // Inputs arrive, stall and come back at different times.
+                if (block + n) % 3 != 0 {
+                    let input = mixer.input(input).unwrap();
+                    input.push(&frame[..960]);
+                    input.set_gain(block as f32 / 100.0);
+                    input.set_pan(n as f32 / 8.0);
+                }
+            }
+            mixer.mix(&mut out);
+        }
+        if let Some(input) = mixer.input(inputs[0]) {
+            input.finish();
+        }
+        mixer.mix(&mut out);
+        COUNTING.with(|counting| counting.set(false));
+
+        assert_eq!(ALLOCATIONS.load(Ordering::Relaxed), 0);
+    }
+}
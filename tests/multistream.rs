@@ -0,0 +1,153 @@
+#[cfg(test)]
+mod tests {
+    use opus::Application;
+    use test_gpui::codec::{AudioDecoder, AudioEncoder, Codec, CodecError};
+    use test_gpui::multistream::{ChannelMapping, MultistreamDecoder, MultistreamEncoder};
+
+    const SAMPLE_RATE: u32 = 16_000;
+    /// Samples per channel in a 20 ms frame.
+    const FRAME: usize = 320;
+
+    /// A frame in which every channel holds a ramp of its own, in WAV order.
+    fn frame(channels: usize) -> Vec<f32> {
+        (0..FRAME * channels)
+            .map(|i| {
+                let (n, channel) = (i / channels, i % channels);
+                0.1 * (channel as f32 - 3.5) + 0.0005 * n as f32
+            })
+            .collect()
+    }
+
+    fn round_trip(mapping: ChannelMapping) {
+        let channels = mapping.channels();
+        let mut encoder =
+            MultistreamEncoder::new(SAMPLE_RATE, mapping.clone(), Application::Audio).unwrap();
+        let mut decoder = MultistreamDecoder::new(SAMPLE_RATE, mapping).unwrap();
+        assert_eq!(encoder.frame_len(20), Some(FRAME * channels));
+
+        let input = frame(channels);
+        let mut packet = vec![0u8; 8000];
+        let size = encoder.encode(&input, &mut packet).unwrap();
+        let mut output = vec![0.0; FRAME * channels];
+        assert_eq!(decoder.decode(&packet[..size], &mut output).unwrap(), FRAME);
+        for (i, (a, b)) in input.iter().zip(&output).enumerate() {
+            assert!(
+                (a - b).abs() < 0.02,
+                "sample {} of {}: {} != {}",
+                i,
+                channels,
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn test_surround_mappings() {
+        let mapping = ChannelMapping::surround(6).unwrap();
+        assert_eq!(
+            (mapping.family, mapping.streams, mapping.coupled),
+            (1, 4, 2)
+        );
+        assert_eq!(mapping.mapping, [0, 4, 1, 2, 3, 5]);
+        assert_eq!(mapping.head(), [1, 4, 2, 0, 4, 1, 2, 3, 5]);
+
+        let mapping = ChannelMapping::surround(8).unwrap();
+        assert_eq!(
+            (mapping.family, mapping.streams, mapping.coupled),
+            (1, 5, 3)
+        );
+        assert_eq!(mapping.mapping, [0, 6, 1, 2, 3, 4, 5, 7]);
+
+        // Mono and stereo need no table.
+        let stereo = ChannelMapping::surround(2).unwrap();
+        assert_eq!((stereo.family, stereo.streams, stereo.coupled), (0, 1, 1));
+        assert_eq!(stereo.head(), [0]);
+        assert_eq!(ChannelMapping::surround(9), None);
+        assert_eq!(ChannelMapping::surround(0), None);
+    }
+
+    #[test]
+    fn test_discrete_mapping() {
+        let mapping = ChannelMapping::discrete(4).unwrap();
+        assert_eq!(
+            (mapping.family, mapping.streams, mapping.coupled),
+            (255, 4, 0)
+        );
+        assert_eq!(mapping.head(), [255, 4, 0, 0, 1, 2, 3]);
+        assert_eq!(ChannelMapping::for_channels(12).unwrap().family, 255);
+        assert_eq!(ChannelMapping::for_channels(4).unwrap().family, 1);
+        assert_eq!(ChannelMapping::discrete(256), None);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_channels() {
+        for channels in [4, 6, 8] {
+            round_trip(ChannelMapping::surround(channels).unwrap());
+        }
+        round_trip(ChannelMapping::discrete(4).unwrap());
+    }
+
+    #[test]
+    fn test_silent_channel() {
+        let mut mapping = ChannelMapping::discrete(3).unwrap();
+        mapping.streams = 2;
+        mapping.mapping[2] = 255;
+        let mut encoder =
+            MultistreamEncoder::new(SAMPLE_RATE, mapping.clone(), Application::Audio).unwrap();
+        let mut decoder = MultistreamDecoder::new(SAMPLE_RATE, mapping).unwrap();
+        let mut packet = vec![0u8; 4000];
+        let size = encoder.encode(&frame(3), &mut packet).unwrap();
+        let mut output = vec![1.0; FRAME * 3];
+        decoder.decode(&packet[..size], &mut output).unwrap();
+        assert!(output.chunks(3).all(|frame| frame[2] == 0.0));
+        assert!(output.chunks(3).all(|frame| frame[0] != 0.0));
+    }
+
+    #[test]
+    fn test_self_delimited_streams() {
+        let mut decoder =
+            MultistreamDecoder::new(SAMPLE_RATE, ChannelMapping::discrete(2).unwrap()).unwrap();
+        let mut output = [0.0; 10];
+        let sample = |byte: u8| byte as i8 as f32 / 127.0;
+
+        // Code 0, then the last stream as it is.
+        let packet = [0xf8, 3, 10, 20, 30, 0xf8, 40, 50, 60];
+        assert_eq!(decoder.decode(&packet, &mut output).unwrap(), 3);
+        assert_eq!(output[..6], [10, 40, 20, 50, 30, 60].map(sample));
+
+        // Code 2, whose second frame length is the added one.
+        let packet = [0xfa, 1, 2, 10, 20, 30, 0xf8, 40, 50, 60, 70];
+        assert_eq!(decoder.decode(&packet, &mut output).unwrap(), 4);
+        assert_eq!(output[..8], [1, 40, 10, 50, 20, 60, 30, 70].map(sample));
+
+        // Code 3 with two frames of the same length.
+        let packet = [0xfb, 0x02, 2, 10, 20, 30, 40, 0xf8, 50, 60, 70, 80, 90];
+        assert_eq!(decoder.decode(&packet, &mut output).unwrap(), 5);
+
+        // A stream cut short.
+        assert!(matches!(
+            decoder.decode(&[0xf8, 200, 1, 2], &mut output),
+            Err(CodecError::InvalidPacket)
+        ));
+    }
+
+    #[test]
+    fn test_concealment() {
+        let mapping = ChannelMapping::surround(6).unwrap();
+        let mut decoder = MultistreamDecoder::new(SAMPLE_RATE, mapping).unwrap();
+        assert!(decoder.supports_plc());
+        let mut output = vec![1.0; FRAME * 6];
+        assert_eq!(decoder.decode(&[], &mut output).unwrap(), FRAME);
+    }
+
+    #[test]
+    fn test_codec_uses_multistream() {
+        assert_eq!(Codec::Opus.format(48_000, 6), (48_000, 6));
+        let encoder = Codec::Opus.encoder(SAMPLE_RATE, 6).unwrap();
+        assert_eq!(encoder.channels(), 6);
+        assert_eq!(encoder.clock_rate(), 48_000);
+        let decoder = Codec::Opus.decoder(SAMPLE_RATE, 8).unwrap();
+        assert_eq!(decoder.channels(), 8);
+    }
+}
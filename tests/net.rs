@@ -0,0 +1,17 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use test_gpui::net::advertised_ip;
+
+    #[test]
+    fn test_advertised_ip() {
+        let peer: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let bound: SocketAddr = "127.0.0.2:5000".parse().unwrap();
+        assert_eq!(advertised_ip(bound, peer).unwrap(), bound.ip());
+
+        // A wildcard bind is never what the peer should send to.
+        let wildcard: SocketAddr = "0.0.0.0:5000".parse().unwrap();
+        assert_eq!(advertised_ip(wildcard, peer).unwrap(), Ipv4Addr::LOCALHOST);
+    }
+}
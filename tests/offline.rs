@@ -0,0 +1,119 @@
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use test_gpui::audio::{FileSource, MemorySink, SharedBuffer, ToneSource};
+    use test_gpui::clock::VirtualClock;
+    use test_gpui::codec::Codec;
+    use test_gpui::offline::Offline;
+    use test_gpui::pipeline::{
+        ConvertChannels, Decode, Encode, Frame, PacedSource, Packet, Pipeline, SinkWriter,
+        Transform,
+    };
+    use test_gpui::wav::WavSpec;
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    /// Signal power at `frequency` using the Goertzel algorithm.
+    fn power_at(samples: &[f32], frequency: f32) -> f32 {
+        let coeff = 2.0 * (2.0 * PI * frequency / SAMPLE_RATE as f32).cos();
+        let (mut s1, mut s2) = (0.0, 0.0);
+        for sample in samples {
+            let s0 = sample + coeff * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        s1 * s1 + s2 * s2 - coeff * s1 * s2
+    }
+
+    /// Loses every `nth` packet.
+    struct Lossy {
+        nth: usize,
+        count: usize,
+    }
+
+    impl Transform for Lossy {
+        type Input = Packet;
+        type Output = Packet;
+
+        fn process(&mut self, packet: Packet, out: &mut Vec<Packet>) {
+            self.count += 1;
+            if !self.count.is_multiple_of(self.nth) {
+                out.push(packet);
+            }
+        }
+    }
+
+    #[test]
+    fn test_minute_long_call() {
+        let output = MemorySink::new();
+        let codec = Encode::new(Codec::Opus.encoder(SAMPLE_RATE, 1).unwrap(), 20)
+            .unwrap()
+            .chain(Lossy { nth: 7, count: 0 })
+            .chain(Decode::new(Codec::Opus.decoder(SAMPLE_RATE, 1).unwrap()));
+        let mut offline = Offline::new(
+            ToneSource::new(440.0, 0.5, SAMPLE_RATE, 1),
+            SAMPLE_RATE,
+            1,
+            10,
+            codec,
+            SinkWriter::new(output.clone()),
+        );
+
+        let started = Instant::now();
+        assert_eq!(offline.run_for(Duration::from_secs(60)), 6000);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(offline.clock().elapsed(), Duration::from_secs(60));
+
+        // Lost packets are concealed, so the output keeps time with the input.
+        let samples = output.samples();
+        assert_eq!(samples.len(), 60 * SAMPLE_RATE as usize);
+        let second = &samples[SAMPLE_RATE as usize..2 * SAMPLE_RATE as usize];
+        assert!(power_at(second, 440.0) > 100.0 * power_at(second, 1000.0));
+    }
+
+    #[test]
+    fn test_run_to_end() {
+        let spec = WavSpec {
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+        };
+        let source = FileSource::from_samples(spec, vec![0.25; 4800 + 100]);
+        let (sink, frames) = mpsc::channel();
+        let mut offline = Offline::new(source, SAMPLE_RATE, 1, 10, ConvertChannels::new(2), sink);
+
+        assert_eq!(offline.run_to_end(), 11);
+        assert_eq!(offline.clock().elapsed(), Duration::from_millis(110));
+        drop(offline);
+        let frames: Vec<Frame> = frames.iter().collect();
+        assert!(frames.iter().all(|frame| frame.samples.len() == 960));
+        // The last block is padded with silence.
+        assert_eq!(frames[10].samples[199..201], [0.25, 0.0]);
+    }
+
+    #[test]
+    fn test_paced_source_on_virtual_clock() {
+        let clock = VirtualClock::new();
+        let (sink, frames) = mpsc::channel();
+        let source = PacedSource::with_clock(
+            SharedBuffer::new(SAMPLE_RATE as usize),
+            SAMPLE_RATE,
+            1,
+            20,
+            clock.clone(),
+        );
+        let running = Pipeline::source("capture", source)
+            .sink("output", sink)
+            .start()
+            .unwrap();
+
+        let started = Instant::now();
+        let frames: Vec<Frame> = frames.iter().take(500).collect();
+        assert_eq!(frames.len(), 500);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(clock.elapsed() >= Duration::from_secs(9));
+        running.stop();
+    }
+}
@@ -0,0 +1,132 @@
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use test_gpui::multistream::ChannelMapping;
+    use test_gpui::ogg::{self, OggOpusWriter};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("test-gpui-{}-{}", std::process::id(), name))
+    }
+
+    /// Flags, granule position and packets of every page, checking each page's CRC.
+    fn pages(bytes: &[u8]) -> Vec<(u8, u64, Vec<Vec<u8>>)> {
+        let mut pages = Vec::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            assert_eq!(&bytes[pos..pos + 4], b"OggS");
+            let flags = bytes[pos + 5];
+            let granule = u64::from_le_bytes(bytes[pos + 6..pos + 14].try_into().unwrap());
+            let segments = bytes[pos + 26] as usize;
+            let lacing = &bytes[pos + 27..pos + 27 + segments];
+            let mut body = pos + 27 + segments;
+            let mut packets = Vec::new();
+            let mut packet = Vec::new();
+            for &len in lacing {
+                packet.extend_from_slice(&bytes[body..body + len as usize]);
+                body += len as usize;
+                if len < 255 {
+                    packets.push(std::mem::take(&mut packet));
+                }
+            }
+
+            let mut page = bytes[pos..body].to_vec();
+            let stored = u32::from_le_bytes(page[22..26].try_into().unwrap());
+            page[22..26].fill(0);
+            assert_eq!(ogg::crc(&page), stored);
+
+            pages.push((flags, granule, packets));
+            pos = body;
+        }
+        pages
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(ogg::crc(b""), 0);
+        assert_eq!(ogg::crc(b"123456789"), 0x89a1_897f);
+    }
+
+    #[test]
+    fn test_packet_samples() {
+        // SILK 20 ms, one frame.
+        assert_eq!(ogg::packet_samples(&[0x08]), Some(960));
+        // CELT 2.5 ms, two frames.
+        assert_eq!(ogg::packet_samples(&[0x81]), Some(240));
+        // Hybrid 10 ms, an arbitrary number of frames given by the second byte.
+        assert_eq!(ogg::packet_samples(&[0x63, 0x03]), Some(1440));
+        assert_eq!(ogg::packet_samples(&[0x63]), None);
+        assert_eq!(ogg::packet_samples(&[]), None);
+
+        let silence = ogg::silence(960 * 2 + 480 + 120 + 50, true);
+        assert_eq!(silence.len(), 4);
+        let total: usize = silence
+            .iter()
+            .map(|packet| ogg::packet_samples(packet).unwrap())
+            .sum();
+        assert_eq!(total, 960 * 2 + 480 + 120);
+        assert!(silence.iter().all(|packet| packet[0] & 0x04 != 0));
+    }
+
+    #[test]
+    fn test_writer() {
+        let path = temp_path("writer.opus");
+        let mut writer = OggOpusWriter::create(&path, 2, 48_000).unwrap();
+        writer.pad_to(960 * 3).unwrap();
+        // Two seconds of 20 ms packets, large enough to need several lacing values each.
+        for _ in 0..100 {
+            writer.write_packet(&[0xfc; 600]).unwrap();
+        }
+        assert_eq!(writer.position(), 960 * 103);
+        writer.finalize().unwrap();
+        drop(writer);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let pages = pages(&bytes);
+
+        let (flags, granule, head) = &pages[0];
+        assert_eq!((*flags, *granule), (0x02, 0));
+        assert_eq!(&head[0][..8], b"OpusHead");
+        assert_eq!(head[0][9], 2);
+        assert_eq!(&pages[1].2[0][..8], b"OpusTags");
+
+        let audio = &pages[2..];
+        assert!(audio.len() >= 3);
+        let packets: usize = audio.iter().map(|(_, _, packets)| packets.len()).sum();
+        assert_eq!(packets, 103);
+        assert!(audio.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        let (flags, granule, _) = audio.last().unwrap();
+        assert_eq!((*flags, *granule), (0x04, 960 * 103));
+    }
+
+    #[test]
+    fn test_surround_head() {
+        let path = temp_path("surround.opus");
+        let mut writer = OggOpusWriter::create(&path, 6, 48_000).unwrap();
+        assert_eq!(writer.mapping(), &ChannelMapping::surround(6).unwrap());
+        writer.pad_to(960).unwrap();
+        writer.finalize().unwrap();
+        drop(writer);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let surround = pages(&bytes);
+
+        let head = &surround[0].2[0];
+        assert_eq!(head[9], 6);
+        // Family 1: four streams, two of them coupled, and the 5.1 mapping table.
+        assert_eq!(head[18..], [1, 4, 2, 0, 4, 1, 2, 3, 5]);
+        // Silence in every stream, self-delimited but for the last, coupled ones stereo.
+        assert_eq!(surround[2].2, [vec![0xfc, 0, 0xfc, 0, 0xf8, 0, 0xf8]]);
+
+        let path = temp_path("discrete.opus");
+        let writer =
+            OggOpusWriter::with_mapping(&path, ChannelMapping::discrete(4).unwrap(), 16_000)
+                .unwrap();
+        drop(writer);
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(pages(&bytes)[0].2[0][18..], [255, 4, 0, 0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,278 @@
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use test_gpui::audio::SharedBuffer;
+    use test_gpui::codec::{AudioDecoder, AudioEncoder, Codec, CodecError};
+    use test_gpui::pipeline::{
+        ConvertChannels, Decode, Encode, Frame, PacedSource, Packet, Pipeline, RtpReceive, RtpSend,
+        Transform,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+    /// 10 ms at 48 kHz, for the pass-through codec.
+    const FRAME: usize = 480;
+
+    fn frame(samples: Vec<f32>, channels: usize) -> Frame {
+        Frame { samples, channels }
+    }
+
+    fn opus_encode() -> Encode {
+        Encode::new(Codec::Opus.encoder(48_000, 1).unwrap(), 20).unwrap()
+    }
+
+    fn opus_decode() -> Decode {
+        Decode::new(Codec::Opus.decoder(48_000, 1).unwrap())
+    }
+
+    /// Sends mono samples as they are, each packet followed by a copy of the frame before it
+    /// to recover that one from.
+    #[derive(Default)]
+    struct PassThrough {
+        previous: Vec<f32>,
+    }
+
+    impl AudioEncoder for PassThrough {
+        fn codec(&self) -> Codec {
+            Codec::L16
+        }
+
+        fn sample_rate(&self) -> u32 {
+            48_000
+        }
+
+        fn channels(&self) -> usize {
+            1
+        }
+
+        fn supports_fec(&self) -> bool {
+            true
+        }
+
+        fn encode(&mut self, pcm: &[f32], out: &mut [u8]) -> Result<usize, CodecError> {
+            let bytes: Vec<u8> = pcm
+                .iter()
+                .chain(&self.previous)
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect();
+            out.get_mut(..bytes.len())
+                .ok_or(CodecError::BufferTooSmall)?
+                .copy_from_slice(&bytes);
+            self.previous = pcm.to_vec();
+            Ok(bytes.len())
+        }
+    }
+
+    impl AudioDecoder for PassThrough {
+        fn codec(&self) -> Codec {
+            Codec::L16
+        }
+
+        fn sample_rate(&self) -> u32 {
+            48_000
+        }
+
+        fn channels(&self) -> usize {
+            1
+        }
+
+        fn supports_fec(&self) -> bool {
+            true
+        }
+
+        fn decode(&mut self, packet: &[u8], out: &mut [f32]) -> Result<usize, CodecError> {
+            if packet.is_empty() {
+                out.fill(0.0);
+                return Ok(out.len());
+            }
+            let samples = packet.len().min(FRAME * 4) / 4;
+            for (sample, bytes) in out.iter_mut().zip(packet.chunks_exact(4).take(samples)) {
+                *sample = f32::from_le_bytes(bytes.try_into().unwrap());
+            }
+            Ok(samples)
+        }
+
+        fn decode_fec(&mut self, packet: &[u8], out: &mut [f32]) -> Result<usize, CodecError> {
+            self.decode(packet.get(FRAME * 4..).unwrap_or_default(), out)
+        }
+    }
+
+    #[test]
+    fn test_pipeline_finishes_with_its_source() {
+        let (input, source) = mpsc::channel();
+        let (sink, output) = mpsc::channel();
+        let pipeline = Pipeline::source("input", source)
+            .map("gain", |frame: Frame| Frame {
+                samples: frame.samples.iter().map(|sample| sample * 0.5).collect(),
+                ..frame
+            })
+            .then("upmix", ConvertChannels::new(2))
+            .sink("output", sink);
+        assert_eq!(
+            pipeline.nodes().collect::<Vec<_>>(),
+            ["input", "gain", "upmix", "output"]
+        );
+
+        let running = pipeline.start().unwrap();
+        input.send(frame(vec![0.5, -1.0], 1)).unwrap();
+        input.send(frame(vec![1.0], 1)).unwrap();
+        drop(input);
+        running.wait();
+
+        let frames: Vec<Frame> = output.iter().collect();
+        assert_eq!(
+            frames,
+            [
+                frame(vec![0.25, 0.25, -0.5, -0.5], 2),
+                frame(vec![0.5, 0.5], 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let (input, source) = mpsc::channel();
+        let (packets, sent) = mpsc::channel();
+        let (sink, output) = mpsc::channel();
+        let running = Pipeline::source("input", source)
+            .then("encode", opus_encode())
+            .tee("sent", packets)
+            .then("decode", opus_decode())
+            .sink("output", sink)
+            .start()
+            .unwrap();
+
+        // Frames of any size are regrouped into 20 ms packets, the rest held back.
+        for size in [700, 700, 700, 700] {
+            input.send(frame(vec![0.25; size], 1)).unwrap();
+        }
+        drop(input);
+        running.wait();
+
+        let packets: Vec<Packet> = sent.iter().collect();
+        assert_eq!(packets.len(), 2);
+        assert_eq!((packets[0].sequence, packets[0].timestamp), (0, 0));
+        assert_eq!((packets[1].sequence, packets[1].timestamp), (1, 960));
+        let frames: Vec<Frame> = output.iter().collect();
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|frame| frame.samples.len() == 960));
+    }
+
+    #[test]
+    fn test_decode_conceals_losses() {
+        let (input, source) = mpsc::channel();
+        let (sink, output) = mpsc::channel();
+        let mut encoder = Pipeline::source("input", source)
+            .then("encode", opus_encode())
+            .sink("output", sink)
+            .start()
+            .unwrap();
+        input.send(frame(vec![0.25; 960 * 4], 1)).unwrap();
+        drop(input);
+        encoder.wait();
+        let mut packets: Vec<Packet> = output.iter().collect();
+        assert_eq!(packets.len(), 4);
+
+        // Lose the second packet and deliver the first again, late.
+        let late = packets[0].clone();
+        packets.remove(1);
+        packets.push(late);
+
+        let (input, source) = mpsc::channel();
+        let (sink, output) = mpsc::channel();
+        encoder = Pipeline::source("input", source)
+            .then("decode", opus_decode())
+            .sink("output", sink)
+            .start()
+            .unwrap();
+        for packet in packets {
+            input.send(packet).unwrap();
+        }
+        drop(input);
+        encoder.wait();
+        let frames: Vec<Frame> = output.iter().collect();
+        assert_eq!(frames.len(), 4);
+        assert!(frames.iter().all(|frame| frame.samples.len() == 960));
+    }
+
+    #[test]
+    fn test_pass_through_codec() {
+        assert!(matches!(
+            Encode::new(Box::new(PassThrough::default()), 0),
+            Err(CodecError::FrameSize(0))
+        ));
+        let mut encode = Encode::new(Box::new(PassThrough::default()), 10).unwrap();
+        let mut packets = Vec::new();
+        for n in 0..6 {
+            encode.process(frame(vec![n as f32 / 10.0; FRAME], 1), &mut packets);
+        }
+        assert_eq!(packets.len(), 6);
+        assert_eq!((packets[5].sequence, packets[5].timestamp), (5, 5 * 480));
+
+        // The first loss is recovered from the packet after it. Of two in a row, only the
+        // second is, and the first played as silence.
+        let mut decode = Decode::new(Box::new(PassThrough::default()));
+        let mut frames = Vec::new();
+        for index in [0, 2, 5] {
+            decode.process(packets[index].clone(), &mut frames);
+        }
+        let played: Vec<f32> = frames.iter().map(|frame| frame.samples[0]).collect();
+        assert_eq!(played, [0.0, 0.1, 0.2, 0.0, 0.4, 0.5]);
+        assert!(frames.iter().all(|frame| frame.samples.len() == FRAME));
+    }
+
+    #[test]
+    fn test_rtp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = receiver.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let (sink, output) = mpsc::channel();
+        let receiving = Pipeline::source("receive", RtpReceive::new(receiver, 111).unwrap())
+            .sink("output", sink)
+            .start()
+            .unwrap();
+        let (input, source) = mpsc::channel();
+        let sending = Pipeline::source("input", source)
+            .sink("send", RtpSend::new(sender, peer, 111, 0x1234))
+            .start()
+            .unwrap();
+
+        let packet = Packet {
+            sequence: 7,
+            timestamp: 960,
+            payload: vec![1, 2, 3],
+        };
+        input.send(packet.clone()).unwrap();
+        assert_eq!(output.recv_timeout(TIMEOUT).unwrap(), packet);
+
+        drop(input);
+        sending.wait();
+        receiving.stop();
+    }
+
+    #[test]
+    fn test_paced_source() {
+        let capture = SharedBuffer::new(48_000);
+        capture.push(&[0.5; 480]);
+        let (sink, output) = mpsc::channel();
+        let started = Instant::now();
+        let running = Pipeline::source("capture", PacedSource::new(capture, 48_000, 1, 10))
+            .sink("output", sink)
+            .start()
+            .unwrap();
+
+        let frames: Vec<Frame> = output.iter().take(5).collect();
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert_eq!(frames[0].samples, vec![0.5; 480]);
+        // Missing audio is filled in with silence.
+        assert_eq!(frames[1].samples, vec![0.0; 480]);
+
+        assert!(running.is_running());
+        let stopping = Instant::now();
+        running.stop();
+        assert!(stopping.elapsed() < TIMEOUT);
+    }
+}
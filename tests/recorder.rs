@@ -0,0 +1,209 @@
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use opus::{Application, Channels, Encoder};
+    use test_gpui::audio::{FileSink, FileSource};
+    use test_gpui::call::{Call, CallConfig};
+    use test_gpui::recorder::{RecordFormat, Recorder, Track};
+    use test_gpui::wav::{self, WavSpec};
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const MONO: WavSpec = WavSpec {
+        sample_rate: SAMPLE_RATE,
+        channels: 1,
+    };
+    const STEREO: WavSpec = WavSpec {
+        sample_rate: SAMPLE_RATE,
+        channels: 2,
+    };
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("test-gpui-{}-{}", std::process::id(), name))
+    }
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Granule position of the last page of an Ogg file.
+    fn last_granule(bytes: &[u8]) -> u64 {
+        let start = (0..bytes.len() - 4)
+            .rev()
+            .find(|&pos| &bytes[pos..pos + 4] == b"OggS")
+            .unwrap();
+        u64::from_le_bytes(bytes[start + 6..start + 14].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_wav_tracks_aligned() {
+        let dir = temp_dir("record-wav");
+        let recorder = Recorder::new(RecordFormat::Wav);
+        let start = Instant::now();
+        recorder.start(&dir, start).unwrap();
+        assert!(recorder.is_recording());
+        assert_eq!(recorder.directory(), Some(dir.clone()));
+
+        // The microphone plays from the start, in blocks with a little jitter.
+        recorder.write(Track::Microphone, &[0.5; 960], MONO, start);
+        recorder.write(Track::Microphone, &[0.5; 960], MONO, start + millis(25));
+        // A participant only speaks up after a second, in stereo.
+        recorder.write(
+            Track::Participant(7),
+            &[0.25; 960],
+            STEREO,
+            start + millis(1000),
+        );
+        // The microphone drops out for half a second.
+        recorder.write(Track::Microphone, &[0.5; 960], MONO, start + millis(540));
+        recorder.stop().unwrap();
+        assert!(!recorder.is_recording());
+
+        let (spec, microphone) = wav::read(dir.join("microphone.wav")).unwrap();
+        let (_, participant) = wav::read(dir.join("participant-7.wav")).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(spec, MONO);
+        // The gap is filled with silence so what follows stays in place.
+        assert_eq!(microphone.len(), 48 * 540 + 960);
+        assert!(microphone[..1920].iter().all(|&sample| sample > 0.4));
+        assert!(
+            microphone[1920..48 * 540]
+                .iter()
+                .all(|&sample| sample == 0.0)
+        );
+
+        assert_eq!(participant.len(), (SAMPLE_RATE as usize + 480) * 2);
+        let speaking = participant.iter().position(|&sample| sample != 0.0);
+        assert_eq!(speaking, Some(SAMPLE_RATE as usize * 2));
+    }
+
+    #[test]
+    fn test_ogg_packets_written_as_received() {
+        let dir = temp_dir("record-ogg");
+        let recorder = Recorder::new(RecordFormat::Ogg);
+        assert!(recorder.wants_packets());
+        let start = Instant::now();
+        recorder.start(&dir, start).unwrap();
+
+        let mut encoder = Encoder::new(SAMPLE_RATE, Channels::Mono, Application::Voip).unwrap();
+        let mut payload = [0u8; 4000];
+        let size = encoder.encode_float(&[0.25; 960], &mut payload).unwrap();
+        let packet = &payload[..size];
+        // The participant starts half a second in and packet 2 is lost.
+        for (sequence, ms) in [(0, 500), (1, 520), (3, 560), (4, 580)] {
+            recorder.write_packet(
+                Track::Participant(3),
+                sequence,
+                packet,
+                MONO,
+                start + millis(ms),
+            );
+        }
+        // The mix only exists as PCM, so it is encoded.
+        for block in 0..50 {
+            recorder.write(Track::Mix, &[0.25; 960], MONO, start + millis(block * 20));
+        }
+        recorder.stop().unwrap();
+
+        let participant = std::fs::read(dir.join("participant-3.opus")).unwrap();
+        let mix = std::fs::read(dir.join("mix.opus")).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(last_granule(&participant), 48 * 500 + 960 * 5);
+        assert_eq!(last_granule(&mix), SAMPLE_RATE as u64);
+        // Sent packets are stored unchanged.
+        assert!(
+            participant
+                .windows(packet.len())
+                .any(|window| window == packet)
+        );
+    }
+
+    #[test]
+    fn test_ogg_surround_track() {
+        let dir = temp_dir("record-surround");
+        let recorder = Recorder::new(RecordFormat::Ogg);
+        let start = Instant::now();
+        recorder.start(&dir, start).unwrap();
+
+        let spec = WavSpec {
+            sample_rate: 16_000,
+            channels: 6,
+        };
+        for block in 0..10 {
+            recorder.write(
+                Track::Mix,
+                &[0.25; 320 * 6],
+                spec,
+                start + millis(block * 20),
+            );
+        }
+        recorder.stop().unwrap();
+
+        let mix = std::fs::read(dir.join("mix.opus")).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        // Every channel is kept, with the 5.1 channel mapping table in the OpusHead.
+        let head = mix
+            .windows(8)
+            .position(|window| window == b"OpusHead")
+            .unwrap();
+        assert_eq!(mix[head + 9], 6);
+        assert_eq!(mix[head + 18..head + 27], [1, 4, 2, 0, 4, 1, 2, 3, 5]);
+        assert_eq!(last_granule(&mix), 960 * 10);
+    }
+
+    #[test]
+    fn test_stopped_recorder_ignores_writes() {
+        let dir = temp_dir("record-stopped");
+        let recorder = Recorder::new(RecordFormat::Wav);
+        recorder.write(Track::Mix, &[0.5; 960], MONO, Instant::now());
+        recorder.stop().unwrap();
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_call_recording() {
+        let dir = temp_dir("record-call");
+        let spec = MONO;
+        let tone = move || {
+            let samples = (0..SAMPLE_RATE as usize * 2)
+                .map(|n| 0.5 * (n as f32 * 0.05).sin())
+                .collect();
+            Box::new(FileSource::from_samples(spec, samples))
+        };
+        let discard = |name: &str| Box::new(FileSink::create(temp_dir(name), spec).unwrap());
+
+        let socket_a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket_b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (addr_a, addr_b) = (
+            socket_a.local_addr().unwrap(),
+            socket_b.local_addr().unwrap(),
+        );
+        let config = CallConfig::default();
+        let call_a = Call::start(socket_a, addr_b, config, tone(), discard("heard-a.wav")).unwrap();
+        let call_b = Call::start(socket_b, addr_a, config, tone(), discard("heard-b.wav")).unwrap();
+
+        let recorder = Recorder::new(RecordFormat::Wav);
+        recorder.start(&dir, Instant::now()).unwrap();
+        call_a.record(Some(recorder.clone()));
+        thread::sleep(millis(500));
+        recorder.stop().unwrap();
+        let remote = call_b.ssrc();
+        call_a.hangup();
+        call_b.hangup();
+
+        let (_, microphone) = wav::read(dir.join("microphone.wav")).unwrap();
+        let (_, participant) = wav::read(dir.join(format!("participant-{}.wav", remote))).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        for name in ["heard-a.wav", "heard-b.wav"] {
+            let _ = std::fs::remove_file(temp_dir(name));
+        }
+        for track in [microphone, participant] {
+            assert!(track.len() > SAMPLE_RATE as usize / 4);
+            assert!(track.iter().any(|&sample| sample.abs() > 0.1));
+        }
+    }
+}
@@ -0,0 +1,132 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use test_gpui::mixer::Mixer;
+    use test_gpui::roster::{Quality, Roster, RosterEvent, SpeechDetector};
+    use test_gpui::stats::CallStats;
+
+    fn record(roster: &Roster) -> Arc<Mutex<Vec<RosterEvent>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        roster.subscribe(move |event| {
+            sink.lock().unwrap().push(event.clone());
+            true
+        });
+        events
+    }
+
+    #[test]
+    fn test_quality_from_stats() {
+        let mut stats = CallStats::default();
+        assert_eq!(Quality::from_stats(&stats), Quality::Unknown);
+
+        stats.packets_received = 500;
+        stats.jitter = Duration::from_millis(5);
+        stats.round_trip_time = Some(Duration::from_millis(40));
+        assert_eq!(Quality::from_stats(&stats), Quality::Excellent);
+        stats.fraction_lost = 0.05;
+        assert_eq!(Quality::from_stats(&stats), Quality::Good);
+        stats.fraction_lost = 0.1;
+        assert_eq!(Quality::from_stats(&stats), Quality::Poor);
+        stats.fraction_lost = 0.2;
+        assert_eq!(Quality::from_stats(&stats), Quality::Bad);
+
+        // Long delays hurt a conversation even without loss.
+        stats.fraction_lost = 0.0;
+        stats.round_trip_time = Some(Duration::from_millis(900));
+        assert_eq!(Quality::from_stats(&stats), Quality::Poor);
+    }
+
+    #[test]
+    fn test_speech_detector() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut detector = SpeechDetector::new();
+        assert!(!detector.update(0.015, at(0)));
+        assert!(detector.update(0.1, at(20)));
+        // Quieter, but not quiet enough to stop.
+        assert!(detector.update(0.015, at(40)));
+        // Pauses shorter than the hang time are bridged.
+        assert!(detector.update(0.0, at(200)));
+        assert!(detector.update(0.0, at(300)));
+        assert!(!detector.update(0.0, at(400)));
+        assert!(!detector.is_speaking());
+    }
+
+    #[test]
+    fn test_publishes_changes_only() {
+        let roster = Roster::new();
+        roster.join(1, "Ada");
+        let events = record(&roster);
+        roster.join(2, "Grace");
+
+        let now = Instant::now();
+        roster.update_level(2, 0.2, now);
+        roster.update_level(2, 0.2, now + Duration::from_millis(20));
+        roster.set_volume(2, 0.5);
+        roster.set_volume(2, 0.5);
+        roster.set_muted(1, true);
+        roster.update_stats(3, &CallStats::default());
+        roster.leave(1);
+        roster.leave(1);
+
+        let events = events.lock().unwrap();
+        let summary: Vec<String> = events
+            .iter()
+            .map(|event| match event {
+                RosterEvent::Joined(participant) => format!("joined {}", participant.name),
+                RosterEvent::Changed(participant) => format!(
+                    "{} speaking={} muted={} volume={}",
+                    participant.name, participant.speaking, participant.muted, participant.volume
+                ),
+                RosterEvent::Left(id) => format!("left {}", id),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "joined Ada",
+                "joined Grace",
+                "Grace speaking=true muted=false volume=1",
+                "Grace speaking=true muted=false volume=0.5",
+                "Ada speaking=false muted=true volume=1",
+                "left 1",
+            ]
+        );
+        assert_eq!(roster.participants().len(), 1);
+    }
+
+    #[test]
+    fn test_listeners_unsubscribe() {
+        let roster = Roster::new();
+        let calls = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&calls);
+        roster.subscribe(move |_| {
+            *counter.lock().unwrap() += 1;
+            false
+        });
+        roster.join(1, "Ada");
+        roster.join(2, "Grace");
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_mixer_input_level() {
+        let mut mixer =
+            Mixer::new(48_000, 2).with_buffering(Duration::ZERO, Duration::from_secs(1));
+        let input = mixer.add_input(1);
+        mixer.input(input).unwrap().push(&[0.5; 720]);
+        mixer.input(input).unwrap().set_muted(true);
+
+        // The level is what the source sends, whatever we do with it locally.
+        let mut out = vec![0.0; 960];
+        mixer.mix(&mut out);
+        assert!((mixer.input(input).unwrap().level() - 0.5).abs() < 1e-6);
+        mixer.mix(&mut out);
+        assert!((mixer.input(input).unwrap().level() - 0.125f32.sqrt()).abs() < 1e-6);
+        mixer.mix(&mut out);
+        assert_eq!(mixer.input(input).unwrap().level(), 0.0);
+    }
+}
@@ -0,0 +1,158 @@
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use test_gpui::rtcp::{ReportBlock, RtcpPacket, RtcpSession};
+    use test_gpui::rtp::{OPUS_PAYLOAD_TYPE, RtpHeader};
+
+    const FRAME: Duration = Duration::from_millis(20);
+
+    fn header(ssrc: u32, sequence: u16) -> RtpHeader {
+        RtpHeader {
+            marker: false,
+            payload_type: OPUS_PAYLOAD_TYPE,
+            sequence,
+            timestamp: sequence as u32 * 960,
+            ssrc,
+        }
+    }
+
+    #[test]
+    fn test_compound_round_trip() {
+        let block = ReportBlock {
+            ssrc: 0xCAFE,
+            fraction_lost: 64,
+            cumulative_lost: -3,
+            highest_sequence: 0x1_0005,
+            jitter: 120,
+            last_sr: 0x1234_5678,
+            delay_since_last_sr: 0x8000,
+        };
+        let packets = vec![
+            RtcpPacket::SenderReport {
+                ssrc: 1,
+                ntp_timestamp: 0xE8F0_0000_8000_0000,
+                rtp_timestamp: 48_000,
+                packet_count: 50,
+                octet_count: 4_000,
+                reports: vec![block],
+            },
+            RtcpPacket::SourceDescription {
+                chunks: vec![(1, "alice@example.com".into()), (2, "bob".into())],
+            },
+            RtcpPacket::Bye {
+                sources: vec![1],
+                reason: Some("hung up".into()),
+            },
+        ];
+        let bytes = RtcpPacket::encode(&packets);
+        assert_eq!(bytes.len() % 4, 0);
+        assert!(RtcpPacket::is_rtcp(&bytes));
+        assert_eq!(RtcpPacket::decode(&bytes), Some(packets));
+
+        let receiver_report = RtcpPacket::ReceiverReport {
+            ssrc: 2,
+            reports: vec![block, ReportBlock::default()],
+        };
+        let bytes = RtcpPacket::encode(std::slice::from_ref(&receiver_report));
+        assert_eq!(RtcpPacket::decode(&bytes), Some(vec![receiver_report]));
+    }
+
+    #[test]
+    fn test_decode_foreign_packets() {
+        // Receiver report with one block followed by a transport feedback packet (type 205),
+        // which is skipped.
+        let bytes = [
+            0x81, 201, 0, 7, 0, 0, 0, 2, // RR from SSRC 2
+            0, 0, 0, 1, 0x10, 0, 0, 4, 0, 0, 0, 99, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, //
+            0x81, 205, 0, 2, 0, 0, 0, 2, 0, 0, 0, 1,
+        ];
+        assert_eq!(
+            RtcpPacket::decode(&bytes),
+            Some(vec![RtcpPacket::ReceiverReport {
+                ssrc: 2,
+                reports: vec![ReportBlock {
+                    ssrc: 1,
+                    fraction_lost: 16,
+                    cumulative_lost: 4,
+                    highest_sequence: 99,
+                    jitter: 7,
+                    last_sr: 0,
+                    delay_since_last_sr: 0,
+                }],
+            }])
+        );
+
+        // A length running past the end of the datagram is rejected.
+        assert_eq!(RtcpPacket::decode(&bytes[..20]), None);
+        // RTP and signaling traffic is not mistaken for RTCP.
+        let mut rtp = [0u8; 12];
+        header(1, 1).write(&mut rtp);
+        assert!(!RtcpPacket::is_rtcp(&rtp));
+        assert!(!RtcpPacket::is_rtcp(b"S\x01\x04\0\0\0\x01"));
+    }
+
+    /// Streams from `alice` to `bob` for one second, with a fixed one-way delay and every
+    /// tenth packet lost, then exchanges reports.
+    #[test]
+    fn test_reports_between_peers() {
+        let start = Instant::now();
+        let delay = Duration::from_millis(30);
+        let mut alice = RtcpSession::new(1, 48_000, start);
+        let mut bob = RtcpSession::new(2, 48_000, start);
+
+        for sequence in 0..50u16 {
+            let sent = start + FRAME * sequence as u32;
+            alice.sent(sequence as u32 * 960, 80, sent);
+            if sequence % 10 != 5 {
+                bob.received(&header(1, sequence), sent + delay);
+            }
+        }
+
+        // Alice reports first, so her sender report reaches Bob after the one-way delay.
+        let sr_sent = start + Duration::from_secs(1);
+        let report = alice.poll(sr_sent).expect("first report is due");
+        assert!(matches!(
+            RtcpPacket::decode(&report).unwrap()[0],
+            RtcpPacket::SenderReport {
+                packet_count: 50,
+                octet_count: 4_000,
+                ..
+            }
+        ));
+        bob.receive(&report, sr_sent + delay);
+
+        // Bob holds on to it for half a second before answering.
+        let rr_sent = sr_sent + delay + Duration::from_millis(500);
+        let report = bob.poll(rr_sent).expect("first report is due");
+        assert!(matches!(
+            RtcpPacket::decode(&report).unwrap()[0],
+            RtcpPacket::ReceiverReport { .. }
+        ));
+        alice.receive(&report, rr_sent + delay);
+
+        let stats = bob.stats();
+        assert_eq!(stats.packets_received, 45);
+        assert_eq!(stats.packets_lost, 5);
+        assert!((stats.fraction_lost - 0.1).abs() < 0.01);
+        assert_eq!(stats.jitter, Duration::ZERO);
+
+        let stats = alice.stats();
+        assert_eq!(stats.packets_sent, 50);
+        assert_eq!(stats.remote_packets_lost, 5);
+        assert!((stats.remote_fraction_lost - 0.1).abs() < 0.01);
+        let rtt = stats.round_trip_time.expect("round trip measured");
+        assert!(
+            rtt.abs_diff(delay * 2) < Duration::from_millis(1),
+            "rtt {:?}",
+            rtt
+        );
+        assert!(stats.to_string().contains("rtt 60"));
+
+        // Reports are rate limited, and a BYE ends the stream.
+        assert_eq!(alice.poll(rr_sent + delay), None);
+        assert!(!bob.remote_left());
+        bob.receive(&alice.bye(rr_sent), rr_sent + delay);
+        assert!(bob.remote_left());
+    }
+}
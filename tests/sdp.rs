@@ -0,0 +1,276 @@
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use test_gpui::codec::{AudioEncoder, Codec, CodecError, CodecSet};
+    use test_gpui::sdp::{OpusConfig, SdpError, SessionDescription};
+    use test_gpui::signaling::CodecParams;
+
+    /// Offer produced by Chrome for an audio-only peer connection.
+    const CHROME_OFFER: &str = "v=0\r
+o=- 4611731400430051336 2 IN IP4 127.0.0.1\r
+s=-\r
+t=0 0\r
+a=group:BUNDLE 0\r
+a=extmap-allow-mixed\r
+a=msid-semantic: WMS\r
+m=audio 9 UDP/TLS/RTP/SAVPF 111 63 9 0 8 13 110 126\r
+c=IN IP4 0.0.0.0\r
+a=rtcp:9 IN IP4 0.0.0.0\r
+a=ice-ufrag:Vd+u\r
+a=ice-pwd:4ymbWA1F8JvQZBPb6hDB3t0n\r
+a=ice-options:trickle\r
+a=fingerprint:sha-256 6B:8B:F0:65:5F:78:E2:51:3B:AC:6F:F3:3F:46:1B:35:DC:B8:5F:64:1A:24:C2:43:F0:A1:58:D0:A1:2C:19:08\r
+a=setup:actpass\r
+a=mid:0\r
+a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r
+a=sendrecv\r
+a=msid:- 7a9b3c2e-5d8f-4b3a-9f1e-2c4d6e8f0a1b\r
+a=rtcp-mux\r
+a=rtpmap:111 opus/48000/2\r
+a=rtcp-fb:111 transport-cc\r
+a=fmtp:111 minptime=10;useinbandfec=1\r
+a=rtpmap:63 red/48000/2\r
+a=fmtp:63 111/111\r
+a=rtpmap:9 G722/8000\r
+a=rtpmap:0 PCMU/8000\r
+a=rtpmap:8 PCMA/8000\r
+a=rtpmap:13 CN/8000\r
+a=rtpmap:110 telephone-event/48000\r
+a=rtpmap:126 telephone-event/8000\r
+a=ssrc:3735928559 cname:4TOk42mSjXCkVIa6\r
+";
+
+    /// Offer produced by Firefox, which uses payload type 109 and asks for stereo.
+    const FIREFOX_OFFER: &str = "v=0\r
+o=mozilla...THIS_IS_SDPARTA-99.0 6592838462935386390 0 IN IP4 0.0.0.0\r
+s=-\r
+t=0 0\r
+a=fingerprint:sha-256 9F:1C:2A:0B:7E:56:44:1D:62:9A:8B:FA:31:57:C7:A6:8E:AB:3F:21:9D:E2:0B:5C:76:48:A3:7E:10:C6:2B:92\r
+a=group:BUNDLE 0\r
+a=ice-options:trickle\r
+a=msid-semantic:WMS *\r
+m=audio 9 UDP/TLS/RTP/SAVPF 109 9 0 8 101\r
+c=IN IP4 0.0.0.0\r
+a=sendrecv\r
+a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r
+a=fmtp:109 maxplaybackrate=48000;stereo=1;useinbandfec=1\r
+a=fmtp:101 0-15\r
+a=ice-pwd:b3a3ac2b3e7a7e2ef6a8b6b2c5b74c2e\r
+a=ice-ufrag:3b8b5d1e\r
+a=mid:0\r
+a=rtcp-mux\r
+a=rtpmap:109 opus/48000/2\r
+a=rtpmap:9 G722/8000/1\r
+a=rtpmap:0 PCMU/8000\r
+a=rtpmap:8 PCMA/8000\r
+a=rtpmap:101 telephone-event/8000/1\r
+a=setup:actpass\r
+a=ssrc:2419016427 cname:{a2b3c4d5-e6f7-4a8b-9c0d-1e2f3a4b5c6d}\r
+";
+
+    /// Offer from an Asterisk PBX with wideband Opus, a bitrate cap and 40 ms packets.
+    const ASTERISK_OFFER: &str = "v=0
+o=- 1700000000 1700000001 IN IP4 192.168.10.5
+s=Asterisk
+c=IN IP4 192.168.10.5
+t=0 0
+m=audio 16384 RTP/AVP 107 0 101
+a=rtpmap:107 opus/48000/2
+a=fmtp:107 maxplaybackrate=16000;sprop-maxcapturerate=16000;maxaveragebitrate=20000;useinbandfec=1;usedtx=1
+a=rtpmap:0 PCMU/8000
+a=rtpmap:101 telephone-event/8000
+a=fmtp:101 0-16
+a=ptime:40
+a=maxptime:60
+a=sendrecv
+";
+
+    const PCMU_ONLY: &str = "v=0
+o=- 1 1 IN IP4 10.0.0.1
+s=-
+c=IN IP4 10.0.0.1
+t=0 0
+m=audio 4000 RTP/AVP 0
+a=rtpmap:0 PCMU/8000
+";
+
+    #[test]
+    fn test_chrome_offer() {
+        let sdp = SessionDescription::parse(CHROME_OFFER).unwrap();
+        assert_eq!(sdp.origin.session_id, 4611731400430051336);
+        assert_eq!(sdp.media.len(), 1);
+        let audio = &sdp.media[0];
+        assert_eq!(audio.protocol, "UDP/TLS/RTP/SAVPF");
+        assert_eq!(audio.formats[0], "111");
+        assert!(audio.has_attribute("rtcp-mux"));
+
+        let opus = OpusConfig::from_sdp(&sdp).unwrap();
+        assert_eq!(
+            opus,
+            OpusConfig {
+                payload_type: 111,
+                sample_rate: 48_000,
+                stereo: false,
+                ptime: 20,
+                max_average_bitrate: None,
+                fec: true,
+                dtx: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_firefox_offer() {
+        let sdp = SessionDescription::parse(FIREFOX_OFFER).unwrap();
+        let opus = OpusConfig::from_sdp(&sdp).unwrap();
+        assert_eq!(opus.payload_type, 109);
+        assert!(opus.stereo);
+        assert!(opus.fec);
+        assert_eq!(opus.to_params().unwrap().channels, 2);
+    }
+
+    #[test]
+    fn test_asterisk_offer_and_answer() {
+        let sdp = SessionDescription::parse(ASTERISK_OFFER).unwrap();
+        assert_eq!(sdp.connection, Some("192.168.10.5".parse().unwrap()));
+        let opus = OpusConfig::from_sdp(&sdp).unwrap();
+        assert_eq!(opus.payload_type, 107);
+        assert_eq!(opus.sample_rate, 16_000);
+        assert_eq!(opus.ptime, 40);
+        assert_eq!(opus.max_average_bitrate, Some(20_000));
+        assert!(opus.fec && opus.dtx);
+
+        let params = opus.to_params().unwrap();
+        assert_eq!(
+            params,
+            CodecParams {
+                sample_rate: 16_000,
+                channels: 1,
+                frame_ms: 40,
+                bitrate: 20_000,
+                fec: true,
+                codecs: CodecSet::only(Codec::Opus),
+            }
+        );
+
+        let local = OpusConfig {
+            fec: true,
+            max_average_bitrate: Some(32_000),
+            ..OpusConfig::default()
+        };
+        let address: IpAddr = "192.168.10.20".parse().unwrap();
+        let (answer, agreed) = sdp.answer(address, 5004, &local).unwrap();
+        assert_eq!(agreed.payload_type, 107);
+        assert_eq!(agreed.sample_rate, 16_000);
+        assert_eq!(agreed.max_average_bitrate, Some(20_000));
+        assert!(agreed.fec && !agreed.dtx);
+
+        // The answer parses back to the agreed configuration.
+        let text = answer.to_string();
+        assert!(text.contains("m=audio 5004 RTP/AVP 107\r\n"));
+        assert!(text.contains("a=rtpmap:107 opus/48000/2\r\n"));
+        assert!(text.contains("a=ptime:40\r\n"));
+        let parsed = SessionDescription::parse(&text).unwrap();
+        assert_eq!(parsed, answer);
+        assert_eq!(OpusConfig::from_sdp(&parsed).unwrap(), agreed);
+    }
+
+    #[test]
+    fn test_offer_round_trip() {
+        let opus = OpusConfig {
+            stereo: true,
+            max_average_bitrate: Some(64_000),
+            fec: true,
+            dtx: true,
+            ..OpusConfig::default()
+        };
+        let offer = SessionDescription::offer("::1".parse().unwrap(), 5000, 42, &opus);
+        let text = offer.to_string();
+        assert!(text.starts_with("v=0\r\no=- 42 1 IN IP6 ::1\r\n"));
+        assert!(text.contains(
+            "a=fmtp:111 maxplaybackrate=48000;stereo=1;sprop-stereo=1;useinbandfec=1;usedtx=1;maxaveragebitrate=64000\r\n"
+        ));
+        let parsed = SessionDescription::parse(&text).unwrap();
+        assert_eq!(parsed, offer);
+        assert_eq!(OpusConfig::from_sdp(&parsed).unwrap(), opus);
+
+        let params = CodecParams {
+            codecs: CodecSet::only(Codec::Opus),
+            ..CodecParams::default()
+        };
+        let opus = OpusConfig::from_params(&params).unwrap();
+        assert_eq!(opus.to_params(), Ok(params));
+
+        // Neither side can hold what the other cannot describe.
+        let odd = OpusConfig { ptime: 276, ..opus };
+        assert_eq!(odd.to_params(), Err(SdpError::UnsupportedPtime(276)));
+        let surround = CodecParams {
+            channels: 6,
+            ..params
+        };
+        assert_eq!(
+            OpusConfig::from_params(&surround),
+            Err(SdpError::TooManyChannels(6))
+        );
+    }
+
+    /// Remembers the bitrate it was last asked for.
+    #[derive(Default)]
+    struct Bitrate(Option<u32>);
+
+    impl AudioEncoder for Bitrate {
+        fn codec(&self) -> Codec {
+            Codec::Opus
+        }
+
+        fn sample_rate(&self) -> u32 {
+            48_000
+        }
+
+        fn channels(&self) -> usize {
+            1
+        }
+
+        fn encode(&mut self, _pcm: &[f32], _out: &mut [u8]) -> Result<usize, CodecError> {
+            Ok(0)
+        }
+
+        fn set_bitrate(&mut self, bitrate: u32) -> Result<(), CodecError> {
+            self.0 = Some(bitrate);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_apply_clamps_bitrate() {
+        for (offered, applied) in [(1_000, 6_000), (64_000, 64_000), (u32::MAX, 510_000)] {
+            let opus = OpusConfig {
+                max_average_bitrate: Some(offered),
+                ..OpusConfig::default()
+            };
+            let mut encoder = Bitrate::default();
+            opus.apply(&mut encoder).unwrap();
+            assert_eq!(encoder.0, Some(applied));
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let sdp = SessionDescription::parse(PCMU_ONLY).unwrap();
+        assert_eq!(OpusConfig::from_sdp(&sdp), Err(SdpError::NoOpus));
+
+        assert_eq!(
+            SessionDescription::parse("v=0\ns=-\n"),
+            Err(SdpError::Missing("o="))
+        );
+        assert!(matches!(
+            SessionDescription::parse("v=0\nnot sdp\n"),
+            Err(SdpError::InvalidLine(2, _))
+        ));
+        assert!(matches!(
+            SessionDescription::parse("v=0\no=- x 1 IN IP4 1.2.3.4\ns=-\n"),
+            Err(SdpError::InvalidLine(2, _))
+        ));
+    }
+}
@@ -0,0 +1,342 @@
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use test_gpui::codec::{Codec, CodecSet};
+    use test_gpui::signaling::{
+        Action, CodecParams, EndReason, Media, Message, RejectReason, Session, State,
+    };
+
+    const CALL_ID: u32 = 0xC0FFEE;
+
+    fn sent(actions: &[Action]) -> Vec<Message> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Send(message) => Some(message.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Runs a caller and callee against each other until the call is set up.
+    fn connect(caller_params: CodecParams, callee_params: CodecParams) -> (Session, Session) {
+        let now = Instant::now();
+        let mut caller = Session::new(caller_params, 1, now);
+        let mut callee = Session::new(callee_params, 2, now);
+
+        let invite = sent(&caller.invite(CALL_ID, now)).remove(0);
+        let actions = callee.receive(invite, now);
+        assert!(matches!(actions[..], [Action::Incoming(_)]));
+        assert!(matches!(callee.state(), State::Ringing { .. }));
+
+        let actions = callee.accept(now);
+        let accept = sent(&actions).remove(0);
+        assert!(matches!(actions.last(), Some(Action::Connected(_))));
+
+        let actions = caller.receive(accept, now);
+        assert!(matches!(actions[..], [Action::Connected(_)]));
+        (caller, callee)
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let messages = [
+            Message::Invite {
+                call_id: CALL_ID,
+                ssrc: 0xDEADBEEF,
+                params: CodecParams::default(),
+                public_key: Some([7; 32]),
+            },
+            Message::Accept {
+                call_id: CALL_ID,
+                ssrc: 7,
+                params: CodecParams {
+                    fec: false,
+                    ..CodecParams::default()
+                },
+                public_key: None,
+            },
+            Message::Reject {
+                call_id: CALL_ID,
+                reason: RejectReason::Busy,
+            },
+            Message::Bye { call_id: CALL_ID },
+            Message::Keepalive { call_id: CALL_ID },
+        ];
+        for message in messages {
+            let bytes = message.encode();
+            assert!(Message::is_signaling(&bytes));
+            assert_eq!(Message::decode(&bytes), Some(message));
+        }
+
+        // RTP packets always start with version 2.
+        assert!(!Message::is_signaling(&[0x80, 111, 0, 1]));
+        assert_eq!(Message::decode(b"S\x01\x09\0\0\0\x01"), None);
+        assert_eq!(Message::decode(b"S\x01\x01\0\0"), None);
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let local = CodecParams {
+            sample_rate: 16_000,
+            channels: 1,
+            frame_ms: 20,
+            bitrate: 24_000,
+            fec: false,
+            codecs: CodecSet::all(),
+        };
+        let offer = CodecParams {
+            sample_rate: 48_000,
+            channels: 2,
+            frame_ms: 40,
+            bitrate: 64_000,
+            fec: true,
+            codecs: [Codec::Opus, Codec::Pcma].into_iter().collect(),
+        };
+        let answer = local.negotiate(&offer).unwrap();
+        assert_eq!(
+            answer,
+            CodecParams {
+                sample_rate: 16_000,
+                channels: 1,
+                frame_ms: 40,
+                bitrate: 24_000,
+                fec: false,
+                codecs: CodecSet::only(Codec::Opus),
+            }
+        );
+        assert!(offer.allows(&answer));
+        assert!(!answer.allows(&offer));
+
+        let odd = CodecParams {
+            sample_rate: 44_100,
+            ..offer
+        };
+        assert_eq!(local.negotiate(&odd), Err(RejectReason::Incompatible));
+
+        // Without Opus in common, the next codec both sides know wins, at its own rate.
+        let legacy = CodecParams {
+            codecs: [Codec::Pcma, Codec::L16].into_iter().collect(),
+            ..offer
+        };
+        let answer = local.negotiate(&legacy).unwrap();
+        assert_eq!(answer.codec(), Some(Codec::Pcma));
+        assert_eq!((answer.sample_rate, answer.channels), (8_000, 1));
+        assert!(legacy.allows(&answer));
+        assert!(!offer.allows(&CodecParams {
+            codecs: CodecSet::only(Codec::L16),
+            ..answer
+        }));
+
+        let opus_only = CodecParams {
+            codecs: CodecSet::only(Codec::Opus),
+            ..local
+        };
+        assert_eq!(
+            opus_only.negotiate(&legacy),
+            Err(RejectReason::Incompatible)
+        );
+    }
+
+    #[test]
+    fn test_surround_is_opus_only() {
+        let local = CodecParams {
+            sample_rate: 16_000,
+            channels: 6,
+            ..CodecParams::default()
+        };
+        let offer = CodecParams {
+            codecs: [Codec::Opus, Codec::L16].into_iter().collect(),
+            ..local
+        };
+        let answer = local.negotiate(&offer).unwrap();
+        assert_eq!((answer.codec(), answer.channels), (Some(Codec::Opus), 6));
+        assert!(offer.allows(&answer));
+
+        // Without Opus, the answer narrows to stereo, and an answer that doesn't is refused.
+        let l16 = CodecParams {
+            codecs: CodecSet::only(Codec::L16),
+            ..local
+        };
+        let answer = local.negotiate(&l16).unwrap();
+        assert_eq!((answer.codec(), answer.channels), (Some(Codec::L16), 2));
+        assert!(l16.allows(&answer));
+        assert!(!l16.allows(&CodecParams {
+            channels: 6,
+            ..answer
+        }));
+    }
+
+    #[test]
+    fn test_call_setup_and_bye() {
+        let (mut caller, mut callee) = connect(CodecParams::default(), CodecParams::default());
+        let expected = Media {
+            call_id: CALL_ID,
+            local_ssrc: 1,
+            remote_ssrc: 2,
+            params: CodecParams {
+                codecs: CodecSet::only(Codec::Opus),
+                ..CodecParams::default()
+            },
+            remote_key: None,
+        };
+        assert_eq!(caller.media(), Some(expected));
+        assert_eq!(callee.media().unwrap().remote_ssrc, 1);
+
+        let now = Instant::now();
+        let actions = caller.hangup(now);
+        let bye = sent(&actions).remove(0);
+        assert_eq!(bye, Message::Bye { call_id: CALL_ID });
+        assert_eq!(caller.state(), &State::Ended(EndReason::Hangup));
+
+        let actions = callee.receive(bye, now);
+        assert_eq!(actions, vec![Action::Ended(EndReason::RemoteHangup)]);
+    }
+
+    #[test]
+    fn test_reject_and_busy() {
+        let now = Instant::now();
+        let mut caller = Session::new(CodecParams::default(), 1, now);
+        let mut callee = Session::new(CodecParams::default(), 2, now);
+
+        let invite = sent(&caller.invite(CALL_ID, now)).remove(0);
+        callee.receive(invite, now);
+
+        // A second caller gets a busy signal while the first one is ringing.
+        let other = Message::Invite {
+            call_id: 99,
+            ssrc: 3,
+            params: CodecParams::default(),
+            public_key: None,
+        };
+        let busy = sent(&callee.receive(other, now));
+        assert_eq!(
+            busy,
+            vec![Message::Reject {
+                call_id: 99,
+                reason: RejectReason::Busy
+            }]
+        );
+
+        let reject = sent(&callee.reject(RejectReason::Declined, now)).remove(0);
+        let actions = caller.receive(reject, now);
+        assert_eq!(
+            actions,
+            vec![Action::Ended(EndReason::Rejected(RejectReason::Declined))]
+        );
+    }
+
+    #[test]
+    fn test_incompatible_offer_is_rejected() {
+        let now = Instant::now();
+        let mut callee = Session::new(CodecParams::default(), 2, now);
+        let invite = Message::Invite {
+            call_id: CALL_ID,
+            ssrc: 1,
+            params: CodecParams {
+                frame_ms: 7,
+                ..CodecParams::default()
+            },
+            public_key: None,
+        };
+        let actions = callee.receive(invite, now);
+        assert_eq!(
+            sent(&actions),
+            vec![Message::Reject {
+                call_id: CALL_ID,
+                reason: RejectReason::Incompatible
+            }]
+        );
+        assert_eq!(callee.state(), &State::Idle);
+    }
+
+    #[test]
+    fn test_answer_wider_than_offer_is_refused() {
+        let now = Instant::now();
+        let mut caller = Session::new(CodecParams::default(), 1, now);
+        caller.invite(CALL_ID, now);
+        let accept = Message::Accept {
+            call_id: CALL_ID,
+            ssrc: 2,
+            params: CodecParams {
+                bitrate: 500_000,
+                ..CodecParams::default()
+            },
+            public_key: None,
+        };
+        let actions = caller.receive(accept, now);
+        assert_eq!(sent(&actions), vec![Message::Bye { call_id: CALL_ID }]);
+        assert_eq!(
+            caller.state(),
+            &State::Ended(EndReason::Rejected(RejectReason::Incompatible))
+        );
+    }
+
+    #[test]
+    fn test_timers() {
+        let start = Instant::now();
+        let mut caller = Session::new(CodecParams::default(), 1, start);
+        caller.invite(CALL_ID, start);
+
+        // Unanswered invites are retransmitted, then given up on.
+        let retry = caller.tick(start + Duration::from_millis(600));
+        assert!(matches!(sent(&retry)[..], [Message::Invite { .. }]));
+        let actions = caller.tick(start + Duration::from_secs(31));
+        assert_eq!(actions.last(), Some(&Action::Ended(EndReason::Timeout)));
+
+        // Established calls send keepalives and time out when the peer goes quiet.
+        let (mut caller, _) = connect(CodecParams::default(), CodecParams::default());
+        let start = Instant::now();
+        let keepalive = caller.tick(start + Duration::from_millis(1100));
+        assert_eq!(
+            sent(&keepalive),
+            vec![Message::Keepalive { call_id: CALL_ID }]
+        );
+        caller.media_received(start + Duration::from_secs(4));
+        assert!(!caller.is_ended());
+        assert!(caller.tick(start + Duration::from_secs(8)).len() <= 1);
+        let actions = caller.tick(start + Duration::from_secs(10));
+        assert_eq!(actions.last(), Some(&Action::Ended(EndReason::Timeout)));
+    }
+
+    #[test]
+    fn test_retransmitted_invite_repeats_accept() {
+        let (_, mut callee) = connect(CodecParams::default(), CodecParams::default());
+        let invite = Message::Invite {
+            call_id: CALL_ID,
+            ssrc: 1,
+            params: CodecParams::default(),
+            public_key: None,
+        };
+        let actions = callee.receive(invite, Instant::now());
+        assert!(matches!(sent(&actions)[..], [Message::Accept { .. }]));
+    }
+
+    #[test]
+    fn test_key_exchange() {
+        let now = Instant::now();
+        let mut caller = Session::new(CodecParams::default(), 1, now).with_public_key([1; 32]);
+        let mut callee = Session::new(CodecParams::default(), 2, now).with_public_key([2; 32]);
+
+        let invite = sent(&caller.invite(CALL_ID, now)).remove(0);
+        callee.receive(invite, now);
+        let accept = sent(&callee.accept(now)).remove(0);
+        caller.receive(accept, now);
+        assert_eq!(caller.media().unwrap().remote_key, Some([2; 32]));
+        assert_eq!(callee.media().unwrap().remote_key, Some([1; 32]));
+
+        // A peer that wants encryption refuses one that offers no key.
+        let mut plain = Session::new(CodecParams::default(), 3, now);
+        let mut secure = Session::new(CodecParams::default(), 4, now).with_public_key([4; 32]);
+        let invite = sent(&plain.invite(CALL_ID, now)).remove(0);
+        let actions = secure.receive(invite, now);
+        assert_eq!(
+            sent(&actions),
+            vec![Message::Reject {
+                call_id: CALL_ID,
+                reason: RejectReason::Incompatible
+            }]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::time::Duration;
+
+    use test_gpui::mixer::Mixer;
+    use test_gpui::spatial::{self, Renderer, Spatializer};
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    fn tone(frequency: f32, samples: usize) -> Vec<f32> {
+        (0..samples)
+            .map(|n| (2.0 * PI * frequency * n as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    /// RMS level of each ear after rendering `input`, skipping the filters' settling time.
+    fn levels(spatializer: &mut Spatializer, input: &[f32]) -> (f32, f32) {
+        let rendered: Vec<(f32, f32)> = input.iter().map(|&x| spatializer.render(x)).collect();
+        let settled = &rendered[rendered.len() / 2..];
+        let rms = |side: fn(&(f32, f32)) -> f32| {
+            (settled.iter().map(|frame| side(frame).powi(2)).sum::<f32>() / settled.len() as f32)
+                .sqrt()
+        };
+        (rms(|frame| frame.0), rms(|frame| frame.1))
+    }
+
+    #[test]
+    fn test_equal_power() {
+        let (left, right) = spatial::equal_power(0.0);
+        assert!((left - right).abs() < 1e-6);
+        assert!((left - 0.5f32.sqrt()).abs() < 1e-6);
+        for azimuth in [-90.0, -45.0, -10.0, 30.0, 90.0] {
+            let (left, right) = spatial::equal_power(azimuth);
+            assert!((left * left + right * right - 1.0).abs() < 1e-5);
+        }
+        let (left, right) = spatial::equal_power(-90.0);
+        assert!((left - 1.0).abs() < 1e-6 && right.abs() < 1e-6);
+
+        assert_eq!(spatial::spread(1), vec![0.0]);
+        assert_eq!(spatial::spread(3), vec![-60.0, 0.0, 60.0]);
+    }
+
+    #[test]
+    fn test_binaural_time_and_level_differences() {
+        // A click on the left reaches the right ear about 0.66 ms later.
+        let mut spatializer = Spatializer::new(Renderer::Binaural, SAMPLE_RATE).with_azimuth(-90.0);
+        let mut click = vec![0.0; 100];
+        click[0] = 1.0;
+        let rendered: Vec<(f32, f32)> = click.iter().map(|&x| spatializer.render(x)).collect();
+        let loudest = |side: fn(&(f32, f32)) -> f32| {
+            (0..rendered.len())
+                .max_by(|&a, &b| {
+                    side(&rendered[a])
+                        .abs()
+                        .total_cmp(&side(&rendered[b]).abs())
+                })
+                .unwrap()
+        };
+        assert_eq!(loudest(|frame| frame.0), 0);
+        let delay = loudest(|frame| frame.1);
+        assert!((30..=33).contains(&delay), "delay {}", delay);
+
+        // The head shadows high frequencies on the far side, but hardly low ones.
+        let mut spatializer = Spatializer::new(Renderer::Binaural, SAMPLE_RATE).with_azimuth(-60.0);
+        let (left, right) = levels(&mut spatializer, &tone(6_000.0, 4_800));
+        assert!(left > 4.0 * right, "{} vs {}", left, right);
+        let mut spatializer = Spatializer::new(Renderer::Binaural, SAMPLE_RATE).with_azimuth(-60.0);
+        let (left, right) = levels(&mut spatializer, &tone(100.0, 48_000));
+        assert!(left / right < 1.2, "{} vs {}", left, right);
+
+        // Straight ahead, both ears hear the same.
+        let mut spatializer = Spatializer::new(Renderer::Binaural, SAMPLE_RATE);
+        let (left, right) = levels(&mut spatializer, &tone(3_000.0, 4_800));
+        assert!((left - right).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_moves_glide() {
+        let mut spatializer = Spatializer::new(Renderer::EqualPower, SAMPLE_RATE);
+        spatializer.render(1.0);
+        spatializer.set_azimuth(90.0);
+        let mut previous = spatializer.render(1.0);
+        for _ in 0..SAMPLE_RATE / 2 {
+            let next = spatializer.render(1.0);
+            assert!((next.0 - previous.0).abs() < 0.001);
+            previous = next;
+        }
+        assert!(previous.0 < 0.01 && previous.1 > 0.99, "{:?}", previous);
+    }
+
+    #[test]
+    fn test_mixer_positions_inputs() {
+        let mut mixer =
+            Mixer::new(SAMPLE_RATE, 2).with_buffering(Duration::ZERO, Duration::from_secs(1));
+        let left = mixer.add_input(1);
+        let right = mixer.add_input(1);
+        mixer.input(left).unwrap().push(&[0.2; 4_800]);
+        mixer.input(right).unwrap().push(&[0.4; 4_800]);
+        mixer.input(left).unwrap().set_azimuth(Some(-90.0));
+        mixer.input(right).unwrap().set_azimuth(Some(90.0));
+
+        let mut out = vec![0.0; 1_920];
+        mixer.mix(&mut out);
+        assert!((out[1_918] - 0.2).abs() < 1e-4, "{}", out[1_918]);
+        assert!((out[1_919] - 0.4).abs() < 1e-4, "{}", out[1_919]);
+
+        // Switching renderer keeps everybody where they are.
+        mixer.set_renderer(Renderer::Binaural);
+        assert_eq!(mixer.input(left).unwrap().azimuth(), Some(-90.0));
+        mixer.mix(&mut out);
+        assert!(out[1_918] > 0.19 && out[1_919] > 0.39);
+
+        // Without a position, inputs go back to plain panning.
+        mixer.input(left).unwrap().set_azimuth(None);
+        assert_eq!(mixer.input(left).unwrap().azimuth(), None);
+    }
+}
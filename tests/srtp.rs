@@ -0,0 +1,272 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use test_gpui::rtcp::{RtcpPacket, RtcpSession};
+    use test_gpui::rtp::{HEADER_LEN, OPUS_PAYLOAD_TYPE, RtpHeader};
+    use test_gpui::signaling::Message;
+    use test_gpui::srtp::{
+        CallKeys, CryptoError, KeyExchange, RTCP_TRAILER_LEN, Role, SIGNALING_TRAILER_LEN,
+        SrtpReceiver, SrtpSender, TAG_LEN,
+    };
+
+    const CALL_ID: u32 = 0xC0FFEE;
+
+    fn packet(sequence: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; HEADER_LEN];
+        RtpHeader {
+            marker: false,
+            payload_type: OPUS_PAYLOAD_TYPE,
+            sequence,
+            timestamp: sequence as u32 * 960,
+            ssrc: 0x1234_5678,
+        }
+        .write(&mut packet);
+        packet.extend_from_slice(format!("frame {}", sequence).as_bytes());
+        packet
+    }
+
+    /// Keys for one direction of a call, as both ends derive them.
+    fn pair() -> (SrtpSender, SrtpReceiver) {
+        let caller = KeyExchange::new();
+        let callee = KeyExchange::new();
+        let (caller_key, callee_key) = (caller.public_key(), callee.public_key());
+        let (send, _) = caller.finish(callee_key, Role::Caller, CALL_ID).unwrap();
+        let (_, receive) = callee.finish(caller_key, Role::Callee, CALL_ID).unwrap();
+        (send, receive)
+    }
+
+    /// Every key of a call, as the caller and the callee derive them.
+    fn call_keys() -> (CallKeys, CallKeys) {
+        let caller = KeyExchange::new();
+        let callee = KeyExchange::new();
+        let (caller_key, callee_key) = (caller.public_key(), callee.public_key());
+        (
+            caller.derive(callee_key, Role::Caller, CALL_ID).unwrap(),
+            callee.derive(caller_key, Role::Callee, CALL_ID).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (mut sender, mut receiver) = pair();
+        let plain = packet(1);
+        let protected = sender.protect(&plain).unwrap();
+        assert_eq!(protected.len(), plain.len() + TAG_LEN);
+        // The header stays readable, the payload does not.
+        assert_eq!(protected[..HEADER_LEN], plain[..HEADER_LEN]);
+        assert_ne!(protected[HEADER_LEN..plain.len()], plain[HEADER_LEN..]);
+        assert_eq!(receiver.unprotect(&protected), Ok(plain));
+    }
+
+    #[test]
+    fn test_directions_use_different_keys() {
+        let caller = KeyExchange::new();
+        let callee = KeyExchange::new();
+        let (caller_key, callee_key) = (caller.public_key(), callee.public_key());
+        let (mut caller_send, mut caller_receive) =
+            caller.finish(callee_key, Role::Caller, CALL_ID).unwrap();
+        let (mut callee_send, mut callee_receive) =
+            callee.finish(caller_key, Role::Callee, CALL_ID).unwrap();
+
+        let to_callee = caller_send.protect(&packet(1)).unwrap();
+        let to_caller = callee_send.protect(&packet(1)).unwrap();
+        assert_ne!(to_callee, to_caller);
+        // A packet reflected back at its sender does not authenticate.
+        assert_eq!(
+            caller_receive.unprotect(&to_callee),
+            Err(CryptoError::Authentication)
+        );
+        assert!(callee_receive.unprotect(&to_callee).is_ok());
+        assert!(caller_receive.unprotect(&to_caller).is_ok());
+    }
+
+    #[test]
+    fn test_tampered_packets_are_rejected() {
+        let (mut sender, mut receiver) = pair();
+        let protected = sender.protect(&packet(1)).unwrap();
+
+        // Flipping a bit anywhere, header included, breaks authentication.
+        for byte in [1, 3, HEADER_LEN, protected.len() - 1] {
+            let mut tampered = protected.clone();
+            tampered[byte] ^= 0x01;
+            assert_eq!(
+                receiver.unprotect(&tampered),
+                Err(CryptoError::Authentication)
+            );
+        }
+        assert_eq!(
+            receiver.unprotect(&protected[..protected.len() - 1]),
+            Err(CryptoError::Authentication)
+        );
+        assert_eq!(
+            receiver.unprotect(&protected[..HEADER_LEN + 4]),
+            Err(CryptoError::Malformed)
+        );
+
+        // A packet from someone without the key is rejected too.
+        let (mut stranger, _) = pair();
+        let forged = stranger.protect(&packet(2)).unwrap();
+        assert_eq!(
+            receiver.unprotect(&forged),
+            Err(CryptoError::Authentication)
+        );
+
+        // Failed attempts do not burn the index of the genuine packet.
+        assert!(receiver.unprotect(&protected).is_ok());
+    }
+
+    #[test]
+    fn test_replayed_packets_are_rejected() {
+        let (mut sender, mut receiver) = pair();
+        let first = sender.protect(&packet(1)).unwrap();
+        let second = sender.protect(&packet(2)).unwrap();
+        assert!(receiver.unprotect(&first).is_ok());
+        assert!(receiver.unprotect(&second).is_ok());
+        assert_eq!(receiver.unprotect(&first), Err(CryptoError::Replay));
+        assert_eq!(receiver.unprotect(&second), Err(CryptoError::Replay));
+    }
+
+    #[test]
+    fn test_reordered_packets_within_window() {
+        let (mut sender, mut receiver) = pair();
+        let packets: Vec<_> = (0..100)
+            .map(|sequence| sender.protect(&packet(sequence)).unwrap())
+            .collect();
+
+        for sequence in [0, 3, 1, 2, 10, 9, 70] {
+            assert_eq!(
+                receiver.unprotect(&packets[sequence]),
+                Ok(packet(sequence as u16)),
+                "packet {}",
+                sequence
+            );
+        }
+        // 9 was already seen, 8 is still inside the window but 5 has fallen out of it.
+        assert_eq!(receiver.unprotect(&packets[9]), Err(CryptoError::Replay));
+        assert!(receiver.unprotect(&packets[8]).is_ok());
+        assert_eq!(receiver.unprotect(&packets[5]), Err(CryptoError::TooOld));
+    }
+
+    #[test]
+    fn test_sequence_rollover() {
+        let (mut sender, mut receiver) = pair();
+        let sequences = [65_533, 65_534, 65_535, 0, 1];
+        let packets: Vec<_> = sequences
+            .iter()
+            .map(|&sequence| sender.protect(&packet(sequence)).unwrap())
+            .collect();
+
+        // Deliver across the wrap out of order; each packet lands on its own index.
+        for i in [0, 3, 2, 4, 1] {
+            assert_eq!(receiver.unprotect(&packets[i]), Ok(packet(sequences[i])));
+        }
+        assert_eq!(receiver.unprotect(&packets[3]), Err(CryptoError::Replay));
+
+        // The sender never protects an index twice, as that would reuse a nonce.
+        assert_eq!(sender.protect(&packet(0)), Err(CryptoError::Replay));
+        assert_eq!(sender.protect(&packet(65_534)), Err(CryptoError::Replay));
+        assert!(sender.protect(&packet(2)).is_ok());
+    }
+
+    #[test]
+    fn test_weak_public_key() {
+        // The all-zero point gives a shared secret anyone can compute.
+        let exchange = KeyExchange::new();
+        assert!(matches!(
+            exchange.finish([0; 32], Role::Caller, CALL_ID),
+            Err(CryptoError::WeakKey)
+        ));
+    }
+
+    #[test]
+    fn test_rtcp_round_trip() {
+        let (mut caller, mut callee) = call_keys();
+        let bye = RtcpSession::new(0x1234_5678, 48_000, Instant::now()).bye(Instant::now());
+        let protected = caller.send_rtcp.protect(&bye).unwrap();
+        assert_eq!(protected.len(), bye.len() + RTCP_TRAILER_LEN);
+        // Still recognisable as RTCP, but only the first header can be read.
+        assert!(RtcpPacket::is_rtcp(&protected));
+        assert_eq!(protected[..8], bye[..8]);
+        assert_ne!(protected[8..bye.len()], bye[8..]);
+
+        // Reflected back at the caller, it does not authenticate.
+        assert_eq!(
+            caller.receive_rtcp.unprotect(&protected),
+            Err(CryptoError::Authentication)
+        );
+        assert_eq!(callee.receive_rtcp.unprotect(&protected), Ok(bye.clone()));
+        assert_eq!(
+            callee.receive_rtcp.unprotect(&protected),
+            Err(CryptoError::Replay)
+        );
+
+        // Nor do the RTP keys protect RTCP.
+        let second = caller.send_rtcp.protect(&bye).unwrap();
+        assert!(callee.receive.unprotect(&second).is_err());
+        assert!(callee.receive_rtcp.unprotect(&second).is_ok());
+    }
+
+    #[test]
+    fn test_forged_rtcp_is_rejected() {
+        let (mut caller, mut callee) = call_keys();
+        let bye = RtcpSession::new(0x1234_5678, 48_000, Instant::now()).bye(Instant::now());
+
+        // Plain RTCP, as an attacker without keys would send.
+        assert!(callee.receive_rtcp.unprotect(&bye).is_err());
+        let mut unencrypted = bye.clone();
+        unencrypted.extend_from_slice(&[0; RTCP_TRAILER_LEN]);
+        assert_eq!(
+            callee.receive_rtcp.unprotect(&unencrypted),
+            Err(CryptoError::Authentication)
+        );
+
+        let protected = caller.send_rtcp.protect(&bye).unwrap();
+        for byte in [
+            1,
+            5,
+            8,
+            protected.len() - RTCP_TRAILER_LEN,
+            protected.len() - 1,
+        ] {
+            let mut tampered = protected.clone();
+            tampered[byte] ^= 0x01;
+            assert_eq!(
+                callee.receive_rtcp.unprotect(&tampered),
+                Err(CryptoError::Authentication),
+                "byte {}",
+                byte
+            );
+        }
+        assert!(callee.receive_rtcp.unprotect(&protected).is_ok());
+    }
+
+    #[test]
+    fn test_signed_signaling() {
+        let (mut caller, mut callee) = call_keys();
+        let bye = Message::Bye { call_id: CALL_ID }.encode();
+        let signed = caller.send_signaling.sign(&bye).unwrap();
+        assert_eq!(signed.len(), bye.len() + SIGNALING_TRAILER_LEN);
+        // Still readable, so the callee can tell it is signaling.
+        assert!(Message::is_signaling(&signed));
+
+        // A bye without a signature, a tampered one, and one from the wrong direction.
+        assert!(callee.receive_signaling.verify(&bye).is_err());
+        let mut tampered = signed.clone();
+        tampered[3] ^= 0x01;
+        assert_eq!(
+            callee.receive_signaling.verify(&tampered),
+            Err(CryptoError::Authentication)
+        );
+        assert_eq!(
+            caller.receive_signaling.verify(&signed),
+            Err(CryptoError::Authentication)
+        );
+
+        assert_eq!(callee.receive_signaling.verify(&signed), Ok(&bye[..]));
+        assert_eq!(
+            callee.receive_signaling.verify(&signed),
+            Err(CryptoError::Replay)
+        );
+    }
+}
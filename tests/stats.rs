@@ -0,0 +1,117 @@
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use test_gpui::stats::ReceptionStats;
+
+    const CLOCK_RATE: u32 = 48_000;
+    const FRAME: Duration = Duration::from_millis(20);
+    const FRAME_TICKS: u32 = 960;
+
+    /// Feeds `(sequence, frame number, arrival offset in ms)` tuples into fresh statistics.
+    fn timeline(packets: &[(u16, u32, u64)]) -> ReceptionStats {
+        let start = Instant::now();
+        let mut stats = ReceptionStats::new(CLOCK_RATE);
+        for &(sequence, frame, arrival) in packets {
+            stats.record(
+                sequence,
+                frame * FRAME_TICKS,
+                start + Duration::from_millis(arrival),
+            );
+        }
+        stats
+    }
+
+    #[test]
+    fn test_steady_stream() {
+        let packets: Vec<_> = (0..100)
+            .map(|i| (1000 + i as u16, i, i as u64 * 20))
+            .collect();
+        let mut stats = timeline(&packets);
+        assert_eq!(stats.expected(), 100);
+        assert_eq!(stats.received(), 100);
+        assert_eq!(stats.lost(), 0);
+        assert_eq!(stats.jitter(), Duration::ZERO);
+
+        let block = stats.report_block(7, Instant::now());
+        assert_eq!(block.highest_sequence, 1099);
+        assert_eq!(block.fraction_lost, 0);
+        assert_eq!(block.last_sr, 0);
+    }
+
+    #[test]
+    fn test_loss_and_fraction_per_interval() {
+        let start = Instant::now();
+        let mut stats = ReceptionStats::new(CLOCK_RATE);
+        // First interval: every fifth packet lost. Loss before the first packet cannot be
+        // known, so 3 of the 19 expected packets count as lost.
+        for i in 0..20u32 {
+            if i % 5 != 0 {
+                stats.record(i as u16, i * FRAME_TICKS, start + FRAME * i);
+            }
+        }
+        let block = stats.report_block(1, start);
+        assert_eq!(stats.expected(), 19);
+        assert_eq!(stats.lost(), 3);
+        assert_eq!(block.cumulative_lost, 3);
+        assert_eq!(block.fraction_lost, (3 * 256 / 19) as u8);
+
+        // Second interval: nothing lost, fraction drops back to zero but the total stays.
+        for i in 20..40u32 {
+            stats.record(i as u16, i * FRAME_TICKS, start + FRAME * i);
+        }
+        let block = stats.report_block(1, start);
+        assert_eq!(block.fraction_lost, 0);
+        assert_eq!(block.cumulative_lost, 3);
+    }
+
+    #[test]
+    fn test_reordering_and_duplicates() {
+        // 3 arrives after 4, and 2 arrives twice.
+        let stats = timeline(&[(1, 0, 0), (2, 1, 20), (4, 3, 60), (3, 2, 61), (2, 1, 62)]);
+        assert_eq!(stats.expected(), 4);
+        assert_eq!(stats.received(), 5);
+        assert_eq!(stats.lost(), -1);
+    }
+
+    #[test]
+    fn test_sequence_wrap() {
+        let packets: Vec<_> = (0..10u32)
+            .map(|i| (65_530u16.wrapping_add(i as u16), i, i as u64 * 20))
+            .collect();
+        let stats = timeline(&packets);
+        assert_eq!(stats.expected(), 10);
+        assert_eq!(stats.lost(), 0);
+        assert_eq!(stats.extended_max(), 0x1_0003);
+    }
+
+    #[test]
+    fn test_restart_after_large_jump() {
+        // A single stray packet far ahead is ignored; two in a row mean the sender restarted.
+        let stats = timeline(&[(1, 0, 0), (2, 1, 20), (40_000, 2, 40), (3, 2, 40)]);
+        assert_eq!(stats.expected(), 3);
+        assert_eq!(stats.received(), 3);
+
+        let stats = timeline(&[(1, 0, 0), (40_000, 1, 20), (40_001, 2, 40)]);
+        assert_eq!(stats.expected(), 1);
+        assert_eq!(stats.received(), 1);
+    }
+
+    #[test]
+    fn test_jitter() {
+        // Every other packet arrives 10 ms late, so each transit time differs from the
+        // previous one by 10 ms and the estimate converges to that.
+        let packets: Vec<_> = (0..400u32)
+            .map(|i| (i as u16, i, i as u64 * 20 + if i % 2 == 1 { 10 } else { 0 }))
+            .collect();
+        let stats = timeline(&packets);
+        let jitter = stats.jitter().as_secs_f64() * 1000.0;
+        assert!((jitter - 10.0).abs() < 0.5, "jitter {} ms", jitter);
+
+        // A constant delay does not count as jitter.
+        let packets: Vec<_> = (0..50u32)
+            .map(|i| (i as u16, i, 500 + i as u64 * 20))
+            .collect();
+        assert_eq!(timeline(&packets).jitter(), Duration::ZERO);
+    }
+}
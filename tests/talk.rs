@@ -0,0 +1,116 @@
+#[cfg(test)]
+mod tests {
+    use test_gpui::audio::{AudioSink, AudioSource, SharedBuffer};
+    use test_gpui::talk::{Gate, GatedSink, GatedSource, TalkControl, TalkMode, TalkState};
+
+    const SAMPLE_RATE: u32 = 48_000;
+    /// Frames in one fade.
+    const FADE: usize = 480;
+
+    #[test]
+    fn test_talk_state() {
+        let control = TalkControl::default();
+        assert!(control.state().transmitting());
+
+        control.toggle_mute();
+        assert!(!control.state().transmitting());
+        assert!(control.state().hearing());
+
+        // Deafening silences both ways and undeafening restores the previous mute.
+        control.toggle_mute();
+        control.toggle_deafen();
+        assert!(!control.state().transmitting());
+        assert!(!control.state().hearing());
+        control.toggle_deafen();
+        assert!(control.state().transmitting());
+
+        control.set_mode(TalkMode::PushToTalk);
+        assert!(!control.state().transmitting());
+        control.set_pressed(true);
+        assert!(control.state().transmitting());
+        control.set_muted(true);
+        assert!(!control.state().transmitting());
+        control.set_muted(false);
+        control.set_pressed(false);
+        assert!(!control.state().transmitting());
+        control.toggle_mode();
+        assert_eq!(control.state().mode, TalkMode::Open);
+    }
+
+    #[test]
+    fn test_control_keeps_every_state() {
+        for bits in 0..16 {
+            let state = TalkState {
+                mode: if bits & 1 != 0 {
+                    TalkMode::PushToTalk
+                } else {
+                    TalkMode::Open
+                },
+                muted: bits & 2 != 0,
+                deafened: bits & 4 != 0,
+                pressed: bits & 8 != 0,
+            };
+            let control = TalkControl::new(state);
+            assert_eq!(control.state(), state);
+            control.toggle_deafen();
+            control.toggle_deafen();
+            control.set_pressed(state.pressed);
+            assert_eq!(control.clone().state(), state);
+        }
+    }
+
+    #[test]
+    fn test_gate_fades() {
+        let mut gate = Gate::new(SAMPLE_RATE);
+        assert!(gate.is_closed());
+
+        let mut samples = vec![1.0; 2 * FADE * 2];
+        gate.process(&mut samples, 2, true);
+        // Both channels of a frame get the same gain, rising steadily to full level.
+        assert_eq!(samples[0], samples[1]);
+        assert!(samples[0] > 0.0 && samples[0] < 0.01);
+        assert!(samples.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert!((samples[FADE * 2 - 1] - 1.0).abs() < 1e-3);
+        assert_eq!(samples[FADE * 2 + 1], 1.0);
+
+        let mut samples = vec![1.0; 2 * FADE];
+        gate.process(&mut samples, 1, false);
+        assert!(samples[0] > 0.99);
+        assert!(samples.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(samples[FADE + 1], 0.0);
+        assert!(gate.is_closed());
+
+        let mut samples = vec![1.0; 10];
+        gate.process(&mut samples, 1, false);
+        assert_eq!(samples, vec![0.0; 10]);
+    }
+
+    #[test]
+    fn test_gated_source_and_sink() {
+        let control = TalkControl::new(TalkState {
+            muted: true,
+            ..TalkState::default()
+        });
+        let microphone = SharedBuffer::new(48_000);
+        microphone.push(&[0.5; 4_800]);
+        let mut source = GatedSource::new(microphone.clone(), control.clone(), SAMPLE_RATE, 1);
+        let mut buf = vec![0.0; 960];
+        assert_eq!(source.read(&mut buf), 960);
+        assert!(buf.iter().all(|&sample| sample == 0.0));
+        control.set_muted(false);
+        source.read(&mut buf);
+        assert!(buf[0] < 0.01 && buf[959] == 0.5);
+
+        let speaker = SharedBuffer::new(48_000);
+        let mut sink = GatedSink::new(speaker.clone(), control.clone(), SAMPLE_RATE, 2);
+        control.set_deafened(true);
+        sink.write(&[0.5; 1_920]);
+        let mut out = vec![1.0; 1_920];
+        assert_eq!(speaker.pop_into(&mut out), 1_920);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+        control.set_deafened(false);
+        sink.write(&[0.5; 1_920]);
+        speaker.pop_into(&mut out);
+        assert_eq!(out[1_919], 0.5);
+    }
+}